@@ -0,0 +1,195 @@
+//! Audio for sinks and the replay buffer: the desktop, through the monitor of the default
+//! PulseAudio or PipeWire sink, and the microphone, through the default source. Files keep them as
+//! separate tracks so they can be mixed later, everything else gets them mixed into one. Other
+//! sources can be picked by their PulseAudio name, as listed by `pactl list short sources`.
+//!
+//! The screens reach the output device a few frames after they were shown, while sound is
+//! captured right away, so the audio is delayed to match by `--av-offset`.
+
+/// Noise suppression for the microphone.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Denoise {
+    /// ffmpeg's FFT denoiser, which needs nothing else
+    Fft,
+    /// RNNoise, with a model file from https://github.com/GregorR/rnnoise-models
+    Rnnoise(String),
+}
+
+impl Denoise {
+    /// `afftdn`, or the path to an RNNoise model.
+    pub fn parse(value: &str) -> Denoise {
+        match value {
+            "afftdn" => Denoise::Fft,
+            model => Denoise::Rnnoise(model.to_string()),
+        }
+    }
+
+    fn filter(&self) -> String {
+        match self {
+            Denoise::Fft => "afftdn".to_string(),
+            Denoise::Rnnoise(model) => format!("arnndn=m='{}'", model),
+        }
+    }
+}
+
+/// How much later the audio is put than it was captured.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Offset {
+    /// In milliseconds, negative to put it earlier
+    Fixed(i64),
+    /// Estimated from the stages the frames go through before the sinks read them
+    Auto,
+}
+
+impl Offset {
+    /// `auto`, or a number of milliseconds.
+    pub fn parse(value: &str) -> Option<Offset> {
+        match value {
+            "auto" => Some(Offset::Auto),
+            ms => ms.parse().ok().map(Offset::Fixed),
+        }
+    }
+}
+
+/// Which audio to capture, and how loud.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Audio {
+    pub desktop: bool,
+    pub mic: bool,
+    /// PulseAudio source of the desktop sound, the monitor of the default sink if not set
+    pub desktop_device: Option<String>,
+    /// PulseAudio source of the microphone, the default source if not set
+    pub mic_device: Option<String>,
+    pub desktop_volume: f64,
+    pub mic_volume: f64,
+    pub mic_denoise: Option<Denoise>,
+    /// Keeps the microphone from clipping after the volume is raised
+    pub mic_limiter: bool,
+    pub offset: Offset,
+}
+
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio {
+            desktop: false,
+            mic: false,
+            desktop_device: None,
+            mic_device: None,
+            desktop_volume: 1.0,
+            mic_volume: 1.0,
+            mic_denoise: None,
+            mic_limiter: false,
+            offset: Offset::Fixed(0),
+        }
+    }
+}
+
+impl Audio {
+    pub fn enabled(&self) -> bool {
+        self.desktop || self.mic
+    }
+
+    /// The sources as they are added to ffmpeg, with their track titles and filters.
+    fn sources(&self) -> Vec<(&str, &'static str, String)> {
+        let mut sources = Vec::new();
+        if self.desktop {
+            sources.push((
+                self.desktop_device
+                    .as_deref()
+                    .unwrap_or("@DEFAULT_MONITOR@"),
+                "Desktop",
+                format!("volume={}", self.desktop_volume),
+            ));
+        }
+        if self.mic {
+            // Noise is removed before it is made louder
+            let mut filters = Vec::new();
+            filters.extend(self.mic_denoise.as_ref().map(Denoise::filter));
+            filters.push(format!("volume={}", self.mic_volume));
+            if self.mic_limiter {
+                filters.push("alimiter=limit=0.9".to_string());
+            }
+            sources.push((
+                self.mic_device.as_deref().unwrap_or("default"),
+                "Microphone",
+                filters.join(","),
+            ));
+        }
+        sources
+    }
+
+    /// How many inputs `input_args` adds.
+    pub fn input_count(&self) -> usize {
+        self.sources().len()
+    }
+
+    /// ffmpeg inputs for the sources, added after the video input. An automatic offset delays
+    /// them by `auto_delay` milliseconds.
+    pub fn input_args(&self, auto_delay: i64) -> Vec<String> {
+        let ms = match self.offset {
+            Offset::Fixed(ms) => ms,
+            Offset::Auto => auto_delay,
+        };
+        let offset = match ms {
+            0 => Vec::new(),
+            ms => vec![
+                "-itsoffset".to_string(),
+                format!("{:.3}", ms as f64 / 1000.0),
+            ],
+        };
+        self.sources()
+            .into_iter()
+            .flat_map(|(source, _, _)| {
+                offset.iter().cloned().chain(
+                    ["-thread_queue_size", "1024", "-f", "pulse", "-i", source].map(String::from),
+                )
+            })
+            .collect()
+    }
+
+    /// ffmpeg options that map the video and the audio of the sources, as one track each when
+    /// `separate`, or mixed into one otherwise. WebRTC only takes Opus.
+    pub fn output_args(&self, separate: bool, opus: bool) -> Vec<String> {
+        let sources = self.sources();
+        if sources.is_empty() {
+            return Vec::new();
+        }
+
+        let mut filters: Vec<String> = sources
+            .iter()
+            .enumerate()
+            .map(|(i, (_, _, filters))| format!("[{}:a]{}[a{}]", i + 1, filters, i))
+            .collect();
+        let tracks: Vec<String> = if separate || sources.len() == 1 {
+            (0..sources.len()).map(|i| format!("[a{}]", i)).collect()
+        } else {
+            let inputs: String = (0..sources.len()).map(|i| format!("[a{}]", i)).collect();
+            filters.push(format!(
+                "{}amix=inputs={}:normalize=0[mixed]",
+                inputs,
+                sources.len()
+            ));
+            vec!["[mixed]".to_string()]
+        };
+
+        let mut args = vec![
+            "-filter_complex".to_string(),
+            filters.join(";"),
+            "-map".to_string(),
+            "0:v".to_string(),
+        ];
+        for (i, track) in tracks.iter().enumerate() {
+            args.extend(["-map".to_string(), track.clone()]);
+            if separate {
+                args.push(format!("-metadata:s:a:{}", i));
+                args.push(format!("title={}", sources[i].1));
+            }
+        }
+        if opus {
+            args.extend(["-c:a", "libopus", "-b:a", "128k"].map(String::from));
+        } else {
+            args.extend(["-c:a", "aac", "-b:a", "160k"].map(String::from));
+        }
+        args
+    }
+}
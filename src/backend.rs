@@ -0,0 +1,109 @@
+use std::env;
+use std::process::Command;
+
+/// The window manager we follow focus on. sway and i3 share the same IPC protocol, the
+/// difference is mostly in how screens get captured. niri has its own JSON IPC. Other wlroots
+/// compositors are followed through the foreign toplevel protocol, without any IPC.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Backend {
+    Sway,
+    I3,
+    Niri,
+    ForeignToplevel,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IpcRequest {
+    Outputs,
+    Workspaces,
+    /// All windows, as a tree on sway and i3
+    Windows,
+    /// Prints one line for every event about windows, workspaces and outputs, until killed
+    Subscribe,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Option<Backend> {
+        match name {
+            "sway" => Some(Backend::Sway),
+            "i3" => Some(Backend::I3),
+            "niri" => Some(Backend::Niri),
+            "foreign-toplevel" => Some(Backend::ForeignToplevel),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Sway => "sway",
+            Backend::I3 => "i3",
+            Backend::Niri => "niri",
+            Backend::ForeignToplevel => "foreign-toplevel",
+        }
+    }
+
+    /// The program used to talk to the window manager over IPC.
+    pub fn ipc_program(&self) -> &'static str {
+        match self {
+            Backend::Sway => "swaymsg",
+            Backend::I3 => "i3-msg",
+            Backend::Niri => "niri",
+            Backend::ForeignToplevel => unreachable!("foreign-toplevel is followed without IPC"),
+        }
+    }
+
+    /// Builds the command for an IPC request, talking to the window manager through `socket` if
+    /// given instead of the one from the environment.
+    pub fn ipc_command(&self, request: IpcRequest, socket: Option<&str>) -> Command {
+        let mut command = Command::new(self.ipc_program());
+
+        match self {
+            Backend::Sway | Backend::I3 => {
+                if let Some(socket) = socket {
+                    command.arg(format!("--socket={}", socket));
+                }
+                match request {
+                    IpcRequest::Outputs => command.args(["-t", "get_outputs"]),
+                    IpcRequest::Workspaces => command.args(["-t", "get_workspaces"]),
+                    IpcRequest::Windows => command.args(["-t", "get_tree"]),
+                    IpcRequest::Subscribe => command.args([
+                        "-t",
+                        "subscribe",
+                        "-m",
+                        "[\"window\", \"workspace\", \"output\"]",
+                    ]),
+                };
+            }
+            Backend::Niri => {
+                if let Some(socket) = socket {
+                    command.env("NIRI_SOCKET", socket);
+                }
+                command.args(["msg", "--json"]);
+                command.arg(match request {
+                    IpcRequest::Outputs => "outputs",
+                    IpcRequest::Workspaces => "workspaces",
+                    IpcRequest::Windows => "windows",
+                    IpcRequest::Subscribe => "event-stream",
+                });
+            }
+            Backend::ForeignToplevel => unreachable!("foreign-toplevel is followed without IPC"),
+        }
+
+        command
+    }
+}
+
+/// Guesses the backend from the session environment, defaulting to sway.
+pub fn detect() -> Backend {
+    if env::var_os("NIRI_SOCKET").is_some() {
+        Backend::Niri
+    } else if env::var_os("SWAYSOCK").is_some() {
+        Backend::Sway
+    } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+        Backend::ForeignToplevel
+    } else if env::var_os("I3SOCK").is_some() || env::var_os("DISPLAY").is_some() {
+        Backend::I3
+    } else {
+        Backend::Sway
+    }
+}
@@ -0,0 +1,328 @@
+//! `wlstreamer bench`: captures the focused screen for a few seconds with every pipeline
+//! configuration that is available, and scales test frames with every scaler the upscaler could
+//! use, to find the settings that work best on this computer.
+//!
+//! Nothing is written to the loopback devices, so this can run next to a running instance.
+
+use crate::capture::{self, CaptureProtocol};
+use crate::{get_outputs, get_valid_screens_for_recording, i18n, Config, Resolution};
+use std::env;
+use std::io::{self, Read};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SECONDS: u64 = 5;
+const SCALERS: [&str; 4] = ["fast_bilinear", "bilinear", "bicubic", "lanczos"];
+/// What ffmpeg's scale filter uses when no flags are given
+const DEFAULT_SCALER: &str = "bicubic";
+
+/// How a configuration did.
+struct Run {
+    name: String,
+    fps: f64,
+    /// Percent of a single core
+    cpu: f64,
+}
+
+/// Waits for a child and returns how much CPU time it and everything it waited for used.
+fn wait_with_usage(child: &mut Child) -> io::Result<(ExitStatus, Duration)> {
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = child.id() as libc::pid_t;
+    if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Ok((
+        ExitStatus::from_raw(status),
+        time(usage.ru_utime) + time(usage.ru_stime),
+    ))
+}
+
+/// Runs a capture command writing raw frames of `frame_size` bytes to stdout for `SECONDS`, and
+/// counts them.
+fn capture(name: &str, mut command: Command, frame_size: usize) -> Option<Run> {
+    let started = Instant::now();
+    let mut child = match command.stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            println!("{}", i18n::tr("Could not run {}: {}", &[&name, &err]));
+            return None;
+        }
+    };
+
+    let mut stdout = child.stdout.take()?;
+    let counter = thread::spawn(move || {
+        let mut bytes = 0;
+        let mut buffer = vec![0; 1 << 16];
+        while let Ok(read) = stdout.read(&mut buffer) {
+            if read == 0 {
+                break;
+            }
+            bytes += read;
+        }
+        bytes
+    });
+
+    thread::sleep(Duration::from_secs(SECONDS));
+    // wf-recorder stops cleanly on SIGINT
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let elapsed = started.elapsed();
+    let (_, cpu) = wait_with_usage(&mut child).ok()?;
+    let bytes = counter.join().unwrap_or(0);
+
+    Some(Run {
+        name: name.to_string(),
+        fps: (bytes / frame_size) as f64 / elapsed.as_secs_f64(),
+        cpu: cpu.as_secs_f64() / elapsed.as_secs_f64() * 100.0,
+    })
+}
+
+/// Scales generated frames from `from` to `to` as fast as possible with a scaler.
+fn scale(config: &Config, scaler: &str, from: Resolution, to: Resolution) -> Option<Run> {
+    let frames = 300;
+    let started = Instant::now();
+    let filter = format!(
+        "scale={}:{}:force_original_aspect_ratio=decrease:flags={},pad={}:{}:(ow-iw)/2:(oh-ih)/2,format=yuyv422",
+        to.width, to.height, scaler, to.width, to.height
+    );
+    let mut child = Command::new(&config.options.ffmpeg_path)
+        .args(["-loglevel", "error", "-f", "lavfi", "-i"])
+        .arg(format!(
+            "testsrc2=size={}x{}:rate=1000,format=yuyv422",
+            from.width, from.height
+        ))
+        .args(["-frames:v", frames.to_string().as_str()])
+        .args(["-vf", filter.as_str(), "-f", "null", "-"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let (status, cpu) = wait_with_usage(&mut child).ok()?;
+    if !status.success() {
+        return None;
+    }
+    let elapsed = started.elapsed();
+
+    Some(Run {
+        name: format!("{} scaler", scaler),
+        fps: frames as f64 / elapsed.as_secs_f64(),
+        cpu: cpu.as_secs_f64() / elapsed.as_secs_f64() * 100.0,
+    })
+}
+
+fn wf_recorder(config: &Config, output: &str, extra: &[String]) -> Command {
+    let mut command = Command::new(&config.options.wf_recorder_path);
+    command
+        .args([
+            "--muxer=rawvideo",
+            "--codec=rawvideo",
+            "--no-damage",
+            "--file=/dev/stdout",
+        ])
+        .arg(format!("-o{}", output))
+        .args(extra)
+        .stdin(Stdio::null());
+    command
+}
+
+fn print(runs: &[Run]) {
+    for run in runs {
+        println!(
+            "  {:<32} {:>7.1} fps {:>6.0}% cpu",
+            run.name, run.fps, run.cpu
+        );
+    }
+}
+
+/// Runs the benchmark on the focused screen and prints the results, then exits.
+pub fn run(config: &Config) -> ! {
+    let outputs = get_outputs(config);
+    let focused = get_valid_screens_for_recording(config)
+        .into_iter()
+        .find(|w| w.focused)
+        .and_then(|w| outputs.iter().find(|o| o.name == w.output))
+        .or_else(|| outputs.first());
+    let output = match focused {
+        Some(output) => output,
+        None => {
+            println!("{}", i18n::tr("There is no screen to benchmark", &[]));
+            std::process::exit(1);
+        }
+    };
+    let resolution = output.resolution();
+    let pixels = resolution.width * resolution.height;
+    let refresh = output
+        .current_mode
+        .as_ref()
+        .map_or(60.0, |mode| mode.refresh as f64 / 1000.0);
+
+    println!(
+        "{}",
+        i18n::tr(
+            "Benchmarking {} at {}x{}, {} seconds for every configuration. Screens are captured without damage tracking, so move something around to see the full frame rate.",
+            &[&output.name, &resolution.width, &resolution.height, &SECONDS]
+        )
+    );
+    println!();
+
+    let mut captures = Vec::new();
+    match config.options.capture_protocol {
+        CaptureProtocol::WlrScreencopy => {
+            for (name, format, size) in [
+                ("wf-recorder yuyv422", "yuyv422", pixels * 2),
+                ("wf-recorder nv12", "nv12", pixels * 3 / 2),
+                ("wf-recorder bgr0", "bgr0", pixels * 4),
+            ] {
+                let extra = [format!("--pixel-format={}", format)];
+                let command = wf_recorder(config, output.name.as_str(), &extra);
+                captures.extend(capture(name, command, size));
+            }
+            if let Some(render_node) = capture::render_node(config.options.drm_device.as_deref()) {
+                let extra = [
+                    "--pixel-format=yuyv422".to_string(),
+                    format!("--device={}", render_node),
+                    "--filter=hwupload,scale_vaapi,hwdownload,format=nv12".to_string(),
+                ];
+                let command = wf_recorder(config, output.name.as_str(), &extra);
+                captures.extend(capture("wf-recorder --gpu-scale", command, pixels * 2));
+            }
+        }
+        CaptureProtocol::ExtImageCopyCapture => {}
+        CaptureProtocol::X11Grab => {
+            let mut command = Command::new(&config.options.ffmpeg_path);
+            command
+                .args(["-loglevel", "error", "-f", "x11grab", "-video_size"])
+                .arg(format!("{}x{}", resolution.width, resolution.height))
+                .args(["-i", std::env::var("DISPLAY").unwrap_or_default().as_str()])
+                .args(["-pix_fmt", "yuyv422", "-f", "rawvideo", "-"])
+                .stdin(Stdio::null());
+            captures.extend(capture("x11grab yuyv422", command, pixels * 2));
+        }
+    }
+    if config.options.capture_protocol != CaptureProtocol::X11Grab
+        && capture::detect_protocols(false).contains(&CaptureProtocol::ExtImageCopyCapture)
+    {
+        let exe = env::current_exe().unwrap_or_else(|_| "wlstreamer".into());
+        let mut command = Command::new(exe);
+        command
+            .args(["capture-output", output.name.as_str()])
+            .stdin(Stdio::null());
+        captures.extend(capture("ext-image-copy-capture", command, pixels * 3));
+    }
+
+    println!("{}", i18n::tr("Capture:", &[]));
+    print(&captures);
+
+    // The upscaler is only used for screens smaller than the combined resolution
+    let target = config
+        .runtime
+        .resolutions
+        .first()
+        .copied()
+        .unwrap_or(resolution);
+    let source = if resolution != target {
+        Some(resolution)
+    } else {
+        config
+            .runtime
+            .resolutions
+            .iter()
+            .skip(1)
+            .find(|r| **r != target)
+            .copied()
+    };
+    let scalers: Vec<Run> = match source {
+        Some(source) => {
+            let scalers: Vec<Run> = SCALERS
+                .iter()
+                .filter_map(|scaler| scale(config, scaler, source, target))
+                .collect();
+            println!();
+            println!(
+                "{}",
+                i18n::tr(
+                    "Upscaler, {}x{} to {}x{}:",
+                    &[&source.width, &source.height, &target.width, &target.height]
+                )
+            );
+            print(&scalers);
+            scalers
+        }
+        None => Vec::new(),
+    };
+
+    println!();
+    println!("{}", i18n::tr("Recommendations:", &[]));
+    let best = captures
+        .iter()
+        .filter(|run| run.name != "wf-recorder nv12" && run.name != "wf-recorder bgr0")
+        .max_by(|a, b| (a.fps / a.cpu.max(1.0)).total_cmp(&(b.fps / b.cpu.max(1.0))));
+    match best.map(|run| run.name.as_str()) {
+        Some("wf-recorder --gpu-scale") => {
+            println!(
+                "{}",
+                i18n::tr(
+                    "  Use --gpu-scale, scaling on the GPU is the cheapest way to capture here",
+                    &[]
+                )
+            )
+        }
+        Some("ext-image-copy-capture") => println!(
+            "{}",
+            i18n::tr(
+                "  ext-image-copy-capture captures more efficiently than wf-recorder here, use --capture-protocol ext-image-copy-capture",
+                &[]
+            )
+        ),
+        Some(name) => println!("{}", i18n::tr("  The default pipeline ({}) works best", &[&name])),
+        None => println!("{}", i18n::tr("  No capture configuration worked", &[])),
+    }
+
+    if source.is_some() {
+        let default = scalers
+            .iter()
+            .find(|run| run.name.starts_with(DEFAULT_SCALER));
+        let fast_enough = |run: &&Run| run.fps >= refresh * 1.5;
+        match default {
+            Some(run) if fast_enough(&run) => println!(
+                "{}",
+                i18n::tr(
+                    "  The default scaler keeps up with {} Hz easily",
+                    &[&format!("{:.0}", refresh)]
+                )
+            ),
+            _ => match scalers.iter().rev().find(fast_enough) {
+                Some(run) => println!(
+                    "{}",
+                    i18n::tr(
+                        "  Scale with --ffmpeg-arg -sws_flags --ffmpeg-arg {} to keep up with {} Hz",
+                        &[&run.name.trim_end_matches(" scaler"), &format!("{:.0}", refresh)]
+                    )
+                ),
+                None => println!(
+                    "{}",
+                    i18n::tr(
+                        "  No scaler keeps up with {} Hz, use --gpu-scale or outputs of the same resolution to skip the upscaler",
+                        &[&format!("{:.0}", refresh)]
+                    )
+                ),
+            },
+        }
+    } else {
+        println!(
+            "{}",
+            i18n::tr(
+                "  All screens have the same resolution, so the upscaler isn't used",
+                &[]
+            )
+        );
+    }
+
+    std::process::exit(0);
+}
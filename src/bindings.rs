@@ -0,0 +1,40 @@
+//! `wlstreamer bindings`: prints sway key bindings for the control socket, to be included from the
+//! sway config.
+
+use crate::{get_outputs, Config};
+
+/// Prints the bindings, with one for locking each output there is right now, then exits.
+pub fn print(config: &Config) -> ! {
+    println!(
+        "# Key bindings for wlstreamer, include this file from your sway config after $mod is set:"
+    );
+    println!("#   wlstreamer bindings > ~/.config/sway/wlstreamer");
+    println!("#   include ~/.config/sway/wlstreamer");
+    println!();
+    println!("set $wlstreamer $mod+Mod1");
+    println!();
+    println!("# Hold to blank the stream, for example while typing a password");
+    println!("bindsym --no-repeat $wlstreamer+b exec wlstreamer ctl blank");
+    println!("bindsym --release $wlstreamer+b exec wlstreamer ctl resume");
+    println!("# Blank the stream, or resume it");
+    println!("bindsym $wlstreamer+p exec wlstreamer ctl toggle");
+    println!("# Save the replay buffer, if wlstreamer runs with --replay-buffer");
+    println!("bindsym $wlstreamer+r exec wlstreamer ctl save-replay");
+    println!("# Save the frame viewers see right now");
+    println!("bindsym $wlstreamer+s exec wlstreamer ctl screenshot ~/wlstreamer.png");
+
+    let mut outputs = get_outputs(config);
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+    if !outputs.is_empty() {
+        println!("# Keep a screen off the stream, or put it back on");
+    }
+    for (i, output) in outputs.iter().take(9).enumerate() {
+        println!(
+            "bindsym $wlstreamer+{} exec wlstreamer ctl toggle-lock {}",
+            i + 1,
+            output.name
+        );
+    }
+
+    std::process::exit(0);
+}
@@ -0,0 +1,186 @@
+//! `--min-bitrate`: adaptive bitrate for network sinks. ffmpeg reports how far into the stream it
+//! got, and when that falls behind the clock or the reports stop altogether, the network can't
+//! keep up with the bitrate. Encoders can't change their bitrate while running, so the sink is
+//! restarted with a lower one, and after a while without trouble with a higher one again, up to
+//! `--bitrate`.
+
+use crate::i18n;
+use crate::secrets;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far the stream may fall behind the clock before the bitrate is lowered.
+const MAX_LAG: f64 = 2.0;
+
+/// How long ffmpeg may go without reporting its progress, which it doesn't while it waits for
+/// the network.
+const MAX_SILENCE: Duration = Duration::from_secs(3);
+
+/// How long a sink has to run without falling behind before the bitrate is raised again.
+const RAISE_AFTER: Duration = Duration::from_secs(120);
+
+/// Parses a bitrate in ffmpeg's notation, like 6M, 4500k or 800000, into bits per second.
+pub fn parse(rate: &str) -> Option<u64> {
+    let (number, factor) = match rate.chars().last()? {
+        'k' | 'K' => (&rate[..rate.len() - 1], 1_000.0),
+        'M' => (&rate[..rate.len() - 1], 1_000_000.0),
+        'G' => (&rate[..rate.len() - 1], 1_000_000_000.0),
+        _ => (rate, 1.0),
+    };
+    let rate = number.parse::<f64>().ok()? * factor;
+    if rate >= 1.0 {
+        Some(rate as u64)
+    } else {
+        None
+    }
+}
+
+/// Formats a bitrate for ffmpeg, in kilobits per second.
+pub fn format(bits: u64) -> String {
+    format!("{}k", bits / 1000)
+}
+
+/// The bitrate a network sink currently sends at, and how far it may go.
+#[derive(Clone, Debug)]
+pub struct Adaptive {
+    min: u64,
+    max: u64,
+    current: u64,
+    verbose: bool,
+}
+
+impl Adaptive {
+    /// Checks --min-bitrate against --bitrate.
+    pub fn new(min: &str, max: Option<&str>, verbose: bool) -> Result<Adaptive, String> {
+        let max = max.ok_or("--min-bitrate needs --bitrate as the highest bitrate")?;
+        let min = parse(min).ok_or(i18n::tr("Invalid bitrate: {}", &[&min]))?;
+        let max = parse(max).ok_or(i18n::tr("Invalid bitrate: {}", &[&max]))?;
+        if min > max {
+            return Err(i18n::tr(
+                "--min-bitrate has to be lower than --bitrate",
+                &[],
+            ));
+        }
+
+        Ok(Adaptive {
+            min,
+            max,
+            current: max,
+            verbose,
+        })
+    }
+
+    /// The bitrate to restart the sink with, if it has to change.
+    pub fn next(&mut self, monitor: &Monitor) -> Option<u64> {
+        let next = if monitor.behind() {
+            (self.current * 3 / 4).max(self.min)
+        } else if monitor.stable() {
+            (self.current * 5 / 4).min(self.max)
+        } else {
+            return None;
+        };
+
+        if next == self.current {
+            return None;
+        }
+        self.current = next;
+        Some(next)
+    }
+
+    /// The sink's command with the current bitrate instead of the one it was started with.
+    pub fn command(&self, command: &Command) -> Command {
+        let rate = format(self.current);
+        let mut args = command
+            .get_args()
+            .map(OsStr::to_os_string)
+            .collect::<Vec<_>>();
+        for i in 1..args.len() {
+            if ["-b:v", "-maxrate", "-bufsize"]
+                .iter()
+                .any(|flag| args[i - 1] == *flag)
+            {
+                args[i] = rate.clone().into();
+            }
+        }
+
+        let mut rebuilt = Command::new(command.get_program());
+        rebuilt
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(secrets::stderr(self.verbose));
+        rebuilt
+    }
+}
+
+#[derive(Debug)]
+struct Progress {
+    started: Instant,
+    last_report: Option<Instant>,
+    /// How far the stream is behind the clock, in seconds, now and at best
+    lag: f64,
+    least_lag: f64,
+}
+
+/// Follows the progress of one run of a sink.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    progress: Arc<Mutex<Progress>>,
+}
+
+impl Monitor {
+    /// Reads the progress of a sink started with `stats::PROGRESS_ARGS` and a piped stdout.
+    pub fn follow(child: &mut Child) -> Monitor {
+        let monitor = Monitor {
+            progress: Arc::new(Mutex::new(Progress {
+                started: Instant::now(),
+                last_report: None,
+                lag: 0.0,
+                least_lag: f64::MAX,
+            })),
+        };
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return monitor,
+        };
+
+        let progress = monitor.progress.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                let mut progress = progress.lock().unwrap();
+                progress.last_report = Some(Instant::now());
+                if let Some(("out_time_us", value)) = line.split_once('=') {
+                    if let Ok(micros) = value.trim().parse::<i64>() {
+                        progress.lag =
+                            progress.started.elapsed().as_secs_f64() - micros as f64 / 1_000_000.0;
+                        progress.least_lag = progress.least_lag.min(progress.lag);
+                    }
+                }
+            }
+        });
+
+        monitor
+    }
+
+    /// Whether the stream fell behind. Nothing is reported while connecting, so that doesn't
+    /// count.
+    fn behind(&self) -> bool {
+        let progress = self.progress.lock().unwrap();
+        match progress.last_report {
+            Some(last_report) => {
+                last_report.elapsed() > MAX_SILENCE || progress.lag - progress.least_lag > MAX_LAG
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the stream kept up for long enough to try a higher bitrate.
+    fn stable(&self) -> bool {
+        let progress = self.progress.lock().unwrap();
+        progress.last_report.is_some() && progress.started.elapsed() > RAISE_AFTER
+    }
+}
@@ -0,0 +1,138 @@
+use crate::i18n;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::process::Command;
+
+const WLR_SCREENCOPY: &str = "zwlr_screencopy_manager_v1";
+const EXT_IMAGE_COPY_CAPTURE: &str = "ext_image_copy_capture_manager_v1";
+const EXT_OUTPUT_IMAGE_CAPTURE_SOURCE: &str = "ext_output_image_capture_source_manager_v1";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CaptureProtocol {
+    WlrScreencopy,
+    ExtImageCopyCapture,
+    X11Grab,
+}
+
+impl CaptureProtocol {
+    pub fn parse(name: &str) -> Option<CaptureProtocol> {
+        match name {
+            "wlr-screencopy" => Some(CaptureProtocol::WlrScreencopy),
+            "ext-image-copy-capture" => Some(CaptureProtocol::ExtImageCopyCapture),
+            "x11grab" => Some(CaptureProtocol::X11Grab),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaptureProtocol::WlrScreencopy => "wlr-screencopy",
+            CaptureProtocol::ExtImageCopyCapture => "ext-image-copy-capture",
+            CaptureProtocol::X11Grab => "x11grab",
+        }
+    }
+}
+
+/// Lists the capture protocols advertised by the compositor, in order of preference.
+///
+/// The globals are read from `wayland-info`. If it is not installed, we assume
+/// wlr-screencopy is available, which is what wlstreamer always relied on before.
+pub fn detect_protocols(verbose: bool) -> Vec<CaptureProtocol> {
+    let output = match Command::new("wayland-info").output() {
+        Ok(output) if output.status.success() => output,
+        _ => {
+            if verbose {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Could not run wayland-info, assuming wlr-screencopy is available",
+                        &[]
+                    )
+                );
+            }
+            return vec![CaptureProtocol::WlrScreencopy];
+        }
+    };
+
+    let globals = String::from_utf8_lossy(&output.stdout);
+    let mut protocols = Vec::new();
+
+    if globals.contains(WLR_SCREENCOPY) {
+        protocols.push(CaptureProtocol::WlrScreencopy);
+    }
+
+    // ext-image-copy-capture on its own can only capture sources we know how to create,
+    // so we need the output image capture source manager as well.
+    if globals.contains(EXT_IMAGE_COPY_CAPTURE) && globals.contains(EXT_OUTPUT_IMAGE_CAPTURE_SOURCE)
+    {
+        protocols.push(CaptureProtocol::ExtImageCopyCapture);
+    }
+
+    if verbose {
+        println!("{}", i18n::tr("Found capture protocols:", &[]));
+        println!("{:?}", protocols);
+    }
+
+    protocols
+}
+
+/// Picks the protocol to capture with, honouring an explicit request from the user.
+pub fn select_protocol(
+    requested: Option<CaptureProtocol>,
+    available: &[CaptureProtocol],
+) -> Option<CaptureProtocol> {
+    match requested {
+        Some(protocol) => Some(protocol),
+        None => available.first().copied(),
+    }
+}
+
+/// The render node to use on the GPU, the one given with --drm-device or the first one.
+pub fn render_node(drm_device: Option<&str>) -> Option<String> {
+    drm_device.map(String::from).or_else(find_render_node)
+}
+
+/// Finds the render node of the GPU the output is connected to, from the connectors in sysfs,
+/// so frames don't have to be copied between GPUs.
+pub fn output_render_node(output: &str) -> Option<String> {
+    // Connectors are named after their card and the output, like card1-DP-2
+    let card = fs::read_dir("/sys/class/drm")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .find_map(|name| {
+            let (card, connector) = name.split_once('-')?;
+            if card.starts_with("card") && connector == output {
+                Some(card.to_string())
+            } else {
+                None
+            }
+        })?;
+
+    fs::read_dir(format!("/sys/class/drm/{}/device/drm", card))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("renderD"))
+        .min()
+        .map(|name| format!("/dev/dri/{}", name))
+}
+
+/// Checks that the --drm-device exists and is a device.
+pub fn validate_drm_device(path: &str) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_char_device() => Ok(()),
+        Ok(_) => Err(i18n::tr("{} is not a DRM device", &[&path])),
+        Err(err) => Err(i18n::tr("Could not access {}: {}", &[&path, &err])),
+    }
+}
+
+/// Finds the first DRM render node, which is used to scale frames on the GPU.
+pub fn find_render_node() -> Option<String> {
+    let entries = fs::read_dir("/dev/dri").ok()?;
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .filter(|path| path.starts_with("/dev/dri/renderD"))
+        .min()
+}
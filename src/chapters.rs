@@ -0,0 +1,89 @@
+//! Chapters for file sinks: every time the stream switches to another screen, a chapter starts.
+//! They are written next to each file in ffmpeg's metadata format, since a file that is still
+//! being written can't get chapters of its own.
+
+use crate::i18n;
+use crate::sink;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct Chapters {
+    /// The chapter files, one for every file sink
+    files: Vec<PathBuf>,
+    started: Option<Instant>,
+    /// When each chapter started, and its title
+    marks: Vec<(Duration, String)>,
+}
+
+impl Chapters {
+    /// Starts keeping chapters for the sinks that are files, from now.
+    pub fn start(sinks: &[String]) -> Chapters {
+        let files = sinks
+            .iter()
+            .map(|sink| sink::split_size(sink).0)
+            .filter(|sink| sink::is_file(sink))
+            .map(|sink| PathBuf::from(format!("{}.chapters", sink)))
+            .collect();
+
+        Chapters {
+            files,
+            started: Some(Instant::now()),
+            marks: Vec::new(),
+        }
+    }
+
+    /// Starts a chapter, unless the stream already shows the same thing.
+    pub fn mark(&mut self, title: &str) {
+        let started = match self.started {
+            Some(started) if !self.files.is_empty() => started,
+            _ => return,
+        };
+        if self.marks.last().map(|(_, last)| last.as_str()) == Some(title) {
+            return;
+        }
+
+        self.marks.push((started.elapsed(), title.to_string()));
+        self.write();
+    }
+
+    /// Rewrites the chapter files, with the last chapter ending now.
+    pub fn write(&self) {
+        let now = match self.started {
+            Some(started) => started.elapsed(),
+            None => return,
+        };
+
+        let mut metadata = ";FFMETADATA1\n".to_string();
+        for (i, (start, title)) in self.marks.iter().enumerate() {
+            let end = self.marks.get(i + 1).map_or(now, |(end, _)| *end);
+            metadata.push_str(&format!(
+                "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                start.as_millis(),
+                end.as_millis(),
+                escape(title)
+            ));
+        }
+
+        for file in &self.files {
+            if let Err(err) = fs::write(file, &metadata) {
+                println!(
+                    "{}",
+                    i18n::tr("Could not write {}: {}", &[&file.display(), &err])
+                );
+            }
+        }
+    }
+}
+
+/// Escapes the characters that are special in ffmpeg's metadata format.
+fn escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '=' | ';' | '#' | '\\' | '\n' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
@@ -0,0 +1,39 @@
+//! Stopping child processes so that none of them is left behind, running or as a zombie.
+
+use std::io::Error;
+use std::process::Child;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a pipeline process gets to exit after being asked to, before it is killed.
+pub const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Asks the child to exit with `signal` and reaps it, killing it if it is still running after
+/// `timeout`.
+pub fn stop(child: &mut Child, signal: libc::c_int, timeout: Duration) -> Result<(), Error> {
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+
+    if unsafe { libc::kill(child.id() as libc::pid_t, signal) } < 0 {
+        let err = Error::last_os_error();
+        // It exited since it was checked, which is just as good
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            child.wait()?;
+            return Ok(());
+        }
+        return Err(err);
+    }
+
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
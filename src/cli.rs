@@ -0,0 +1,797 @@
+//! `wlstreamer completions <shell>` and `wlstreamer manpage`, and the options of the command line
+//! they are made from. The options are described once here, and the help, the completions for
+//! bash, zsh and fish and the man page are all written from that, so none can fall behind the
+//! others. Completions and the man page are in English whatever the locale, since they are made
+//! once for a package.
+
+use crate::{control, help_sections, i18n, VERSION};
+
+/// The ways to run wlstreamer
+pub const USAGE: [&str; 9] = [
+    "wlstreamer [options]",
+    "wlstreamer ctl [--instance <name>] <command>",
+    "wlstreamer tui [--instance <name>]",
+    "wlstreamer tray [--instance <name>]",
+    "wlstreamer bench [options]",
+    "wlstreamer bindings [options]",
+    "wlstreamer install-service [options]",
+    "wlstreamer completions <bash|zsh|fish>",
+    "wlstreamer manpage",
+];
+
+pub const SUMMARY: &str = "Wrapper around wf-recorder and ffmpeg that automatically switches the screen being recorded based on current window focus";
+
+/// The commands besides streaming, with what they do
+const SUBCOMMANDS: [(&str, &str); 8] = [
+    ("ctl", "Change the running instance"),
+    (
+        "tui",
+        "Show and change the running instance in the terminal",
+    ),
+    ("tray", "Show the running instance in the system tray"),
+    ("bench", "Compare the ways of capturing the screens"),
+    ("bindings", "Print sway key bindings for wlstreamer ctl"),
+    (
+        "install-service",
+        "Run wlstreamer as a systemd user service",
+    ),
+    ("completions", "Print completions for bash, zsh or fish"),
+    ("manpage", "Print the man page"),
+];
+
+/// An option of the command line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Flag {
+    /// Like `-d` and `--devices-from`
+    pub names: &'static [&'static str],
+    /// What it takes, like `<path>`
+    pub value: Option<&'static str>,
+    pub description: &'static str,
+}
+
+/// Every option, in the order of the help.
+pub const FLAGS: [Flag; 83] = [
+    Flag {
+        names: &["--not-ws"],
+        value: Some("<ws>"),
+        description: "Do not show this workspace, by number, or by name for workspaces without a number. Can be used multiple times. Example: 3",
+    },
+    Flag {
+        names: &["--not-screen"],
+        value: Some("<screen>"),
+        description: "Do not show this screen. Can be used multiple times. Example: HDMI-A-1",
+    },
+    Flag {
+        names: &["-d", "--devices-from"],
+        value: Some("<id>"),
+        description: "Use video devices starting at $id. Defaults to 0. /dev/video$id will be used as output. See DIFFERENT RESOLUTIONS below.",
+    },
+    Flag {
+        names: &["--output-device"],
+        value: Some("<path>"),
+        description: "Use this device as output instead of /dev/video$id, for example /dev/video10 or a link to it in /dev/v4l/by-path, which keeps working when devices are numbered differently after a reboot. The devices after it are used the same as with --devices-from.",
+    },
+    Flag {
+        names: &["--intermediate-devices"],
+        value: Some("<path>,<path>"),
+        description: "Capture screens that don't have the combined resolution into these devices, in order, instead of the ones after the output device. Keeps wlstreamer off devices like real webcams that happen to be numbered in between. See DIFFERENT RESOLUTIONS below.",
+    },
+    Flag {
+        names: &["--backend"],
+        value: Some("<name>"),
+        description: "Window manager to follow, either sway, i3, niri or foreign-toplevel. Detected from the environment by default.",
+    },
+    Flag {
+        names: &["--capture-protocol"],
+        value: Some("<name>"),
+        description: "Screen capture protocol to use, either wlr-screencopy or ext-image-copy-capture. Detected automatically by default, preferring wlr-screencopy.",
+    },
+    Flag {
+        names: &["--mode"],
+        value: Some("<mode>"),
+        description: "What to stream, either follow (default), mirror-all, grid, side-by-side <screen>,<screen> or headless. See MODES below.",
+    },
+    Flag {
+        names: &["--enlarge-focused"],
+        value: None,
+        description: "In grid mode, give the focused screen most of the space instead of an equal tile",
+    },
+    Flag {
+        names: &["--highlight"],
+        value: Some("<color>"),
+        description: "In grid and side-by-side mode, draw a border in this color around the focused screen. Takes ffmpeg colors, for example red or 0xff8800",
+    },
+    Flag {
+        names: &["--highlight-width"],
+        value: Some("<px>"),
+        description: "Width of the --highlight border. Defaults to 6.",
+    },
+    Flag {
+        names: &["--normalize-scale"],
+        value: None,
+        description: "Plan and capture screens at their scaled size in the layout instead of their size in pixels. See DIFFERENT RESOLUTIONS below.",
+    },
+    Flag {
+        names: &["--gpu-scale"],
+        value: None,
+        description: "Scale screens on the GPU with VAAPI instead of in ffmpeg. Only a single output device is needed. See GPU SCALING below.",
+    },
+    Flag {
+        names: &["--drm-device"],
+        value: Some("<path>"),
+        description: "DRM render node to scale and encode on, for example /dev/dri/renderD129. See GPU SCALING below.",
+    },
+    Flag {
+        names: &["--wf-recorder-arg"],
+        value: Some("<arg>"),
+        description: "Pass an extra argument to wf-recorder. Can be used multiple times. Example: --wf-recorder-arg --no-damage",
+    },
+    Flag {
+        names: &["--ffmpeg-arg"],
+        value: Some("<arg>"),
+        description: "Pass an extra output argument to ffmpeg, placed right before the output device. Can be used multiple times.",
+    },
+    Flag {
+        names: &["--wf-recorder-path"],
+        value: Some("<path>"),
+        description: "Run this wf-recorder binary instead of the one in $PATH",
+    },
+    Flag {
+        names: &["--ffmpeg-path"],
+        value: Some("<path>"),
+        description: "Run this ffmpeg binary instead of the one in $PATH",
+    },
+    Flag {
+        names: &["--ipc-socket"],
+        value: Some("<path>"),
+        description: "Talk to sway through this IPC socket instead of $SWAYSOCK",
+    },
+    Flag {
+        names: &["--config"],
+        value: Some("<path>"),
+        description: "Read options from this config file instead of $XDG_CONFIG_HOME/wlstreamer/config.toml. See CONFIG FILE below.",
+    },
+    Flag {
+        names: &["--countdown"],
+        value: Some("<seconds>"),
+        description: "Count down on a black screen for this many seconds at startup and on wlstreamer ctl resume, before the screens are streamed. See IDLE AND LOCKING below.",
+    },
+    Flag {
+        names: &["--blank-when-idle"],
+        value: Some("<min>"),
+        description: "Stream a black screen after this many minutes without input, until the next input. See IDLE AND LOCKING below.",
+    },
+    Flag {
+        names: &["--schedule"],
+        value: Some("<window>"),
+        description: "Only stream the screens during this time, like \"Mon-Fri 09:00-17:00\", and a black screen outside of it. Can be used multiple times. See SCHEDULE below.",
+    },
+    Flag {
+        names: &["--duration"],
+        value: Some("<time>"),
+        description: "Stream a black screen once wlstreamer ran this long, like 2h, 90m or 1h30m. See SCHEDULE below.",
+    },
+    Flag {
+        names: &["--damage"],
+        value: None,
+        description: "Only capture frames when something changed on the screen, and let the devices repeat the last frame in between. Needs v4l2-ctl. See DAMAGE below.",
+    },
+    Flag {
+        names: &["--hidden-text"],
+        value: None,
+        description: "Write which workspace is hidden onto the black screen shown instead of a workspace from --not-ws or a screen from --not-screen, for example \"Workspace 3 (hidden)\"",
+    },
+    Flag {
+        names: &["--blank-on-hidden"],
+        value: None,
+        description: "Show the black screen while the focused workspace is hidden with --not-ws or --not-screen. By default, another screen showing a workspace that isn't hidden is streamed instead, and the black screen only comes up when there is none.",
+    },
+    Flag {
+        names: &["--placeholder-fps"],
+        value: Some("<fps>"),
+        description: "Frame rate of the black screen. Defaults to --fps, or the refresh rate of the screen recorded before it, so apps reading the output device keep the frame rate they locked onto. The black screen always has the size of the output device.",
+    },
+    Flag {
+        names: &["--redact"],
+        value: Some("<regex>"),
+        description: "Stream a black screen while a window with a matching title is visible on the recorded screen, for example Incognito or 1Password. Can be used multiple times.",
+    },
+    Flag {
+        names: &["--mask-app"],
+        value: Some("<app_id>"),
+        description: "Cover the windows of this app with black boxes, and stream the rest of their screen. Can be used multiple times. Only in follow mode. See REDACTION below.",
+    },
+    Flag {
+        names: &["--urgent-app"],
+        value: Some("<app_id>"),
+        description: "Cut to the screen of a window of this app while it asks for attention, then follow the focus again. Can be used multiple times. See CALL APPS below.",
+    },
+    Flag {
+        names: &["--urgent-seconds"],
+        value: Some("<s>"),
+        description: "How long to stay on the screen of a window asking for attention. Defaults to 10.",
+    },
+    Flag {
+        names: &["--call-app"],
+        value: Some("<app_id>"),
+        description: "Only stream the screens while a window of this app is open, for example zoom or teams-for-linux. Can be used multiple times. See CALL APPS below.",
+    },
+    Flag {
+        names: &["--lazy"],
+        value: None,
+        description: "Only capture the screens while another program reads from the output device, and stream a black screen otherwise. See LAZY CAPTURE below.",
+    },
+    Flag {
+        names: &["--standby"],
+        value: None,
+        description: "Keep recording every screen into a device of its own in follow mode, so switching screens is near instant. Needs a device per screen after the output device. See STANDBY below.",
+    },
+    Flag {
+        names: &["--compat"],
+        value: None,
+        description: "Stream what Chrome, Zoom and Teams take from a camera, and explain what keeps them from listing the output device. See VIDEO CALL APPS below.",
+    },
+    Flag {
+        names: &["--zoom"],
+        value: Some("<factor>"),
+        description: "Stream a part of the focused screen around the focused window, scaled up by this factor, for example 2. Only in follow mode. See ZOOM below.",
+    },
+    Flag {
+        names: &["--zoom-duration"],
+        value: Some("<ms>"),
+        description: "How long --zoom takes to glide over to the next window, 0 to jump. Defaults to 600.",
+    },
+    Flag {
+        names: &["--sink"],
+        value: Some("<url|file>"),
+        description: "Also encode the stream and send it to this URL or write it to this file, for example rtmp://example.com/live/key or recording.mkv. Add @<width>x<height> to scale it to that size. Can be used multiple times. See SINKS below.",
+    },
+    Flag {
+        names: &["--quality"],
+        value: Some("<quality>"),
+        description: "low, medium, high or custom. Sets the frame rate, the size of the stream and how sinks are encoded all at once, options given on their own win. Defaults to custom, which only uses those. See SINKS below.",
+    },
+    Flag {
+        names: &["--codec"],
+        value: Some("<codec>"),
+        description: "Codec for --sink, h264, hevc or av1, encoded in software or on the GPU with a suffix like h264-vaapi, hevc-nvenc or av1-qsv. Defaults to h264.",
+    },
+    Flag {
+        names: &["--bitrate"],
+        value: Some("<rate>"),
+        description: "Bitrate for --sink, for example 6M",
+    },
+    Flag {
+        names: &["--min-bitrate"],
+        value: Some("<rate>"),
+        description: "Lower the bitrate of RTMP, SRT and RIST sinks down to this when the network can't keep up, and raise it back to --bitrate once it can. See SINKS below.",
+    },
+    Flag {
+        names: &["--preset"],
+        value: Some("<name>"),
+        description: "Encoder preset for --sink, for example veryfast for libx264 or p4 for nvenc",
+    },
+    Flag {
+        names: &["--crf"],
+        value: Some("<n>"),
+        description: "Constant quality for --sink instead of a bitrate, lower is better",
+    },
+    Flag {
+        names: &["--sink-passphrase"],
+        value: Some("<text>"),
+        description: "Encrypt SRT and RIST sinks with this passphrase, 10 to 79 characters",
+    },
+    Flag {
+        names: &["--sink-latency"],
+        value: Some("<ms>"),
+        description: "How long the receivers of SRT and RIST sinks wait for lost packets",
+    },
+    Flag {
+        names: &["--whip-token"],
+        value: Some("<token>"),
+        description: "Bearer token for WHIP sinks",
+    },
+    Flag {
+        names: &["--secrets-file"],
+        value: Some("<file>"),
+        description: "Look up the ${NAME} secrets in sink URLs, --sink-passphrase and --whip-token in this file of NAME=value lines first, then in the environment and the keyring. See SINKS below.",
+    },
+    Flag {
+        names: &["--audio-desktop"],
+        value: None,
+        description: "Add the sound of the desktop to sinks and the replay buffer",
+    },
+    Flag {
+        names: &["--audio-mic"],
+        value: None,
+        description: "Add the default microphone to sinks and the replay buffer",
+    },
+    Flag {
+        names: &["--desktop-device"],
+        value: Some("<source>"),
+        description: "Take the desktop sound from this PulseAudio source instead of the monitor of the default output, for example alsa_output.usb-headset.analog-stereo.monitor. See pactl list short sources.",
+    },
+    Flag {
+        names: &["--mic-device"],
+        value: Some("<source>"),
+        description: "Take the microphone from this PulseAudio source instead of the default one",
+    },
+    Flag {
+        names: &["--desktop-volume"],
+        value: Some("<factor>"),
+        description: "Volume of the desktop sound, 1 is unchanged",
+    },
+    Flag {
+        names: &["--mic-volume"],
+        value: Some("<factor>"),
+        description: "Volume of the microphone, 1 is unchanged",
+    },
+    Flag {
+        names: &["--mic-denoise"],
+        value: Some("<afftdn|model>"),
+        description: "Remove noise from the microphone with ffmpeg's afftdn, or with RNNoise given the path to a model",
+    },
+    Flag {
+        names: &["--mic-limiter"],
+        value: None,
+        description: "Keep the microphone from clipping, useful together with --mic-volume",
+    },
+    Flag {
+        names: &["--av-offset"],
+        value: Some("<ms|auto>"),
+        description: "Delay the audio by this many milliseconds, or put it earlier with a negative number. auto estimates how long the screens take to reach the sinks.",
+    },
+    Flag {
+        names: &["--replay-buffer"],
+        value: Some("<seconds>"),
+        description: "Keep this many seconds of the stream, to save them with wlstreamer ctl save-replay. Encoded with --codec and the other sink options. See SINKS below.",
+    },
+    Flag {
+        names: &["--serve-hls"],
+        value: Some("<dir|port|addr>"),
+        description: "Write the stream as HLS into a directory, or serve it over HTTP on this port of localhost or at this address, like 0.0.0.0:8080 for the whole network. See SINKS below.",
+    },
+    Flag {
+        names: &["--preview-listen"],
+        value: Some("<addr>"),
+        description: "Serve a preview of what is on the output device at this address, for example 127.0.0.1:8642, to check in a browser tab what viewers see. It has two frames per second and is only captured while the tab is open.",
+    },
+    Flag {
+        names: &["--fps"],
+        value: Some("<fps>"),
+        description: "Stream at this constant frame rate, repeating or dropping frames as needed, no matter the refresh rate of the screen being recorded",
+    },
+    Flag {
+        names: &["--color-range"],
+        value: Some("<range>"),
+        description: "Range of the frames on the devices, full or limited. Defaults to limited, try full if colors look washed out in the app reading the output device, or limited if dark colors are crushed.",
+    },
+    Flag {
+        names: &["--colorspace"],
+        value: Some("<space>"),
+        description: "Matrix converting the screens to YUV, bt601 or bt709. Defaults to bt601, which most apps assume for webcams.",
+    },
+    Flag {
+        names: &["--tonemap"],
+        value: Some("<algorithm>"),
+        description: "How screens in HDR mode are brought down to SDR: hable, reinhard, mobius, clip, linear, gamma or off. Defaults to hable. See HDR below.",
+    },
+    Flag {
+        names: &["--tonemap-peak"],
+        value: Some("<nits>"),
+        description: "Brightness that ends up as white when tone mapping. Defaults to 100, raise it if HDR screens look too bright.",
+    },
+    Flag {
+        names: &["--battery-fps"],
+        value: Some("<fps>"),
+        description: "Capture at most this many frames per second while running on battery. See BATTERY below.",
+    },
+    Flag {
+        names: &["--battery-threshold"],
+        value: Some("<%>"),
+        description: "Only lower the frame rate once the battery is charged to this percentage or less. Defaults to 100.",
+    },
+    Flag {
+        names: &["--measure-latency"],
+        value: None,
+        description: "Stamp the recorded screen with the time and log how long frames take to reach each device. See LATENCY below.",
+    },
+    Flag {
+        names: &["--dnd"],
+        value: None,
+        description: "Turn on do-not-disturb in mako, dunst or swaync while streaming, so notifications don't show up on stream",
+    },
+    Flag {
+        names: &["--indicator"],
+        value: None,
+        description: "Show a red dot in the top right corner of the screens that are being streamed. Needs wlr-layer-shell.",
+    },
+    Flag {
+        names: &["--stats"],
+        value: Some("<seconds>"),
+        description: "Log the frame rate, dropped frames and uptime of the output device at this interval. Also shown by wlstreamer ctl status.",
+    },
+    Flag {
+        names: &["--stall-timeout"],
+        value: Some("<seconds>"),
+        description: "Also rebuild the pipeline when no frames reach the output device for this long, not only when part of it exits. Only works where frames keep coming while the screen doesn't change: with --wf-recorder-arg --no-damage or --damage for wf-recorder, and without --damage for ext-image-copy-capture and x11grab. Defaults to 0, only watching for exits. A screen whose pipeline keeps failing is left alone for a while, from 2 seconds doubling up to 5 minutes, and the next screen or the black screen is streamed meanwhile.",
+    },
+    Flag {
+        names: &["--events-json"],
+        value: Some("<path>"),
+        description: "Write a JSON object per line for every switch, blank, restart and error to this file or FIFO, or to stdout for -. See EVENTS below.",
+    },
+    Flag {
+        names: &["--record-segments"],
+        value: Some("<dir>"),
+        description: "Record a new file to this directory every time the stream switches to another screen, named after when it started and the screen, with a manifest in segments.json. Encoded like file sinks.",
+    },
+    Flag {
+        names: &["--timeline"],
+        value: Some("<file>"),
+        description: "Write which screen and workspace was live when, and why the black screen was shown, to this file when wlstreamer exits. As CSV if the file ends in .csv, as JSON otherwise.",
+    },
+    Flag {
+        names: &["--profile"],
+        value: Some("<name>"),
+        description: "Start with this profile from the config file. See PROFILES below.",
+    },
+    Flag {
+        names: &["-v", "--version"],
+        value: None,
+        description: "Display version and exit",
+    },
+    Flag {
+        names: &["--verbose"],
+        value: None,
+        description: "Verbose logging",
+    },
+    Flag {
+        names: &["--instance"],
+        value: Some("<name>"),
+        description: "Run as a separate instance with its own control socket, to run several at once on different devices. Give it to wlstreamer ctl and tui too, to talk to this instance.",
+    },
+    Flag {
+        names: &["--session"],
+        value: Some("<name>"),
+        description: "Stream this session, by its Wayland display like wayland-1 or its compositor like sway, when wlstreamer runs outside of it. See SYSTEMD below.",
+    },
+    Flag {
+        names: &["--daemonize"],
+        value: None,
+        description: "Keep running in the background once started, logging to $XDG_RUNTIME_DIR/wlstreamer.log. See SYSTEMD below.",
+    },
+    Flag {
+        names: &["--selftest"],
+        value: None,
+        description: "Start the pipeline for the focused screen, read a few frames back from the output device and check that they have the right size and aren't black, then exit with 0 if all is well and 1 otherwise. Useful before a meeting.",
+    },
+];
+
+impl Flag {
+    /// The line of the help, like `  -d|--devices-from <id>    Use video devices…`.
+    pub fn help_line(&self) -> String {
+        let mut head = self.names.join("|");
+        if let Some(value) = self.value {
+            head = format!("{} {}", head, value);
+        }
+        format!("  {:<25} {}", head, self.description)
+    }
+
+    /// Whether the value is a file or directory, so file names are completed.
+    fn takes_path(&self) -> bool {
+        self.value.is_some_and(|value| {
+            ["path", "file", "dir"]
+                .iter()
+                .any(|kind| value.contains(kind))
+        })
+    }
+
+    /// The first sentence of the description, for completions.
+    fn summary(&self) -> &str {
+        let end = self
+            .description
+            .find(". ")
+            .unwrap_or(self.description.len());
+        self.description[..end].trim_end_matches('.')
+    }
+
+    fn long(&self) -> Option<&str> {
+        self.names.iter().find_map(|name| name.strip_prefix("--"))
+    }
+
+    fn short(&self) -> Option<&str> {
+        self.names
+            .iter()
+            .filter(|name| !name.starts_with("--"))
+            .find_map(|name| name.strip_prefix('-'))
+    }
+}
+
+/// A section of the help after the options, like SINKS.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Section {
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+/// What the help says after the options.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cli {
+    /// What comes after the options, before the first section
+    pub notes: Vec<String>,
+    pub sections: Vec<Section>,
+}
+
+fn is_title(line: &str) -> bool {
+    !line.starts_with(' ') && line.chars().any(char::is_alphabetic) && line == line.to_uppercase()
+}
+
+/// Splits the text after the options into the notes and the sections.
+pub fn model() -> Cli {
+    let mut cli = Cli {
+        notes: Vec::new(),
+        sections: Vec::new(),
+    };
+
+    for line in help_sections() {
+        if is_title(&line) {
+            cli.sections.push(Section {
+                title: line,
+                lines: Vec::new(),
+            });
+        } else if let Some(section) = cli.sections.last_mut() {
+            section.lines.push(line);
+        } else {
+            cli.notes.push(line);
+        }
+    }
+
+    cli
+}
+
+fn bash() -> String {
+    let names = |flags: &mut dyn Iterator<Item = &Flag>| {
+        flags
+            .flat_map(|flag| flag.names.iter().copied())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+    let all = names(&mut FLAGS.iter());
+    let paths = names(&mut FLAGS.iter().filter(|flag| flag.takes_path()));
+    let values = names(
+        &mut FLAGS
+            .iter()
+            .filter(|flag| flag.value.is_some() && !flag.takes_path()),
+    );
+    let subcommands: Vec<&str> = SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+
+    format!(
+        r#"# bash completion for wlstreamer
+_wlstreamer() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    if [ "${{COMP_WORDS[1]}}" = ctl ] && [ "$COMP_CWORD" -eq 2 ]; then
+        COMPREPLY=($(compgen -W "{ctl}" -- "$cur"))
+        return
+    fi
+    if [ "${{COMP_WORDS[1]}}" = completions ] && [ "$COMP_CWORD" -eq 2 ]; then
+        COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
+        return
+    fi
+    case " {paths} " in
+        *" $prev "*) COMPREPLY=($(compgen -f -- "$cur")); return;;
+    esac
+    case " {values} " in
+        *" $prev "*) COMPREPLY=(); return;;
+    esac
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{subcommands} {all}" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -W "{all}" -- "$cur"))
+    fi
+}}
+complete -F _wlstreamer wlstreamer
+"#,
+        ctl = control::COMMANDS.join(" "),
+        paths = paths,
+        values = values,
+        subcommands = subcommands.join(" "),
+        all = all,
+    )
+}
+
+/// Escapes text for the brackets and single quotes of zsh's `_arguments`.
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh() -> String {
+    let mut specs = Vec::new();
+    for flag in FLAGS.iter() {
+        let action = match &flag.value {
+            Some(value) if flag.takes_path() => format!(":{}:_files", zsh_escape(value)),
+            Some(value) => format!(":{}: ", zsh_escape(value)),
+            None => String::new(),
+        };
+        let description = format!("[{}]{}", zsh_escape(flag.summary()), action);
+        if flag.names.len() > 1 {
+            specs.push(format!(
+                "'({names})'{{{list}}}'{description}'",
+                names = flag.names.join(" "),
+                list = flag.names.join(","),
+                description = description
+            ));
+        } else {
+            specs.push(format!("'{}{}'", flag.names.join(""), description));
+        }
+    }
+    let subcommands: Vec<String> = SUBCOMMANDS
+        .iter()
+        .map(|(name, description)| format!("{}\\:\"{}\"", name, zsh_escape(description)))
+        .collect();
+
+    format!(
+        r#"#compdef wlstreamer
+
+if [[ $words[2] == ctl ]]; then
+    _arguments '2:command:({ctl})'
+    return
+fi
+if [[ $words[2] == completions ]]; then
+    _arguments '2:shell:(bash zsh fish)'
+    return
+fi
+_arguments -s \
+    '1:: :(({subcommands}))' \
+    {specs}
+"#,
+        ctl = control::COMMANDS.join(" "),
+        subcommands = subcommands.join(" "),
+        specs = specs.join(" \\\n    "),
+    )
+}
+
+fn fish() -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut lines = vec!["# fish completion for wlstreamer".to_string()];
+
+    for (name, description) in SUBCOMMANDS.iter() {
+        lines.push(format!(
+            "complete -c wlstreamer -n __fish_use_subcommand -f -a {} -d {}",
+            name,
+            quote(description)
+        ));
+    }
+    lines.push(format!(
+        "complete -c wlstreamer -n '__fish_seen_subcommand_from ctl' -f -a {}",
+        quote(&control::COMMANDS.join(" "))
+    ));
+    lines.push(
+        "complete -c wlstreamer -n '__fish_seen_subcommand_from completions' -f -a 'bash zsh fish'"
+            .to_string(),
+    );
+
+    for flag in FLAGS.iter() {
+        let mut line = "complete -c wlstreamer".to_string();
+        if let Some(short) = flag.short() {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if let Some(long) = flag.long() {
+            line.push_str(&format!(" -l {}", long));
+        }
+        if flag.takes_path() {
+            line.push_str(" -r -F");
+        } else if flag.value.is_some() {
+            line.push_str(" -x");
+        }
+        line.push_str(&format!(" -d {}", quote(flag.summary())));
+        lines.push(line);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Prints the completions for the shell, then exits.
+pub fn completions(args: &[String]) -> ! {
+    let script = match args.first().map(|arg| arg.as_str()) {
+        Some("bash") => bash(),
+        Some("zsh") => zsh(),
+        Some("fish") => fish(),
+        _ => {
+            println!(
+                "{}",
+                i18n::tr("Usage: wlstreamer completions <bash|zsh|fish>", &[])
+            );
+            std::process::exit(1);
+        }
+    };
+    print!("{}", script);
+    std::process::exit(0);
+}
+
+/// Escapes text for roff, keeping lines from being taken as requests.
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// A paragraph for every line of text, with the indented examples kept as they are.
+fn roff_lines(lines: &[String]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut example = false;
+    for line in lines.iter() {
+        let indented = line.starts_with("  ");
+        if indented && !example {
+            out.extend([".RS", ".nf"].map(String::from));
+        } else if !indented && example {
+            out.extend([".fi", ".RE"].map(String::from));
+        }
+        example = indented;
+
+        if indented {
+            out.push(roff(line.trim_start()));
+        } else if !line.is_empty() {
+            out.push(".PP".to_string());
+            out.push(roff(line));
+        }
+    }
+    if example {
+        out.extend([".fi", ".RE"].map(String::from));
+    }
+    out
+}
+
+fn manpage_text(cli: &Cli) -> String {
+    let mut out = vec![
+        format!(
+            ".TH WLSTREAMER 1 \"\" \"wlstreamer {}\" \"User Commands\"",
+            VERSION
+        ),
+        ".SH NAME".to_string(),
+        format!("wlstreamer \\- {}", roff(SUMMARY)),
+        ".SH SYNOPSIS".to_string(),
+        ".nf".to_string(),
+    ];
+    out.extend(USAGE.iter().map(|usage| roff(usage)));
+    out.push(".fi".to_string());
+    out.push(".SH DESCRIPTION".to_string());
+    out.extend(roff_lines(&cli.notes));
+
+    out.push(".SH OPTIONS".to_string());
+    for flag in FLAGS.iter() {
+        let names: Vec<String> = flag
+            .names
+            .iter()
+            .map(|name| format!("\\fB{}\\fR", roff(name)))
+            .collect();
+        let value = flag
+            .value
+            .as_ref()
+            .map(|value| format!(" \\fI{}\\fR", roff(value)))
+            .unwrap_or_default();
+        out.push(".TP".to_string());
+        out.push(format!("{}{}", names.join(", "), value));
+        out.push(roff(flag.description));
+    }
+
+    for section in cli.sections.iter() {
+        out.push(format!(".SH {}", roff(&section.title)));
+        out.extend(roff_lines(&section.lines));
+    }
+
+    out.join("\n") + "\n"
+}
+
+/// Prints the man page in roff, then exits.
+pub fn manpage() -> ! {
+    print!("{}", manpage_text(&model()));
+    std::process::exit(0);
+}
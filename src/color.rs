@@ -0,0 +1,203 @@
+//! `--color-range` and `--colorspace`: how the RGB frames of the screens are turned into the YUV
+//! frames on the devices. The devices don't say which range or matrix their frames use, so
+//! everything writing to or reading from them has to agree on it. Without either option, ffmpeg
+//! and wf-recorder pick limited range BT.601, which is what most webcams send.
+
+/// The range of the Y, U and V values.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Range {
+    /// 0 to 255
+    Full,
+    /// 16 to 235, and 16 to 240 for U and V
+    Limited,
+}
+
+impl Range {
+    pub fn parse(value: &str) -> Option<Range> {
+        match value {
+            "full" | "pc" => Some(Range::Full),
+            "limited" | "tv" => Some(Range::Limited),
+            _ => None,
+        }
+    }
+
+    /// The name in ffmpeg's options.
+    fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            Range::Full => "pc",
+            Range::Limited => "tv",
+        }
+    }
+
+    /// The name in the options of scale_vaapi.
+    fn vaapi_name(&self) -> &'static str {
+        match self {
+            Range::Full => "full",
+            Range::Limited => "limited",
+        }
+    }
+}
+
+/// The matrix converting RGB to YUV.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Space {
+    /// Standard definition video
+    Bt601,
+    /// HD video
+    Bt709,
+}
+
+impl Space {
+    pub fn parse(value: &str) -> Option<Space> {
+        match value {
+            "bt601" => Some(Space::Bt601),
+            "bt709" => Some(Space::Bt709),
+            _ => None,
+        }
+    }
+
+    /// The name in the options of ffmpeg's scale filter.
+    fn matrix_name(&self) -> &'static str {
+        match self {
+            Space::Bt601 => "bt601",
+            Space::Bt709 => "bt709",
+        }
+    }
+
+    /// The name in `-colorspace` and the options of scale_vaapi.
+    fn colorspace_name(&self) -> &'static str {
+        match self {
+            Space::Bt601 => "smpte170m",
+            Space::Bt709 => "bt709",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Color {
+    pub range: Option<Range>,
+    pub space: Option<Space>,
+}
+
+impl Color {
+    /// Options for ffmpeg's scale filter, for the frames it outputs and, when they come from a
+    /// device, for the frames it reads.
+    fn scale_options(&self, from_device: bool) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(range) = self.range {
+            if from_device {
+                options.push(format!("in_range={}", range.ffmpeg_name()));
+            }
+            options.push(format!("out_range={}", range.ffmpeg_name()));
+        }
+        if let Some(space) = self.space {
+            if from_device {
+                options.push(format!("in_color_matrix={}", space.matrix_name()));
+            }
+            options.push(format!("out_color_matrix={}", space.matrix_name()));
+        }
+        options
+    }
+
+    /// A filter converting captured frames to yuyv422 with the range and matrix.
+    pub fn convert_filter(&self) -> Option<String> {
+        let options = self.scale_options(false);
+        if options.is_empty() {
+            None
+        } else {
+            Some(format!("scale={},format=yuyv422", options.join(":")))
+        }
+    }
+
+    /// Options to append to a scale filter resizing frames read from a device, each preceded by
+    /// a colon.
+    pub fn rescale_options(&self) -> String {
+        self.scale_options(true)
+            .iter()
+            .map(|option| format!(":{}", option))
+            .collect()
+    }
+
+    /// Options for scale_vaapi, each preceded by a colon.
+    pub fn vaapi_options(&self) -> String {
+        let mut options = String::new();
+        if let Some(range) = self.range {
+            options.push_str(format!(":out_range={}", range.vaapi_name()).as_str());
+        }
+        if let Some(space) = self.space {
+            options.push_str(format!(":out_color_matrix={}", space.colorspace_name()).as_str());
+        }
+        options
+    }
+
+    /// ffmpeg options telling what the frames are, before the input of a device or before the
+    /// output to one.
+    pub fn tag_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(range) = self.range {
+            args.extend(["-color_range".to_string(), range.ffmpeg_name().to_string()]);
+        }
+        if let Some(space) = self.space {
+            args.extend([
+                "-colorspace".to_string(),
+                space.colorspace_name().to_string(),
+            ]);
+        }
+        args
+    }
+}
+
+/// `--tonemap` and `--tonemap-peak`: how frames of screens in HDR mode are brought down to the
+/// 8-bit SDR the devices carry. Compositors hand out those frames as they are sent to the monitor,
+/// PQ encoded with BT.2020 primaries, which looks washed out and too dark when converted like any
+/// other frame.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Tonemap {
+    /// The algorithm of ffmpeg's tonemap filter, None when turned off
+    pub algorithm: Option<&'static str>,
+    /// The brightness in nits that ends up as white
+    pub peak: u32,
+}
+
+impl Default for Tonemap {
+    fn default() -> Tonemap {
+        Tonemap {
+            algorithm: Some("hable"),
+            peak: 100,
+        }
+    }
+}
+
+const ALGORITHMS: [&str; 6] = ["hable", "reinhard", "mobius", "clip", "linear", "gamma"];
+
+impl Tonemap {
+    /// Parses a --tonemap value, an algorithm or off. None if it is neither.
+    pub fn parse_algorithm(value: &str) -> Option<Option<&'static str>> {
+        if value == "off" {
+            return Some(None);
+        }
+        ALGORITHMS
+            .iter()
+            .find(|algorithm| **algorithm == value)
+            .map(|algorithm| Some(*algorithm))
+    }
+
+    /// Filters turning frames of an HDR screen into limited range BT.709 yuyv422.
+    pub fn filter(&self) -> Option<String> {
+        let algorithm = self.algorithm?;
+        Some(format!(
+            "zscale=tin=smpte2084:pin=bt2020:t=linear:npl={},format=gbrpf32le,zscale=p=bt709,tonemap=tonemap={}:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuyv422",
+            self.peak, algorithm
+        ))
+    }
+
+    /// Filters for frames of an HDR screen that are uploaded to the GPU, before and after the
+    /// upload.
+    pub fn vaapi_filters(&self) -> Option<(&'static str, &'static str)> {
+        self.algorithm?;
+        Some((
+            "setparams=color_trc=smpte2084:color_primaries=bt2020:colorspace=bt2020nc",
+            "tonemap_vaapi=format=nv12:t=bt709:m=bt709:p=bt709",
+        ))
+    }
+}
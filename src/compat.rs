@@ -0,0 +1,124 @@
+//! `--compat`: settings that Chrome, Zoom and Teams accept from a camera, and checks for the
+//! v4l2loopback misconfigurations that make them not list the output device at all. Chrome, and
+//! everything built on it, only lists devices that can't also be written to, which v4l2loopback
+//! only does with `exclusive_caps=1`. Zoom and Teams then pick one of the sizes webcams have, and
+//! read YUYV as limited range BT.601 no matter what is written.
+
+use crate::color::{Range, Space};
+use crate::{i18n, loopback, set_canvas, Config, Options, Resolution};
+use std::fs::OpenOptions;
+
+/// The largest size all of them take from a webcam
+pub const SIZE: Resolution = Resolution {
+    width: 1920,
+    height: 1080,
+};
+/// Chrome asks cameras for 30 frames per second, and Zoom doesn't send more
+pub const FPS: u32 = 30;
+
+/// Turns the options into ones the apps accept, saying what was changed.
+pub fn apply(options: &mut Options) {
+    match options.fps {
+        Some(fps) if fps > FPS => {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Streaming at {} instead of {} frames per second, which is the most video call apps take",
+                    &[&FPS, &fps]
+                )
+            );
+            options.fps = Some(FPS);
+        }
+        Some(_) => {}
+        None => options.fps = Some(FPS),
+    }
+
+    if options.color.range == Some(Range::Full) {
+        println!(
+            "{}",
+            i18n::tr(
+                "Writing limited range, since video call apps show full range washed out",
+                &[]
+            )
+        );
+    }
+    if options.color.space == Some(Space::Bt709) {
+        println!(
+            "{}",
+            i18n::tr(
+                "Writing BT.601, since video call apps take the colors of webcams as BT.601",
+                &[]
+            )
+        );
+    }
+    options.color.range = Some(Range::Limited);
+    options.color.space = Some(Space::Bt601);
+}
+
+/// Puts the size the apps take in place of the combined resolution the screens are scaled to.
+pub fn canvas(resolutions: &mut Vec<Resolution>) {
+    set_canvas(resolutions, SIZE);
+}
+
+/// The capabilities the device has right now, which with `exclusive_caps=1` are only those of a
+/// capture device while something writes to it, and only those of an output device otherwise.
+fn device_caps(device_number: usize) -> Result<u32, String> {
+    let device = format!("/dev/video{}", device_number);
+    let file = OpenOptions::new()
+        .read(true)
+        .open(&device)
+        .map_err(|err| i18n::tr("Could not open {}: {}", &[&device, &err]))?;
+
+    Ok(loopback::query(&file, &device)?.device_caps)
+}
+
+/// Looks for what keeps the apps from listing the devices, and prints how to fix it.
+pub fn check(config: &Config) {
+    let devices: Vec<usize> = loopback::devices_in_use(config)
+        .into_iter()
+        .map(|(device_number, _)| device_number)
+        .collect();
+
+    let mut shared = Vec::new();
+    for device_number in devices.iter() {
+        match device_caps(*device_number) {
+            Ok(caps)
+                if caps & loopback::V4L2_CAP_VIDEO_CAPTURE != 0
+                    && caps & loopback::V4L2_CAP_VIDEO_OUTPUT != 0 =>
+            {
+                shared.push(format!("/dev/video{}", device_number));
+            }
+            Ok(_) => {}
+            Err(err) => println!("{}", err),
+        }
+    }
+    if shared.is_empty() {
+        return;
+    }
+
+    let numbers = devices
+        .iter()
+        .map(|device_number| device_number.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    println!(
+        "{}",
+        i18n::tr(
+            "{} can be written to and read from at the same time, which Chrome, Zoom and Teams take as not being a camera, so they won't list it. Load v4l2loopback with exclusive_caps=1 while nothing uses the devices:",
+            &[&shared.join(", ")]
+        )
+    );
+    println!("  sudo modprobe -r v4l2loopback");
+    println!(
+        "  sudo modprobe v4l2loopback video_nr={} exclusive_caps={}",
+        numbers,
+        vec!["1"; devices.len()].join(",")
+    );
+    println!(
+        "{}",
+        i18n::tr(
+            "To keep it, put the options into /etc/modprobe.d/v4l2loopback.conf. Apps that still don't list the device were started before it, and need to be restarted.",
+            &[]
+        )
+    );
+}
@@ -0,0 +1,220 @@
+use crate::i18n;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Options read from the config file. Every field is optional, command line options are applied
+/// on top of whatever is set here.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    pub not_ws: Vec<WorkspaceId>,
+    pub not_screen: Vec<String>,
+    pub devices_from: Option<usize>,
+    pub output_device: Option<String>,
+    pub intermediate_devices: Vec<String>,
+    pub backend: Option<String>,
+    pub capture_protocol: Option<String>,
+    pub mode: Option<String>,
+    pub side_by_side: Vec<String>,
+    pub enlarge_focused: Option<bool>,
+    pub highlight: Option<String>,
+    pub highlight_width: Option<usize>,
+    pub blank_when_idle: Option<u64>,
+    pub schedule: Vec<String>,
+    pub duration: Option<String>,
+    pub hidden_text: Option<bool>,
+    pub blank_on_hidden: Option<bool>,
+    pub placeholder_fps: Option<u32>,
+    pub dnd: Option<bool>,
+    pub lazy: Option<bool>,
+    pub standby: Option<bool>,
+    pub compat: Option<bool>,
+    pub quality: Option<String>,
+    pub zoom: Option<f64>,
+    pub zoom_duration: Option<u64>,
+    pub damage: Option<bool>,
+    pub normalize_scale: Option<bool>,
+    pub fps: Option<u32>,
+    pub color_range: Option<String>,
+    pub colorspace: Option<String>,
+    pub tonemap: Option<String>,
+    pub tonemap_peak: Option<u32>,
+    pub sinks: Vec<String>,
+    pub session: Option<String>,
+    pub instance: Option<String>,
+    pub call_apps: Vec<String>,
+    pub urgent_apps: Vec<String>,
+    pub urgent_seconds: Option<u64>,
+    pub countdown: Option<u64>,
+    pub redact: Vec<String>,
+    pub mask_apps: Vec<String>,
+    pub codec: Option<String>,
+    pub bitrate: Option<String>,
+    pub min_bitrate: Option<String>,
+    pub preset: Option<String>,
+    pub crf: Option<u32>,
+    pub sink_passphrase: Option<String>,
+    pub sink_latency: Option<u32>,
+    pub whip_token: Option<String>,
+    pub secrets_file: Option<String>,
+    pub serve_hls: Option<String>,
+    pub preview_listen: Option<String>,
+    pub replay_buffer: Option<u32>,
+    pub record_segments: Option<String>,
+    pub audio_desktop: Option<bool>,
+    pub audio_mic: Option<bool>,
+    pub desktop_volume: Option<f64>,
+    pub desktop_device: Option<String>,
+    pub mic_device: Option<String>,
+    pub mic_volume: Option<f64>,
+    pub mic_denoise: Option<String>,
+    pub mic_limiter: Option<bool>,
+    pub av_offset: Option<AvOffset>,
+    pub battery_fps: Option<u32>,
+    pub battery_threshold: Option<u8>,
+    pub stats: Option<u64>,
+    pub stall_timeout: Option<u64>,
+    pub events_json: Option<String>,
+    pub timeline: Option<String>,
+    pub indicator: Option<bool>,
+    pub gpu_scale: Option<bool>,
+    pub drm_device: Option<String>,
+    pub verbose: Option<bool>,
+    pub wf_recorder_args: Vec<String>,
+    pub ffmpeg_args: Vec<String>,
+    pub wf_recorder_path: Option<String>,
+    pub ffmpeg_path: Option<String>,
+    pub ipc_socket: Option<String>,
+    pub profile: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    pub output: HashMap<String, OutputConfig>,
+}
+
+/// A workspace to hide, by its number, or by its name for workspaces that have none.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(untagged)]
+pub enum WorkspaceId {
+    Num(usize),
+    Name(String),
+}
+
+impl WorkspaceId {
+    pub fn parse(value: &str) -> WorkspaceId {
+        match value.parse() {
+            Ok(num) => WorkspaceId::Num(num),
+            Err(_) => WorkspaceId::Name(value.to_string()),
+        }
+    }
+
+    pub fn matches(&self, num: Option<usize>, name: &str) -> bool {
+        match self {
+            WorkspaceId::Num(wanted) => num == Some(*wanted),
+            WorkspaceId::Name(wanted) => wanted == name,
+        }
+    }
+}
+
+/// How much to delay the audio, in milliseconds, or `auto`.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(untagged)]
+pub enum AvOffset {
+    Ms(i64),
+    Name(String),
+}
+
+/// Settings for a single output, in a `[output."<name>"]` table.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OutputConfig {
+    /// Added to the arguments of wf-recorder, or of ffmpeg for ext-image-copy-capture and x11grab, when capturing
+    /// the output
+    #[serde(alias = "capture_args")]
+    pub capture_args: Vec<String>,
+}
+
+/// A named set of options that can be switched to at runtime with `wlstreamer ctl profile`.
+/// Anything not set falls back to the options wlstreamer was started with.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub not_ws: Option<Vec<WorkspaceId>>,
+    pub not_screen: Option<Vec<String>>,
+    pub enlarge_focused: Option<bool>,
+    pub highlight: Option<String>,
+    pub highlight_width: Option<usize>,
+    pub hidden_text: Option<bool>,
+    pub placeholder_fps: Option<u32>,
+    /// Replaces the sinks, the ones both have keep running
+    pub sinks: Option<Vec<String>>,
+}
+
+/// The config file used when none is given on the command line,
+/// `$XDG_CONFIG_HOME/wlstreamer/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_home.join("wlstreamer").join("config.toml"))
+}
+
+/// Reads the config file. A missing file is only an error if it was requested explicitly.
+pub fn load(path: Option<&str>) -> Result<ConfigFile, String> {
+    let (path, explicit) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(ConfigFile::default()),
+        },
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) if !explicit && !path.exists() => return Ok(ConfigFile::default()),
+        Err(err) => return Err(i18n::tr("Could not read {}: {}", &[&path.display(), &err])),
+    };
+
+    toml::from_str(contents.as_str())
+        .map_err(|err| i18n::tr("Invalid {}: {}", &[&path.display(), &err]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_match_the_workspace_number() {
+        let id = WorkspaceId::parse("3");
+        assert_eq!(id, WorkspaceId::Num(3));
+        assert!(id.matches(Some(3), "3"));
+        assert!(id.matches(Some(3), "3:mail"));
+        assert!(!id.matches(Some(4), "3"));
+        // A workspace named like a number but without one isn't it
+        assert!(!id.matches(None, "3"));
+    }
+
+    #[test]
+    fn names_match_the_whole_name() {
+        let id = WorkspaceId::parse("chat");
+        assert_eq!(id, WorkspaceId::Name("chat".to_string()));
+        assert!(id.matches(None, "chat"));
+        assert!(id.matches(Some(2), "chat"));
+        assert!(!id.matches(None, "chatter"));
+        assert!(!id.matches(Some(2), "2"));
+    }
+
+    #[test]
+    fn not_ws_takes_numbers_and_names() {
+        let file: ConfigFile = toml::from_str(r#"not-ws = [3, "chat"]"#).unwrap();
+        assert_eq!(
+            file.not_ws,
+            vec![WorkspaceId::Num(3), WorkspaceId::Name("chat".to_string())]
+        );
+        assert!(file.not_ws.iter().any(|id| id.matches(None, "chat")));
+        assert!(!file.not_ws.iter().any(|id| id.matches(None, "3")));
+    }
+}
@@ -0,0 +1,78 @@
+//! `--lazy`: finds out whether anything is reading from the output devices, by looking for other
+//! processes that have them open. While nobody is watching, a black screen is streamed instead
+//! of capturing the screens, which keeps the device available to programs that only list
+//! v4l2loopback devices with a producer.
+
+use crate::Event;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// The parent of a process, from the fourth field of /proc/<pid>/stat. The command name before it
+/// is in parentheses and may contain spaces, so the fields are counted from the last `)`.
+fn parent(pid: &str) -> Option<u32> {
+    let stat = fs::read_to_string(Path::new("/proc").join(pid).join("stat")).ok()?;
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Whether a process other than wlstreamer and the processes it started has one of the devices
+/// open.
+pub fn watched(devices: &[PathBuf]) -> bool {
+    let own = process::id();
+    let processes = match fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return true,
+    };
+
+    for process in processes.map_while(Result::ok) {
+        let pid = process.file_name().to_string_lossy().to_string();
+        if pid.parse::<u32>().ok().is_none_or(|pid| pid == own) {
+            continue;
+        }
+
+        // Processes of other users can't be looked into, and don't count
+        let fds = match fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let open = fds
+            .map_while(Result::ok)
+            .filter_map(|fd| fs::read_link(fd.path()).ok())
+            .any(|target| devices.contains(&target));
+        if open && parent(pid.as_str()) != Some(own) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Checks the devices every second in a background thread, and signals `Event::Watched` whenever
+/// something starts or stops reading from them. Returns whether they are being watched right now.
+pub fn watch(device_numbers: &[usize], events: Sender<Event>) -> bool {
+    let devices: Vec<PathBuf> = device_numbers
+        .iter()
+        .map(|device_number| PathBuf::from(format!("/dev/video{}", device_number)))
+        .collect();
+
+    let mut was_watched = watched(&devices);
+    let initial = was_watched;
+    thread::spawn(move || loop {
+        thread::sleep(INTERVAL);
+        let is_watched = watched(&devices);
+        if is_watched != was_watched {
+            was_watched = is_watched;
+            if events.send(Event::Watched(is_watched)).is_err() {
+                return;
+            }
+        }
+    });
+
+    initial
+}
@@ -0,0 +1,211 @@
+//! The control socket, through which `wlstreamer ctl` changes a running instance. Every
+//! connection sends a single command line and gets back either `ok`, followed by the output of
+//! the command if there is any, or `error: <reason>`.
+
+use crate::{i18n, runtime_dir, Event};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread;
+
+/// The name given with --instance, to run several instances side by side
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// Switch to the named profile from the config file
+    Profile(String),
+    /// Stream a black screen until resumed, or for this many seconds
+    Blank(Option<u64>),
+    /// Go back to streaming the screens
+    Resume,
+    /// Blank the stream, or resume it if it is blanked
+    Toggle,
+    /// Report what is being streamed where
+    Status {
+        json: bool,
+    },
+    /// Never stream this output, until unlocked
+    Lock(String),
+    Unlock(String),
+    /// Lock the output, or unlock it if it is locked
+    ToggleLock(String),
+    /// Write the replay buffer to a file, or to the default one
+    SaveReplay(Option<String>),
+    /// Save the frame on the output device to a PNG file
+    Screenshot(String),
+}
+
+/// The commands `parse` knows, for shell completions
+pub const COMMANDS: [&str; 10] = [
+    "profile",
+    "blank",
+    "resume",
+    "toggle",
+    "status",
+    "lock",
+    "unlock",
+    "toggle-lock",
+    "save-replay",
+    "screenshot",
+];
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["profile", name] => Ok(Command::Profile(name.to_string())),
+            ["profile"] => Err(i18n::tr("profile needs a name", &[])),
+            ["blank"] => Ok(Command::Blank(None)),
+            ["blank", seconds] => seconds
+                .parse::<u64>()
+                .map(|seconds| Command::Blank(Some(seconds)))
+                .map_err(|_| i18n::tr("blank takes a number of seconds, not {}", &[&seconds])),
+            ["resume"] => Ok(Command::Resume),
+            ["toggle"] => Ok(Command::Toggle),
+            ["status"] => Ok(Command::Status { json: false }),
+            ["status", "--json"] => Ok(Command::Status { json: true }),
+            ["lock", output] => Ok(Command::Lock(output.to_string())),
+            ["unlock", output] => Ok(Command::Unlock(output.to_string())),
+            ["toggle-lock", output] => Ok(Command::ToggleLock(output.to_string())),
+            ["save-replay"] => Ok(Command::SaveReplay(None)),
+            ["save-replay", path @ ..] => Ok(Command::SaveReplay(Some(path.join(" ")))),
+            ["screenshot"] => Err(i18n::tr("screenshot needs a file", &[])),
+            ["screenshot", path @ ..] => Ok(Command::Screenshot(path.join(" "))),
+            [] => Err(i18n::tr("no command given", &[])),
+            _ => Err(i18n::tr("unknown command: {}", &[&line.trim()])),
+        }
+    }
+}
+
+/// Sets the instance this process is, or talks to. Only the first call counts, so the command line
+/// goes before the config file.
+pub fn set_instance(name: &str) {
+    let _ = INSTANCE.set(name.to_string());
+}
+
+/// `wlstreamer`, or `wlstreamer-<name>` with --instance, which names the files of the instance in
+/// the runtime dir.
+pub fn instance_name() -> String {
+    match INSTANCE.get() {
+        Some(name) => format!("wlstreamer-{}", name),
+        None => "wlstreamer".to_string(),
+    }
+}
+
+/// `wlstreamer.sock` in the runtime dir.
+pub fn socket_path() -> Result<PathBuf, String> {
+    runtime_dir::join(&format!("{}.sock", instance_name()))
+}
+
+/// Accepts commands on the control socket in a background thread, and passes them on as events
+/// together with a channel for the answer.
+pub fn listen(events: Sender<Event>) -> Result<(), String> {
+    let path = socket_path()?;
+
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(i18n::tr(
+                "{} is in use, is wlstreamer already running?",
+                &[&path.display()],
+            ));
+        }
+        // Left behind by an instance that didn't shut down cleanly
+        fs::remove_file(&path)
+            .map_err(|err| i18n::tr("Could not remove {}: {}", &[&path.display(), &err]))?;
+    }
+
+    let listener = UnixListener::bind(&path)
+        .map_err(|err| i18n::tr("Could not listen on {}: {}", &[&path.display(), &err]))?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_err() {
+                continue;
+            }
+
+            let result = match Command::parse(&line) {
+                Ok(command) => {
+                    let (reply, answer) = mpsc::channel();
+                    if events.send(Event::Control(command, reply)).is_err() {
+                        break;
+                    }
+                    answer
+                        .recv()
+                        .unwrap_or_else(|_| Err(i18n::tr("wlstreamer is shutting down", &[])))
+                }
+                Err(err) => Err(err),
+            };
+
+            let _ = match result {
+                Ok(output) if output.is_empty() => writeln!(stream, "ok"),
+                Ok(output) => writeln!(stream, "ok\n{}", output),
+                Err(err) => writeln!(stream, "error: {}", err),
+            };
+        }
+    });
+
+    Ok(())
+}
+
+/// Sends a command to the running instance, and returns its output.
+pub fn request(command: &str) -> Result<String, String> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path).map_err(|err| {
+        i18n::tr(
+            "Could not connect to {}, is wlstreamer running? {}",
+            &[&path.display(), &err],
+        )
+    })?;
+
+    let mut answer = String::new();
+    writeln!(stream, "{}", command)
+        .and_then(|_| BufReader::new(&stream).read_to_string(&mut answer))
+        .map_err(|err| i18n::tr("Could not talk to wlstreamer: {}", &[&err]))?;
+
+    let answer = answer.trim_end();
+    match answer.split_once('\n') {
+        Some(("ok", output)) => Ok(output.to_string()),
+        None if answer == "ok" => Ok(String::new()),
+        _ => Err(answer.strip_prefix("error: ").unwrap_or(answer).to_string()),
+    }
+}
+
+/// `status --json` of the running instance.
+pub fn status() -> Result<Value, String> {
+    let output = request("status --json")?;
+    serde_json::from_str(output.as_str()).map_err(|err| i18n::tr("Invalid status: {}", &[&err]))
+}
+
+/// Commands that take a file as their argument, which is relative to where `ctl` runs.
+const PATH_COMMANDS: [&str; 2] = ["save-replay", "screenshot"];
+
+/// `wlstreamer ctl <command>`: sends the command to the running instance and prints the answer.
+pub fn run_client(args: &[String]) -> ! {
+    let mut args = args.to_vec();
+    if args.len() > 1 && PATH_COMMANDS.contains(&args[0].as_str()) {
+        if let Ok(dir) = env::current_dir() {
+            let path = dir.join(args[1..].join(" "));
+            args = vec![args[0].clone(), path.display().to_string()];
+        }
+    }
+
+    match request(args.join(" ").as_str()) {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            std::process::exit(0);
+        }
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,1069 @@
+//! `org.wlstreamer.Control` on the session bus, for desktop widgets and other apps that would
+//! rather talk D-Bus than the control socket. The methods Pause, Resume, LockOutput, UnlockOutput
+//! and Status do what `wlstreamer ctl blank`, `resume`, `lock`, `unlock` and `status --json` do,
+//! and the signals OutputChanged and Error are sent along with the `switch` and `error` events.
+//! Only the little of the D-Bus protocol this and `wlstreamer tray` need is spoken here: a single
+//! connection, and the basic types without 64-bit numbers and file descriptors.
+
+use crate::control::{self, Command};
+use crate::i18n;
+use crate::Event;
+use serde_json::Value;
+use std::convert::TryInto;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const NAME: &str = "org.wlstreamer.Control";
+const PATH: &str = "/org/wlstreamer/Control";
+const INTERFACE: &str = "org.wlstreamer.Control";
+const FAILED: &str = "org.wlstreamer.Error.Failed";
+
+pub const METHOD_CALL: u8 = 1;
+pub const METHOD_RETURN: u8 = 2;
+pub const ERROR: u8 = 3;
+pub const SIGNAL: u8 = 4;
+pub const UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+/// Set on calls that don't wait for an answer
+const NO_REPLY_EXPECTED: u8 = 1;
+/// RequestName fails instead of waiting in line while another instance has the name
+const DO_NOT_QUEUE: u32 = 4;
+const PRIMARY_OWNER: u32 = 1;
+/// How long the bus has to let wlstreamer in and give it its name
+const SETUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Anything larger is not a D-Bus message
+const MAX_MESSAGE: usize = 1 << 27;
+/// How deep arrays, structs and variants can be nested in each other
+const MAX_DEPTH: usize = 64;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.wlstreamer.Control">
+    <method name="Pause"/>
+    <method name="Resume"/>
+    <method name="LockOutput">
+      <arg name="output" type="s" direction="in"/>
+    </method>
+    <method name="UnlockOutput">
+      <arg name="output" type="s" direction="in"/>
+    </method>
+    <method name="Status">
+      <arg name="status" type="s" direction="out"/>
+    </method>
+    <signal name="OutputChanged">
+      <arg name="output" type="s"/>
+    </signal>
+    <signal name="Error">
+      <arg name="message" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// A value of one of the types spoken here.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Arg {
+    Byte(u8),
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    Str(String),
+    Path(String),
+    /// An array with the signature of its elements, which is needed when it is empty
+    Array(String, Vec<Arg>),
+    Struct(Vec<Arg>),
+    /// An entry of a dictionary, which is an array of them
+    Entry(Box<Arg>, Box<Arg>),
+    Variant(Box<Arg>),
+}
+
+impl Arg {
+    pub fn signature(&self) -> String {
+        match self {
+            Arg::Byte(_) => "y".to_string(),
+            Arg::Bool(_) => "b".to_string(),
+            Arg::I32(_) => "i".to_string(),
+            Arg::U32(_) => "u".to_string(),
+            Arg::Str(_) => "s".to_string(),
+            Arg::Path(_) => "o".to_string(),
+            Arg::Array(element, _) => format!("a{}", element),
+            Arg::Struct(fields) => format!("({})", signature(fields)),
+            Arg::Entry(key, value) => format!("{{{}{}}}", key.signature(), value.signature()),
+            Arg::Variant(_) => "v".to_string(),
+        }
+    }
+
+    pub fn str(&self) -> Option<&str> {
+        match self {
+            Arg::Str(value) | Arg::Path(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn i32(&self) -> Option<i32> {
+        match self {
+            Arg::I32(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// The signature of a list of values.
+pub fn signature(args: &[Arg]) -> String {
+    args.iter().map(Arg::signature).collect()
+}
+
+/// The first complete type of a signature, and the rest.
+fn split_type(signature: &str) -> Option<(&str, &str)> {
+    let bytes = signature.as_bytes();
+    let mut end = 0;
+    while bytes.get(end) == Some(&b'a') {
+        end += 1;
+    }
+    let mut depth = 0;
+    loop {
+        match bytes.get(end)? {
+            b'(' | b'{' => depth += 1,
+            b')' | b'}' => depth -= 1,
+            _ => {}
+        }
+        end += 1;
+        if depth == 0 {
+            return Some(signature.split_at(end));
+        }
+    }
+}
+
+/// What values of the type start at a multiple of.
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'(' | b'{') => 8,
+        Some(b'y' | b'g' | b'v') => 1,
+        _ => 4,
+    }
+}
+
+/// Builds the little-endian wire format, aligned from the start of the message.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, to: usize) {
+        while !self.buf.len().is_multiple_of(to) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.u8(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn arg(&mut self, arg: &Arg) {
+        match arg {
+            Arg::Byte(value) => self.u8(*value),
+            Arg::Bool(value) => self.u32(*value as u32),
+            Arg::I32(value) => self.u32(*value as u32),
+            Arg::U32(value) => self.u32(*value),
+            Arg::Str(value) | Arg::Path(value) => self.string(value),
+            Arg::Array(element, items) => {
+                // The length in bytes is filled in once it is known, without the padding before
+                // the first element
+                self.u32(0);
+                let len_end = self.buf.len();
+                self.align(alignment(element));
+                let start = self.buf.len();
+                for item in items {
+                    self.arg(item);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_end - 4..len_end].copy_from_slice(&len.to_le_bytes());
+            }
+            Arg::Struct(fields) => {
+                self.align(8);
+                for field in fields {
+                    self.arg(field);
+                }
+            }
+            Arg::Entry(key, value) => {
+                self.align(8);
+                self.arg(key);
+                self.arg(value);
+            }
+            Arg::Variant(value) => {
+                self.signature(&value.signature());
+                self.arg(value);
+            }
+        }
+    }
+}
+
+/// Reads the wire format in the byte order the message was sent in.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+    /// How many containers the value being read is in
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn align(&mut self, to: usize) {
+        self.pos = self.pos.div_ceil(to) * to;
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(value)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let bytes: [u8; 4] = self.data.get(self.pos..self.pos + 4)?.try_into().ok()?;
+        self.pos += 4;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn text(&mut self, len: usize) -> Option<String> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        // Followed by a nul byte
+        self.pos += len + 1;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.text(len)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        self.text(len)
+    }
+
+    /// Reads a value of a single complete type, or none if the type or the value is broken.
+    fn arg(&mut self, kind: &str) -> Option<Arg> {
+        // Otherwise a message of nested variants runs out of stack
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let arg = self.value(kind);
+        self.depth -= 1;
+        arg
+    }
+
+    fn value(&mut self, kind: &str) -> Option<Arg> {
+        Some(match kind.as_bytes().first()? {
+            b'y' => Arg::Byte(self.u8()?),
+            b'b' => Arg::Bool(self.u32()? != 0),
+            b'i' => Arg::I32(self.u32()? as i32),
+            b'u' => Arg::U32(self.u32()?),
+            b's' => Arg::Str(self.string()?),
+            b'o' => Arg::Path(self.string()?),
+            b'g' => Arg::Str(self.signature()?),
+            b'v' => {
+                let signature = self.signature()?;
+                Arg::Variant(Box::new(self.arg(&signature)?))
+            }
+            b'a' => {
+                let element = &kind[1..];
+                let len = self.u32()? as usize;
+                self.align(alignment(element));
+                let end = self.pos + len;
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.arg(element)?);
+                }
+                Arg::Array(element.to_string(), items)
+            }
+            b'(' | b'{' => {
+                // Signatures of variants come from the sender, and may be cut off or mismatched
+                let close = if kind.starts_with('(') { ')' } else { '}' };
+                let mut rest = kind
+                    .strip_suffix(close)
+                    .and_then(|kind| kind.get(1..))
+                    .filter(|fields| !fields.is_empty())?;
+                self.align(8);
+                let mut fields = Vec::new();
+                while !rest.is_empty() {
+                    let (field, others) = split_type(rest)?;
+                    fields.push(self.arg(field)?);
+                    rest = others;
+                }
+                if kind.starts_with('{') {
+                    let mut fields = fields.into_iter();
+                    Arg::Entry(Box::new(fields.next()?), Box::new(fields.next()?))
+                } else {
+                    Arg::Struct(fields)
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Message {
+    pub kind: u8,
+    flags: u8,
+    pub serial: u32,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    pub signature: String,
+    body: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Message {
+    fn new(kind: u8) -> Message {
+        Message {
+            kind,
+            ..Message::default()
+        }
+    }
+
+    pub fn call(destination: &str, path: &str, interface: &str, member: &str) -> Message {
+        Message {
+            destination: Some(destination.to_string()),
+            ..Message::signal(path, interface, member)
+        }
+        .with_kind(METHOD_CALL)
+    }
+
+    pub fn signal(path: &str, interface: &str, member: &str) -> Message {
+        Message {
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            ..Message::new(SIGNAL)
+        }
+    }
+
+    fn with_kind(mut self, kind: u8) -> Message {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_args(mut self, args: &[Arg]) -> Message {
+        let mut body = Writer::default();
+        for arg in args {
+            body.arg(arg);
+        }
+        self.signature = signature(args);
+        self.body = body.buf;
+        self
+    }
+
+    /// The answer to a method call, for the caller.
+    pub fn reply(&self) -> Message {
+        Message {
+            reply_serial: Some(self.serial),
+            destination: self.sender.clone(),
+            ..Message::new(METHOD_RETURN)
+        }
+    }
+
+    /// The method call failed with the error `name`.
+    pub fn error(&self, name: &str, text: &str) -> Message {
+        Message {
+            error_name: Some(name.to_string()),
+            ..self.reply().with_kind(ERROR)
+        }
+        .with_args(&[Arg::Str(text.to_string())])
+    }
+
+    /// Whether the caller waits for an answer to the method call.
+    pub fn wants_reply(&self) -> bool {
+        self.kind == METHOD_CALL && self.flags & NO_REPLY_EXPECTED == 0
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut header = Writer::default();
+        header
+            .buf
+            .extend_from_slice(&[b'l', self.kind, self.flags, 1]);
+        header.u32(self.body.len() as u32);
+        header.u32(self.serial);
+
+        // The array of header fields, whose length is filled in once it is known
+        header.u32(0);
+        let start = header.buf.len();
+        let strings = [
+            (1, "o", &self.path),
+            (2, "s", &self.interface),
+            (3, "s", &self.member),
+            (4, "s", &self.error_name),
+            (6, "s", &self.destination),
+            (7, "s", &self.sender),
+        ];
+        for (code, kind, value) in strings.iter() {
+            if let Some(value) = value {
+                header.align(8);
+                header.u8(*code);
+                header.signature(kind);
+                header.string(value);
+            }
+        }
+        if let Some(serial) = self.reply_serial {
+            header.align(8);
+            header.u8(5);
+            header.signature("u");
+            header.u32(serial);
+        }
+        if !self.signature.is_empty() {
+            header.align(8);
+            header.u8(8);
+            header.signature("g");
+            header.signature(&self.signature);
+        }
+        let len = (header.buf.len() - start) as u32;
+        header.buf[start - 4..start].copy_from_slice(&len.to_le_bytes());
+
+        header.align(8);
+        header.buf.extend_from_slice(&self.body);
+        header.buf
+    }
+
+    fn decode(data: &[u8]) -> Option<Message> {
+        let mut reader = Reader {
+            data,
+            pos: 4,
+            big_endian: data[0] == b'B',
+            depth: 0,
+        };
+        let mut message = Message {
+            kind: data[1],
+            flags: data[2],
+            big_endian: reader.big_endian,
+            ..Message::default()
+        };
+        let body_len = reader.u32()? as usize;
+        message.serial = reader.u32()?;
+        let fields_end = reader.u32()? as usize + 16;
+
+        while reader.pos < fields_end {
+            reader.align(8);
+            let code = reader.u8()?;
+            match reader.signature()?.as_str() {
+                "s" | "o" => {
+                    let value = Some(reader.string()?);
+                    match code {
+                        1 => message.path = value,
+                        2 => message.interface = value,
+                        3 => message.member = value,
+                        4 => message.error_name = value,
+                        6 => message.destination = value,
+                        7 => message.sender = value,
+                        _ => {}
+                    }
+                }
+                "g" => {
+                    let signature = reader.signature()?;
+                    if code == 8 {
+                        message.signature = signature;
+                    }
+                }
+                "u" => {
+                    let value = reader.u32()?;
+                    if code == 5 {
+                        message.reply_serial = Some(value);
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        reader.align(8);
+        message.body = data.get(reader.pos..reader.pos + body_len)?.to_vec();
+        Some(message)
+    }
+
+    /// The arguments, or none if they can't be read.
+    pub fn args(&self) -> Vec<Arg> {
+        let mut reader = Reader {
+            data: &self.body,
+            pos: 0,
+            big_endian: self.big_endian,
+            depth: 0,
+        };
+        let mut args = Vec::new();
+        let mut rest = self.signature.as_str();
+        while let Some((kind, others)) = split_type(rest) {
+            match reader.arg(kind) {
+                Some(arg) => args.push(arg),
+                None => return Vec::new(),
+            }
+            rest = others;
+        }
+        args
+    }
+
+    /// The arguments, if they are all strings.
+    fn strings(&self) -> Option<Vec<String>> {
+        self.args()
+            .iter()
+            .map(|arg| arg.str().map(str::to_string))
+            .collect()
+    }
+
+    fn read(stream: &mut UnixStream) -> io::Result<Message> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a D-Bus message");
+
+        let mut fixed = [0u8; 16];
+        stream.read_exact(&mut fixed)?;
+        let mut reader = Reader {
+            data: &fixed,
+            pos: 4,
+            big_endian: fixed[0] == b'B',
+            depth: 0,
+        };
+        let body_len = reader.u32().ok_or_else(invalid)? as usize;
+        reader.pos = 12;
+        let fields_len = reader.u32().ok_or_else(invalid)? as usize;
+        let len = (16 + fields_len).div_ceil(8) * 8 + body_len;
+        if len > MAX_MESSAGE {
+            return Err(invalid());
+        }
+
+        let mut data = fixed.to_vec();
+        data.resize(len, 0);
+        stream.read_exact(&mut data[16..])?;
+        Message::decode(&data).ok_or_else(invalid)
+    }
+}
+
+/// The connection to the session bus, shared by the thread answering calls and the signals sent
+/// with events.
+#[derive(Clone, Debug)]
+pub struct Bus {
+    stream: Arc<Mutex<UnixStream>>,
+    serial: Arc<AtomicU32>,
+}
+
+impl Bus {
+    /// Connects to the session bus, without a name yet. Returns the connection to read from as
+    /// well, which doesn't wait for more than a few seconds until it is handed to `incoming`. None
+    /// if there is no session bus.
+    pub fn open() -> Result<Option<(Bus, UnixStream)>, String> {
+        let mut stream = match connect()? {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        // A bus that doesn't answer shouldn't keep wlstreamer from starting
+        stream
+            .set_read_timeout(Some(SETUP_TIMEOUT))
+            .map_err(|err| i18n::tr("Could not talk to the session bus: {}", &[&err]))?;
+        authenticate(&mut stream)?;
+        let mut reader = stream
+            .try_clone()
+            .map_err(|err| i18n::tr("Could not talk to the session bus: {}", &[&err]))?;
+        let bus = Bus {
+            stream: Arc::new(Mutex::new(stream)),
+            serial: Arc::new(AtomicU32::new(1)),
+        };
+
+        bus.call_bus(&mut reader, "Hello", &[])?;
+        Ok(Some((bus, reader)))
+    }
+
+    /// Sends the message, and returns its serial to match the reply with.
+    pub fn send(&self, mut message: Message) -> io::Result<u32> {
+        message.serial = self.serial.fetch_add(1, Ordering::Relaxed);
+        self.stream.lock().unwrap().write_all(&message.encode())?;
+        Ok(message.serial)
+    }
+
+    /// Calls a method of the bus itself and waits for the answer, while nothing else is read yet.
+    pub fn call_bus(
+        &self,
+        reader: &mut UnixStream,
+        member: &str,
+        args: &[Arg],
+    ) -> Result<Message, String> {
+        let serial = self
+            .send(
+                Message::call(
+                    "org.freedesktop.DBus",
+                    "/org/freedesktop/DBus",
+                    "org.freedesktop.DBus",
+                    member,
+                )
+                .with_args(args),
+            )
+            .map_err(|err| i18n::tr("Could not talk to the session bus: {}", &[&err]))?;
+
+        loop {
+            let message = Message::read(reader)
+                .map_err(|err| i18n::tr("Could not talk to the session bus: {}", &[&err]))?;
+            if message.reply_serial != Some(serial) {
+                continue;
+            }
+            if message.kind == ERROR {
+                let reason = message.strings().unwrap_or_default().join(" ");
+                return Err(i18n::tr(
+                    "The session bus refused {}: {}",
+                    &[
+                        &member,
+                        &if reason.is_empty() {
+                            message.error_name.unwrap_or_default()
+                        } else {
+                            reason
+                        },
+                    ],
+                ));
+            }
+            return Ok(message);
+        }
+    }
+
+    /// Takes the name on the bus, unless another connection has it. Returns whether it got it.
+    pub fn request_name(&self, reader: &mut UnixStream, name: &str) -> Result<bool, String> {
+        let reply = self.call_bus(
+            reader,
+            "RequestName",
+            &[Arg::Str(name.to_string()), Arg::U32(DO_NOT_QUEUE)],
+        )?;
+        Ok(reply.args().first() == Some(&Arg::U32(PRIMARY_OWNER)))
+    }
+
+    /// Sends the signal that goes with the event, if there is one.
+    pub fn event(&self, event: &str, fields: &Value) {
+        let (member, value) = match event {
+            // The output is null for the black screen
+            "switch" => ("OutputChanged", fields["output"].as_str().unwrap_or("")),
+            "error" => ("Error", fields["message"].as_str().unwrap_or("")),
+            _ => return,
+        };
+
+        let _ = self.send(
+            Message::signal(PATH, INTERFACE, member).with_args(&[Arg::Str(value.to_string())]),
+        );
+    }
+
+    /// Answers a method call, passing the commands on to the main loop. Returns false once the
+    /// main loop is gone.
+    fn answer(&self, call: &Message, events: &Sender<Event>) -> bool {
+        let path = call.path.as_deref().unwrap_or_default();
+        let interface = call.interface.as_deref();
+        let member = call.member.as_deref().unwrap_or_default();
+        let args = call.strings().unwrap_or_default();
+
+        let result = if member == "Introspect"
+            && matches!(
+                interface,
+                Some("org.freedesktop.DBus.Introspectable") | None
+            ) {
+            Ok(Some(introspect(path)))
+        } else if path != PATH {
+            Err((
+                "org.freedesktop.DBus.Error.UnknownObject",
+                format!("There is nothing at {}", path),
+            ))
+        } else {
+            let command = match (member, args.as_slice()) {
+                _ if !matches!(interface, Some(INTERFACE) | None) => None,
+                ("Pause", []) => Some(Command::Blank(None)),
+                ("Resume", []) => Some(Command::Resume),
+                ("LockOutput", [output]) => Some(Command::Lock(output.clone())),
+                ("UnlockOutput", [output]) => Some(Command::Unlock(output.clone())),
+                ("Status", []) => Some(Command::Status { json: true }),
+                _ => None,
+            };
+            match command {
+                Some(command) => {
+                    let status = matches!(command, Command::Status { .. });
+                    let (reply, answer) = mpsc::channel();
+                    if events.send(Event::Control(command, reply)).is_err() {
+                        return false;
+                    }
+                    match answer.recv() {
+                        Ok(Ok(output)) => Ok(status.then_some(output)),
+                        Ok(Err(err)) => Err((FAILED, err)),
+                        Err(_) => return false,
+                    }
+                }
+                None => Err((
+                    UNKNOWN_METHOD,
+                    format!(
+                        "There is no method {}.{}({})",
+                        interface.unwrap_or(INTERFACE),
+                        member,
+                        call.signature
+                    ),
+                )),
+            }
+        };
+
+        if !call.wants_reply() {
+            return true;
+        }
+        let reply = match result {
+            Ok(Some(value)) => call.reply().with_args(&[Arg::Str(value)]),
+            Ok(None) => call.reply(),
+            Err((name, message)) => call.error(name, &message),
+        };
+        self.send(reply).is_ok()
+    }
+}
+
+/// The description of the object at `path`, or of the way down to it from the root, for tools that
+/// look around the bus.
+fn introspect(path: &str) -> String {
+    if path == PATH {
+        return INTROSPECTION.to_string();
+    }
+    let prefix = if path == "/" {
+        path.to_string()
+    } else {
+        format!("{}/", path)
+    };
+    match PATH
+        .strip_prefix(&prefix)
+        .and_then(|rest| rest.split('/').next())
+    {
+        Some(child) => format!("<node>\n  <node name=\"{}\"/>\n</node>\n", child),
+        None => "<node/>\n".to_string(),
+    }
+}
+
+/// `org.wlstreamer.Control`, with the name given with --instance added to it.
+fn bus_name() -> String {
+    let instance = control::instance_name();
+    match instance.strip_prefix("wlstreamer-") {
+        Some(instance) => {
+            let mut element: String = instance
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            // Elements of bus names can't start with a digit
+            if element.starts_with(|c: char| c.is_ascii_digit()) {
+                element.insert(0, '_');
+            }
+            format!("{}.{}", NAME, element)
+        }
+        None => NAME.to_string(),
+    }
+}
+
+/// Decodes the %XX escapes of D-Bus addresses.
+fn unescape(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 3;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    unescaped
+}
+
+/// Connects to the bus in $DBUS_SESSION_BUS_ADDRESS, or to the one systemd starts in the runtime
+/// dir. None if there is no session bus.
+fn connect() -> Result<Option<UnixStream>, String> {
+    let address = env::var("DBUS_SESSION_BUS_ADDRESS").unwrap_or_default();
+    if address.is_empty() {
+        let path = match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => Path::new(&dir).join("bus"),
+            _ => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        return UnixStream::connect(&path).map(Some).map_err(|err| {
+            i18n::tr(
+                "Could not connect to the session bus at {}: {}",
+                &[&path.display(), &err],
+            )
+        });
+    }
+
+    for params in address
+        .split(';')
+        .filter_map(|entry| entry.strip_prefix("unix:"))
+    {
+        for param in params.split(',') {
+            let stream = match param.split_once('=') {
+                Some(("path", path)) => UnixStream::connect(OsStr::from_bytes(&unescape(path))),
+                Some(("abstract", name)) => SocketAddr::from_abstract_name(unescape(name))
+                    .and_then(|name| UnixStream::connect_addr(&name)),
+                _ => continue,
+            };
+            return stream.map(Some).map_err(|err| {
+                i18n::tr(
+                    "Could not connect to the session bus at {}: {}",
+                    &[&address, &err],
+                )
+            });
+        }
+    }
+    Err(i18n::tr(
+        "Could not connect to the session bus at {}, only unix:path and unix:abstract addresses are supported",
+        &[&address]
+    ))
+}
+
+/// Logs in as the user wlstreamer runs as, which the bus checks on the socket.
+fn authenticate(stream: &mut UnixStream) -> Result<(), String> {
+    let uid: String = unsafe { libc::getuid() }
+        .to_string()
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let mut answer = Vec::new();
+    stream
+        .write_all(format!("\0AUTH EXTERNAL {}\r\n", uid).as_bytes())
+        .and_then(|_| {
+            let mut byte = [0u8];
+            while !answer.ends_with(b"\r\n") {
+                stream.read_exact(&mut byte)?;
+                answer.push(byte[0]);
+            }
+            Ok(())
+        })
+        .map_err(|err| i18n::tr("Could not talk to the session bus: {}", &[&err]))?;
+    if !answer.starts_with(b"OK ") {
+        return Err(i18n::tr(
+            "The session bus didn't let wlstreamer in: {}",
+            &[&String::from_utf8_lossy(&answer).trim()],
+        ));
+    }
+
+    stream
+        .write_all(b"BEGIN\r\n")
+        .map_err(|err| i18n::tr("Could not talk to the session bus: {}", &[&err]))
+}
+
+/// Reads the messages from the bus in a background thread, until the connection is gone.
+pub fn incoming(mut reader: UnixStream) -> Receiver<Message> {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        if reader.set_read_timeout(None).is_err() {
+            return;
+        }
+        while let Ok(message) = Message::read(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    messages
+}
+
+/// Takes the name on the session bus and answers calls in a background thread. None if there is
+/// no session bus.
+pub fn serve(events: Sender<Event>) -> Result<Option<Bus>, String> {
+    let (bus, mut reader) = match Bus::open()? {
+        Some(bus) => bus,
+        None => return Ok(None),
+    };
+    let name = bus_name();
+    if !bus.request_name(&mut reader, &name)? {
+        return Err(i18n::tr(
+            "{} is taken on the session bus, is wlstreamer already running?",
+            &[&name],
+        ));
+    }
+
+    let answering = bus.clone();
+    let messages = incoming(reader);
+    thread::spawn(move || {
+        for message in messages {
+            if message.kind == METHOD_CALL && !answering.answer(&message, &events) {
+                break;
+            }
+        }
+    });
+
+    Ok(Some(bus))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: &Message) -> Message {
+        Message::decode(&message.encode()).unwrap()
+    }
+
+    fn reader(data: &[u8]) -> Reader<'_> {
+        Reader {
+            data,
+            pos: 0,
+            big_endian: false,
+            depth: 0,
+        }
+    }
+
+    #[test]
+    fn every_arg_survives_a_round_trip() {
+        let args = vec![
+            Arg::Byte(7),
+            Arg::Bool(true),
+            Arg::I32(-5),
+            Arg::U32(70000),
+            Arg::Str("héllo".to_string()),
+            Arg::Path("/org/wlstreamer/Control".to_string()),
+            Arg::Array(
+                "s".to_string(),
+                vec![Arg::Str("a".to_string()), Arg::Str(String::new())],
+            ),
+            Arg::Array("i".to_string(), Vec::new()),
+            Arg::Struct(vec![Arg::Byte(1), Arg::Str("x".to_string())]),
+            Arg::Array(
+                "{sv}".to_string(),
+                vec![Arg::Entry(
+                    Box::new(Arg::Str("key".to_string())),
+                    Box::new(Arg::Variant(Box::new(Arg::I32(3)))),
+                )],
+            ),
+            Arg::Variant(Box::new(Arg::Struct(vec![Arg::U32(1), Arg::Bool(false)]))),
+        ];
+        let message = Message::signal(PATH, INTERFACE, "Test").with_args(&args);
+
+        let decoded = round_trip(&message);
+        assert_eq!(decoded.signature, "ybiusoasai(ys)a{sv}v");
+        assert_eq!(decoded.args(), args);
+    }
+
+    #[test]
+    fn values_are_aligned() {
+        let body = |args: &[Arg]| Message::new(SIGNAL).with_args(args).body;
+
+        assert_eq!(body(&[Arg::Byte(1), Arg::U32(2)]), [1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(
+            body(&[Arg::Byte(1), Arg::Struct(vec![Arg::Byte(2)])]),
+            [1, 0, 0, 0, 0, 0, 0, 0, 2]
+        );
+        // The length of an array leaves out the padding before its first element
+        assert_eq!(
+            body(&[Arg::Array(
+                "(y)".to_string(),
+                vec![Arg::Struct(vec![Arg::Byte(9)])]
+            )]),
+            [1, 0, 0, 0, 0, 0, 0, 0, 9]
+        );
+
+        // The body starts at a multiple of 8
+        let message = Message::signal(PATH, INTERFACE, "Test").with_args(&[Arg::Byte(1)]);
+        assert_eq!((message.encode().len() - 1) % 8, 0);
+    }
+
+    #[test]
+    fn header_fields_survive_a_round_trip() {
+        let mut call = Message::call(NAME, PATH, INTERFACE, "LockOutput")
+            .with_args(&[Arg::Str("DP-1".to_string())]);
+        call.serial = 42;
+
+        let mut decoded = round_trip(&call);
+        assert_eq!(decoded.kind, METHOD_CALL);
+        assert_eq!(decoded.serial, 42);
+        assert_eq!(decoded.path.as_deref(), Some(PATH));
+        assert_eq!(decoded.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(decoded.member.as_deref(), Some("LockOutput"));
+        assert_eq!(decoded.destination.as_deref(), Some(NAME));
+        assert_eq!(decoded.signature, "s");
+        assert!(decoded.wants_reply());
+
+        decoded.sender = Some(":1.7".to_string());
+        let error = round_trip(&decoded.error(FAILED, "No such output"));
+        assert_eq!(error.kind, ERROR);
+        assert_eq!(error.reply_serial, Some(42));
+        assert_eq!(error.error_name.as_deref(), Some(FAILED));
+        assert_eq!(error.destination.as_deref(), Some(":1.7"));
+        assert_eq!(error.path, None);
+        assert_eq!(error.args(), [Arg::Str("No such output".to_string())]);
+    }
+
+    #[test]
+    fn reads_big_endian_messages() {
+        let mut data = vec![b'B', SIGNAL, 0, 1];
+        data.extend_from_slice(&4u32.to_be_bytes());
+        data.extend_from_slice(&9u32.to_be_bytes());
+        data.extend_from_slice(&23u32.to_be_bytes());
+        // The member, then the signature after padding to 8
+        data.extend_from_slice(&[3, 1, b's', 0]);
+        data.extend_from_slice(&4u32.to_be_bytes());
+        data.extend_from_slice(b"Ping\0");
+        data.extend_from_slice(&[0, 0, 0]);
+        data.extend_from_slice(&[8, 1, b'g', 0, 1, b'i', 0]);
+        data.push(0);
+        data.extend_from_slice(&(-2i32).to_be_bytes());
+
+        let message = Message::decode(&data).unwrap();
+        assert_eq!(message.serial, 9);
+        assert_eq!(message.member.as_deref(), Some("Ping"));
+        assert_eq!(message.args(), [Arg::I32(-2)]);
+    }
+
+    #[test]
+    fn broken_signatures_are_no_args() {
+        let data = [1, 0, 0, 0, 2, 0, 0, 0];
+        for kind in ["(", "{", "()", "(y", "{s)", "a()", "a", ""] {
+            assert_eq!(reader(&data).arg(kind), None, "{}", kind);
+        }
+
+        // A variant in a variant in a variant and so on
+        let mut nested = Message::new(SIGNAL);
+        nested.signature = "v".to_string();
+        nested.body = [1, b'v', 0].repeat(100_000);
+        assert!(nested.args().is_empty());
+    }
+}
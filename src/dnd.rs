@@ -0,0 +1,71 @@
+//! Do-not-disturb for the notification daemons that offer it over their command line clients.
+
+use crate::i18n;
+use std::process::Command;
+
+/// The notification daemon do-not-disturb was turned on in.
+pub enum Dnd {
+    Mako,
+    Dunst,
+    SwayNc,
+}
+
+/// Runs a command and returns its output, if it succeeded. Fails when the daemon isn't running.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Turns on do-not-disturb in the first notification daemon that is running. Returns None if
+/// there is none, or if do-not-disturb was already on and should stay on afterwards.
+pub fn enable() -> Option<Dnd> {
+    let dnd = if let Some(modes) = run("makoctl", &["mode"]) {
+        if modes.lines().any(|mode| mode == "do-not-disturb") {
+            return None;
+        }
+        run("makoctl", &["mode", "-a", "do-not-disturb"]).map(|_| Dnd::Mako)
+    } else if let Some(paused) = run("dunstctl", &["is-paused"]) {
+        if paused == "true" {
+            return None;
+        }
+        run("dunstctl", &["set-paused", "true"]).map(|_| Dnd::Dunst)
+    } else if let Some(enabled) = run("swaync-client", &["--get-dnd"]) {
+        if enabled == "true" {
+            return None;
+        }
+        run("swaync-client", &["--dnd-on"]).map(|_| Dnd::SwayNc)
+    } else {
+        println!(
+            "{}",
+            i18n::tr(
+                "No notification daemon with do-not-disturb found (mako, dunst or swaync)",
+                &[]
+            )
+        );
+        return None;
+    };
+
+    if dnd.is_some() {
+        println!("{}", i18n::tr("Turned on do-not-disturb", &[]));
+    }
+    dnd
+}
+
+impl Dnd {
+    pub fn restore(&self) {
+        let result = match self {
+            Dnd::Mako => run("makoctl", &["mode", "-r", "do-not-disturb"]),
+            Dnd::Dunst => run("dunstctl", &["set-paused", "false"]),
+            Dnd::SwayNc => run("swaync-client", &["--dnd-off"]),
+        };
+
+        match result {
+            Some(_) => println!("{}", i18n::tr("Turned off do-not-disturb", &[])),
+            None => println!("{}", i18n::tr("Could not turn off do-not-disturb", &[])),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! `--events-json`: one JSON object per line for every change in what is being streamed, for
+//! scripts that want to react to wlstreamer without parsing its log. The latest events are also
+//! kept for `wlstreamer tui`, and switches and errors are sent as signals on D-Bus.
+
+use crate::dbus::Bus;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+struct Target {
+    /// None for stdout
+    path: Option<PathBuf>,
+    file: Option<File>,
+}
+
+/// How many events are kept around for `wlstreamer tui`.
+const RECENT: usize = 20;
+
+#[derive(Clone, Default, Debug)]
+pub struct EventLog {
+    target: Option<Arc<Mutex<Target>>>,
+    recent: Arc<Mutex<VecDeque<Value>>>,
+    bus: Option<Bus>,
+}
+
+impl EventLog {
+    /// The latest events, oldest first, whether they are written anywhere or not.
+    pub fn recent(&self) -> Vec<Value> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Logs events to the file or FIFO at `path`, or to stdout for `-`.
+    pub fn open(path: &str) -> EventLog {
+        let path = if path == "-" {
+            None
+        } else {
+            Some(PathBuf::from(path))
+        };
+
+        EventLog {
+            target: Some(Arc::new(Mutex::new(Target { path, file: None }))),
+            recent: Arc::default(),
+            bus: None,
+        }
+    }
+
+    /// Sends the events that have a signal of their own on the session bus too.
+    pub fn signal_on(&mut self, bus: Bus) {
+        self.bus = Some(bus);
+    }
+
+    /// Writes an event with the given fields, adding its name and the current time.
+    ///
+    /// Writing never blocks: while nobody is reading from a FIFO, events are dropped.
+    pub fn emit(&self, event: &str, fields: Value) {
+        let mut line = json!({
+            "event": event,
+            "time": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0),
+        });
+        if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), fields) {
+            line.extend(fields);
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(line.clone());
+        drop(recent);
+        if let Some(bus) = &self.bus {
+            bus.event(event, &line);
+        }
+
+        let target = match &self.target {
+            Some(target) => target,
+            None => return,
+        };
+
+        let mut target = target.lock().unwrap();
+        let path = match &target.path {
+            Some(path) => path.clone(),
+            None => {
+                println!("{}", line);
+                return;
+            }
+        };
+
+        if target.file.is_none() {
+            // Opening a FIFO without a reader fails instead of waiting for one
+            target.file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path)
+                .ok();
+        }
+
+        if let Some(file) = &mut target.file {
+            if writeln!(file, "{}", line).is_err() {
+                // The reader went away, try again with the next event
+                target.file = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! `--mode grid` and `--mode side-by-side`: every output that is shown is recorded into an
+//! intermediate device, and ffmpeg tiles them all into a single stream on the output device.
+
+use crate::color::Color;
+use crate::i18n;
+use crate::mirror::Mirrors;
+use crate::mode::Mode;
+use crate::pipeline::{Invocation, Pipeline, Step, Stream};
+use crate::systemd;
+use crate::{
+    blank_over, get_valid_screens_for_recording, handle_event, stalled, stream_black,
+    update_indicator, Config, Event, Resolution,
+};
+use serde_json::json;
+use std::io::Error;
+use std::sync::mpsc::Receiver;
+use std::time;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+pub fn run(config: &mut Config, events: Receiver<Event>) -> Result<(), Error> {
+    let mut mirrors = Mirrors::new(
+        config.options.devices_from + 1,
+        config.options.side_by_side.clone(),
+    );
+    let mut compositor = Pipeline::default();
+    let mut current = None;
+    let mut rebuild = false;
+
+    'events: loop {
+        mirrors.sync(config)?;
+        let mut devices = mirrors.devices();
+        if config.options.mode == Mode::SideBySide {
+            devices.sort_by_key(|(name, ..)| {
+                config.options.side_by_side.iter().position(|n| n == name)
+            });
+        }
+        let enlarge = config.enlarge_focused() && config.options.mode == Mode::Grid;
+        let focused = if enlarge || config.highlight().is_some() {
+            get_valid_screens_for_recording(config)
+                .into_iter()
+                .find(|w| w.focused)
+                .and_then(|w| devices.iter().position(|(name, ..)| *name == w.output))
+        } else {
+            None
+        };
+        let enlarged = focused.filter(|_| enlarge);
+
+        let state = (
+            devices.clone(),
+            focused,
+            config.highlight().cloned(),
+            config.highlight_width(),
+            config.frame_rate(),
+        );
+        if rebuild || current.as_ref() != Some(&state) {
+            if let Err(err) = compositor.stop() {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Could not stop the grid compositor: {}",
+                        &[&format!("{:?}", err)]
+                    )
+                );
+            }
+
+            config.runtime.stats.restart();
+            compositor = if devices.is_empty() {
+                stream_black(config, None)?
+            } else {
+                let (canvas, tiles) = match config.options.mode {
+                    Mode::SideBySide => side_by_side(&devices),
+                    _ => (
+                        config.runtime.resolutions[0],
+                        layout(devices.len(), enlarged, config.runtime.resolutions[0]),
+                    ),
+                };
+                let highlighted = focused.map(|i| tiles[i]);
+                spawn_compositor(config, &devices, &tiles, canvas, highlighted)?
+            };
+            update_indicator(config, &mirrors.names());
+            if current.is_none() {
+                systemd::ready(mirrors.status().as_str());
+            } else {
+                systemd::status(mirrors.status().as_str());
+            }
+            current = Some(state);
+        }
+
+        // Wait for something that needs the layout to be checked again
+        loop {
+            let event = match systemd::next_event(&events) {
+                Some(event) => event,
+                None => break 'events,
+            };
+            if !matches!(event, Event::Tick) {
+                if !handle_event(config, event) {
+                    break 'events;
+                }
+                rebuild = false;
+                break;
+            }
+
+            let recorders_exited = mirrors.restart_exited(config);
+            rebuild = stalled(config, &mut compositor);
+            if rebuild {
+                println!("{}", i18n::tr("The compositor stalled, rebuilding it", &[]));
+                config
+                    .runtime
+                    .event_log
+                    .emit("restart", json!({ "reason": "stalled" }));
+            }
+            if rebuild || recorders_exited || blank_over(config) {
+                break;
+            }
+        }
+    }
+
+    if let Err(err) = compositor.stop() {
+        println!(
+            "{}",
+            i18n::tr(
+                "Could not stop the grid compositor: {}",
+                &[&format!("{:?}", err)]
+            )
+        );
+    }
+    mirrors.stop();
+
+    Ok(())
+}
+
+/// Splits the canvas into a tile per output, filling rows first. An enlarged output takes up the
+/// left two thirds of the canvas, with the others stacked in the remaining third.
+fn layout(count: usize, enlarged: Option<usize>, canvas: Resolution) -> Vec<Tile> {
+    // yuyv422 needs even widths
+    let even = |n: usize| n & !1;
+
+    match enlarged {
+        Some(enlarged) if count > 1 => {
+            let big_width = even(canvas.width * 2 / 3);
+            let small_height = canvas.height / (count - 1);
+            let mut small = (0..count - 1).map(|i| Tile {
+                x: big_width,
+                y: i * small_height,
+                width: even(canvas.width - big_width),
+                height: small_height,
+            });
+
+            (0..count)
+                .map(|i| {
+                    if i == enlarged {
+                        Tile {
+                            x: 0,
+                            y: 0,
+                            width: big_width,
+                            height: canvas.height,
+                        }
+                    } else {
+                        small.next().unwrap()
+                    }
+                })
+                .collect()
+        }
+        _ => {
+            let columns = (1..).find(|c| c * c >= count).unwrap();
+            let rows = count.div_ceil(columns);
+            let width = even(canvas.width / columns);
+            let height = canvas.height / rows;
+
+            (0..count)
+                .map(|i| Tile {
+                    x: (i % columns) * width,
+                    y: (i / columns) * height,
+                    width,
+                    height,
+                })
+                .collect()
+        }
+    }
+}
+
+/// Puts the outputs next to each other at their own resolution, vertically centered on a canvas
+/// as wide as all of them together.
+fn side_by_side(devices: &[(String, usize, Resolution)]) -> (Resolution, Vec<Tile>) {
+    let canvas = Resolution {
+        width: devices.iter().map(|(_, _, r)| r.width & !1).sum(),
+        height: devices.iter().map(|(_, _, r)| r.height).max().unwrap_or(0),
+    };
+
+    let mut x = 0;
+    let tiles = devices
+        .iter()
+        .map(|(_, _, resolution)| {
+            let tile = Tile {
+                x,
+                y: (canvas.height - resolution.height) / 2,
+                width: resolution.width & !1,
+                height: resolution.height,
+            };
+            x += tile.width;
+            tile
+        })
+        .collect();
+
+    (canvas, tiles)
+}
+
+/// Builds the ffmpeg filter graph that scales every input into its tile on a black canvas of
+/// `fps` frames per second, and draws a border of `thickness` pixels in `color` around the
+/// highlighted tile.
+fn filter_graph(
+    tiles: &[Tile],
+    canvas: Resolution,
+    highlight: Option<(Tile, &str, usize)>,
+    fps: u32,
+    color: &Color,
+) -> String {
+    let mut filters = vec![format!(
+        "color=c=black:s={}x{}:r={}[bg]",
+        canvas.width, canvas.height, fps
+    )];
+    let mut previous = "bg".to_string();
+
+    for (i, tile) in tiles.iter().enumerate() {
+        filters.push(format!(
+            "[{}:v]scale={}:{}:force_original_aspect_ratio=decrease{},pad={}:{}:(ow-iw)/2:(oh-ih)/2,setsar=1[v{}]",
+            i, tile.width, tile.height, color.rescale_options(), tile.width, tile.height, i
+        ));
+
+        let next = if i + 1 == tiles.len() && highlight.is_none() {
+            "out".to_string()
+        } else {
+            format!("o{}", i)
+        };
+        filters.push(format!(
+            "[{}][v{}]overlay={}:{}[{}]",
+            previous, i, tile.x, tile.y, next
+        ));
+        previous = next;
+    }
+
+    if let Some((tile, color, thickness)) = highlight {
+        filters.push(format!(
+            "[{}]drawbox=x={}:y={}:w={}:h={}:color={}:t={}[out]",
+            previous, tile.x, tile.y, tile.width, tile.height, color, thickness
+        ));
+    }
+
+    filters.join(";")
+}
+
+fn spawn_compositor(
+    config: &Config,
+    devices: &[(String, usize, Resolution)],
+    tiles: &[Tile],
+    canvas: Resolution,
+    highlighted: Option<Tile>,
+) -> Result<Pipeline, Error> {
+    if config.options.verbose {
+        for ((name, ..), tile) in devices.iter().zip(tiles.iter()) {
+            println!(
+                "{}",
+                i18n::tr("Placing {} at {}", &[&name, &format!("{:?}", tile)])
+            );
+        }
+    }
+
+    let highlight = match (highlighted, config.highlight()) {
+        (Some(tile), Some(color)) => Some((tile, color.as_str(), config.highlight_width())),
+        _ => None,
+    };
+
+    let mut compositor = Invocation::new(&config.options.ffmpeg_path).progress(true);
+    for (_, device_number, _) in devices {
+        compositor = compositor
+            .args(["-f", "v4l2", "-i"])
+            .arg(format!("/dev/video{}", device_number));
+    }
+
+    let compositor = compositor
+        .args([
+            "-filter_complex",
+            filter_graph(
+                tiles,
+                canvas,
+                highlight,
+                config.frame_rate().unwrap_or(25),
+                &config.options.color,
+            )
+            .as_str(),
+            "-map",
+            "[out]",
+            "-vcodec",
+            "rawvideo",
+            "-pix_fmt",
+            "yuyv422",
+            "-f",
+            "v4l2",
+        ])
+        .args(config.options.color.tag_args())
+        .args(&config.options.ffmpeg_args)
+        .arg(format!("/dev/video{}", config.options.devices_from))
+        .stderr(Stream::verbose(config.options.verbose))
+        // Same as with the upscaler, ffmpeg fails on devices that aren't being written to yet
+        .delay(time::Duration::from_millis(100));
+
+    let mut pipeline = Pipeline::new(config, vec![Step::Process(compositor)]);
+    pipeline.start()?;
+    Ok(pipeline)
+}
@@ -0,0 +1,275 @@
+//! `--mode headless`: sway creates a headless output at the combined resolution, and wl-mirror
+//! shows the focused screen on it, scaled by the compositor. Only the headless output is
+//! captured, so switching screens just tells wl-mirror to show another one, and the capture
+//! never restarts. The black screen is streamed the same way as in follow mode.
+
+use crate::i18n;
+use crate::pipeline::Pipeline;
+use crate::state::{StreamerState, Target};
+use crate::systemd;
+use crate::{
+    blank_over, countdown_over, emit_switch, get_output, get_outputs, get_workspaces, handle_event,
+    recording_status, stalled, stream_black, stream_countdown, update_indicator, wanted_targets,
+    Config, Event,
+};
+use serde_json::{json, Value};
+use std::io::{Error, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::Receiver;
+
+/// Runs a sway command through the socket, failing if sway refuses it.
+fn swaymsg(socket: Option<&str>, command: &str) -> Result<(), String> {
+    let mut swaymsg = Command::new("swaymsg");
+    if let Some(socket) = socket {
+        swaymsg.arg(format!("--socket={}", socket));
+    }
+    let output = swaymsg
+        .arg(command)
+        .output()
+        .map_err(|err| i18n::tr("Could not run swaymsg: {}", &[&err]))?;
+
+    let reply: Value = serde_json::from_slice(&output.stdout).unwrap_or(Value::Null);
+    match reply[0]["success"].as_bool() {
+        Some(true) => Ok(()),
+        _ => Err(i18n::tr(
+            "sway refused {}: {}",
+            &[&command, &reply[0]["error"].as_str().unwrap_or("no reply")],
+        )),
+    }
+}
+
+/// The headless output and the wl-mirror showing a screen on it. Both go away when dropped.
+struct Mirror {
+    name: String,
+    socket: Option<String>,
+    wl_mirror: Option<(Child, ChildStdin)>,
+}
+
+impl Mirror {
+    /// Creates a headless output at the combined resolution, to the right of all other outputs
+    /// so it doesn't get in the way of the mouse.
+    fn create(config: &Config) -> Result<Mirror, String> {
+        let socket = config.options.ipc_socket.clone();
+        let before = get_outputs(config);
+        swaymsg(socket.as_deref(), "create_output")?;
+        config.runtime.layout.invalidate();
+        let name = get_outputs(config)
+            .into_iter()
+            .map(|o| o.name)
+            .find(|name| before.iter().all(|o| o.name != *name))
+            .ok_or("sway did not create a headless output")?;
+        let mirror = Mirror {
+            name,
+            socket,
+            wl_mirror: None,
+        };
+
+        let right = before
+            .iter()
+            .map(|o| o.rect.x + o.rect.width as i64)
+            .max()
+            .unwrap_or(0);
+        let resolution = config.runtime.resolutions[0];
+        swaymsg(
+            mirror.socket.as_deref(),
+            format!(
+                "output {} mode {}x{} position {} 0",
+                mirror.name, resolution.width, resolution.height, right
+            )
+            .as_str(),
+        )?;
+        config.runtime.layout.invalidate();
+        println!("{}", i18n::tr("Mirroring screens onto {}", &[&mirror.name]));
+
+        Ok(mirror)
+    }
+
+    /// Shows the output on the headless output, starting wl-mirror if it isn't running.
+    fn show(&mut self, config: &Config, output: &str) -> Result<(), Error> {
+        if let Some((_, stdin)) = self.wl_mirror.as_mut() {
+            if writeln!(stdin, "{}", output).is_ok() {
+                return Ok(());
+            }
+            // wl-mirror is gone, start it again
+            self.stop_wl_mirror();
+        }
+
+        let mut child = Command::new("wl-mirror")
+            .args([
+                "--fullscreen-output",
+                self.name.as_str(),
+                "--scaling",
+                "fit",
+            ])
+            .arg("--stream")
+            .arg(output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(if config.options.verbose {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            })
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        self.wl_mirror = Some((child, stdin));
+        Ok(())
+    }
+
+    /// Whether wl-mirror was started and stopped on its own.
+    fn exited(&mut self) -> bool {
+        match self.wl_mirror.as_mut() {
+            Some((child, _)) => !matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    fn stop_wl_mirror(&mut self) {
+        if let Some((mut child, _)) = self.wl_mirror.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Mirror {
+    fn drop(&mut self) {
+        self.stop_wl_mirror();
+        println!("{}", i18n::tr("Removing {}", &[&self.name]));
+        let unplug = format!("output {} unplug", self.name);
+        if let Err(err) = swaymsg(self.socket.as_deref(), unplug.as_str()) {
+            println!("{}", err);
+        }
+    }
+}
+
+/// Streams `target`, through the headless output for screens. The capture of the headless output
+/// is only started when coming from the black screen.
+fn switch(
+    config: &mut Config,
+    mirror: &mut Mirror,
+    pipeline: &mut Pipeline,
+    target: Target,
+) -> Result<(), Error> {
+    let capturing = config.runtime.streamer.output() != "";
+    config.runtime.streamer.begin(target.clone());
+
+    let result = match &target {
+        Target::Output(name) => mirror.show(config, name).and_then(|()| {
+            if capturing {
+                return Ok(());
+            }
+            let output = get_output(config, &mirror.name).map_err(Error::other)?;
+            if let Err(err) = pipeline.stop() {
+                println!(
+                    "{}",
+                    i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+                );
+            }
+            config.runtime.stats.restart();
+            *pipeline = Pipeline::capture(output)
+                .sink(config.options.devices_from)
+                .build(config);
+            pipeline.start()
+        }),
+        Target::Black(_) | Target::Countdown => {
+            mirror.stop_wl_mirror();
+            if let Err(err) = pipeline.stop() {
+                println!(
+                    "{}",
+                    i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+                );
+            }
+            config.runtime.stats.restart();
+            match &target {
+                Target::Black(text) => stream_black(config, text.as_deref()),
+                _ => stream_countdown(config),
+            }
+            .map(|black| *pipeline = black)
+        }
+    };
+
+    match result {
+        Ok(()) => config.runtime.streamer.finish(),
+        Err(ref err) => config.runtime.streamer.fail(err.to_string()),
+    }
+    result
+}
+
+pub fn run(config: &mut Config, events: Receiver<Event>) -> Result<(), Error> {
+    let mut mirror = Mirror::create(config).map_err(Error::other)?;
+    // The workspace on the headless output is never streamed itself
+    config.runtime.excluded.push(mirror.name.clone());
+    let mut pipeline = Pipeline::default();
+
+    let target = wanted_targets(config).remove(0);
+    switch(config, &mut mirror, &mut pipeline, target)?;
+    update_indicator(config, &[config.runtime.streamer.output().to_string()]);
+    emit_switch(config);
+    systemd::ready(recording_status(config).as_str());
+
+    while let Some(event) = systemd::next_event(&events) {
+        let tick = matches!(event, Event::Tick);
+        if !handle_event(config, event) {
+            break;
+        }
+        let failed = matches!(config.runtime.streamer, StreamerState::Failed { .. });
+        let exited = tick && mirror.exited();
+        let restart = tick && (failed || exited || stalled(config, &mut pipeline));
+        let live = tick && countdown_over(config);
+        let resumed = tick && blank_over(config);
+        if tick && !restart && !live && !resumed {
+            continue;
+        }
+
+        // Focusing the headless output, for example by moving the mouse onto it, keeps the
+        // screen that was shown
+        let on_mirror = get_workspaces(config)
+            .iter()
+            .any(|w| w.focused && w.output == mirror.name);
+        let target = wanted_targets(config).remove(0);
+        if !restart && (on_mirror || !config.runtime.streamer.needs_switch(&target)) {
+            continue;
+        }
+
+        if restart {
+            println!("{}", i18n::tr("Mirroring stopped, starting it again", &[]));
+            let reason = if exited { "exited" } else { "stalled" };
+            config
+                .runtime
+                .event_log
+                .emit("restart", json!({ "reason": reason }));
+            mirror.stop_wl_mirror();
+            if let Err(err) = pipeline.stop() {
+                println!(
+                    "{}",
+                    i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+                );
+            }
+            config.runtime.streamer = Default::default();
+        }
+        if let Err(err) = switch(config, &mut mirror, &mut pipeline, target) {
+            println!("{}", i18n::tr("Could not start mirroring: {}", &[&err]));
+            config
+                .runtime
+                .event_log
+                .emit("error", json!({ "message": format!("{:?}", err) }));
+            systemd::status(recording_status(config).as_str());
+            continue;
+        }
+
+        println!("{}", recording_status(config));
+        update_indicator(config, &[config.runtime.streamer.output().to_string()]);
+        emit_switch(config);
+        systemd::status(recording_status(config).as_str());
+    }
+
+    if let Err(err) = pipeline.stop() {
+        println!(
+            "{}",
+            i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Backing off from targets whose pipeline keeps failing, for example because of a broken driver.
+//! Every failure in a row doubles how long a target is left alone, from 2 seconds up to 5 minutes.
+//! Meanwhile outputs are skipped in favour of the next target, in the end the black screen, and a
+//! black screen that failed isn't tried again before its time is up. A failure long after the
+//! last backoff ended starts over at 2 seconds.
+
+use crate::state::Target;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const FIRST_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How long a target has to keep running after a backoff for its failures to be forgotten.
+const FORGET_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Failures {
+    /// Failures in a row
+    count: u32,
+    /// When the target may be tried again
+    until: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct Health {
+    failures: HashMap<Target, Failures>,
+    /// How many targets were left alone at the last check
+    unhealthy: usize,
+}
+
+/// The black screen fails the same whatever is written on it.
+fn key(target: &Target) -> Target {
+    match target {
+        Target::Black(_) => Target::Black(None),
+        target => target.clone(),
+    }
+}
+
+impl Health {
+    /// Records that the pipeline of the target failed, and returns how long it is left alone.
+    pub fn failed(&mut self, target: &Target) -> Duration {
+        let now = Instant::now();
+        let failures = self.failures.entry(key(target)).or_insert(Failures {
+            count: 0,
+            until: now,
+        });
+        if now.saturating_duration_since(failures.until) > FORGET_AFTER {
+            failures.count = 0;
+        }
+
+        let backoff = FIRST_BACKOFF
+            .saturating_mul(1 << failures.count.min(16))
+            .min(MAX_BACKOFF);
+        failures.count += 1;
+        failures.until = now + backoff;
+        self.unhealthy = self.unhealthy_count();
+        backoff
+    }
+
+    /// How much longer the target is left alone, if it is.
+    pub fn backoff(&self, target: &Target) -> Option<Duration> {
+        let failures = self.failures.get(&key(target))?;
+        failures
+            .until
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn healthy(&self, target: &Target) -> bool {
+        self.backoff(target).is_none()
+    }
+
+    /// The outputs that are left alone, with how much longer, in order of name.
+    pub fn unhealthy_outputs(&self) -> Vec<(String, Duration)> {
+        let mut outputs: Vec<(String, Duration)> = self
+            .failures
+            .keys()
+            .filter_map(|target| match target {
+                Target::Output(name) => Some((name.clone(), self.backoff(target)?)),
+                Target::Black(_) | Target::Countdown => None,
+            })
+            .collect();
+        outputs.sort();
+        outputs
+    }
+
+    fn unhealthy_count(&self) -> usize {
+        self.failures
+            .keys()
+            .filter(|target| !self.healthy(target))
+            .count()
+    }
+
+    /// Whether the time of a target that was left alone ran out since the last call, so what to
+    /// stream has to be looked at again.
+    pub fn recovered(&mut self) -> bool {
+        let unhealthy = self.unhealthy_count();
+        let recovered = unhealthy < self.unhealthy;
+        self.unhealthy = unhealthy;
+        recovered
+    }
+}
@@ -0,0 +1,129 @@
+//! `--serve-hls`: writes the stream as HLS into a directory, and optionally serves that directory
+//! over HTTP, so the stream can be watched in a browser on this computer or, given an address to
+//! listen on, on the same network.
+
+use crate::{control, i18n, runtime_dir};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// The playlist ffmpeg writes into the directory.
+pub const PLAYLIST: &str = "stream.m3u8";
+
+/// Plays the playlist in browsers that play HLS themselves, and links to it everywhere else. Nothing
+/// is loaded from elsewhere, so it works without internet access.
+const INDEX: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>wlstreamer</title>
+<style>html, body { margin: 0; height: 100%; background: black; } video { width: 100%; height: 100%; }</style>
+</head>
+<body>
+<video id="video" autoplay muted controls></video>
+<p id="unsupported" hidden style="color: white; font-family: sans-serif; text-align: center">
+This browser can't play HLS on its own, open <a href="stream.m3u8" style="color: white">the playlist</a> in a player like mpv or VLC instead.
+</p>
+<script>
+const video = document.getElementById("video");
+if (video.canPlayType("application/vnd.apple.mpegurl")) {
+  video.src = "stream.m3u8";
+} else {
+  video.hidden = true;
+  document.getElementById("unsupported").hidden = false;
+}
+</script>
+</body>
+</html>
+"#;
+
+/// Where to write the stream for a `--serve-hls` value, which is either a directory, or an address
+/// to serve a directory in the runtime dir on. A port on its own is only served to this computer.
+/// Creates the directory with a page playing the stream.
+pub fn prepare(target: &str) -> Result<(PathBuf, Option<SocketAddr>), String> {
+    let address = match target.parse::<u16>() {
+        Ok(port) => Some(SocketAddr::from(([127, 0, 0, 1], port))),
+        Err(_) => target.parse::<SocketAddr>().ok(),
+    };
+    let dir = match address {
+        Some(_) => runtime_dir::join(&format!("{}-hls", control::instance_name()))?,
+        None => PathBuf::from(target),
+    };
+
+    fs::create_dir_all(&dir)
+        .map_err(|err| i18n::tr("Could not create {}: {}", &[&dir.display(), &err]))?;
+    let index = dir.join("index.html");
+    fs::write(&index, INDEX)
+        .map_err(|err| i18n::tr("Could not write {}: {}", &[&index.display(), &err]))?;
+
+    Ok((dir, address))
+}
+
+fn content_type(name: &str) -> &'static str {
+    match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        Some("mpd") => "application/dash+xml",
+        Some("m4s") => "video/iso.segment",
+        Some("mp4") => "video/mp4",
+        Some("html") => "text/html; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Answers a single GET request with a file from the directory. Only the files directly in it are
+/// served.
+fn respond(dir: &Path, mut stream: TcpStream) {
+    let mut line = String::new();
+    if BufReader::new(&stream).read_line(&mut line).is_err() {
+        return;
+    }
+
+    let path = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["GET", path, ..] => path.split('?').next().unwrap_or_default().to_string(),
+        _ => {
+            let _ = stream.write_all(b"HTTP/1.0 405 Method Not Allowed\r\n\r\n");
+            return;
+        }
+    };
+    let name = match path.trim_start_matches('/') {
+        "" => "index.html",
+        name => name,
+    };
+    if name.contains('/') || name.starts_with('.') {
+        let _ = stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n");
+        return;
+    }
+
+    match fs::read(dir.join(name)) {
+        Ok(body) => {
+            let header = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\n\r\n",
+                content_type(name),
+                body.len()
+            );
+            let _ = stream.write_all(header.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+        Err(_) => {
+            let _ = stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n");
+        }
+    }
+}
+
+/// Serves the directory over HTTP at the address in a background thread.
+pub fn serve(dir: PathBuf, address: SocketAddr) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| i18n::tr("Could not listen on {}: {}", &[&address, &err]))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let dir = dir.clone();
+            thread::spawn(move || respond(&dir, stream));
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,366 @@
+//! Translations of what wlstreamer tells the user while it runs, picked by `LC_ALL`,
+//! `LC_MESSAGES` or `LANG` like other programs do. Messages are looked up by their English text,
+//! which is also what is printed when there is no translation, so a new message only needs an
+//! entry in the catalogue of each language. Only the beginning of the help is translated so far,
+//! the descriptions of the options are in English.
+
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Locale {
+    En,
+    De,
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// The language of the first of the variables that is set, as in `de_DE.UTF-8`.
+fn locale() -> Locale {
+    *LOCALE.get_or_init(|| {
+        let name = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        if name.starts_with("de") {
+            Locale::De
+        } else {
+            Locale::En
+        }
+    })
+}
+
+fn german(message: &str) -> Option<&'static str> {
+    Some(match message {
+        "Usage: {}" => "Aufruf: {}",
+        "wlstreamer [options]" => "wlstreamer [Optionen]",
+        "Wrapper around wf-recorder and ffmpeg that automatically switches the screen being recorded based on current window focus" => "Hülle um wf-recorder und ffmpeg, die den aufgenommenen Bildschirm automatisch nach dem Fokus der Fenster wechselt",
+        "Options:" => "Optionen (bisher nur auf Englisch beschrieben):",
+        "Unknown option: {}" => "Unbekannte Option: {}",
+        "Unknown mode: {}" => "Unbekannter Modus: {}",
+        "Unknown backend: {}" => "Unbekanntes Backend: {}",
+        "Unknown codec: {}" => "Unbekannter Codec: {}",
+        "Unknown quality: {}" => "Unbekannte Qualität: {}",
+        "Unknown capture protocol: {}" => "Unbekanntes Aufnahmeprotokoll: {}",
+        "Unknown color range: {}" => "Unbekannter Farbbereich: {}",
+        "Unknown colorspace: {}" => "Unbekannter Farbraum: {}",
+        "Unknown tonemap algorithm: {}" => "Unbekanntes Tone-Mapping-Verfahren: {}",
+        "Unknown mode in config file: {}" => "Unbekannter Modus in der Konfigurationsdatei: {}",
+        "Unknown backend in config file: {}" => "Unbekanntes Backend in der Konfigurationsdatei: {}",
+        "Unknown codec in config file: {}" => "Unbekannter Codec in der Konfigurationsdatei: {}",
+        "Unknown capture protocol in config file: {}" => "Unbekanntes Aufnahmeprotokoll in der Konfigurationsdatei: {}",
+        "Unknown color range in config file: {}" => "Unbekannter Farbbereich in der Konfigurationsdatei: {}",
+        "Unknown colorspace in config file: {}" => "Unbekannter Farbraum in der Konfigurationsdatei: {}",
+        "Unknown tonemap algorithm in config file: {}" => "Unbekanntes Tone-Mapping-Verfahren in der Konfigurationsdatei: {}",
+        "Invalid frame rate for {}: {}" => "Ungültige Bildrate für {}: {}",
+        "Invalid frame rate for {} in config file: 0" => "Ungültige Bildrate für {} in der Konfigurationsdatei: 0",
+        "Invalid --battery-threshold {}, it should be a percentage from 0 to 100" => "Ungültiger --battery-threshold {}, er sollte ein Prozentwert von 0 bis 100 sein",
+        "Invalid battery-threshold {} in config file, it should be a percentage from 0 to 100" => "Ungültiger battery-threshold {} in der Konfigurationsdatei, er sollte ein Prozentwert von 0 bis 100 sein",
+        "User is idle" => "Keine Eingaben mehr, der Benutzer ist abwesend",
+        "User is active" => "Der Benutzer ist wieder aktiv",
+        "Streaming in {} mode" => "Übertragung im Modus {}",
+        "Following focus in {}" => "Folge dem Fokus in {}",
+        "No enabled outputs yet, waiting for the compositor to set them up" => "Noch keine aktiven Bildschirme, warte darauf, dass der Compositor sie einrichtet",
+        "Sinks need a single stream, they don't work in mirror-all mode" => "Senken brauchen einen einzelnen Stream und funktionieren nicht im Modus mirror-all",
+        "Delaying the audio by {}ms to match the screens" => "Der Ton wird um {}ms verzögert, passend zu den Bildschirmen",
+        "Recording {}" => "Aufnahme von {}",
+        "Focus switched event" => "Der Fokus hat gewechselt",
+        "Screen is the same, no need to switch" => "Der Bildschirm ist derselbe, kein Wechsel nötig",
+        "Could not start the pipeline: {}" => "Die Pipeline konnte nicht gestartet werden: {}",
+        "Retrying to start the pipeline" => "Neuer Versuch, die Pipeline zu starten",
+        "Trying the screens that were left alone again" => "Die zurückgestellten Bildschirme werden erneut versucht",
+        "Following the focus again" => "Folge wieder dem Fokus",
+        "Blanking the stream" => "Der Stream wird ausgeblendet",
+        "Blanking the stream for {}s" => "Der Stream wird für {}s ausgeblendet",
+        "Going live in {}s" => "Live in {}s",
+        "Going live" => "Jetzt live",
+        "Resuming the stream" => "Der Stream wird fortgesetzt",
+        "Locked {}" => "{} gesperrt",
+        "Unlocked {}" => "{} entsperrt",
+        "Switched to profile {}" => "Zum Profil {} gewechselt",
+        "Running on battery, capturing at a lower frame rate" => "Akkubetrieb, Aufnahme mit niedrigerer Bildrate",
+        "The schedule starts, streaming the screens" => "Der Zeitplan beginnt, die Bildschirme werden übertragen",
+        "The schedule is over, streaming a black screen" => "Der Zeitplan ist vorbei, ein schwarzes Bild wird übertragen",
+        "Outside of the schedule, streaming a black screen until it starts" => "Außerhalb des Zeitplans, bis zu seinem Beginn wird ein schwarzes Bild übertragen",
+        "A call started, streaming the screens" => "Ein Anruf hat begonnen, die Bildschirme werden übertragen",
+        "No call app is open, streaming a black screen" => "Keine Anruf-App ist geöffnet, ein schwarzes Bild wird übertragen",
+        "A window to redact is visible on {}, hiding it" => "Auf {} ist ein zu verbergendes Fenster zu sehen, der Bildschirm wird ausgeblendet",
+        "Nothing to redact on {} anymore" => "Auf {} ist nichts mehr zu verbergen",
+        "{} asks for attention on {}, streaming it for {}s" => "{} bittet auf {} um Aufmerksamkeit, der Bildschirm wird {}s lang übertragen",
+        "Saved a screenshot to {}" => "Bildschirmfoto unter {} gespeichert",
+        "Saved the replay to {}" => "Wiederholung unter {} gespeichert",
+        "Sending the stream to {}" => "Der Stream wird an {} gesendet",
+        "Sending to {} stopped, restarting it" => "Das Senden an {} hat aufgehört, es wird neu gestartet",
+        "Could not send the stream to {}: {}" => "Der Stream konnte nicht an {} gesendet werden: {}",
+        "Sending to {} at {} now" => "An {} wird jetzt mit {} gesendet",
+        "Going on in {}" => "Weiter geht es in {}",
+        "Windows to mask moved, hiding the screen until they are covered again" => "Zu verdeckende Fenster haben sich bewegt, der Bildschirm wird versteckt, bis sie wieder verdeckt sind",
+        "Covering the windows to mask at their new places" => "Die zu verdeckenden Fenster werden an ihrer neuen Stelle verdeckt",
+        "Recording a segment to {}" => "Ein Abschnitt wird nach {} aufgenommen",
+        "Wrote the timeline to {}" => "Zeitleiste nach {} geschrieben",
+        "Could not run {}: {}" => "{} konnte nicht ausgeführt werden: {}",
+        "There is no screen to benchmark" => "Es gibt keinen Bildschirm zum Messen",
+        "Benchmarking {} at {}x{}, {} seconds for every configuration. Screens are captured without damage tracking, so move something around to see the full frame rate." => "{} wird bei {}x{} gemessen, {} Sekunden für jede Konfiguration. Die Bildschirme werden ohne Damage-Tracking aufgenommen, bewege also etwas, um die volle Bildrate zu sehen.",
+        "Capture:" => "Aufnahme:",
+        "Upscaler, {}x{} to {}x{}:" => "Hochskalierer, {}x{} auf {}x{}:",
+        "Recommendations:" => "Empfehlungen:",
+        "  Use --gpu-scale, scaling on the GPU is the cheapest way to capture here" => "  Nutze --gpu-scale, Skalieren auf der GPU ist hier die günstigste Art aufzunehmen",
+        "  ext-image-copy-capture captures more efficiently than wf-recorder here, use --capture-protocol ext-image-copy-capture" => "  ext-image-copy-capture nimmt hier effizienter auf als wf-recorder, nutze --capture-protocol ext-image-copy-capture",
+        "  The default pipeline ({}) works best" => "  Die Standard-Pipeline ({}) funktioniert am besten",
+        "  No capture configuration worked" => "  Keine Aufnahmekonfiguration hat funktioniert",
+        "  The default scaler keeps up with {} Hz easily" => "  Der Standard-Skalierer hält mit {} Hz leicht mit",
+        "  Scale with --ffmpeg-arg -sws_flags --ffmpeg-arg {} to keep up with {} Hz" => "  Skaliere mit --ffmpeg-arg -sws_flags --ffmpeg-arg {}, um mit {} Hz mitzuhalten",
+        "  No scaler keeps up with {} Hz, use --gpu-scale or outputs of the same resolution to skip the upscaler" => "  Kein Skalierer hält mit {} Hz mit, nutze --gpu-scale oder Ausgänge mit derselben Auflösung, um den Hochskalierer zu umgehen",
+        "  All screens have the same resolution, so the upscaler isn't used" => "  Alle Bildschirme haben dieselbe Auflösung, der Hochskalierer wird also nicht genutzt",
+        "Invalid bitrate: {}" => "Ungültige Bitrate: {}",
+        "--min-bitrate has to be lower than --bitrate" => "--min-bitrate muss niedriger als --bitrate sein",
+        "Could not run wayland-info, assuming wlr-screencopy is available" => "wayland-info konnte nicht ausgeführt werden, wlr-screencopy wird als verfügbar angenommen",
+        "Found capture protocols:" => "Gefundene Aufnahmeprotokolle:",
+        "{} is not a DRM device" => "{} ist kein DRM-Gerät",
+        "Could not access {}: {}" => "Auf {} konnte nicht zugegriffen werden: {}",
+        "Could not write {}: {}" => "{} konnte nicht geschrieben werden: {}",
+        "Usage: wlstreamer completions <bash|zsh|fish>" => "Aufruf: wlstreamer completions <bash|zsh|fish>",
+        "Streaming at {} instead of {} frames per second, which is the most video call apps take" => "Übertragung mit {} statt {} Bildern pro Sekunde, mehr nehmen Videoanruf-Apps nicht an",
+        "Writing limited range, since video call apps show full range washed out" => "Es wird eingeschränkter Farbbereich geschrieben, da Videoanruf-Apps den vollen Bereich blass zeigen",
+        "Writing BT.601, since video call apps take the colors of webcams as BT.601" => "Es wird BT.601 geschrieben, da Videoanruf-Apps die Farben von Webcams als BT.601 lesen",
+        "Could not open {}: {}" => "{} konnte nicht geöffnet werden: {}",
+        "{} can be written to and read from at the same time, which Chrome, Zoom and Teams take as not being a camera, so they won't list it. Load v4l2loopback with exclusive_caps=1 while nothing uses the devices:" => "{} kann gleichzeitig beschrieben und gelesen werden, was Chrome, Zoom und Teams nicht für eine Kamera halten, daher zeigen sie es nicht an. Lade v4l2loopback mit exclusive_caps=1, während nichts die Geräte nutzt:",
+        "To keep it, put the options into /etc/modprobe.d/v4l2loopback.conf. Apps that still don't list the device were started before it, and need to be restarted." => "Damit es so bleibt, trage die Optionen in /etc/modprobe.d/v4l2loopback.conf ein. Apps, die das Gerät immer noch nicht anzeigen, wurden davor gestartet und müssen neu gestartet werden.",
+        "Could not read {}: {}" => "{} konnte nicht gelesen werden: {}",
+        "Invalid {}: {}" => "Ungültiges {}: {}",
+        "profile needs a name" => "profile braucht einen Namen",
+        "blank takes a number of seconds, not {}" => "blank nimmt eine Anzahl Sekunden, nicht {}",
+        "screenshot needs a file" => "screenshot braucht eine Datei",
+        "no command given" => "kein Befehl angegeben",
+        "unknown command: {}" => "unbekannter Befehl: {}",
+        "{} is in use, is wlstreamer already running?" => "{} wird benutzt, läuft wlstreamer schon?",
+        "Could not remove {}: {}" => "{} konnte nicht entfernt werden: {}",
+        "Could not listen on {}: {}" => "Auf {} konnte nicht gelauscht werden: {}",
+        "wlstreamer is shutting down" => "wlstreamer wird beendet",
+        "Could not connect to {}, is wlstreamer running? {}" => "Keine Verbindung zu {}, läuft wlstreamer? {}",
+        "Could not talk to wlstreamer: {}" => "Keine Verständigung mit wlstreamer möglich: {}",
+        "Invalid status: {}" => "Ungültiger Status: {}",
+        "Could not talk to the session bus: {}" => "Keine Verständigung mit dem Session-Bus möglich: {}",
+        "The session bus refused {}: {}" => "Der Session-Bus hat {} abgelehnt: {}",
+        "Could not connect to the session bus at {}: {}" => "Keine Verbindung zum Session-Bus unter {}: {}",
+        "Could not connect to the session bus at {}, only unix:path and unix:abstract addresses are supported" => "Keine Verbindung zum Session-Bus unter {}, nur unix:path- und unix:abstract-Adressen werden unterstützt",
+        "The session bus didn't let wlstreamer in: {}" => "Der Session-Bus hat wlstreamer nicht hereingelassen: {}",
+        "{} is taken on the session bus, is wlstreamer already running?" => "{} ist auf dem Session-Bus schon vergeben, läuft wlstreamer schon?",
+        "No notification daemon with do-not-disturb found (mako, dunst or swaync)" => "Kein Benachrichtigungsdienst mit Nicht-stören-Modus gefunden (mako, dunst oder swaync)",
+        "Turned on do-not-disturb" => "Nicht stören eingeschaltet",
+        "Turned off do-not-disturb" => "Nicht stören ausgeschaltet",
+        "Could not turn off do-not-disturb" => "Nicht stören konnte nicht ausgeschaltet werden",
+        "Could not stop the grid compositor: {}" => "Der Raster-Compositor konnte nicht beendet werden: {}",
+        "The compositor stalled, rebuilding it" => "Der Compositor hängt, er wird neu aufgebaut",
+        "Placing {} at {}" => "{} wird bei {} platziert",
+        "Could not run swaymsg: {}" => "swaymsg konnte nicht ausgeführt werden: {}",
+        "sway refused {}: {}" => "sway hat {} abgelehnt: {}",
+        "Mirroring screens onto {}" => "Die Bildschirme werden auf {} gespiegelt",
+        "Removing {}" => "{} wird entfernt",
+        "Could not stop recorder: {}" => "Die Aufnahme konnte nicht beendet werden: {}",
+        "Mirroring stopped, starting it again" => "Das Spiegeln hat aufgehört, es wird neu gestartet",
+        "Could not start mirroring: {}" => "Das Spiegeln konnte nicht gestartet werden: {}",
+        "Could not create {}: {}" => "{} konnte nicht angelegt werden: {}",
+        "Could not connect to the Wayland display: {}" => "Keine Verbindung zum Wayland-Display: {}",
+        "Could not list Wayland globals: {}" => "Die Wayland-Globals konnten nicht aufgelistet werden: {}",
+        "The compositor does not support ext-idle-notify" => "Der Compositor unterstützt ext-idle-notify nicht",
+        "The compositor has no seat to watch for input" => "Der Compositor hat keinen Seat, dessen Eingaben beobachtet werden könnten",
+        "Lost connection to the Wayland display: {}" => "Die Verbindung zum Wayland-Display ist verloren: {}",
+        "The compositor offers none of the frame formats wlstreamer reads, only {}" => "Der Compositor bietet keines der Bildformate, die wlstreamer liest, nur {}",
+        "Could not create a frame buffer: {}" => "Es konnte kein Bildpuffer angelegt werden: {}",
+        "Could not size the frame buffer: {}" => "Die Größe des Bildpuffers konnte nicht gesetzt werden: {}",
+        "Could not map the frame buffer: {}" => "Der Bildpuffer konnte nicht eingeblendet werden: {}",
+        "Invalid frame rate {}" => "Ungültige Bildrate {}",
+        "Could not read the Wayland outputs: {}" => "Die Wayland-Ausgänge konnten nicht gelesen werden: {}",
+        "Could not find output {}" => "Ausgang {} wurde nicht gefunden",
+        "Stopped capturing {}" => "Die Aufnahme von {} hat aufgehört",
+        "Could not update the indicator: {}" => "Die Anzeige konnte nicht aktualisiert werden: {}",
+        "Could not write the indicator: {}" => "Die Anzeige konnte nicht geschrieben werden: {}",
+        "The compositor has no wl_compositor" => "Der Compositor hat kein wl_compositor",
+        "The compositor does not support wlr-layer-shell" => "Der Compositor unterstützt wlr-layer-shell nicht",
+        "The compositor has no wl_shm" => "Der Compositor hat kein wl_shm",
+        "Could not read the Wayland state: {}" => "Der Wayland-Zustand konnte nicht gelesen werden: {}",
+        "Could not find {} to measure latency on" => "{} zum Messen der Latenz wurde nicht gefunden",
+        "Could not show the latency stamp: {}" => "Die Latenzmarke konnte nicht angezeigt werden: {}",
+        "Could not read back /dev/video{}: {}" => "/dev/video{} konnte nicht zurückgelesen werden: {}",
+        "Latency: {}" => "Latenz: {}",
+        "Could not create the latency stamp: {}" => "Die Latenzmarke konnte nicht angelegt werden: {}",
+        "Could not ask {} what it can do: {}" => "{} konnte nicht nach seinen Fähigkeiten gefragt werden: {}",
+        "{} is not a video device" => "{} ist kein Videogerät",
+        "{} does not exist, load v4l2loopback with enough devices, for example modprobe v4l2loopback devices={}" => "{} existiert nicht, lade v4l2loopback mit genug Geräten, zum Beispiel modprobe v4l2loopback devices={}",
+        "Could not open {}: {}. Is your user in the video group?" => "{} konnte nicht geöffnet werden: {}. Ist dein Benutzer in der Gruppe video?",
+        "{} is not a v4l2loopback device but {} ({}), pick other devices with --devices-from" => "{} ist kein v4l2loopback-Gerät, sondern {} ({}), wähle andere Geräte mit --devices-from",
+        "{} is busy, another program writes to it. Stop it, or use other devices with --devices-from" => "{} ist belegt, ein anderes Programm schreibt darauf. Beende es oder nutze andere Geräte mit --devices-from",
+        "{} is already in use with {} frames, but wlstreamer writes {}. Stop whatever else writes to it, or use other devices with --devices-from" => "{} wird schon mit {}-Bildern benutzt, aber wlstreamer schreibt {}. Beende, was sonst darauf schreibt, oder nutze andere Geräte mit --devices-from",
+        "{} is already in use at {}x{}, but needs to be {}x{}. Stop whatever else writes to it, or use other devices with --devices-from" => "{} wird schon mit {}x{} benutzt, muss aber {}x{} haben. Beende, was sonst darauf schreibt, oder nutze andere Geräte mit --devices-from",
+        "Could not find {}: {}" => "{} wurde nicht gefunden: {}",
+        "{} is not a video device like /dev/video10" => "{} ist kein Videogerät wie /dev/video10",
+        "Could not run v4l2-ctl: {}" => "v4l2-ctl konnte nicht ausgeführt werden: {}",
+        "Could not keep the frame rate of {} up: {}" => "Die Bildrate von {} konnte nicht gehalten werden: {}",
+        "/dev/video{} is used by another wlstreamer (process {}), give this one other devices with --devices-from" => "/dev/video{} wird von einem anderen wlstreamer benutzt (Prozess {}), gib diesem andere Geräte mit --devices-from",
+        "Workspace {} (hidden)" => "Arbeitsbereich {} (ausgeblendet)",
+        "Using device number {} through {}" => "Gerätenummer {} über {} wird benutzt",
+        "Using device number {}" => "Gerätenummer {} wird benutzt",
+        "Does not have the maximum combined resolution, filtering through ffmpeg" => "Hat nicht die größte gemeinsame Auflösung, es wird durch ffmpeg gefiltert",
+        "Passing on /dev/video{}" => "/dev/video{} wird weitergegeben",
+        "No device left for {}x{}, screens with that resolution can't be streamed. Add one to --intermediate-devices." => "Kein Gerät mehr frei für {}x{}, Bildschirme mit dieser Auflösung können nicht übertragen werden. Füge eines zu --intermediate-devices hinzu.",
+        "No device left for {}x{}, add one to --intermediate-devices" => "Kein Gerät mehr frei für {}x{}, füge eines zu --intermediate-devices hinzu",
+        "No screen has {}x{} anymore, releasing /dev/video{}" => "Kein Bildschirm hat mehr {}x{}, /dev/video{} wird freigegeben",
+        "Found outputs" => "Gefundene Ausgänge",
+        "No enabled outputs found after {} seconds" => "Nach {} Sekunden keine eingeschalteten Ausgänge gefunden",
+        "Found resolutions, the combined maximum resolution first:" => "Gefundene Auflösungen, die größte gemeinsame Auflösung zuerst:",
+        "Found workspaces:" => "Gefundene Arbeitsbereiche:",
+        "Blacklisted workspaces filtered out:" => "Ausgeschlossene Arbeitsbereiche herausgefiltert:",
+        "Failed: {}" => "Fehlgeschlagen: {}",
+        "Streaming a black screen" => "Ein schwarzes Bild wird übertragen",
+        "unknown profile: {}" => "unbekanntes Profil: {}",
+        "Invalid --redact pattern {}: {}" => "Ungültiges --redact-Muster {}: {}",
+        "The replay buffer is off, start wlstreamer with --replay-buffer" => "Der Wiederholungspuffer ist aus, starte wlstreamer mit --replay-buffer",
+        "There is no single stream to take a screenshot of in mirror-all mode" => "Im mirror-all-Modus gibt es keinen einzelnen Stream für ein Bildschirmfoto",
+        "The output device is being watched, capturing the screens" => "Das Ausgabegerät wird angesehen, die Bildschirme werden aufgenommen",
+        "Nobody is watching the output device anymore, streaming a black screen" => "Niemand sieht das Ausgabegerät mehr an, ein schwarzes Bild wird übertragen",
+        "Plugged in, capturing at the full frame rate" => "Am Netz, Aufnahme mit voller Bildrate",
+        "av-offset has to be auto or a number of milliseconds, not {}" => "av-offset muss auto oder eine Anzahl Millisekunden sein, nicht {}",
+        "side-by-side needs the screens to show, for example --mode side-by-side DP-1,DP-2" => "side-by-side braucht die zu zeigenden Bildschirme, zum Beispiel --mode side-by-side DP-1,DP-2",
+        "--av-offset has to be auto or a number of milliseconds" => "--av-offset muss auto oder eine Anzahl Millisekunden sein",
+        "Could not start with profile {}: {}" => "Start mit Profil {} nicht möglich: {}",
+        "side-by-side needs at least two screens, for example --mode side-by-side DP-1,DP-2" => "side-by-side braucht mindestens zwei Bildschirme, zum Beispiel --mode side-by-side DP-1,DP-2",
+        "The self-test only works in follow mode" => "Der Selbsttest funktioniert nur im follow-Modus",
+        "Watch the stream at http://localhost:{}/ or this computer's address on the network" => "Der Stream ist unter http://localhost:{}/ oder der Adresse dieses Computers im Netzwerk zu sehen",
+        "Watch the stream at http://{}/" => "Der Stream ist unter http://{}/ zu sehen",
+        "Writing HLS to {}" => "HLS wird nach {} geschrieben",
+        "Preview of /dev/video{} at http://{}/" => "Vorschau von /dev/video{} unter http://{}/",
+        "Audio is only added to sinks and the replay buffer, the virtual camera has no sound" => "Ton gibt es nur für Senken und den Wiederholungspuffer, die virtuelle Kamera hat keinen Ton",
+        "The replay buffer needs a single stream, it doesn't work in mirror-all mode" => "Der Wiederholungspuffer braucht einen einzelnen Stream, im mirror-all-Modus funktioniert er nicht",
+        "Segments need a single stream, they don't work in mirror-all mode" => "Abschnitte brauchen einen einzelnen Stream, im mirror-all-Modus funktionieren sie nicht",
+        "{} capture is not available on X11" => "Aufnahme mit {} ist unter X11 nicht verfügbar",
+        "The compositor does not offer a supported screen capture protocol (wlr-screencopy or ext-image-copy-capture)" => "Der Compositor bietet kein unterstütztes Protokoll zur Bildschirmaufnahme (wlr-screencopy oder ext-image-copy-capture)",
+        "Capturing with {}" => "Aufnahme mit {}",
+        "headless mode needs sway, which is the only compositor that can create outputs on request" => "Der headless-Modus braucht sway, den einzigen Compositor, der auf Anfrage Ausgänge anlegen kann",
+        "{}, headless mode needs it to mirror screens" => "{}, der headless-Modus braucht das, um Bildschirme zu spiegeln",
+        "--zoom only works in follow mode" => "--zoom funktioniert nur im follow-Modus",
+        "--zoom needs a factor larger than 1" => "--zoom braucht einen Faktor größer als 1",
+        "Scaling on the GPU is not supported with --zoom, scaling with ffmpeg" => "Skalieren auf der GPU wird mit --zoom nicht unterstützt, es wird mit ffmpeg skaliert",
+        "--mask-app only works in follow mode" => "--mask-app funktioniert nur im follow-Modus",
+        "Scaling on the GPU is not supported with --mask-app, scaling with ffmpeg" => "Skalieren auf der GPU wird mit --mask-app nicht unterstützt, es wird mit ffmpeg skaliert",
+        "Screens can only be kept ready with --standby in follow mode" => "Bildschirme können mit --standby nur im follow-Modus bereitgehalten werden",
+        "Scaling on the GPU is not supported with --standby, scaling with ffmpeg" => "Skalieren auf der GPU wird mit --standby nicht unterstützt, es wird mit ffmpeg skaliert",
+        "Scaling on the GPU is not supported with {}, scaling with ffmpeg" => "Skalieren auf der GPU wird mit {} nicht unterstützt, es wird mit ffmpeg skaliert",
+        "Scaling on the GPU using {}" => "Skalieren auf der GPU mit {}",
+        "No DRM render node found, scaling with ffmpeg" => "Kein DRM-Render-Node gefunden, es wird mit ffmpeg skaliert",
+        "Latency can only be measured in follow mode" => "Die Latenz kann nur im follow-Modus gemessen werden",
+        "{}, not measuring latency" => "{}, die Latenz wird nicht gemessen",
+        "{}, not showing the indicator" => "{}, die Anzeige wird nicht gezeigt",
+        "{}, not blanking the stream when idle" => "{}, der Stream wird bei Untätigkeit nicht ausgeblendet",
+        "{}, wlstreamer ctl won't work" => "{}, wlstreamer ctl wird nicht funktionieren",
+        "{}, the D-Bus interface won't be available" => "{}, die D-Bus-Schnittstelle wird nicht verfügbar sein",
+        "No device left to zoom or mask from, add one to --intermediate-devices" => "Kein Gerät mehr frei zum Zoomen oder Verdecken, füge eines zu --intermediate-devices hinzu",
+        "wf-recorder is told to ignore damage, --damage only keeps the frame rate up" => "wf-recorder soll Damage ignorieren, --damage hält nur die Bildrate aufrecht",
+        "{}, readers may time out on a static screen" => "{}, Leser können bei einem unveränderten Bildschirm aufgeben",
+        "No frames are sent while the screen doesn't change, so --stall-timeout only rebuilds pipelines that exit. Add --wf-recorder-arg --no-damage or --damage to count frames." => "Solange sich der Bildschirm nicht ändert, werden keine Bilder gesendet, --stall-timeout baut also nur Pipelines neu auf, die sich beenden. Füge --wf-recorder-arg --no-damage oder --damage hinzu, um Bilder zu zählen.",
+        "Nobody is watching the output device, streaming a black screen until someone does" => "Niemand sieht das Ausgabegerät an, bis jemand zusieht, wird ein schwarzes Bild übertragen",
+        "Could not stop {}: {}" => "{} konnte nicht beendet werden: {}",
+        "{} mirrors {}, streaming {} for both" => "{} spiegelt {}, für beide wird {} übertragen",
+        "{}, falling back" => "{}, es wird ausgewichen",
+        "Stopping the pipeline" => "Die Pipeline wird beendet",
+        "Could not record {}: {}, leaving it alone for {}s" => "{} konnte nicht aufgenommen werden: {}, es wird {}s lang in Ruhe gelassen",
+        "Pipeline stalled, rebuilding it and leaving what it streamed alone for {}s" => "Die Pipeline hängt, sie wird neu aufgebaut und was sie übertragen hat wird {}s lang in Ruhe gelassen",
+        "Could not keep a screen ready: {}" => "Ein Bildschirm konnte nicht bereitgehalten werden: {}",
+        "Stopped recording {}" => "Die Aufnahme von {} wurde beendet",
+        "Could not stop recorder for {}: {}" => "Die Aufnahme von {} konnte nicht beendet werden: {}",
+        "Recorder for {} stopped, restarting it" => "Die Aufnahme von {} hat aufgehört, sie wird neu gestartet",
+        "Could not restart recorder for {}: {}" => "Die Aufnahme von {} konnte nicht neu gestartet werden: {}",
+        "Recording {} screens" => "{} Bildschirme werden aufgenommen",
+        "Keeping {} ready on /dev/video{}" => "{} wird auf /dev/video{} bereitgehalten",
+        "Recording {} to /dev/video{}" => "{} wird auf /dev/video{} aufgenommen",
+        "Could not load the NDI runtime, is it installed?" => "Die NDI-Laufzeitbibliothek konnte nicht geladen werden, ist sie installiert?",
+        "The NDI runtime has no {}" => "Die NDI-Laufzeitbibliothek hat kein {}",
+        "The NDI runtime does not support this CPU" => "Die NDI-Laufzeitbibliothek unterstützt diesen Prozessor nicht",
+        "Could not create the NDI source {}" => "Die NDI-Quelle {} konnte nicht angelegt werden",
+        "Unexpected stream header: {}" => "Unerwarteter Stream-Kopf: {}",
+        "Could not stop the pipeline: {}" => "Die Pipeline konnte nicht beendet werden: {}",
+        "Could not start the preview: {}" => "Die Vorschau konnte nicht gestartet werden: {}",
+        "{} is not an address like 127.0.0.1:8642" => "{} ist keine Adresse wie 127.0.0.1:8642",
+        "The replay buffer is still empty" => "Der Wiederholungspuffer ist noch leer",
+        "Could not keep {}: {}" => "{} konnte nicht behalten werden: {}",
+        "Could not write the playlist: {}" => "Die Wiedergabeliste konnte nicht geschrieben werden: {}",
+        "ffmpeg could not write {}" => "ffmpeg konnte {} nicht schreiben",
+        "{} is not a directory" => "{} ist kein Verzeichnis",
+        "{} belongs to somebody else, it should be the runtime dir of user {}" => "{} gehört jemand anderem, es sollte das Laufzeitverzeichnis von Benutzer {} sein",
+        "{} can be accessed by others, it should have mode 0700" => "Andere können auf {} zugreifen, es sollte die Rechte 0700 haben",
+        "Invalid duration: {}, use for example 2h, 90m or 1h30m" => "Ungültige Dauer: {}, nutze zum Beispiel 2h, 90m oder 1h30m",
+        "Invalid schedule: {}, use for example \"Mon-Fri 09:00-17:00\"" => "Ungültiger Zeitplan: {}, nutze zum Beispiel \"Mon-Fri 09:00-17:00\"",
+        "There was no frame on the output device" => "Auf dem Ausgabegerät war kein Bild",
+        "Could not read the secrets from {}: {}" => "Die Geheimnisse konnten nicht aus {} gelesen werden: {}",
+        "{} can be read by other users, make it only yours with chmod 600" => "{} kann von anderen Benutzern gelesen werden, mache es mit chmod 600 nur für dich lesbar",
+        "The secret {} is not in the --secrets-file, the environment or the keyring. Store it with: secret-tool store --label={} service wlstreamer key {}" => "Das Geheimnis {} ist weder in der --secrets-file noch in der Umgebung oder im Schlüsselbund. Speichere es mit: secret-tool store --label={} service wlstreamer key {}",
+        "{} has a ${ without a }" => "{} hat ein ${ ohne }",
+        "Could not record {}: {}" => "{} konnte nicht aufgenommen werden: {}",
+        "Could not run ffmpeg: {}" => "ffmpeg konnte nicht ausgeführt werden: {}",
+        "Could not read from {}: {}" => "Von {} konnte nicht gelesen werden: {}",
+        "No frames arrived on {} within {} seconds" => "Auf {} sind innerhalb von {} Sekunden keine Bilder angekommen",
+        "Got {} bytes instead of {} frames of {}x{}, the output device has another format than the pipeline writes" => "{} Bytes statt {} Bildern mit {}x{} erhalten, das Ausgabegerät hat ein anderes Format, als die Pipeline schreibt",
+        "The output device only shows a black screen" => "Das Ausgabegerät zeigt nur ein schwarzes Bild",
+        "There is no screen to stream, a black screen is shown instead" => "Es gibt keinen Bildschirm zum Übertragen, stattdessen wird ein schwarzes Bild gezeigt",
+        "Self-test passed, /dev/video{} shows {}" => "Selbsttest bestanden, /dev/video{} zeigt {}",
+        "Self-test failed: {}" => "Selbsttest fehlgeschlagen: {}",
+        "Could not run systemctl: {}" => "systemctl konnte nicht ausgeführt werden: {}",
+        "systemctl --user {} failed: {}" => "systemctl --user {} ist fehlgeschlagen: {}",
+        "Could not find the wlstreamer binary: {}" => "Das Programm wlstreamer wurde nicht gefunden: {}",
+        "Wrote {}" => "{} geschrieben",
+        "No graphical session found, run wlstreamer install-service from a terminal in sway, niri or i3" => "Keine grafische Sitzung gefunden, führe wlstreamer install-service in einem Terminal in sway, niri oder i3 aus",
+        "Started {}.service, follow it with journalctl --user -u {} -f" => "{}.service gestartet, verfolge es mit journalctl --user -u {} -f",
+        "It starts with graphical-session.target, which sway doesn't reach on its own. Unless your session is started through systemd, add this to the sway config instead:" => "Es startet mit graphical-session.target, das sway von sich aus nicht erreicht. Wenn deine Sitzung nicht über systemd gestartet wird, füge stattdessen dies zur sway-Konfiguration hinzu:",
+        "Could not open /dev/null: {}" => "/dev/null konnte nicht geöffnet werden: {}",
+        "Could not fork: {}" => "Fork nicht möglich: {}",
+        "Running in the background as process {}, logging to {}" => "Läuft im Hintergrund als Prozess {}, das Protokoll geht nach {}",
+        "No session {} found in {}" => "Keine Sitzung {} in {} gefunden",
+        "Not running in a graphical session, and no Wayland session found in {}" => "Nicht in einer grafischen Sitzung gestartet, und keine Wayland-Sitzung in {} gefunden",
+        "Found several sessions, pick one with --session: {}" => "Mehrere Sitzungen gefunden, wähle eine mit --session: {}",
+        "Using the session on {}" => "Die Sitzung auf {} wird benutzt",
+        "Could not handle signals: {}" => "Signale können nicht behandelt werden: {}",
+        "--sink-passphrase must be between 10 and 79 characters long" => "--sink-passphrase muss zwischen 10 und 79 Zeichen lang sein",
+        "{} does not support the {} encoder, pick another --codec" => "{} unterstützt den Encoder {} nicht, wähle einen anderen --codec",
+        "{} can't be scaled to an empty size or an odd width" => "{} kann nicht auf eine leere Größe oder eine ungerade Breite skaliert werden",
+        "wlstreamer was built without NDI support, build it with --features ndi" => "wlstreamer wurde ohne NDI-Unterstützung gebaut, baue es mit --features ndi",
+        "WHIP sinks only support --codec h264" => "WHIP-Senken unterstützen nur --codec h264",
+        "{} can't send WHIP, it needs ffmpeg 8 or newer" => "{} kann kein WHIP senden, dafür braucht es ffmpeg 8 oder neuer",
+        "Output device: {}" => "Ausgabegerät: {}",
+        "{}x{} on /dev/video{} (output)" => "{}x{} auf /dev/video{} (Ausgabe)",
+        "{}x{} on /dev/video{}" => "{}x{} auf /dev/video{}",
+        "Could not notify systemd: {}" => "systemd konnte nicht benachrichtigt werden: {}",
+        "Could not write the timeline to {}: {}" => "Die Zeitleiste konnte nicht nach {} geschrieben werden: {}",
+        "Could not find {} in $PATH" => "{} wurde in $PATH nicht gefunden",
+        "{} is not an executable file" => "{} ist keine ausführbare Datei",
+        "{} is not a socket" => "{} ist kein Socket",
+        "Streaming {}" => "{} wird übertragen",
+        "Could not put the icon into the tray, waiting for one to start: {}" => "Das Symbol konnte nicht in den Infobereich gesetzt werden, es wird auf dessen Start gewartet: {}",
+        "There is no session bus to show the icon on" => "Es gibt keinen Session-Bus, auf dem das Symbol gezeigt werden könnte",
+        "Lost the connection to the session bus" => "Die Verbindung zum Session-Bus ist verloren",
+        "stdin is not a terminal: {}" => "stdin ist kein Terminal: {}",
+        "Could not set up the terminal: {}" => "Das Terminal konnte nicht eingerichtet werden: {}",
+        "The {} reply of {} is not UTF-8" => "Die {}-Antwort von {} ist kein UTF-8",
+        "Could not understand the {} reply of {}: {}" => "Die {}-Antwort von {} ist unverständlich: {}",
+        _ => return None,
+    })
+}
+
+/// The message in the language of the user, with every `{}` replaced by the next argument.
+pub fn tr(message: &str, args: &[&dyn Display]) -> String {
+    let translated = match locale() {
+        Locale::De => german(message).unwrap_or(message),
+        Locale::En => message,
+    };
+
+    let mut args = args.iter();
+    let mut text = String::new();
+    for (i, part) in translated.split("{}").enumerate() {
+        if i > 0 {
+            text.push_str(&args.next().map(|arg| arg.to_string()).unwrap_or_default());
+        }
+        text.push_str(part);
+    }
+    text
+}
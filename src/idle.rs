@@ -0,0 +1,104 @@
+//! Notices when the user goes idle through the ext-idle-notify protocol, so the stream can be
+//! blanked while nobody is at the computer.
+
+use crate::i18n;
+use crate::Event;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry, wl_seat};
+use wayland_client::{Connection, Dispatch, QueueHandle};
+use wayland_protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1 as idle_notification, ext_idle_notifier_v1 as idle_notifier,
+};
+
+struct State {
+    changed: Sender<Event>,
+}
+
+/// Connects to the compositor and signals `Event::Idle` whenever the user has been idle for
+/// `timeout`, or becomes active again after that.
+pub fn start(timeout: Duration, changed: Sender<Event>) -> Result<(), String> {
+    let conn = Connection::connect_to_env()
+        .map_err(|err| i18n::tr("Could not connect to the Wayland display: {}", &[&err]))?;
+    let (globals, mut queue) = registry_queue_init::<State>(&conn)
+        .map_err(|err| i18n::tr("Could not list Wayland globals: {}", &[&err]))?;
+    let qh = queue.handle();
+
+    let notifier = globals
+        .bind::<idle_notifier::ExtIdleNotifierV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| i18n::tr("The compositor does not support ext-idle-notify", &[]))?;
+    let seat = globals
+        .bind::<wl_seat::WlSeat, _, _>(&qh, 1..=1, ())
+        .map_err(|_| i18n::tr("The compositor has no seat to watch for input", &[]))?;
+    notifier.get_idle_notification(timeout.as_millis() as u32, &seat, &qh, ());
+
+    let mut state = State { changed };
+    thread::spawn(move || loop {
+        if let Err(err) = queue.blocking_dispatch(&mut state) {
+            println!(
+                "{}",
+                i18n::tr("Lost connection to the Wayland display: {}", &[&err])
+            );
+            break;
+        }
+    });
+
+    Ok(())
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_seat::WlSeat,
+        _: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<idle_notifier::ExtIdleNotifierV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &idle_notifier::ExtIdleNotifierV1,
+        _: idle_notifier::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<idle_notification::ExtIdleNotificationV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &idle_notification::ExtIdleNotificationV1,
+        event: idle_notification::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let idle = match event {
+            idle_notification::Event::Idled => true,
+            idle_notification::Event::Resumed => false,
+            _ => return,
+        };
+        // The receiving end only goes away when wlstreamer shuts down
+        let _ = state.changed.send(Event::Idle(idle));
+    }
+}
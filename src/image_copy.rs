@@ -0,0 +1,442 @@
+//! `wlstreamer capture-output`: captures an output through ext-image-copy-capture and writes its
+//! frames to stdout as PPM images, for ffmpeg to read with image2pipe. wf-recorder only speaks
+//! wlr-screencopy, so this is what the pipeline runs on compositors that only offer the newer
+//! protocol.
+//!
+//! The compositor only hands out a new frame once something changed on the output. With
+//! `--repeat <fps>`, the last frame is written again whenever no new one came in time, so the
+//! frame rate stays up on a static screen.
+
+use crate::i18n;
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::process;
+use std::ptr;
+use std::time::{Duration, Instant};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_buffer, wl_output, wl_registry, wl_shm, wl_shm_pool};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum};
+use wayland_protocols::ext::image_capture_source::v1::client::{
+    ext_image_capture_source_v1 as capture_source,
+    ext_output_image_capture_source_manager_v1 as source_manager,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::{
+    ext_image_copy_capture_frame_v1 as capture_frame,
+    ext_image_copy_capture_manager_v1 as capture_manager,
+    ext_image_copy_capture_session_v1 as capture_session,
+};
+
+/// The shm formats frames can be turned into PPM from, with where red, green and blue are in
+/// each pixel of four bytes.
+const FORMATS: [(wl_shm::Format, [usize; 3]); 4] = [
+    (wl_shm::Format::Xrgb8888, [2, 1, 0]),
+    (wl_shm::Format::Argb8888, [2, 1, 0]),
+    (wl_shm::Format::Xbgr8888, [0, 1, 2]),
+    (wl_shm::Format::Abgr8888, [0, 1, 2]),
+];
+
+/// What the session says buffers have to look like.
+#[derive(Default, Debug)]
+struct Constraints {
+    width: u32,
+    height: u32,
+    formats: Vec<wl_shm::Format>,
+    done: bool,
+}
+
+#[derive(PartialEq, Debug)]
+enum Capture {
+    Waiting,
+    Ready,
+    /// The buffer doesn't fit anymore, for example after the mode of the output changed
+    Resize,
+    Stopped,
+}
+
+struct State {
+    outputs: Vec<(wl_output::WlOutput, String)>,
+    constraints: Constraints,
+    /// Constraints that are still coming in, until done
+    pending: Constraints,
+    capture: Capture,
+}
+
+/// A wl_buffer in shared memory that frames are copied into.
+struct Buffer {
+    buffer: wl_buffer::WlBuffer,
+    pool: wl_shm_pool::WlShmPool,
+    data: *mut u8,
+    size: usize,
+    width: usize,
+    height: usize,
+    format: wl_shm::Format,
+    /// Where red, green and blue are in a pixel
+    channels: [usize; 3],
+    _fd: OwnedFd,
+}
+
+impl Buffer {
+    fn new(
+        shm: &wl_shm::WlShm,
+        constraints: &Constraints,
+        qh: &QueueHandle<State>,
+    ) -> Result<Buffer, String> {
+        let (format, channels) = FORMATS
+            .iter()
+            .find(|(format, _)| constraints.formats.contains(format))
+            .ok_or_else(|| {
+                i18n::tr(
+                    "The compositor offers none of the frame formats wlstreamer reads, only {}",
+                    &[&format!("{:?}", constraints.formats)],
+                )
+            })?;
+        let (width, height) = (constraints.width as usize, constraints.height as usize);
+        let size = width * height * 4;
+
+        let name = CStr::from_bytes_with_nul(b"wlstreamer-frame\0").unwrap();
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(i18n::tr(
+                "Could not create a frame buffer: {}",
+                &[&io::Error::last_os_error()],
+            ));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), size as libc::off_t) } < 0 {
+            return Err(i18n::tr(
+                "Could not size the frame buffer: {}",
+                &[&io::Error::last_os_error()],
+            ));
+        }
+        let data = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            return Err(i18n::tr(
+                "Could not map the frame buffer: {}",
+                &[&io::Error::last_os_error()],
+            ));
+        }
+
+        let pool = shm.create_pool(fd.as_fd(), size as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            width as i32 * 4,
+            *format,
+            qh,
+            (),
+        );
+
+        Ok(Buffer {
+            buffer,
+            pool,
+            data: data as *mut u8,
+            size,
+            width,
+            height,
+            format: *format,
+            channels: *channels,
+            _fd: fd,
+        })
+    }
+
+    fn fits(&self, constraints: &Constraints) -> bool {
+        (self.width, self.height) == (constraints.width as usize, constraints.height as usize)
+            && constraints.formats.contains(&self.format)
+    }
+
+    /// Turns the frame in the buffer into a PPM image.
+    fn ppm(&self, image: &mut Vec<u8>) {
+        let pixels = unsafe { std::slice::from_raw_parts(self.data, self.size) };
+        ppm(pixels, self.width, self.height, self.channels, image);
+    }
+}
+
+/// Writes `pixels` of four bytes each as a PPM image of three.
+fn ppm(pixels: &[u8], width: usize, height: usize, channels: [usize; 3], image: &mut Vec<u8>) {
+    image.clear();
+    image.extend(format!("P6\n{} {}\n255\n", width, height).as_bytes());
+    for pixel in pixels.chunks_exact(4) {
+        image.extend(channels.iter().map(|channel| pixel[*channel]));
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+        unsafe { libc::munmap(self.data as *mut libc::c_void, self.size) };
+    }
+}
+
+/// Waits until an event came in, or `timeout` passed.
+fn wait(queue: &mut EventQueue<State>, state: &mut State, timeout: Option<Duration>) {
+    let result = queue.flush().map_err(|err| err.to_string()).and_then(|_| {
+        let guard = match queue.prepare_read() {
+            Some(guard) => guard,
+            // Events are queued already
+            None => return Ok(()),
+        };
+        let mut poll = libc::pollfd {
+            fd: guard.connection_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis() as libc::c_int);
+        if unsafe { libc::poll(&mut poll, 1, timeout) } > 0 {
+            guard.read().map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    });
+
+    if let Err(err) =
+        result.and_then(|_| queue.dispatch_pending(state).map_err(|err| err.to_string()))
+    {
+        fail(i18n::tr(
+            "Lost connection to the Wayland display: {}",
+            &[&err],
+        ));
+    }
+}
+
+fn write(stdout: &mut impl Write, image: &[u8]) {
+    // ffmpeg went away, so nobody wants the frames anymore
+    if stdout
+        .write_all(image)
+        .and_then(|_| stdout.flush())
+        .is_err()
+    {
+        process::exit(0);
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Captures the output named in `args` until stdout is closed.
+pub fn run(args: &[String]) -> ! {
+    let (name, repeat) = match args {
+        [name] => (name, None),
+        [name, flag, fps] if flag == "--repeat" => match fps.parse::<u32>() {
+            Ok(fps) if fps > 0 => (name, Some(Duration::from_secs(1) / fps)),
+            _ => fail(i18n::tr("Invalid frame rate {}", &[&fps])),
+        },
+        _ => fail("Usage: wlstreamer capture-output <output> [--repeat <fps>]".to_string()),
+    };
+
+    let conn = Connection::connect_to_env().unwrap_or_else(|err| {
+        fail(i18n::tr(
+            "Could not connect to the Wayland display: {}",
+            &[&err],
+        ))
+    });
+    let (globals, mut queue) = registry_queue_init::<State>(&conn)
+        .unwrap_or_else(|err| fail(i18n::tr("Could not list Wayland globals: {}", &[&err])));
+    let qh = queue.handle();
+
+    let shm: wl_shm::WlShm = globals
+        .bind(&qh, 1..=1, ())
+        .unwrap_or_else(|_| fail("The compositor does not offer wl_shm".to_string()));
+    let sources: source_manager::ExtOutputImageCaptureSourceManagerV1 =
+        globals.bind(&qh, 1..=1, ()).unwrap_or_else(|_| {
+            fail("The compositor does not support ext-image-capture-source".to_string())
+        });
+    let manager: capture_manager::ExtImageCopyCaptureManagerV1 =
+        globals.bind(&qh, 1..=1, ()).unwrap_or_else(|_| {
+            fail("The compositor does not support ext-image-copy-capture".to_string())
+        });
+
+    let mut state = State {
+        outputs: Vec::new(),
+        constraints: Constraints::default(),
+        pending: Constraints::default(),
+        capture: Capture::Waiting,
+    };
+    for global in globals.contents().clone_list() {
+        if global.interface == wl_output::WlOutput::interface().name {
+            if global.version < 4 {
+                fail("Capturing by output name needs wl_output version 4".to_string());
+            }
+            let output: wl_output::WlOutput = globals.registry().bind(global.name, 4, &qh, ());
+            state.outputs.push((output, String::new()));
+        }
+    }
+    if let Err(err) = queue.roundtrip(&mut state) {
+        fail(i18n::tr("Could not read the Wayland outputs: {}", &[&err]));
+    }
+
+    let output = match state.outputs.iter().find(|(_, o)| o == name) {
+        Some((output, _)) => output.clone(),
+        None => fail(i18n::tr("Could not find output {}", &[&name])),
+    };
+    let source = sources.create_source(&output, &qh, ());
+    let session = manager.create_session(&source, capture_manager::Options::PaintCursors, &qh, ());
+
+    let mut stdout = io::stdout().lock();
+    let mut image = Vec::new();
+    let mut buffer: Option<Buffer> = None;
+    let mut written = Instant::now();
+    loop {
+        while !state.constraints.done && state.capture != Capture::Stopped {
+            wait(&mut queue, &mut state, None);
+        }
+        if state.capture == Capture::Stopped {
+            fail(i18n::tr("Stopped capturing {}", &[&name]));
+        }
+        if !buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.fits(&state.constraints))
+        {
+            buffer =
+                Some(Buffer::new(&shm, &state.constraints, &qh).unwrap_or_else(|err| fail(err)));
+        }
+        let current = buffer.as_ref().unwrap();
+
+        let frame = session.create_frame(&qh, ());
+        frame.attach_buffer(&current.buffer);
+        frame.damage_buffer(0, 0, current.width as i32, current.height as i32);
+        frame.capture();
+        state.capture = Capture::Waiting;
+
+        while state.capture == Capture::Waiting {
+            // Nothing is repeated before the first frame
+            let timeout = repeat
+                .filter(|_| !image.is_empty())
+                .map(|every| every.saturating_sub(written.elapsed()));
+            if timeout == Some(Duration::ZERO) {
+                write(&mut stdout, &image);
+                written = Instant::now();
+                continue;
+            }
+            wait(&mut queue, &mut state, timeout);
+        }
+        frame.destroy();
+
+        match state.capture {
+            Capture::Ready => {
+                current.ppm(&mut image);
+                write(&mut stdout, &image);
+                written = Instant::now();
+            }
+            // Unless the new constraints came in already, wait for them
+            Capture::Resize if current.fits(&state.constraints) => state.constraints.done = false,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Outputs plugged in later are of no interest, this one is captured until it goes away
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            if let Some(entry) = state.outputs.iter_mut().find(|(o, _)| o == output) {
+                entry.1 = name;
+            }
+        }
+    }
+}
+
+impl Dispatch<capture_session::ExtImageCopyCaptureSessionV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &capture_session::ExtImageCopyCaptureSessionV1,
+        event: capture_session::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            capture_session::Event::BufferSize { width, height } => {
+                state.pending.width = width;
+                state.pending.height = height;
+            }
+            capture_session::Event::ShmFormat {
+                format: WEnum::Value(format),
+            } => state.pending.formats.push(format),
+            capture_session::Event::Done => {
+                state.constraints = Constraints {
+                    done: true,
+                    ..mem::take(&mut state.pending)
+                }
+            }
+            capture_session::Event::Stopped => state.capture = Capture::Stopped,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<capture_frame::ExtImageCopyCaptureFrameV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &capture_frame::ExtImageCopyCaptureFrameV1,
+        event: capture_frame::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            capture_frame::Event::Ready => state.capture = Capture::Ready,
+            capture_frame::Event::Failed {
+                reason: WEnum::Value(capture_frame::FailureReason::BufferConstraints),
+            } => state.capture = Capture::Resize,
+            capture_frame::Event::Failed { .. } => state.capture = Capture::Stopped,
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_buffer::WlBuffer);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: capture_source::ExtImageCaptureSourceV1);
+delegate_noop!(State: source_manager::ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(State: capture_manager::ExtImageCopyCaptureManagerV1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_turn_into_rgb() {
+        let pixels = [30, 20, 10, 255, 3, 2, 1, 0];
+        let mut image = Vec::new();
+
+        ppm(&pixels, 2, 1, FORMATS[0].1, &mut image);
+        assert_eq!(image, b"P6\n2 1\n255\n\x0a\x14\x1e\x01\x02\x03");
+
+        ppm(&pixels, 2, 1, FORMATS[2].1, &mut image);
+        assert_eq!(image, b"P6\n2 1\n255\n\x1e\x14\x0a\x03\x02\x01");
+    }
+}
@@ -0,0 +1,260 @@
+//! A small tally light in the corner of every screen that is currently being streamed, drawn as
+//! a layer-shell overlay so it stays on top of all windows.
+
+use crate::i18n;
+use crate::runtime_dir;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_output, wl_region, wl_registry, wl_shm, wl_shm_pool, wl_surface,
+};
+use wayland_client::{delegate_noop, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1 as layer_shell, zwlr_layer_surface_v1 as layer_surface,
+};
+
+const SIZE: i32 = 16;
+const MARGIN: i32 = 8;
+const COLOR: u32 = 0xffe0_1b24;
+
+type Outputs = Arc<Mutex<HashMap<ObjectId, (wl_output::WlOutput, String)>>>;
+
+struct State {
+    outputs: Outputs,
+    buffer: wl_buffer::WlBuffer,
+}
+
+/// Shows the indicator on the screens it is asked to. Surfaces are created from whichever thread
+/// calls `show`, their events are handled on a background thread.
+#[derive(Clone, Debug)]
+pub struct Indicator {
+    conn: Connection,
+    qh: QueueHandle<State>,
+    compositor: wl_compositor::WlCompositor,
+    layer_shell: layer_shell::ZwlrLayerShellV1,
+    outputs: Outputs,
+    surfaces: Arc<Mutex<Vec<(wl_surface::WlSurface, layer_surface::ZwlrLayerSurfaceV1)>>>,
+}
+
+impl Indicator {
+    /// Moves the indicator to exactly these outputs, hiding it everywhere else.
+    pub fn show(&self, names: &[String]) {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        for (surface, layer) in surfaces.drain(..) {
+            layer.destroy();
+            surface.destroy();
+        }
+
+        let outputs = self.outputs.lock().unwrap();
+        for (output, _) in outputs.values().filter(|(_, name)| names.contains(name)) {
+            let surface = self.compositor.create_surface(&self.qh, ());
+            // An empty input region lets clicks through to the windows below
+            let region = self.compositor.create_region(&self.qh, ());
+            surface.set_input_region(Some(&region));
+            region.destroy();
+
+            let layer = self.layer_shell.get_layer_surface(
+                &surface,
+                Some(output),
+                layer_shell::Layer::Overlay,
+                "wlstreamer-indicator".to_string(),
+                &self.qh,
+                surface.clone(),
+            );
+            layer.set_size(SIZE as u32, SIZE as u32);
+            layer.set_anchor(layer_surface::Anchor::Top | layer_surface::Anchor::Right);
+            layer.set_margin(MARGIN, MARGIN, 0, 0);
+            layer.set_exclusive_zone(-1);
+            surface.commit();
+            surfaces.push((surface, layer));
+        }
+
+        if let Err(err) = self.conn.flush() {
+            println!(
+                "{}",
+                i18n::tr("Could not update the indicator: {}", &[&err])
+            );
+        }
+    }
+}
+
+/// Creates the buffer every indicator surface shows, a square in `COLOR`.
+fn create_buffer(
+    shm: &wl_shm::WlShm,
+    qh: &QueueHandle<State>,
+) -> Result<wl_buffer::WlBuffer, String> {
+    let path = runtime_dir::join(&format!("wlstreamer-indicator-{}", process::id()))?;
+
+    let mut file = runtime_dir::open(
+        &path,
+        OpenOptions::new().read(true).write(true).create_new(true),
+    )?;
+    // Only the compositor needs it from here on
+    let _ = fs::remove_file(&path);
+
+    let pixels: Vec<u8> = (0..SIZE * SIZE).flat_map(|_| COLOR.to_le_bytes()).collect();
+    file.write_all(&pixels)
+        .map_err(|err| i18n::tr("Could not write the indicator: {}", &[&err]))?;
+
+    let pool = shm.create_pool(file.as_fd(), pixels.len() as i32, qh, ());
+    let buffer = pool.create_buffer(0, SIZE, SIZE, SIZE * 4, wl_shm::Format::Argb8888, qh, ());
+    pool.destroy();
+
+    Ok(buffer)
+}
+
+/// Connects to the compositor and keeps handling the indicator's events on a background thread.
+pub fn start() -> Result<Indicator, String> {
+    let conn = Connection::connect_to_env()
+        .map_err(|err| i18n::tr("Could not connect to the Wayland display: {}", &[&err]))?;
+    let (globals, mut queue) = registry_queue_init::<State>(&conn)
+        .map_err(|err| i18n::tr("Could not list Wayland globals: {}", &[&err]))?;
+    let qh = queue.handle();
+
+    let compositor = globals
+        .bind::<wl_compositor::WlCompositor, _, _>(&qh, 1..=4, ())
+        .map_err(|_| i18n::tr("The compositor has no wl_compositor", &[]))?;
+    let layer_shell = globals
+        .bind::<layer_shell::ZwlrLayerShellV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| i18n::tr("The compositor does not support wlr-layer-shell", &[]))?;
+    let shm = globals
+        .bind::<wl_shm::WlShm, _, _>(&qh, 1..=1, ())
+        .map_err(|_| i18n::tr("The compositor has no wl_shm", &[]))?;
+
+    let outputs = Outputs::default();
+    for global in globals.contents().clone_list() {
+        if global.interface == wl_output::WlOutput::interface().name {
+            bind_output(
+                &outputs,
+                globals.registry(),
+                global.name,
+                global.version,
+                &qh,
+            );
+        }
+    }
+
+    let mut state = State {
+        outputs: outputs.clone(),
+        buffer: create_buffer(&shm, &qh)?,
+    };
+    // Wait for the output names
+    queue
+        .roundtrip(&mut state)
+        .map_err(|err| i18n::tr("Could not read the Wayland state: {}", &[&err]))?;
+
+    thread::spawn(move || loop {
+        if let Err(err) = queue.blocking_dispatch(&mut state) {
+            println!(
+                "{}",
+                i18n::tr("Lost connection to the Wayland display: {}", &[&err])
+            );
+            break;
+        }
+    });
+
+    Ok(Indicator {
+        conn,
+        qh,
+        compositor,
+        layer_shell,
+        outputs,
+        surfaces: Arc::default(),
+    })
+}
+
+fn bind_output(
+    outputs: &Outputs,
+    registry: &wl_registry::WlRegistry,
+    name: u32,
+    version: u32,
+    qh: &QueueHandle<State>,
+) {
+    // Names are only sent from version 4 on
+    if version < 4 {
+        return;
+    }
+    let output: wl_output::WlOutput = registry.bind(name, 4, qh, name);
+    outputs
+        .lock()
+        .unwrap()
+        .insert(output.id(), (output, String::new()));
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == wl_output::WlOutput::interface().name => {
+                bind_output(&state.outputs, registry, name, version, qh);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                state
+                    .outputs
+                    .lock()
+                    .unwrap()
+                    .retain(|_, (output, _)| output.data::<u32>() != Some(&name));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, u32> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        _: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            if let Some(entry) = state.outputs.lock().unwrap().get_mut(&output.id()) {
+                entry.1 = name;
+            }
+        }
+    }
+}
+
+impl Dispatch<layer_surface::ZwlrLayerSurfaceV1, wl_surface::WlSurface> for State {
+    fn event(
+        state: &mut Self,
+        layer: &layer_surface::ZwlrLayerSurfaceV1,
+        event: layer_surface::Event,
+        surface: &wl_surface::WlSurface,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let layer_surface::Event::Configure { serial, .. } = event {
+            layer.ack_configure(serial);
+            surface.attach(Some(&state.buffer), 0, 0);
+            surface.commit();
+        }
+    }
+}
+
+delegate_noop!(State: ignore wl_compositor::WlCompositor);
+delegate_noop!(State: ignore wl_surface::WlSurface);
+delegate_noop!(State: ignore wl_region::WlRegion);
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_shm_pool::WlShmPool);
+delegate_noop!(State: ignore wl_buffer::WlBuffer);
+delegate_noop!(State: ignore layer_shell::ZwlrLayerShellV1);
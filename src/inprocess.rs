@@ -0,0 +1,328 @@
+//! ffmpeg stages running inside wlstreamer through libavfilter and libavdevice, instead of as
+//! separate ffmpeg processes. Enabled with the `ffmpeg` cargo feature.
+
+use ffmpeg_next as ffmpeg;
+
+use crate::zoom::Pan;
+use ffmpeg::{codec, encoder, filter, format, frame, Packet, Rational};
+use std::ffi::CString;
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const BLACK_FRAME_RATE: i32 = 25;
+const DEVICE_OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A pipeline stage running on its own thread.
+pub struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Result<(), ffmpeg::Error>>>,
+}
+
+impl Worker {
+    fn spawn<F>(run: F) -> Worker
+    where
+        F: FnOnce(&AtomicBool) -> Result<(), ffmpeg::Error> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || run(&thread_stop));
+
+        Worker {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn exited(&self) -> bool {
+        self.handle
+            .as_ref()
+            .map_or(true, |handle| handle.is_finished())
+    }
+
+    /// Stops the stage and waits for it to release its devices.
+    pub fn kill(&mut self) -> Result<(), Error> {
+        self.stop.store(true, Ordering::SeqCst);
+
+        match self.handle.take() {
+            Some(handle) => match handle.join() {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(err)) => Err(to_io_error(err)),
+                Err(_) => Err(Error::other("ffmpeg worker panicked")),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn init() -> Result<(), Error> {
+    ffmpeg::init().map_err(to_io_error)?;
+    ffmpeg::log::set_level(ffmpeg::log::Level::Error);
+    Ok(())
+}
+
+/// Writes black frames of the given size to the device until stopped, passed through `filters`,
+/// which have to end with the conversion to yuyv422.
+pub fn stream_black(
+    width: u32,
+    height: u32,
+    rate: u32,
+    device: String,
+    filters: String,
+) -> Result<Worker, Error> {
+    let (mut graph, mut sink) =
+        open_black(width, height, rate, device.as_str(), filters.as_str()).map_err(to_io_error)?;
+
+    Ok(Worker::spawn(move |stop| {
+        let frame_duration = Duration::from_secs(1) / rate;
+        let mut frame = frame::Video::empty();
+
+        while !stop.load(Ordering::SeqCst) {
+            let started = Instant::now();
+            graph.get("out").unwrap().sink().frame(&mut frame)?;
+            sink.write(&frame)?;
+
+            if let Some(remaining) = frame_duration.checked_sub(started.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+
+        sink.close()
+    }))
+}
+
+fn open_black(
+    width: u32,
+    height: u32,
+    rate: u32,
+    device: &str,
+    filters: &str,
+) -> Result<(filter::Graph, Sink), ffmpeg::Error> {
+    let mut graph = filter::Graph::new();
+    add_filter(
+        &mut graph,
+        "color",
+        "in",
+        format!("c=black:s={}x{}:r={}", width, height, rate).as_str(),
+    )?;
+    add_filter(&mut graph, "buffersink", "out", "")?;
+    connect(&mut graph, filters)?;
+
+    let sink = Sink::open(device, width, height)?;
+
+    Ok((graph, sink))
+}
+
+/// Reads frames from the `from` device, scales and pads them to the given size and writes them
+/// to the `to` device until stopped. With a pan, only its crop is scaled, which is moved before
+/// every frame.
+pub fn upscale(
+    from: String,
+    to: String,
+    width: u32,
+    height: u32,
+    color: String,
+    masks: String,
+    pan: Option<Pan>,
+) -> Worker {
+    Worker::spawn(move |stop| {
+        // The device only becomes readable once the recorder has started writing to it, so
+        // keep trying instead of guessing how long that takes.
+        let started = Instant::now();
+        let mut input = loop {
+            match format::input(&from) {
+                Ok(input) => break input,
+                Err(err) => {
+                    if stop.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                    if started.elapsed() > DEVICE_OPEN_TIMEOUT {
+                        return Err(err);
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+            }
+        };
+
+        let stream = input
+            .streams()
+            .best(ffmpeg::media::Type::Video)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+        let stream_index = stream.index();
+        let time_base = stream.time_base();
+        let mut decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()?;
+
+        let mut graph = filter::Graph::new();
+        add_filter(
+            &mut graph,
+            "buffer",
+            "in",
+            format!(
+                "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect=1/1",
+                decoder.width(),
+                decoder.height(),
+                decoder
+                    .format()
+                    .descriptor()
+                    .ok_or(ffmpeg::Error::InvalidData)?
+                    .name(),
+                time_base.numerator(),
+                time_base.denominator()
+            )
+            .as_str(),
+        )?;
+        add_filter(&mut graph, "buffersink", "out", "")?;
+        connect(
+            &mut graph,
+            format!(
+                "{}{}scale={}:{}:force_original_aspect_ratio=decrease{},pad={}:{}:(ow-iw)/2:(oh-ih)/2,setsar=1,format=yuyv422",
+                masks,
+                pan.as_ref().map(|pan| pan.start.filter()).unwrap_or_default(),
+                width, height, color, width, height
+            )
+            .as_str(),
+        )?;
+
+        let mut sink = Sink::open(to.as_str(), width, height)?;
+        let mut decoded = frame::Video::empty();
+        let mut scaled = frame::Video::empty();
+        let mut glide = pan.as_ref().map(Pan::glide);
+        let mut cropped = pan.map(|pan| pan.start);
+
+        for (packet_stream, packet) in input.packets() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            if packet_stream.index() != stream_index {
+                continue;
+            }
+
+            decoder.send_packet(&packet)?;
+            while decoder.receive_frame(&mut decoded).is_ok() {
+                if let Some(glide) = glide.as_mut() {
+                    let crop = glide.position();
+                    if cropped != Some(crop) {
+                        move_crop(&mut graph, "x", crop.x)?;
+                        move_crop(&mut graph, "y", crop.y)?;
+                        cropped = Some(crop);
+                    }
+                }
+                graph.get("in").unwrap().source().add(&decoded)?;
+                while graph.get("out").unwrap().sink().frame(&mut scaled).is_ok() {
+                    sink.write(&scaled)?;
+                }
+            }
+        }
+
+        sink.close()
+    })
+}
+
+/// A v4l2 output device that takes yuyv422 frames.
+struct Sink {
+    output: format::context::Output,
+    encoder: encoder::Video,
+    time_base: Rational,
+    pts: i64,
+}
+
+impl Sink {
+    fn open(device: &str, width: u32, height: u32) -> Result<Sink, ffmpeg::Error> {
+        let time_base = Rational(1, BLACK_FRAME_RATE);
+        let mut output = format::output_as(&device, "v4l2")?;
+        let codec = encoder::find(codec::Id::RAWVIDEO).ok_or(ffmpeg::Error::EncoderNotFound)?;
+        let mut stream = output.add_stream(codec)?;
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_format(format::Pixel::YUYV422);
+        encoder.set_time_base(time_base);
+        let encoder = encoder.open_as(codec)?;
+
+        stream.set_parameters(&encoder);
+        stream.set_time_base(time_base);
+        output.write_header()?;
+
+        Ok(Sink {
+            output,
+            encoder,
+            time_base,
+            pts: 0,
+        })
+    }
+
+    fn write(&mut self, frame: &frame::Video) -> Result<(), ffmpeg::Error> {
+        let mut frame = frame.clone();
+        frame.set_pts(Some(self.pts));
+        self.pts += 1;
+
+        self.encoder.send_frame(&frame)?;
+        self.write_packets()
+    }
+
+    fn write_packets(&mut self) -> Result<(), ffmpeg::Error> {
+        let stream_time_base = self.output.stream(0).unwrap().time_base();
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(0);
+            packet.rescale_ts(self.time_base, stream_time_base);
+            packet.write_interleaved(&mut self.output)?;
+        }
+
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<(), ffmpeg::Error> {
+        self.encoder.send_eof()?;
+        self.write_packets()?;
+        self.output.write_trailer()
+    }
+}
+
+fn add_filter(
+    graph: &mut filter::Graph,
+    name: &str,
+    id: &str,
+    args: &str,
+) -> Result<(), ffmpeg::Error> {
+    let filter = filter::find(name).ok_or(ffmpeg::Error::FilterNotFound)?;
+    graph.add(&filter, id, args)
+}
+
+fn connect(graph: &mut filter::Graph, filters: &str) -> Result<(), ffmpeg::Error> {
+    graph.output("in", 0)?.input("out", 0)?.parse(filters)?;
+    graph.validate()
+}
+
+/// Moves the crop filter of the graph, which takes effect from the next frame on.
+fn move_crop(graph: &mut filter::Graph, option: &str, value: usize) -> Result<(), ffmpeg::Error> {
+    let target = CString::new("crop").unwrap();
+    let option = CString::new(option).unwrap();
+    let value = CString::new(value.to_string()).unwrap();
+    let result = unsafe {
+        ffmpeg::ffi::avfilter_graph_send_command(
+            graph.as_mut_ptr(),
+            target.as_ptr(),
+            option.as_ptr(),
+            value.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            0,
+        )
+    };
+    if result < 0 {
+        return Err(ffmpeg::Error::from(result));
+    }
+    Ok(())
+}
+
+fn to_io_error(err: ffmpeg::Error) -> Error {
+    Error::other(err)
+}
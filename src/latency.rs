@@ -0,0 +1,547 @@
+//! `--measure-latency`: stamps the recorded screen with a bar encoding the current time, and reads
+//! it back from the devices the pipeline writes to, to see how long frames take from the screen
+//! to each device.
+//!
+//! The bar is a row of black and white blocks in the top left corner of the screen, drawn as a
+//! layer-shell overlay that is redrawn with a new time for every frame the compositor shows. The
+//! first bits are a fixed marker, so frames without the bar are ignored.
+
+use crate::{i18n, runtime_dir, Resolution};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{
+    wl_buffer, wl_callback, wl_compositor, wl_output, wl_region, wl_registry, wl_shm, wl_shm_pool,
+    wl_surface,
+};
+use wayland_client::{delegate_noop, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1 as layer_shell, zwlr_layer_surface_v1 as layer_surface,
+};
+
+const MARKER: u32 = 0b1011;
+const MARKER_BITS: usize = 4;
+const TIME_BITS: usize = 20;
+const BITS: usize = MARKER_BITS + TIME_BITS;
+/// Size of a block in surface coordinates
+const BLOCK: i32 = 8;
+const WIDTH: i32 = BITS as i32 * BLOCK;
+const HEIGHT: i32 = BLOCK;
+/// Timestamps are milliseconds, wrapping around after about 17 minutes
+const TIME_MASK: u32 = (1 << TIME_BITS) - 1;
+/// Anything slower than this is a frame that was repeated, not a real measurement
+const MAX_LATENCY: u32 = 5000;
+
+/// wl_output names and integer scales
+type Outputs = Arc<Mutex<HashMap<ObjectId, (wl_output::WlOutput, String, i32)>>>;
+
+/// Latencies in milliseconds measured since the last report, for every stage.
+type Samples = Arc<Mutex<Vec<(String, Vec<u32>)>>>;
+
+struct Buffer {
+    buffer: wl_buffer::WlBuffer,
+    offset: u64,
+    busy: bool,
+}
+
+struct State {
+    outputs: Outputs,
+    file: File,
+    buffers: Vec<Buffer>,
+    /// The surface the bar is drawn on, older ones are left alone until they are destroyed
+    current: Arc<Mutex<Option<ObjectId>>>,
+    epoch: Instant,
+}
+
+/// The stamp for the current time, with the most significant bit first.
+fn stamp(epoch: Instant) -> u32 {
+    (MARKER << TIME_BITS) | (epoch.elapsed().as_millis() as u32 & TIME_MASK)
+}
+
+/// Reads a stamp back, from one gray value for every block.
+fn decode(blocks: &[u8]) -> Option<u32> {
+    let value = blocks
+        .iter()
+        .fold(0, |value, block| (value << 1) | (*block >= 128) as u32);
+    if value >> TIME_BITS != MARKER {
+        return None;
+    }
+    Some(value & TIME_MASK)
+}
+
+impl State {
+    /// Draws the current time into a free buffer and shows it, asking for a callback once the
+    /// compositor has shown it so the next one can be drawn.
+    fn draw(&mut self, surface: &wl_surface::WlSurface, qh: &QueueHandle<State>) {
+        if self.current.lock().unwrap().as_ref() != Some(&surface.id()) {
+            return;
+        }
+
+        surface.frame(qh, surface.clone());
+        if let Some(buffer) = self.buffers.iter_mut().find(|buffer| !buffer.busy) {
+            let value = stamp(self.epoch);
+            let mut pixels = Vec::with_capacity((WIDTH * HEIGHT * 4) as usize);
+            for _ in 0..HEIGHT {
+                for bit in (0..BITS).rev() {
+                    let color: u32 = if value >> bit & 1 == 1 {
+                        0xffff_ffff
+                    } else {
+                        0xff00_0000
+                    };
+                    for _ in 0..BLOCK {
+                        pixels.extend_from_slice(&color.to_le_bytes());
+                    }
+                }
+            }
+
+            if self.file.write_all_at(&pixels, buffer.offset).is_ok() {
+                buffer.busy = true;
+                surface.attach(Some(&buffer.buffer), 0, 0);
+                surface.damage_buffer(0, 0, WIDTH, HEIGHT);
+            }
+        }
+        surface.commit();
+    }
+}
+
+/// The stamp on a screen, and the processes reading it back from the devices.
+#[derive(Debug)]
+pub struct Meter {
+    conn: Connection,
+    qh: QueueHandle<State>,
+    compositor: wl_compositor::WlCompositor,
+    layer_shell: layer_shell::ZwlrLayerShellV1,
+    outputs: Outputs,
+    current: Arc<Mutex<Option<ObjectId>>>,
+    surface: Option<(wl_surface::WlSurface, layer_surface::ZwlrLayerSurfaceV1)>,
+    epoch: Instant,
+    readers: Vec<Child>,
+    samples: Samples,
+}
+
+impl Meter {
+    /// Stamps `output`, and reads the stamp back from every device in `stages`, each given with
+    /// a name, the device number and its resolution. The screen is assumed to be scaled to fit
+    /// the device's resolution and centered on it, like the upscaler does.
+    pub fn measure(
+        &mut self,
+        ffmpeg_path: &str,
+        output: &str,
+        resolution: Resolution,
+        stages: &[(&str, usize, Resolution)],
+    ) {
+        self.stop();
+
+        let found = self
+            .outputs
+            .lock()
+            .unwrap()
+            .values()
+            .find(|(_, name, _)| name == output)
+            .map(|(output, _, scale)| (output.clone(), *scale));
+        let (wl_output, scale) = match found {
+            Some(found) => found,
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr("Could not find {} to measure latency on", &[&output])
+                );
+                return;
+            }
+        };
+
+        let surface = self.compositor.create_surface(&self.qh, ());
+        let region = self.compositor.create_region(&self.qh, ());
+        surface.set_input_region(Some(&region));
+        region.destroy();
+        // The bar is drawn at its size in surface coordinates, and scaled up by the compositor
+        let layer = self.layer_shell.get_layer_surface(
+            &surface,
+            Some(&wl_output),
+            layer_shell::Layer::Overlay,
+            "wlstreamer-latency".to_string(),
+            &self.qh,
+            surface.clone(),
+        );
+        layer.set_size(WIDTH as u32, HEIGHT as u32);
+        layer.set_anchor(layer_surface::Anchor::Top | layer_surface::Anchor::Left);
+        layer.set_exclusive_zone(-1);
+        *self.current.lock().unwrap() = Some(surface.id());
+        surface.commit();
+        self.surface = Some((surface, layer));
+        if let Err(err) = self.conn.flush() {
+            println!(
+                "{}",
+                i18n::tr("Could not show the latency stamp: {}", &[&err])
+            );
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        for (index, (name, device_number, target)) in stages.iter().enumerate() {
+            samples.push((name.to_string(), Vec::new()));
+
+            let factor = f64::min(
+                target.width as f64 / resolution.width as f64,
+                target.height as f64 / resolution.height as f64,
+            );
+            let x = (target.width as f64 - resolution.width as f64 * factor) / 2.0;
+            let y = (target.height as f64 - resolution.height as f64 * factor) / 2.0;
+            let width = (WIDTH * scale) as f64 * factor;
+            let height = (HEIGHT * scale) as f64 * factor;
+            // Every block is averaged down to a single gray pixel
+            let filter = format!(
+                "crop={}:{}:{}:{},scale={}:1:flags=area,format=gray",
+                width as usize, height as usize, x as usize, y as usize, BITS
+            );
+
+            let reader = Command::new(ffmpeg_path)
+                .args(["-loglevel", "error", "-f", "v4l2", "-i"])
+                .arg(format!("/dev/video{}", device_number))
+                .args(["-vf", filter.as_str(), "-f", "rawvideo", "-"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut reader = match reader {
+                Ok(reader) => reader,
+                Err(err) => {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "Could not read back /dev/video{}: {}",
+                            &[&device_number, &err]
+                        )
+                    );
+                    continue;
+                }
+            };
+
+            if let Some(stdout) = reader.stdout.take() {
+                let samples = self.samples.clone();
+                let epoch = self.epoch;
+                thread::spawn(move || read_stamps(stdout, epoch, samples, index));
+            }
+            self.readers.push(reader);
+        }
+    }
+
+    /// Removes the stamp and stops reading it back, while nothing is being recorded.
+    pub fn stop(&mut self) {
+        for reader in self.readers.iter_mut() {
+            let _ = reader.kill();
+            let _ = reader.wait();
+        }
+        self.readers.clear();
+        self.samples.lock().unwrap().clear();
+
+        *self.current.lock().unwrap() = None;
+        if let Some((surface, layer)) = self.surface.take() {
+            layer.destroy();
+            surface.destroy();
+            let _ = self.conn.flush();
+        }
+    }
+
+    /// Logs the latencies every `interval` on a background thread.
+    pub fn log_every(&self, interval: Duration) {
+        let samples = self.samples.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            println!("{}", i18n::tr("Latency: {}", &[&summary(&samples)]));
+        });
+    }
+}
+
+/// The latency of every stage since the last report.
+fn summary(samples: &Samples) -> String {
+    let mut samples = samples.lock().unwrap();
+    if samples.is_empty() {
+        return "nothing is being recorded".to_string();
+    }
+
+    let stages: Vec<String> = samples
+        .iter_mut()
+        .map(|(name, latencies)| {
+            let summary = if latencies.is_empty() {
+                format!("{}: no stamped frames", name)
+            } else {
+                let average = latencies.iter().sum::<u32>() / latencies.len() as u32;
+                format!(
+                    "{}: {}ms (min {}ms, max {}ms)",
+                    name,
+                    average,
+                    latencies.iter().min().unwrap(),
+                    latencies.iter().max().unwrap()
+                )
+            };
+            latencies.clear();
+            summary
+        })
+        .collect();
+
+    stages.join(", ")
+}
+
+/// Decodes the stamps of every frame a reader outputs, and records how old they are.
+fn read_stamps(mut stdout: impl Read, epoch: Instant, samples: Samples, index: usize) {
+    let mut blocks = [0; BITS];
+    let mut previous = None;
+    while stdout.read_exact(&mut blocks).is_ok() {
+        let now = epoch.elapsed().as_millis() as u32 & TIME_MASK;
+        let stamp = match decode(&blocks) {
+            Some(stamp) => stamp,
+            None => continue,
+        };
+        // Devices repeat frames, only the first time a stamp shows up counts
+        if previous == Some(stamp) {
+            continue;
+        }
+        previous = Some(stamp);
+
+        let latency = now.wrapping_sub(stamp) & TIME_MASK;
+        if latency <= MAX_LATENCY {
+            if let Some((_, latencies)) = samples.lock().unwrap().get_mut(index) {
+                latencies.push(latency);
+            }
+        }
+    }
+}
+
+/// Creates the two buffers the stamp is drawn into in turn.
+fn create_buffers(
+    shm: &wl_shm::WlShm,
+    qh: &QueueHandle<State>,
+) -> Result<(File, Vec<Buffer>), String> {
+    let path = runtime_dir::join(&format!("wlstreamer-latency-{}", process::id()))?;
+
+    let file = runtime_dir::open(
+        &path,
+        OpenOptions::new().read(true).write(true).create_new(true),
+    )?;
+    let _ = fs::remove_file(&path);
+
+    let size = WIDTH * HEIGHT * 4;
+    file.set_len(size as u64 * 2)
+        .map_err(|err| i18n::tr("Could not create the latency stamp: {}", &[&err]))?;
+
+    let pool = shm.create_pool(file.as_fd(), size * 2, qh, ());
+    let buffers = (0..2)
+        .map(|index| Buffer {
+            buffer: pool.create_buffer(
+                size * index,
+                WIDTH,
+                HEIGHT,
+                WIDTH * 4,
+                wl_shm::Format::Argb8888,
+                qh,
+                index as usize,
+            ),
+            offset: (size * index) as u64,
+            busy: false,
+        })
+        .collect();
+    pool.destroy();
+
+    Ok((file, buffers))
+}
+
+/// Connects to the compositor and keeps drawing the stamp on a background thread.
+pub fn start() -> Result<Meter, String> {
+    let conn = Connection::connect_to_env()
+        .map_err(|err| i18n::tr("Could not connect to the Wayland display: {}", &[&err]))?;
+    let (globals, mut queue) = registry_queue_init::<State>(&conn)
+        .map_err(|err| i18n::tr("Could not list Wayland globals: {}", &[&err]))?;
+    let qh = queue.handle();
+
+    let compositor = globals
+        .bind::<wl_compositor::WlCompositor, _, _>(&qh, 1..=4, ())
+        .map_err(|_| i18n::tr("The compositor has no wl_compositor", &[]))?;
+    let layer_shell = globals
+        .bind::<layer_shell::ZwlrLayerShellV1, _, _>(&qh, 1..=1, ())
+        .map_err(|_| i18n::tr("The compositor does not support wlr-layer-shell", &[]))?;
+    let shm = globals
+        .bind::<wl_shm::WlShm, _, _>(&qh, 1..=1, ())
+        .map_err(|_| i18n::tr("The compositor has no wl_shm", &[]))?;
+
+    let outputs = Outputs::default();
+    for global in globals.contents().clone_list() {
+        if global.interface == wl_output::WlOutput::interface().name {
+            bind_output(
+                &outputs,
+                globals.registry(),
+                global.name,
+                global.version,
+                &qh,
+            );
+        }
+    }
+
+    let (file, buffers) = create_buffers(&shm, &qh)?;
+    let current = Arc::default();
+    let epoch = Instant::now();
+    let mut state = State {
+        outputs: outputs.clone(),
+        file,
+        buffers,
+        current: Arc::clone(&current),
+        epoch,
+    };
+    queue
+        .roundtrip(&mut state)
+        .map_err(|err| i18n::tr("Could not read the Wayland state: {}", &[&err]))?;
+
+    thread::spawn(move || loop {
+        if let Err(err) = queue.blocking_dispatch(&mut state) {
+            println!(
+                "{}",
+                i18n::tr("Lost connection to the Wayland display: {}", &[&err])
+            );
+            break;
+        }
+    });
+
+    Ok(Meter {
+        conn,
+        qh,
+        compositor,
+        layer_shell,
+        outputs,
+        current,
+        surface: None,
+        epoch,
+        readers: Vec::new(),
+        samples: Arc::default(),
+    })
+}
+
+fn bind_output(
+    outputs: &Outputs,
+    registry: &wl_registry::WlRegistry,
+    name: u32,
+    version: u32,
+    qh: &QueueHandle<State>,
+) {
+    // Names are only sent from version 4 on
+    if version < 4 {
+        return;
+    }
+    let output: wl_output::WlOutput = registry.bind(name, 4, qh, name);
+    outputs
+        .lock()
+        .unwrap()
+        .insert(output.id(), (output, String::new(), 1));
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == wl_output::WlOutput::interface().name => {
+                bind_output(&state.outputs, registry, name, version, qh);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                state
+                    .outputs
+                    .lock()
+                    .unwrap()
+                    .retain(|_, (output, ..)| output.data::<u32>() != Some(&name));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, u32> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        _: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let mut outputs = state.outputs.lock().unwrap();
+        let entry = match outputs.get_mut(&output.id()) {
+            Some(entry) => entry,
+            None => return,
+        };
+        match event {
+            wl_output::Event::Name { name } => entry.1 = name,
+            wl_output::Event::Scale { factor } => entry.2 = factor,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<layer_surface::ZwlrLayerSurfaceV1, wl_surface::WlSurface> for State {
+    fn event(
+        state: &mut Self,
+        layer: &layer_surface::ZwlrLayerSurfaceV1,
+        event: layer_surface::Event,
+        surface: &wl_surface::WlSurface,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let layer_surface::Event::Configure { serial, .. } = event {
+            layer.ack_configure(serial);
+            state.draw(surface, qh);
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, wl_surface::WlSurface> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        surface: &wl_surface::WlSurface,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.draw(surface, qh);
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            if let Some(buffer) = state.buffers.get_mut(*index) {
+                buffer.busy = false;
+            }
+        }
+    }
+}
+
+delegate_noop!(State: ignore wl_compositor::WlCompositor);
+delegate_noop!(State: ignore wl_surface::WlSurface);
+delegate_noop!(State: ignore wl_region::WlRegion);
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_shm_pool::WlShmPool);
+delegate_noop!(State: ignore layer_shell::ZwlrLayerShellV1);
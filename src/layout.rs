@@ -0,0 +1,152 @@
+//! The outputs and workspaces of the window manager, kept between its events instead of being
+//! asked for again on every one. Workspace focus changes on sway and i3 are applied to the kept
+//! workspaces, other events drop what they may have changed, and everything is asked for again
+//! after 10 seconds at the latest, in case an event went missing.
+
+use crate::backend::Backend;
+use crate::{SwayOutput, SwayWorkspace};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const REFRESH_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Entry<T> {
+    fetched: Instant,
+    value: Vec<T>,
+}
+
+impl<T: Clone> Entry<T> {
+    fn fresh(entry: &Option<Entry<T>>) -> Option<Vec<T>> {
+        entry
+            .as_ref()
+            .filter(|entry| entry.fetched.elapsed() < REFRESH_AFTER)
+            .map(|entry| entry.value.clone())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Kept {
+    outputs: Option<Entry<SwayOutput>>,
+    workspaces: Option<Entry<SwayWorkspace>>,
+}
+
+/// Shared between the thread reading the events and the one deciding what to stream.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    kept: Arc<Mutex<Kept>>,
+}
+
+impl Layout {
+    /// The outputs as they were last asked for with `query`, or asked for now. A failed query
+    /// isn't kept, so the next call asks again.
+    pub fn outputs<F>(&self, query: F) -> Result<Vec<SwayOutput>, String>
+    where
+        F: FnOnce() -> Result<Vec<SwayOutput>, String>,
+    {
+        if let Some(outputs) = Entry::fresh(&self.kept.lock().unwrap().outputs) {
+            return Ok(outputs);
+        }
+
+        let outputs = query()?;
+        self.kept.lock().unwrap().outputs = Some(Entry {
+            fetched: Instant::now(),
+            value: outputs.clone(),
+        });
+        Ok(outputs)
+    }
+
+    /// The workspaces as they were last asked for with `query`, or asked for now. A failed query
+    /// isn't kept, so the next call asks again.
+    pub fn workspaces<F>(&self, query: F) -> Result<Vec<SwayWorkspace>, String>
+    where
+        F: FnOnce() -> Result<Vec<SwayWorkspace>, String>,
+    {
+        if let Some(workspaces) = Entry::fresh(&self.kept.lock().unwrap().workspaces) {
+            return Ok(workspaces);
+        }
+
+        let workspaces = query()?;
+        self.kept.lock().unwrap().workspaces = Some(Entry {
+            fetched: Instant::now(),
+            value: workspaces.clone(),
+        });
+        Ok(workspaces)
+    }
+
+    /// Drops everything, so it is asked for again.
+    pub fn invalidate(&self) {
+        let mut kept = self.kept.lock().unwrap();
+        kept.outputs = None;
+        kept.workspaces = None;
+    }
+
+    fn invalidate_workspaces(&self) {
+        self.kept.lock().unwrap().workspaces = None;
+    }
+
+    /// Applies an event of the window manager, as printed by its subscribe command.
+    pub fn apply(&self, backend: Backend, event: &Value) {
+        match backend {
+            Backend::Sway | Backend::I3 => self.apply_sway(event),
+            Backend::Niri => {
+                let name = event
+                    .as_object()
+                    .and_then(|event| event.keys().next())
+                    .map(String::as_str);
+                match name {
+                    Some("WorkspacesChanged") | None => self.invalidate(),
+                    Some("WorkspaceActivated") => self.invalidate_workspaces(),
+                    _ => {}
+                }
+            }
+            Backend::ForeignToplevel => {}
+        }
+    }
+
+    fn apply_sway(&self, event: &Value) {
+        let change = event["change"].as_str().unwrap_or_default();
+
+        if event.get("container").is_some() {
+            // A window moved to another workspace may have created or emptied one
+            if change == "move" {
+                self.invalidate_workspaces();
+            }
+        } else if let Some(current) = event.get("current") {
+            match (change, current["name"].as_str()) {
+                ("focus", Some(name)) => {
+                    if !self.focus(name) {
+                        self.invalidate_workspaces();
+                    }
+                }
+                ("urgent", _) => {}
+                _ => self.invalidate_workspaces(),
+            }
+        } else {
+            self.invalidate();
+        }
+    }
+
+    /// Marks the workspace with this name as the focused one, and as the one visible on its
+    /// output. Returns false if it isn't known.
+    fn focus(&self, name: &str) -> bool {
+        let mut kept = self.kept.lock().unwrap();
+        let workspaces = match kept.workspaces.as_mut() {
+            Some(entry) => &mut entry.value,
+            None => return true,
+        };
+        let output = match workspaces.iter().find(|w| w.name == name) {
+            Some(workspace) => workspace.output.clone(),
+            None => return false,
+        };
+
+        for workspace in workspaces.iter_mut() {
+            workspace.focused = workspace.name == name;
+            if workspace.output == output {
+                workspace.visible = workspace.focused;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,293 @@
+//! Checks on the v4l2loopback devices before anything is written to them, since wf-recorder and
+//! ffmpeg only fail with cryptic errors when a device is missing or taken.
+
+use crate::mode::Mode;
+use crate::{get_outputs, i18n, runtime_dir, sink, Config, Resolution};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// `VIDIOC_QUERYCAP`, `_IOR('V', 0, struct v4l2_capability)`
+const VIDIOC_QUERYCAP: libc::c_ulong = 0x8068_5600;
+pub const V4L2_CAP_VIDEO_CAPTURE: u32 = 0x1;
+pub const V4L2_CAP_VIDEO_OUTPUT: u32 = 0x2;
+/// The driver name v4l2loopback devices report
+const DRIVER: &str = "v4l2 loopback";
+/// The pixel format wf-recorder and ffmpeg write, as v4l2loopback shows it in sysfs
+const FOURCC: &str = "YUYV";
+
+#[repr(C)]
+pub struct Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    /// What the device can do right now, which with `exclusive_caps=1` is only capturing while
+    /// something writes to it, and only writing otherwise
+    pub device_caps: u32,
+    reserved: [u32; 3],
+}
+
+/// A nul-terminated string of `VIDIOC_QUERYCAP`.
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+impl Capability {
+    fn driver(&self) -> String {
+        c_string(&self.driver)
+    }
+
+    fn card(&self) -> String {
+        c_string(&self.card)
+    }
+}
+
+/// Asks the open `device` what it is and what it can do.
+pub fn query(file: &File, device: &str) -> Result<Capability, String> {
+    let mut capability: Capability = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(file.as_raw_fd(), VIDIOC_QUERYCAP, &mut capability) } < 0 {
+        return Err(i18n::tr(
+            "Could not ask {} what it can do: {}",
+            &[&device, &std::io::Error::last_os_error()],
+        ));
+    }
+
+    Ok(capability)
+}
+
+/// The pixel format and size v4l2loopback shows for a device a producer set up, as in
+/// `YUYV:1920x1080@30`.
+fn parse_format(format: &str) -> Option<(&str, usize, usize)> {
+    let (fourcc, rest) = format.trim().split_once(':')?;
+    let size = rest.split('@').next()?;
+    let (width, height) = size.split_once('x')?;
+    Some((fourcc, width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Checks that `/dev/video{device_number}` is a v4l2loopback device we can write to, that no other
+/// program writes to it, and that it isn't fixed to another pixel format or to a different size
+/// than `resolution`.
+pub fn validate(device_number: usize, resolution: Option<Resolution>) -> Result<(), String> {
+    let device = format!("/dev/video{}", device_number);
+
+    match fs::metadata(&device) {
+        Ok(metadata) if metadata.file_type().is_char_device() => {}
+        Ok(_) => return Err(i18n::tr("{} is not a video device", &[&device])),
+        Err(_) => {
+            return Err(i18n::tr(
+                "{} does not exist, load v4l2loopback with enough devices, for example modprobe v4l2loopback devices={}",
+                &[&device, &(device_number + 1)]
+            ))
+        }
+    }
+
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&device)
+        .map_err(|err| {
+            i18n::tr(
+                "Could not open {}: {}. Is your user in the video group?",
+                &[&device, &err],
+            )
+        })?;
+
+    let capability =
+        query(&file, &device).map_err(|_| i18n::tr("{} is not a video device", &[&device]))?;
+    if capability.driver() != DRIVER {
+        return Err(i18n::tr(
+            "{} is not a v4l2loopback device but {} ({}), pick other devices with --devices-from",
+            &[&device, &capability.card(), &capability.driver()],
+        ));
+    }
+    // With exclusive_caps=1, a device can only be written to while nothing else writes to it
+    if capability.device_caps & V4L2_CAP_VIDEO_OUTPUT == 0 {
+        return Err(i18n::tr(
+            "{} is busy, another program writes to it. Stop it, or use other devices with --devices-from",
+            &[&device]
+        ));
+    }
+
+    // Without sysfs there is nothing more to check
+    let format = fs::read_to_string(
+        Path::new("/sys/class/video4linux")
+            .join(format!("video{}", device_number))
+            .join("format"),
+    )
+    .unwrap_or_default();
+    let (fourcc, width, height) = match parse_format(&format) {
+        Some(format) => format,
+        None => return Ok(()),
+    };
+
+    if fourcc != FOURCC {
+        return Err(i18n::tr(
+            "{} is already in use with {} frames, but wlstreamer writes {}. Stop whatever else writes to it, or use other devices with --devices-from",
+            &[&device, &fourcc, &FOURCC]
+        ));
+    }
+    match resolution {
+        Some(resolution) if (width, height) != (resolution.width, resolution.height) => {
+            Err(i18n::tr(
+                "{} is already in use at {}x{}, but needs to be {}x{}. Stop whatever else writes to it, or use other devices with --devices-from",
+                &[&device, &width, &height, &resolution.width, &resolution.height]
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The number of a video device given by its path, following links like the ones in
+/// /dev/v4l/by-id, so `/dev/video10` is 10.
+pub fn device_number(path: &str) -> Result<usize, String> {
+    let device =
+        fs::canonicalize(path).map_err(|err| i18n::tr("Could not find {}: {}", &[&path, &err]))?;
+    device
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("video"))
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| i18n::tr("{} is not a video device like /dev/video10", &[&path]))
+}
+
+/// The devices wlstreamer is going to write to, with the resolution they'll get if that is known
+/// up front.
+pub fn devices_in_use(config: &Config) -> Vec<(usize, Option<Resolution>)> {
+    if config.options.mode == Mode::Headless {
+        return vec![(
+            config.options.devices_from,
+            Some(config.runtime.resolutions[0]),
+        )];
+    }
+    if config.options.mode == Mode::Follow && !config.options.standby {
+        let mut devices: Vec<(usize, Option<Resolution>)> = config
+            .runtime
+            .outputs
+            .iter()
+            .map(|(resolution, device_number)| (*device_number, Some(*resolution)))
+            .chain(
+                config
+                    .runtime
+                    .through_device
+                    .map(|device_number| (device_number, None)),
+            )
+            .collect();
+        devices.sort_by_key(|(device_number, _)| *device_number);
+        return devices;
+    }
+
+    let mut outputs = get_outputs(config);
+    outputs.retain(|o| {
+        !config.screen_blacklisted(&o.name)
+            && (config.options.side_by_side.is_empty()
+                || config.options.side_by_side.contains(&o.name))
+    });
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut devices = Vec::new();
+    let mut first = config.options.devices_from;
+    if config.options.mode != Mode::MirrorAll {
+        let canvas = if config.options.mode != Mode::SideBySide {
+            Some(config.runtime.resolutions[0])
+        } else {
+            None
+        };
+        devices.push((config.options.devices_from, canvas));
+        first += 1;
+    }
+    for (i, output) in outputs.iter().enumerate() {
+        devices.push((first + i, Some(output.resolution())));
+    }
+
+    devices
+}
+
+/// Makes v4l2loopback repeat the last frame of `/dev/video{device_number}` when the producer
+/// doesn't write a new one in time, so readers keep getting frames from a static screen.
+pub fn sustain_frame_rate(device_number: usize) -> Result<(), String> {
+    let device = format!("/dev/video{}", device_number);
+    let output = Command::new("v4l2-ctl")
+        .args(["-d", device.as_str(), "-c", "sustain_framerate=1"])
+        .output()
+        .map_err(|err| i18n::tr("Could not run v4l2-ctl: {}", &[&err]))?;
+
+    if !output.status.success() {
+        return Err(i18n::tr(
+            "Could not keep the frame rate of {} up: {}",
+            &[&device, &String::from_utf8_lossy(&output.stderr).trim()],
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks every device that is going to be used, stopping at the first problem.
+pub fn validate_all(config: &Config) -> Result<(), String> {
+    for (device_number, resolution) in devices_in_use(config) {
+        validate(device_number, resolution)?;
+    }
+
+    Ok(())
+}
+
+/// `wlstreamer-video<n>.lock` in the runtime dir, held by the instance writing to the device.
+fn lock_path(device_number: usize) -> Result<PathBuf, String> {
+    runtime_dir::join(&format!("wlstreamer-video{}.lock", device_number))
+}
+
+/// Locks `/dev/video{device_number}`, so a second instance doesn't write to it as well. The lock is
+/// held for as long as the returned file is open, and is released by the kernel whenever the
+/// process exits.
+pub fn lock(device_number: usize) -> Result<File, String> {
+    let path = lock_path(device_number)?;
+    let mut file = runtime_dir::open(
+        &path,
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false),
+    )?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let mut pid = String::new();
+        let _ = file.read_to_string(&mut pid);
+        return Err(i18n::tr(
+            "/dev/video{} is used by another wlstreamer (process {}), give this one other devices with --devices-from",
+            &[&device_number, &pid.trim()]
+        ));
+    }
+
+    // The pid is only there to tell who has the lock
+    let _ = file
+        .set_len(0)
+        .and_then(|_| file.rewind())
+        .and_then(|_| write!(file, "{}", process::id()));
+    Ok(file)
+}
+
+/// Locks the devices that are going to be written to, including those of device sinks, by device
+/// number.
+pub fn lock_all(config: &Config) -> Result<HashMap<usize, File>, String> {
+    let mut device_numbers: Vec<usize> = devices_in_use(config)
+        .into_iter()
+        .map(|(device_number, _)| device_number)
+        .chain(sink::loopback_devices(&config.options.every_sink()))
+        .collect();
+    device_numbers.sort_unstable();
+    device_numbers.dedup();
+
+    device_numbers
+        .into_iter()
+        .map(|device_number| Ok((device_number, lock(device_number)?)))
+        .collect()
+}
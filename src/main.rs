@@ -1,16 +1,95 @@
+mod audio;
+mod backend;
+mod bench;
+mod bindings;
+mod bitrate;
+mod capture;
+mod chapters;
+mod children;
+mod cli;
+mod color;
+mod compat;
+mod config_file;
+mod consumers;
+mod control;
+mod dbus;
+mod dnd;
+mod event_log;
+mod grid;
+mod headless;
+mod health;
+mod hls;
+mod i18n;
+mod idle;
+mod image_copy;
+mod indicator;
+#[cfg(feature = "ffmpeg")]
+mod inprocess;
+mod latency;
+mod layout;
+mod loopback;
+mod mask;
+mod mirror;
+mod mode;
+#[cfg(feature = "ndi")]
+mod ndi;
+mod niri;
+mod pipeline;
+mod power;
+mod preview;
+mod quality;
+mod replay;
+mod runtime_dir;
+mod scene;
+mod schedule;
+mod screenshot;
+mod secrets;
+mod segments;
+mod selftest;
+mod service;
+mod session;
+mod signals;
+mod sink;
+mod state;
+mod stats;
+mod status;
+mod subscribe;
+mod systemd;
+mod timeline;
+mod tools;
+mod toplevel;
+mod tray;
+mod tui;
+mod windows;
+mod zoom;
+
+use backend::{Backend, IpcRequest};
+use capture::CaptureProtocol;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use mirror::Mirrors;
+use mode::Mode;
+use pipeline::Pipeline;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use state::{StreamerState, Target};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fs::File;
 use std::io::{BufRead, BufReader, Error};
-use std::process::{Child, Command, Stdio};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::{thread, time};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// Outputs left of or above the origin have negative positions
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+#[serde(default)]
 struct SwayScreenRect {
-    x: usize,
-    y: usize,
+    x: i64,
+    y: i64,
     width: usize,
     height: usize,
 }
@@ -18,260 +97,1377 @@ struct SwayScreenRect {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct SwayWorkspace {
     name: String,
+    #[serde(default)]
     focus: Vec<usize>,
+    #[serde(default)]
     output: String,
+    #[serde(default)]
     focused: bool,
+    #[serde(default)]
     rect: SwayScreenRect,
+    #[serde(default)]
     visible: bool,
-    num: usize,
+    // sway gives workspaces without a number -1
+    #[serde(default, deserialize_with = "lenient")]
+    num: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct SwayOutputMode {
     width: usize,
     height: usize,
+    #[serde(default)]
     refresh: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct SwayOutput {
     name: String,
+    #[serde(default = "default_active")]
+    active: bool,
+    #[serde(default)]
     rect: SwayScreenRect,
-    current_mode: SwayOutputMode,
+    // i3 has no modes, its outputs are only described by their rect, and disabled outputs in
+    // sway have a null mode
+    #[serde(default, deserialize_with = "lenient")]
+    current_mode: Option<SwayOutputMode>,
+    // sway before 1.8 only has dpms, newer versions have both
+    #[serde(default, deserialize_with = "lenient")]
+    power: Option<bool>,
+    #[serde(default, deserialize_with = "lenient")]
+    dpms: Option<bool>,
+    // How the output is rotated or flipped, in sway's names like 90 or flipped-270
+    #[serde(default, deserialize_with = "lenient")]
+    transform: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    scale: Option<f64>,
+    // Only sway 1.11 and newer tell whether an output runs in HDR mode
+    #[serde(default, deserialize_with = "lenient")]
+    hdr: Option<bool>,
+    /// The size frames are scaled to when capturing, with --normalize-scale
+    #[serde(skip)]
+    scaled_to: Option<Resolution>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Deserializes a field that compositors or their versions set to null or to another type than
+/// expected as missing, instead of failing on the whole reply.
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+impl SwayOutput {
+    /// Whether the output is enabled and has a size. i3 lists the X root window as an extra,
+    /// inactive output, and sway, niri and wl_output list disabled outputs too, like the screen
+    /// of a laptop with its lid closed.
+    fn enabled(&self) -> bool {
+        let resolution = self.mode_resolution();
+        self.active && resolution.width > 0 && resolution.height > 0
+    }
+
+    /// Whether the output is turned on. Recording one that is powered off only gives a frozen
+    /// or broken stream.
+    fn powered(&self) -> bool {
+        self.power.or(self.dpms).unwrap_or(true)
+    }
+
+    /// Whether the output runs in HDR mode, so its frames have to be tone mapped.
+    fn hdr(&self) -> bool {
+        self.hdr.unwrap_or(false)
+    }
+
+    /// Whether the output is turned on its side, so its mode is the wrong way around.
+    fn rotated(&self) -> bool {
+        matches!(
+            self.transform.as_deref(),
+            Some("90" | "270" | "flipped-90" | "flipped-270")
+        )
+    }
+
+    /// The ffmpeg filter turning captured frames upright. wlr-screencopy hands out frames the way
+    /// they are sent to the monitor, before the output transform is undone by the monitor
+    /// itself.
+    fn transform_filter(&self) -> Option<&'static str> {
+        match self.transform.as_deref()? {
+            "90" => Some("transpose=clock"),
+            "180" => Some("hflip,vflip"),
+            "270" => Some("transpose=cclock"),
+            "flipped" => Some("hflip"),
+            "flipped-90" => Some("transpose=cclock_flip"),
+            "flipped-180" => Some("vflip"),
+            "flipped-270" => Some("transpose=clock_flip"),
+            _ => None,
+        }
+    }
+
+    /// The filters applied while capturing, to turn frames upright and scale them.
+    fn capture_filter(&self) -> Option<String> {
+        let mut filters: Vec<String> = self
+            .transform_filter()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if let Some(size) = self.scaled_to {
+            filters.push(format!("scale={}:{}", size.width, size.height));
+        }
+
+        if filters.is_empty() {
+            None
+        } else {
+            Some(filters.join(","))
+        }
+    }
+
+    /// The size of the output in the layout, which is its resolution divided by its scale.
+    /// Rounded to even numbers, since yuyv422 can't have odd widths.
+    fn logical_resolution(&self) -> Resolution {
+        let resolution = self.mode_resolution();
+        let scale = self.scale.filter(|scale| *scale > 0.0).unwrap_or(1.0);
+        Resolution {
+            height: (resolution.height as f64 / scale).round() as usize & !1,
+            width: (resolution.width as f64 / scale).round() as usize & !1,
+        }
+    }
+
+    /// The size of the frames captured from this output.
+    fn resolution(&self) -> Resolution {
+        self.scaled_to.unwrap_or_else(|| self.mode_resolution())
+    }
+
+    fn mode_resolution(&self) -> Resolution {
+        match &self.current_mode {
+            Some(mode) if self.rotated() => Resolution {
+                height: mode.width,
+                width: mode.height,
+            },
+            Some(mode) => Resolution {
+                height: mode.height,
+                width: mode.width,
+            },
+            None => Resolution {
+                height: self.rect.height,
+                width: self.rect.width,
+            },
+        }
+    }
+
+    /// The refresh rate in whole frames per second, if the window manager tells it.
+    fn refresh_rate(&self) -> Option<u32> {
+        self.current_mode
+            .as_ref()
+            .map(|mode| ((mode.refresh + 500) / 1000) as u32)
+            .filter(|rate| *rate > 0)
+    }
 }
 
-#[derive(Copy, Clone, Hash, Eq, Debug)]
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
 struct Resolution {
     height: usize,
     width: usize,
 }
 
+/// The options wlstreamer was started with, from the command line and config file. They are
+/// settled before `Config` is put together and only read afterwards, what changes later is in
+/// `RuntimeState`.
 #[derive(Debug)]
-struct Config {
-    current_output: String,
+struct Options {
     devices_from: usize,
-    last_device_index: usize,
+    /// Devices for the captures of resolutions other than the combined one, in order, instead of
+    /// the ones after the output device
+    intermediate_devices: Vec<usize>,
     screen_blacklist: Vec<String>,
-    workspace_blacklist: Vec<usize>,
+    workspace_blacklist: Vec<config_file::WorkspaceId>,
     verbose: bool,
+    capture_protocol: CaptureProtocol,
+    gpu_scale: bool,
+    render_node: Option<String>,
+    /// The render node given with --drm-device, instead of picking one
+    drm_device: Option<String>,
+    wf_recorder_args: Vec<String>,
+    ffmpeg_args: Vec<String>,
+    /// Extra arguments for capturing single outputs, by name
+    capture_args: HashMap<String, Vec<String>>,
+    wf_recorder_path: String,
+    ffmpeg_path: String,
+    ipc_socket: Option<String>,
+    backend: Backend,
+    mode: Mode,
+    side_by_side: Vec<String>,
+    enlarge_focused: bool,
+    highlight: Option<String>,
+    highlight_width: usize,
+    profiles: HashMap<String, config_file::Profile>,
+    blank_when_idle: Option<u64>,
+    /// When the screens are streamed, with --schedule and --duration
+    schedule: schedule::Schedule,
+    dnd: bool,
+    show_indicator: bool,
+    /// Capture only while something reads from the output device
+    lazy: bool,
+    /// Constant frame rate of the stream
+    fps: Option<u32>,
+    /// Range and matrix of the frames on the devices
+    color: color::Color,
+    tonemap: color::Tonemap,
+    /// Frame rate to capture at while on battery
+    battery_fps: Option<u32>,
+    /// Only throttle once the battery is charged to this percentage or less
+    battery_threshold: u8,
+    measure_latency: bool,
+    /// Plan resolutions by the size of outputs in the layout instead of their pixels
+    normalize_scale: bool,
+    /// Only capture frames when the screen changed, with the devices repeating the last one
+    damage: bool,
+    stats_interval: Option<u64>,
+    /// Seconds without a frame before a pipeline is rebuilt, 0 to only rebuild it when part of it
+    /// exits
+    stall_timeout: u64,
+    /// URLs and files the stream is also encoded to
+    sinks: Vec<String>,
+    encoding: sink::Encoding,
+    transport: sink::Transport,
+    /// `NAME=value` lines with the secrets sink options refer to
+    secrets_file: Option<String>,
+    /// Directory, port or address for `--serve-hls`
+    serve_hls: Option<String>,
+    /// Address to serve the MJPEG preview on
+    preview_listen: Option<String>,
+    /// Seconds of the stream to keep for `wlstreamer ctl save-replay`
+    replay_buffer: Option<u32>,
+    /// Directory for a file per switch
+    record_segments: Option<String>,
+    /// Audio for sinks and the replay buffer
+    audio: audio::Audio,
+    /// app_ids of call apps, the stream is blanked while none of them has a window
+    call_apps: Vec<String>,
+    /// app_ids of apps whose windows may cut to their screen when they ask for attention
+    urgent_apps: Vec<String>,
+    /// How long to stay on the screen of a window asking for attention
+    urgent_seconds: u64,
+    /// Seconds to count down before going live, 0 to go live right away
+    countdown: u64,
+    /// Window titles that blank the stream while such a window is visible on the recorded output
+    redact: Vec<Regex>,
+    /// app_ids of apps whose windows are covered with black boxes
+    mask_apps: Vec<String>,
+    /// Whether to write the hidden workspace onto the black screen
+    hidden_text: bool,
+    /// Whether a hidden focused workspace blanks the stream, instead of streaming another screen
+    blank_on_hidden: bool,
+    /// Frame rate of the black screen, that of the screen recorded before if not set
+    placeholder_fps: Option<u32>,
+    /// Keep every screen recording into a device of its own in follow mode, so switching only
+    /// replaces the ffmpeg passing one of them on
+    standby: bool,
+    /// Stream what Chrome, Zoom and Teams accept from a camera
+    compat: bool,
+    /// Frame rate, size and encoding picked together
+    quality: quality::Quality,
+    /// How far to zoom in on the focused window in follow mode
+    zoom: Option<f64>,
+    /// How long the zoomed in part takes to get to the next window, in milliseconds
+    zoom_duration: u64,
+}
+
+/// What changes while wlstreamer runs.
+#[derive(Debug)]
+struct RuntimeState {
+    /// What `--mode follow` is streaming
+    streamer: StreamerState,
+    /// The combined resolution the output device gets, then those of the outputs
     resolutions: Vec<Resolution>,
+    /// How many milliseconds `--av-offset auto` delays the audio
+    audio_delay: i64,
     outputs: HashMap<Resolution, usize>,
+    /// The locks on the devices being written to, by device number
+    device_locks: HashMap<usize, File>,
+    toplevel: Option<toplevel::Tracker>,
+    indicator: Option<indicator::Indicator>,
+    /// Switched to with wlstreamer ctl profile, what it doesn't set comes from the options
+    profile: config_file::Profile,
+    /// Outputs locked through wlstreamer ctl lock
+    locked: Vec<String>,
+    /// Outputs wlstreamer created itself, which are never streamed
+    excluded: Vec<String>,
+    /// Blanked through wlstreamer ctl
+    paused: bool,
+    /// When to resume after wlstreamer ctl blank <seconds>
+    blank_until: Option<time::Instant>,
+    /// When the --countdown ends
+    countdown: Option<time::Instant>,
+    idle: bool,
+    /// Nothing reads from the output device, with --lazy
+    unwatched: bool,
+    /// Outside of --schedule, or past --duration
+    off_schedule: bool,
+    on_battery: bool,
+    latency: Option<latency::Meter>,
+    /// The screens recorded in mirror-all, grid and side-by-side mode, with their devices
+    mirrored: Vec<(String, usize, Resolution)>,
+    /// The screens kept ready with --standby, with their devices
+    standby: Vec<(String, usize, Resolution)>,
+    /// Refresh rate of the screen that was recorded last
+    recorded_rate: Option<u32>,
+    /// Where --zoom looks, and what moves the crop
+    zoom: Option<zoom::Zoom>,
+    /// The device screens with the combined resolution are captured into with --zoom and
+    /// --mask-app, to be cropped or masked from there
+    through_device: Option<usize>,
+    /// The windows of --mask-app, by output
+    masks: BTreeMap<String, Vec<mask::Mask>>,
+    /// What the running pipeline covers
+    masked: Vec<mask::Mask>,
+    /// The streamed output, while the windows to mask on it moved and a black screen is streamed
+    /// until a pipeline covering them again starts
+    unmasked: Option<String>,
+    stats: stats::Stats,
+    event_log: event_log::EventLog,
+    /// What was live when, with --timeline
+    timeline: timeline::Timeline,
+    /// The files of --record-segments
+    segments: segments::Segments,
+    no_call: bool,
+    /// The output of a window that asked for attention, streamed until then
+    urgent: Option<(String, time::Instant)>,
+    /// The windows of --urgent-app asking for attention, by app_id and output
+    urgent_windows: Vec<(String, String)>,
+    /// The outputs a window to redact is visible on
+    redacted: Vec<String>,
+    /// Chapters of the files written by sinks
+    chapters: chapters::Chapters,
+    /// The running sinks by URL, once they are started
+    sinks: Option<BTreeMap<String, sink::Sinks>>,
+    /// Starts the processes of pipelines
+    runner: Arc<dyn pipeline::CommandRunner>,
+    /// Targets left alone after their pipeline failed
+    health: health::Health,
+    /// The outputs and workspaces of the window manager
+    layout: layout::Layout,
+}
+
+#[derive(Default, Debug)]
+struct Config {
+    options: Options,
+    runtime: RuntimeState,
 }
 
-impl PartialEq<Resolution> for Resolution {
-    fn eq(&self, other: &Resolution) -> bool {
-        self.width == other.width && self.height == other.height
+impl RuntimeState {
+    /// Whether a black screen should be streamed instead of any output.
+    fn blanked(&self) -> bool {
+        self.paused || self.idle || self.unwatched || self.no_call || self.off_schedule
     }
 }
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+impl Config {
+    fn blanked(&self) -> bool {
+        self.runtime.blanked()
+    }
 
-fn help() {
-    println!("Usage: wlstreamer [options]");
-    println!("Wrapper around wf-recorder and ffmpeg that automatically switches the screen being recorded based on current window focus");
-    println!("");
-    println!("Options:");
-    println!("  --not-ws <ws-num>         Do not show this workspace. Can be used multiple times. Example: 3");
-    println!("  --not-screen <screen>     Do not show this screen. Can be used multiple times. Example: HDMI-A-1");
-    println!("  -d|--devices-from <id>    Use video devices starting at $id. Defaults to 0. /dev/video$id will be used as output. See DIFFERENT RESOLUTIONS below.");
-    println!("  -v|--version              Display version and exit");
-    println!("  --verbose                 Verbose logging");
-    println!("");
-    println!(
-        "If there are no screens available for streaming, a black screen will be shown instead."
+    /// Whether the output may not be recorded, because the profile or the options leave it out,
+    /// it is locked, or wlstreamer created it.
+    fn screen_blacklisted(&self, output: &str) -> bool {
+        self.runtime
+            .profile
+            .not_screen
+            .as_ref()
+            .unwrap_or(&self.options.screen_blacklist)
+            .iter()
+            .chain(self.runtime.locked.iter())
+            .chain(self.runtime.excluded.iter())
+            .any(|screen| screen == output)
+    }
+
+    fn workspace_blacklist(&self) -> &[config_file::WorkspaceId] {
+        self.runtime
+            .profile
+            .not_ws
+            .as_ref()
+            .unwrap_or(&self.options.workspace_blacklist)
+    }
+
+    fn enlarge_focused(&self) -> bool {
+        self.runtime
+            .profile
+            .enlarge_focused
+            .unwrap_or(self.options.enlarge_focused)
+    }
+
+    fn highlight(&self) -> Option<&String> {
+        self.runtime
+            .profile
+            .highlight
+            .as_ref()
+            .or(self.options.highlight.as_ref())
+    }
+
+    fn highlight_width(&self) -> usize {
+        self.runtime
+            .profile
+            .highlight_width
+            .unwrap_or(self.options.highlight_width)
+    }
+
+    /// The sinks of the profile, or those wlstreamer was started with.
+    fn sinks(&self) -> &[String] {
+        self.runtime
+            .profile
+            .sinks
+            .as_deref()
+            .unwrap_or(&self.options.sinks)
+    }
+
+    fn hidden_text(&self) -> bool {
+        self.runtime
+            .profile
+            .hidden_text
+            .unwrap_or(self.options.hidden_text)
+    }
+
+    fn placeholder_fps(&self) -> Option<u32> {
+        self.runtime
+            .profile
+            .placeholder_fps
+            .or(self.options.placeholder_fps)
+    }
+
+    /// The frame rate screens are captured at, if it is fixed.
+    fn frame_rate(&self) -> Option<u32> {
+        match self.options.battery_fps {
+            Some(fps) if self.runtime.on_battery => Some(fps),
+            _ => self.options.fps,
+        }
+    }
+}
+
+impl Options {
+    /// The sinks wlstreamer starts with and those of every profile, which all have to be valid
+    /// before any of them starts.
+    fn every_sink(&self) -> Vec<String> {
+        self.sinks
+            .iter()
+            .chain(
+                self.profiles
+                    .values()
+                    .flat_map(|profile| profile.sinks.iter().flatten()),
+            )
+            .unique()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for Options {
+    /// The defaults of the options, before the command line and config file are read.
+    fn default() -> Options {
+        Options {
+            devices_from: 0,
+            intermediate_devices: Vec::new(),
+            screen_blacklist: Vec::new(),
+            workspace_blacklist: Vec::new(),
+            verbose: false,
+            capture_protocol: CaptureProtocol::WlrScreencopy,
+            gpu_scale: false,
+            render_node: None,
+            drm_device: None,
+            wf_recorder_args: Vec::new(),
+            ffmpeg_args: Vec::new(),
+            capture_args: HashMap::new(),
+            wf_recorder_path: "wf-recorder".to_string(),
+            ffmpeg_path: "ffmpeg".to_string(),
+            ipc_socket: None,
+            backend: Backend::Sway,
+            mode: Mode::Follow,
+            side_by_side: Vec::new(),
+            enlarge_focused: false,
+            highlight: None,
+            highlight_width: 6,
+            profiles: HashMap::new(),
+            blank_when_idle: None,
+            schedule: schedule::Schedule::default(),
+            dnd: false,
+            show_indicator: false,
+            lazy: false,
+            fps: None,
+            color: color::Color::default(),
+            tonemap: color::Tonemap::default(),
+            battery_fps: None,
+            battery_threshold: 100,
+            damage: false,
+            normalize_scale: false,
+            sinks: Vec::new(),
+            encoding: sink::Encoding::default(),
+            transport: sink::Transport::default(),
+            secrets_file: None,
+            serve_hls: None,
+            preview_listen: None,
+            replay_buffer: None,
+            record_segments: None,
+            audio: audio::Audio::default(),
+            call_apps: Vec::new(),
+            urgent_apps: Vec::new(),
+            urgent_seconds: 10,
+            countdown: 0,
+            redact: Vec::new(),
+            mask_apps: Vec::new(),
+            hidden_text: false,
+            blank_on_hidden: false,
+            placeholder_fps: None,
+            standby: false,
+            compat: false,
+            quality: quality::Quality::default(),
+            zoom: None,
+            zoom_duration: 600,
+            measure_latency: false,
+            stats_interval: None,
+            stall_timeout: 0,
+        }
+    }
+}
+
+impl Default for RuntimeState {
+    fn default() -> RuntimeState {
+        RuntimeState {
+            streamer: StreamerState::Idle,
+            resolutions: Vec::new(),
+            audio_delay: 0,
+            outputs: HashMap::new(),
+            device_locks: HashMap::new(),
+            toplevel: None,
+            indicator: None,
+            profile: config_file::Profile::default(),
+            locked: Vec::new(),
+            excluded: Vec::new(),
+            paused: false,
+            blank_until: None,
+            countdown: None,
+            unwatched: false,
+            on_battery: false,
+            no_call: false,
+            off_schedule: false,
+            urgent: None,
+            urgent_windows: Vec::new(),
+            redacted: Vec::new(),
+            chapters: chapters::Chapters::default(),
+            sinks: None,
+            latency: None,
+            idle: false,
+            mirrored: Vec::new(),
+            standby: Vec::new(),
+            recorded_rate: None,
+            zoom: None,
+            through_device: None,
+            masks: BTreeMap::new(),
+            masked: Vec::new(),
+            unmasked: None,
+            stats: stats::Stats::default(),
+            event_log: event_log::EventLog::default(),
+            timeline: timeline::Timeline::default(),
+            segments: segments::Segments::default(),
+            runner: Arc::new(pipeline::System),
+            health: health::Health::default(),
+            layout: layout::Layout::default(),
+        }
+    }
+}
+
+/// Something that may change what is being streamed.
+enum Event {
+    /// Focus or the output layout changed in the window manager
+    Changed,
+    /// A command from `wlstreamer ctl`, to be answered on the sender
+    Control(control::Command, Sender<Result<String, String>>),
+    /// The user went idle, or became active again
+    Idle(bool),
+    /// The connection to the window manager is gone, so there is nothing left to follow
+    Disconnected,
+    /// wlstreamer was asked to stop with a signal
+    Quit,
+    /// Time to check whether the pipeline is still running
+    Tick,
+    /// Something started or stopped reading from the output device
+    Watched(bool),
+    /// The computer switched to battery, or back to AC
+    Battery(bool),
+    /// The schedule opened, or closed
+    Scheduled(bool),
+    /// Time to look whether the windows to mask moved, which sway doesn't tell
+    Windows,
+}
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How long to wait at startup for the compositor to set up an output, for example when wlstreamer
+/// is started by exec in the sway config.
+const OUTPUTS_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// The help, one line per entry.
+fn help_text() -> Vec<String> {
+    let mut lines = vec![i18n::tr("Usage: {}", &[&i18n::tr(cli::USAGE[0], &[])])];
+    lines.extend(
+        cli::USAGE[1..]
+            .iter()
+            .map(|usage| format!("       {}", usage)),
     );
-    println!("");
-    println!("DIFFERENT RESOLUTIONS");
-    println!("");
-    println!("When running outputs with different resolutions, the resulting stream will be the smallest possible resolution that can fit all output resolutions.");
-    println!("For example, two outputs, one 1600x1200, another 1920x1080, will result in an output stream of 1920x1200. Any remaining space will be padded black.");
-    println!("Another example, two outputs, one 640x480, another 1920x1080, will result in an output stream of 1920x1080. Space will only be padded black on the smaller screen.");
-    println!("");
-    println!("To support this behaviour, wlstreamer needs access to a v4l2loopback device for each resolution, included the combined upscaled one if applicable. For the first example above, this would mean you would need 3 devices. For the second, you'd need two. If all your outputs have the same resolution, you only need an output device.");
-    println!("");
-    println!("The --devices-from or -d option specifies at which device index it is okay to start using loopback devices. For example, if you specify -d 3, and you need 2 capture devices, /dev/video3 and /dev/video4 will be used by wlstreamer, with /dev/video3 being the output you want to use in other applications.");
-    println!("");
-    println!("DYNAMICALLY CHANGING RESOLUTIONS");
-    println!("");
-    println!("As long as you have enough v4l2loopback devices available for new resolutions, it should be fine to change resolutions on an output.");
-    println!("However, if your resolution is either wider or taller than the output resolution, this will result in failures, since dynamically changing the v4l2loopback device resolution is not possible.");
+    lines.push(i18n::tr(cli::SUMMARY, &[]));
+    lines.push(String::new());
+    lines.push(i18n::tr("Options:", &[]));
+    lines.extend(cli::FLAGS.iter().map(cli::Flag::help_line));
+    lines.extend(help_sections());
+    lines
+}
+
+/// The help after the options, which the man page is made from as well.
+fn help_sections() -> Vec<String> {
+    vec![
+        String::new(),
+        "If there are no screens available for streaming, a black screen will be shown instead."
+            .to_string(),
+        "Screens that mirror each other, like a laptop screen shown on a projector at the same position in the layout, count as one screen. The stream stays on the one it shows when the focus moves to its twin, since both show the same.".to_string(),
+        "On sway, screens that are powered off (for example with swaymsg output DP-1 power off) are not available for streaming until they are turned back on.".to_string(),
+        "Messages are printed in German when LC_ALL, LC_MESSAGES or LANG ask for it, in English otherwise. Of this help, only the part up to the options is translated, and the completions and the man page are always in English.".to_string(),
+        "When the compositor hasn't set up any output yet, for example when wlstreamer is started by exec in the sway config, it waits up to 30 seconds for one.".to_string(),
+        String::new(),
+        "DIFFERENT RESOLUTIONS".to_string(),
+        String::new(),
+        "When running outputs with different resolutions, the resulting stream will be the smallest possible resolution that can fit all output resolutions.".to_string(),
+        "For example, two outputs, one 1600x1200, another 1920x1080, will result in an output stream of 1920x1200. Any remaining space will be padded black.".to_string(),
+        "Another example, two outputs, one 640x480, another 1920x1080, will result in an output stream of 1920x1080. Space will only be padded black on the smaller screen.".to_string(),
+        String::new(),
+        "To support this behaviour, wlstreamer needs access to a v4l2loopback device for each resolution, included the combined upscaled one if applicable. For the first example above, this would mean you would need 3 devices. For the second, you'd need two. If all your outputs have the same resolution, you only need an output device.".to_string(),
+        "Rotated screens (for example with swaymsg output DP-1 transform 90) count with their width and height swapped, and are turned upright before they are streamed.".to_string(),
+        "Screens are captured in pixels, so a 4K screen with scale 2 needs a 3840x2160 device next to the 1920x1080 device of a screen with scale 1, even though both take up the same space in the layout. With --normalize-scale, scaled screens are captured at their size in the layout instead, 1920x1080 for both in this example, which keeps the combined resolution down and makes text the same size on every screen. Frames are scaled down while capturing.".to_string(),
+        "Devices are handed out at startup, ordered from the smallest to the largest resolution, so the same outputs always use the same devices. The assignment is printed at startup and by wlstreamer ctl status. When no screen has a resolution anymore, for example after unplugging a monitor, its device is released and goes to the next new resolution.".to_string(),
+        "Before streaming, wlstreamer checks that every device it needs exists, is a v4l2loopback device it can write to, isn't being written to by another program, and isn't already fixed to another pixel format or size. Devices handed out while streaming are checked the same way.".to_string(),
+        String::new(),
+        "The --devices-from or -d option specifies at which device index it is okay to start using loopback devices. For example, if you specify -d 3, and you need 2 capture devices, /dev/video3 and /dev/video4 will be used by wlstreamer, with /dev/video3 being the output you want to use in other applications. With --intermediate-devices, the devices for the other resolutions are taken from that list instead.".to_string(),
+        String::new(),
+        "DYNAMICALLY CHANGING RESOLUTIONS".to_string(),
+        String::new(),
+        "As long as you have enough v4l2loopback devices available for new resolutions, it should be fine to change resolutions on an output.".to_string(),
+        "However, if your resolution is either wider or taller than the output resolution, this will result in failures, since dynamically changing the v4l2loopback device resolution is not possible.".to_string(),
+        String::new(),
+        "MODES".to_string(),
+        String::new(),
+        "follow: A single stream on the output device that switches to the focused screen. This is the default.".to_string(),
+        "mirror-all: Every screen that isn't blacklisted with --not-screen is recorded into a device of its own at the same time, for switching scenes yourself, for example in OBS. Devices are assigned in order of screen name starting at --devices-from, and printed at startup. A screen keeps its device until wlstreamer exits, even when it is unplugged and plugged back in.".to_string(),
+        "grid: All screens that aren't blacklisted with --not-screen are tiled into a single stream on the output device, which has the combined resolution. Every screen is recorded into a device of its own first, starting after the output device. With --enlarge-focused, the focused screen takes up two thirds of the stream and the others are stacked next to it.".to_string(),
+        "side-by-side: The given screens are put next to each other in a single stream on the output device, in the order they are listed and at their own resolution, for example --mode side-by-side DP-1,DP-2 to show an editor and a browser at the same time. In the config file, set mode = \"side-by-side\" and list the screens in side-by-side = [\"DP-1\", \"DP-2\"]. Screens are recorded into devices of their own first, the same as in grid mode.".to_string(),
+        "headless: sway creates a headless output at the combined resolution, to the right of the other screens, and wl-mirror shows the focused screen on it, scaled by the compositor. Only the headless output is recorded, so switching screens never restarts the recording and no intermediate devices are needed. Needs sway and wl-mirror. The headless output is removed again when wlstreamer exits, and focusing it keeps showing the screen that was shown before.".to_string(),
+        String::new(),
+        "CAPTURE PROTOCOLS".to_string(),
+        String::new(),
+        "Screens are captured with wf-recorder through the wlr-screencopy protocol. Compositors that only offer the newer ext-image-copy-capture protocol are captured by wlstreamer itself, which hands the frames to ffmpeg as images. That is heavier on the CPU than wf-recorder, since every frame is converted and copied once more.".to_string(),
+        String::new(),
+        "X11".to_string(),
+        String::new(),
+        "With --backend i3, focus is followed through i3's IPC, which works the same as sway's, and screens are captured with ffmpeg's x11grab from $DISPLAY. The i3 backend is picked automatically when running in an X11 session.".to_string(),
+        String::new(),
+        "NIRI".to_string(),
+        String::new(),
+        "With --backend niri, focus is followed through niri's event stream (niri msg --json event-stream) and the visible workspace of each output is the active one in its column. Screens are captured the same way as with sway. The niri backend is picked automatically when $NIRI_SOCKET is set.".to_string(),
+        String::new(),
+        "OTHER WAYLAND COMPOSITORS".to_string(),
+        String::new(),
+        "With --backend foreign-toplevel, the screen being recorded is the one showing the currently activated window, as reported through the wlr-foreign-toplevel-management protocol. This works on most wlroots based compositors, even without an IPC wlstreamer knows about. There are no workspaces in this protocol, so --not-ws has no effect. This backend is picked automatically in Wayland sessions other than sway and niri.".to_string(),
+        String::new(),
+        "GPU SCALING".to_string(),
+        String::new(),
+        "With --gpu-scale, wf-recorder uploads every frame to the GPU, scales it to the combined resolution with VAAPI, and downloads it again to write it to the output device. Frames are still copied to and from the GPU, but the scaling itself costs hardly any CPU time, and no extra device per resolution or ffmpeg upscaler is needed. It requires a VAAPI capable GPU and only works with wlr-screencopy; otherwise wlstreamer falls back to the regular pipeline. Frames can't stay in GPU memory all the way to the output device: wf-recorder only captures into GPU buffers for its own hardware encoders, and v4l2loopback takes frames from main memory.".to_string(),
+        String::new(),
+        "On computers with several GPUs, every screen is scaled on the GPU it is connected to. --drm-device pins scaling, as well as encoding for sinks and the replay buffer, to one render node instead, for example to keep it off the GPU a game runs on. Without it, encoding happens on the first render node.".to_string(),
+        String::new(),
+        "HDR".to_string(),
+        String::new(),
+        "Screens running in HDR mode or with 10 bits per color hand out frames the way they are sent to the monitor. wlstreamer converts them to the 8 bits per color the devices carry, and tone maps the frames of HDR screens with --tonemap, since apps reading from the devices only understand SDR. Without tone mapping, HDR screens look washed out. Only sway 1.11 and newer tell which screens run in HDR mode. With --gpu-scale, tone mapping happens on the GPU with VAAPI's own algorithm instead.".to_string(),
+        String::new(),
+        "CONFIG FILE".to_string(),
+        String::new(),
+        "Options can also be set in a TOML config file, which is read from $XDG_CONFIG_HOME/wlstreamer/config.toml by default. Keys are named after the long options, with the repeatable ones taking lists. Options given on the command line take precedence, repeatable ones are added to the lists from the config file. For example:".to_string(),
+        String::new(),
+        "  not-ws = [3, 4, \"mail\"]".to_string(),
+        "  devices-from = 10".to_string(),
+        "  wf-recorder-args = [\"--no-damage\"]".to_string(),
+        "  ffmpeg-args = [\"-r\", \"30\"]".to_string(),
+        String::new(),
+        "Outputs that need special handling get a table of their own, named after the output. capture-args are added to the arguments of wf-recorder, or of ffmpeg with ext-image-copy-capture and x11grab, only when capturing that output, after the ones for every output. For example, for a dock that can't keep up with 60 frames per second:".to_string(),
+        String::new(),
+        "  [output.\"DP-3\"]".to_string(),
+        "  capture-args = [\"--framerate\", \"30\"]".to_string(),
+        String::new(),
+        "PROFILES".to_string(),
+        String::new(),
+        "Profiles are named sets of options in the config file, which can be switched between while wlstreamer is running with wlstreamer ctl profile <name>. Only the outputs that have to change are restarted. A profile can set not-ws, not-screen, enlarge-focused, highlight, highlight-width, hidden-text, placeholder-fps and sinks, anything it leaves out is taken from the command line and the rest of the config file. wlstreamer ctl profile default goes back to those options, unless there is a profile called default. For example:".to_string(),
+        String::new(),
+        "  [profiles.meeting]".to_string(),
+        "  not-screen = [\"HDMI-A-1\"]".to_string(),
+        "  [profiles.presentation]".to_string(),
+        "  not-ws = [1, 2, 3]".to_string(),
+        "  highlight = \"red\"".to_string(),
+        "  hidden-text = true".to_string(),
+        "  sinks = [\"rtmp://live.twitch.tv/app/${TWITCH_KEY}\"]".to_string(),
+        String::new(),
+        "Switching to a profile with other sinks stops the sinks it leaves out and starts the new ones, sinks in both keep running. The HLS page of --serve-hls is kept in every profile.".to_string(),
+        String::new(),
+        "wlstreamer ctl status prints the screen being recorded and which resolution or screen is on which device, add --json for a machine readable version.".to_string(),
+        "wlstreamer ctl save-replay [file] saves the replay buffer, see SINKS below.".to_string(),
+        "wlstreamer ctl screenshot <file> saves the frame on the output device as a PNG, to check exactly what viewers see.".to_string(),
+        "wlstreamer ctl lock <screen> keeps a screen off the stream the same as --not-screen, until wlstreamer ctl unlock <screen>.".to_string(),
+        "wlstreamer ctl toggle and wlstreamer ctl toggle-lock <screen> switch between blank and resume, and lock and unlock.".to_string(),
+        "wlstreamer ctl blank <seconds> resumes on its own after that long, for example to type a 2FA code without having to remember to resume. Resuming or blanking again before then cancels it.".to_string(),
+        "wlstreamer tui shows the screens, which one is live, the devices, the health of the pipeline and the latest events of the running instance, and lets you lock screens and pause the stream with the keyboard.".to_string(),
+        "wlstreamer tray puts an icon into the tray of KDE, of GNOME with the AppIndicator extension or of waybar, which shows whether the stream is live or blanked. Clicking it pauses or resumes the stream, and its menu locks and unlocks screens. It can be started before or after wlstreamer, for example from the sway config with exec wlstreamer tray.".to_string(),
+        "wlstreamer ctl talks to the running instance through $XDG_RUNTIME_DIR/wlstreamer.sock. Without $XDG_RUNTIME_DIR, the socket, the device locks and the log are kept in /tmp/wlstreamer-<uid>, which has to belong to the user and be closed to everyone else (mode 0700), or wlstreamer refuses to use it.".to_string(),
+        "The same commands are on the session bus as org.wlstreamer.Control, at /org/wlstreamer/Control: the methods Pause, Resume, LockOutput(output), UnlockOutput(output) and Status, which returns the JSON of wlstreamer ctl status --json. The signal OutputChanged(output) is sent on every switch, with an empty output for the black screen, and Error(message) when something fails. To watch them:".to_string(),
+        String::new(),
+        "  gdbus monitor --session --dest org.wlstreamer.Control".to_string(),
+        String::new(),
+        "With --instance, the name on the bus is org.wlstreamer.Control.<name>.".to_string(),
+        "Several instances can run at once, for example one camera per meeting app, when each gets its own --instance name and its own devices with --devices-from. Each device is locked by the instance writing to it, so two instances never write to the same one. wlstreamer ctl --instance <name> talks to $XDG_RUNTIME_DIR/wlstreamer-<name>.sock instead.".to_string(),
+        String::new(),
+        "wlstreamer bindings prints sway key bindings for these commands, with one to lock each screen, to include from the sway config:".to_string(),
+        String::new(),
+        "  wlstreamer bindings > ~/.config/sway/wlstreamer".to_string(),
+        "  echo 'include ~/.config/sway/wlstreamer' >> ~/.config/sway/config".to_string(),
+        String::new(),
+        "Holding $mod+Alt+b blanks the stream until the keys are released, $mod+Alt+p blanks or resumes it, $mod+Alt+r saves the replay buffer, $mod+Alt+s saves a screenshot and $mod+Alt+1 to 9 lock and unlock the screens.".to_string(),
+        String::new(),
+        "IDLE AND LOCKING".to_string(),
+        String::new(),
+        "With --blank-when-idle, wlstreamer watches for input through the ext-idle-notify protocol and streams a black screen while you are away. The stream can also be blanked and resumed by hand with wlstreamer ctl blank and wlstreamer ctl resume, or by sending wlstreamer SIGUSR1 and SIGUSR2 (for example pkill -USR1 wlstreamer), which is how locking is handled: swayidle runs its lock and unlock commands when the session is locked through logind, for example:".to_string(),
+        String::new(),
+        "  swayidle -w lock 'wlstreamer ctl blank; swaylock' unlock 'wlstreamer ctl resume'"
+            .to_string(),
+        String::new(),
+        "In mirror-all mode, blanking stops recording the screens instead.".to_string(),
+        String::new(),
+        "--countdown 5 streams 5, 4, 3, 2, 1 in large digits on the black screen when wlstreamer starts and whenever the stream is resumed, also after wlstreamer ctl blank <seconds>, so there is a moment to get ready before the screens go out. It is shown in follow and headless mode, the other modes go live right away.".to_string(),
+        String::new(),
+        "SCHEDULE".to_string(),
+        String::new(),
+        "For displays that should only show the screens at certain times, --schedule takes days and a time of day in local time. Days are Mon to Sun, as ranges like Mon-Fri or lists like Sat,Sun, and every day without them. A window like 22:00-02:00 goes on past midnight. Outside of all windows, and once --duration has passed since wlstreamer started, the pipeline is stopped and the black screen is streamed, so apps reading the output device keep it open:".to_string(),
+        String::new(),
+        "  wlstreamer --schedule \"Mon-Fri 09:00-17:00\" --schedule \"Sat 10:00-14:00\"".to_string(),
+        String::new(),
+        "DAMAGE".to_string(),
+        String::new(),
+        "wf-recorder already skips frames when nothing on the screen changed, but programs reading from a v4l2loopback device tend to give up on a device that stops sending frames, which is why --wf-recorder-arg --no-damage is commonly used. With --damage, wlstreamer instead turns on sustain_framerate on all devices it writes to with v4l2-ctl, so v4l2loopback keeps repeating the last frame while the screen is static. ext-image-copy-capture also only gets a frame after a change, and wlstreamer repeats the last one at the frame rate unless --damage is given. x11grab can't tell what changed, so with it ffmpeg drops duplicate frames instead.".to_string(),
+        String::new(),
+        "LAZY CAPTURE".to_string(),
+        String::new(),
+        "With --lazy, wlstreamer checks every second whether a program other than itself has the output device open, and only captures the screens while one does. The rest of the time it streams a black screen, which takes hardly any CPU but keeps the device listed as a camera in browsers and video call apps. In mirror-all mode, all screens are captured as soon as any of the devices is opened, and none are while nobody is watching.".to_string(),
+        "Only the processes of your own user can be checked, so a program running as another user that reads from the device is not noticed.".to_string(),
+        String::new(),
+        "STANDBY".to_string(),
+        String::new(),
+        "Switching screens in follow mode normally stops the pipeline and starts a new one, which takes about a second. With --standby, every screen that can be streamed is recorded into a device of its own all the time, starting after the output device in order of screen name, the same as in grid mode. Switching then only replaces the ffmpeg that passes the device of the focused screen on to the output device. This costs a recorder per screen, so it only pays off with a few screens. Screens are only kept ready while the stream isn't blanked, and --gpu-scale has no effect.".to_string(),
+        String::new(),
+        "VIDEO CALL APPS".to_string(),
+        String::new(),
+        "Chrome, and the apps built on it like Teams, only list a v4l2loopback device as a camera if v4l2loopback was loaded with exclusive_caps=1, and Zoom and Teams expect the sizes and colors of a webcam. With --compat, the screens are scaled to 1920x1080 at no more than 30 frames per second, written as limited range BT.601 YUYV, and wlstreamer checks the devices before starting and prints the modprobe commands that fix them if they were loaded without exclusive_caps=1. Apps only look for cameras when they start, so restart them after reloading v4l2loopback.".to_string(),
+        String::new(),
+        "ZOOM".to_string(),
+        String::new(),
+        "With --zoom 2, only a quarter of the focused screen is streamed, scaled up to the size of the output device, which keeps text readable on a 4K screen. The part is centered on the focused window, as far as the edges of the screen allow, and glides over to the next window when the focus moves, speeding up and slowing down again over --zoom-duration. A move that starts while the last one hasn't finished sets off from wherever the part is. sway, i3 and niri don't tell where the cursor is, so it can't be followed instead. The part is cut out by the scaler that puts the frames onto the output device. The one built in with the ffmpeg feature moves it on every frame, while an ffmpeg process only takes a new position every 100 milliseconds, so it moves in steps. Screens are always captured into a device of their own first, also those with the combined resolution, which needs one more device. Window positions are only known on sway and i3, on other backends the middle of the screen is streamed.".to_string(),
+        String::new(),
+        "REDACTION".to_string(),
+        String::new(),
+        "With --redact, wlstreamer watches the titles of all windows, and streams a black screen for as long as a window whose title matches one of the regular expressions is visible on the screen being recorded, whether it is focused or not. Prefix a pattern with (?i) to ignore case:".to_string(),
+        String::new(),
+        "  wlstreamer --redact '(?i)incognito|private browsing' --redact '1Password'".to_string(),
+        String::new(),
+        "In mirror-all mode, only the screens showing such a window are blanked.".to_string(),
+        String::new(),
+        "--mask-app covers just the windows of an app instead, by app_id as with --call-app, so a password manager or a chat can stay open next to what is shown. Wayland has no protocol through which a recorder could leave the windows of other apps out, so the boxes are drawn by the scaler at the places sway and i3 report for the windows. Screens are captured into a device of their own first, the same as with --zoom. Whenever a window to mask opens, closes, moves or is resized, a black screen is streamed until a pipeline with boxes at the new places is running. sway doesn't tell anyone about floating windows being dragged or resized, so the windows are looked up four times a second, and a window can still be seen for a frame or two right after it changed. Other window managers don't report where windows are, so there a window to mask blanks its whole screen the same as --redact does. Use --redact for windows that must never be seen at all.".to_string(),
+        String::new(),
+        "  wlstreamer --mask-app org.keepassxc.KeePassXC --mask-app Signal".to_string(),
+        String::new(),
+        "CALL APPS".to_string(),
+        String::new(),
+        "With --call-app, wlstreamer streams a black screen while no window of the given apps is open, and starts capturing the screens as soon as one opens, so nothing is captured between calls. The app_id is what swaymsg -t get_tree shows as app_id, or the class for X11 apps, for example:".to_string(),
+        String::new(),
+        "  wlstreamer --call-app zoom --call-app teams-for-linux --call-app Slack".to_string(),
+        String::new(),
+        "With --urgent-app, a window of the app asking for attention, like a call coming in or a build that finished, cuts the stream to its screen for --urgent-seconds, 10 by default, before it follows the focus again. Only windows that can be seen on their screen count, and only the apps given may take over the stream, so a chat popping up in the background stays off it. Screens hidden with --not-screen and workspaces hidden with --not-ws stay hidden. niri only tells about urgency since version 25.08, and foreign-toplevel not at all.".to_string(),
+        String::new(),
+        "  wlstreamer --urgent-app org.gnome.Calls --urgent-seconds 5".to_string(),
+        String::new(),
+        "BATTERY".to_string(),
+        String::new(),
+        "With --battery-fps, wlstreamer checks the power supplies every ten seconds and captures the screens at a lower frame rate while the laptop runs on battery, going back to the full frame rate, or --fps, as soon as it is plugged in. Every switch restarts the capture. The size of the stream stays the same, since it can't change once a program is reading from the device. For example, to drop to 10 frames per second once the battery is down to half:".to_string(),
+        String::new(),
+        "  wlstreamer --battery-fps 10 --battery-threshold 50".to_string(),
+        String::new(),
+        "LATENCY".to_string(),
+        String::new(),
+        "With --measure-latency, a small bar of black and white blocks encoding the current time is drawn in the top left corner of the screen being recorded, and read back from the devices the pipeline writes to. Every five seconds, the time it took for frames to get from the screen to the capture device and to the output device after the upscaler is logged, for comparing pipeline options like --gpu-scale. The bar shows up on the stream, so this is meant for testing only. It needs wlr-layer-shell, only works in follow mode, and doesn't support fractional scaling.".to_string(),
+        String::new(),
+        "SINKS".to_string(),
+        String::new(),
+        "With --sink, the stream on the output device is also encoded by ffmpeg and sent to a server or written to a file, while it keeps switching screens the same as on the device. RTMP URLs are sent as FLV, SRT and RIST URLs as MPEG-TS, for files the container follows the extension. The encoder is checked when wlstreamer starts, and a sink that stops is restarted after two seconds. For example, to stream to a server with the GPU while recording a file:".to_string(),
+        String::new(),
+        "  wlstreamer --codec h264-vaapi --bitrate 6M --sink rtmp://example.com/live/key --sink recording.mkv".to_string(),
+        String::new(),
+        "--quality picks the frame rate, size and encoding together. Network sinks get a bitrate, files a constant quality that keeps them small while little changes on the screens, and software encoders a preset that leaves enough CPU time for capturing. --fps, --bitrate, --crf and --preset still override it:".to_string(),
+        String::new(),
+        "  low      15 fps, at most 1280x720, 2500k or CRF 28, for slow uplinks and old laptops"
+            .to_string(),
+        "  medium   30 fps, at most 1920x1080, 6M or CRF 23".to_string(),
+        "  high     the refresh rate and size of the screens, 12M or CRF 18".to_string(),
+        String::new(),
+        "SRT and RIST have lower latency than RTMP, for feeds to an encoder on another computer. --sink-passphrase and --sink-latency are added to their URLs, unless a URL sets them itself:".to_string(),
+        String::new(),
+        "  wlstreamer --sink srt://encoder.local:9000 --sink-passphrase 'a long secret' --sink-latency 200".to_string(),
+        String::new(),
+        "On a flaky uplink, --min-bitrate lets RTMP, SRT and RIST sinks degrade instead of stalling. When a sink falls more than two seconds behind, or ffmpeg stops making progress while it waits for the network, it is restarted at three quarters of its bitrate, down to --min-bitrate. After two minutes without trouble, the bitrate goes back up a step, up to --bitrate. Every change is logged. Encoders can't change their bitrate while running, so viewers see a short reconnect on every step:".to_string(),
+        String::new(),
+        "  wlstreamer --sink rtmp://example.com/live/key --bitrate 6M --min-bitrate 1500k"
+            .to_string(),
+        String::new(),
+        "With --audio-desktop and --audio-mic, sinks and the replay buffer get sound through PulseAudio or PipeWire. Files and saved replays keep the desktop and the microphone as separate tracks, so they can be balanced while editing, while streams get them mixed into one track. --desktop-volume and --mic-volume set the volume of each, and --desktop-device and --mic-device pick other sources by the names pactl list short sources prints. The virtual camera itself never has sound, which is also why wf-recorder's own audio capture isn't used: it only writes to the devices, while sinks encode what arrives on the output device after every switch.".to_string(),
+        String::new(),
+        "The microphone can be cleaned up without setting up a PipeWire filter chain: --mic-denoise afftdn removes steady noise like fans, while an RNNoise model (for example from https://github.com/GregorR/rnnoise-models) also handles keyboards and voices in the background. --mic-limiter catches peaks after the volume is raised:".to_string(),
+        String::new(),
+        "  wlstreamer --sink recording.mkv --audio-mic --mic-denoise ~/rnnoise/sh.rnnn --mic-volume 2 --mic-limiter".to_string(),
+        String::new(),
+        "Sound is captured as it plays, while the screens pass through the capture, the upscaler and the buffers of every device before the sinks read them, so the picture lags behind. --av-offset auto delays the audio by an estimate of a couple of frames for every device on the way. For the exact value, run once with --measure-latency and pass the latency it logs for the output device to --av-offset instead.".to_string(),
+        String::new(),
+        "Every file written by a sink gets a chapter for each screen it switches to, in <file>.chapters next to it. To add them to the file once it is done:".to_string(),
+        String::new(),
+        "  ffmpeg -i recording.mkv -i recording.mkv.chapters -map_metadata 1 -map_chapters 1 -c copy chaptered.mkv".to_string(),
+        String::new(),
+        "Some editors would rather get a clip for every screen than one long file. --record-segments <dir> starts a new file there on every switch, like 004-2024-05-17-21-03-09-DP-1.mkv, or ...-black.mkv for the black screen, and keeps segments.json up to date with when each one starts and ends, and the screen and workspace it shows. The next file starts before the last one stops, so the segments overlap by a few frames rather than leaving gaps.".to_string(),
+        String::new(),
+        "WHIP pushes the stream over WebRTC with sub-second latency, to ingests like Cloudflare Stream, MediaMTX or Broadcast Box. Prefix the endpoint with whip+, this needs ffmpeg 8 or newer and --codec h264:".to_string(),
+        String::new(),
+        "  wlstreamer --sink whip+https://example.com/api/whip --whip-token secret".to_string(),
+        String::new(),
+        "RTMP sinks name the scene, as <output>/<workspace> or black, in an onTextData tag whenever wlstreamer switches, for ingests and tools that log or show it. ffmpeg can't send data along with WHIP, so WHIP and the other sinks don't carry the scene; follow --events-json or the OutputChanged signal of org.wlstreamer.Control on D-Bus instead.".to_string(),
+        String::new(),
+        "Stream keys written on the command line or into the config file end up in the shell history, in service files and in dotfiles kept in git. Write ${NAME} in their place, in sink URLs, --sink-passphrase and --whip-token, and wlstreamer looks NAME up in the --secrets-file, then in the environment, then in the keyring through secret-tool. Keys, passphrases and stream IDs are shown as *** in the log, in what ffmpeg prints with --verbose and in wlstreamer ctl status, however they were given. ffmpeg only takes the address to send to as an argument, so the keys are still on the command line of the sinks' ffmpeg processes, where other users of the computer can read them:".to_string(),
+        String::new(),
+        "  secret-tool store --label=TWITCH_KEY service wlstreamer key TWITCH_KEY".to_string(),
+        "  wlstreamer --sink 'rtmp://live.twitch.tv/app/${TWITCH_KEY}'".to_string(),
+        String::new(),
+        "ndi://<name> publishes the stream as an NDI source with that name, for vision mixers or OBS on another computer. It isn't encoded, so --codec and the other encoding options don't apply. This needs wlstreamer built with --features ndi and the NDI runtime installed:".to_string(),
+        String::new(),
+        "  wlstreamer --sink ndi://Desktop".to_string(),
+        String::new(),
+        "Files ending in .m3u8 or .mpd are written as live HLS or DASH playlists, keeping the last few segments. --serve-hls does that with a page that plays the stream, and given a port instead of a directory it serves them over HTTP on this computer as well. Given an address to listen on, like 0.0.0.0:8080, anyone on the network can watch the screens in a browser, like in a classroom:".to_string(),
+        String::new(),
+        "  wlstreamer --serve-hls 0.0.0.0:8080".to_string(),
+        String::new(),
+        "The page plays the stream in browsers that play HLS on their own, like Safari, and links to the playlist for players like mpv or VLC in the others. It loads nothing from the internet.".to_string(),
+        String::new(),
+        "With --replay-buffer, the last seconds of the stream are kept in the runtime dir, and wlstreamer ctl save-replay [file] writes them to a file, by default ~/Videos/replay-<date>.mkv. Bind it to a key to save whatever just happened on the screens you were working on, for example in sway:".to_string(),
+        String::new(),
+        "  bindsym $mod+Shift+r exec wlstreamer ctl save-replay".to_string(),
+        String::new(),
+        "Sinks get the stream at the combined resolution of the screens, which keeps the full detail of the largest one. A sink ending in @<width>x<height> is scaled to that size on its own instead, and a sink that is a v4l2loopback device gets the stream without encoding it. Together, the output device can carry a 4K screen at its native resolution for a recording, while another device is a 1080p camera for calls:".to_string(),
+        String::new(),
+        "  wlstreamer --sink recording.mkv --sink /dev/video9@1920x1080".to_string(),
+        String::new(),
+        "Sinks and the replay buffer don't work in mirror-all mode, since there is no single stream there.".to_string(),
+        String::new(),
+        "DO NOT DISTURB".to_string(),
+        String::new(),
+        "With --dnd, notifications are held back while wlstreamer runs, and let through again when it exits. For dunst and swaync this uses their own do-not-disturb switch. mako has none built in, so wlstreamer turns on the do-not-disturb mode, which needs to be set up in the mako config:".to_string(),
+        String::new(),
+        "  [mode=do-not-disturb]".to_string(),
+        "  invisible=1".to_string(),
+        String::new(),
+        "If do-not-disturb was already on when wlstreamer started, it is left on.".to_string(),
+        String::new(),
+        "EVENTS".to_string(),
+        String::new(),
+        "With --events-json, every change is written as a line of JSON with an event name and a unix timestamp, for example:".to_string(),
+        String::new(),
+        "  {\"event\":\"switch\",\"output\":\"DP-1\",\"time\":1700000000}".to_string(),
+        String::new(),
+        "The events are switch (with the screen being streamed, or null for the black screen), blank (with the seconds it lasts, or null until resumed), resume, idle, active, watched, unwatched, power (with whether it runs on battery), schedule (with whether it is open), call (with whether a call app is open), redact (with the screens showing a window to redact), urgent (with the app_id and screen of a window asking for attention), lock and unlock (with the screen), profile (with its name), restart (with the reason) and error (with a message). When writing to a FIFO, events are dropped while nothing reads from it, so wlstreamer never waits for a reader.".to_string(),
+        String::new(),
+        "BENCHMARK".to_string(),
+        String::new(),
+        "wlstreamer bench captures the focused screen for a few seconds with every pixel format, with --gpu-scale if there is a render node and through ext-image-copy-capture if the compositor offers it, and scales test frames to the combined resolution with every scaler the upscaler could use. It prints the frame rate and CPU usage of each, and recommends the options that work best. Other options like --backend and --wf-recorder-path are taken into account. Nothing is written to the loopback devices, so it can run while wlstreamer is streaming.".to_string(),
+        String::new(),
+        "SYSTEMD".to_string(),
+        String::new(),
+        "wlstreamer can run as a user service with Type=notify. It reports itself ready once the first stream is running, keeps the service status up to date with the screen being recorded, and sends watchdog keepalives when WatchdogSec= is set, so a hung wlstreamer gets restarted.".to_string(),
+        String::new(),
+        "wlstreamer install-service [options] writes such a service to ~/.config/systemd/user/wlstreamer.service, running wlstreamer with the given options, imports WAYLAND_DISPLAY, SWAYSOCK and the other variables of the current session into the service manager, and enables and starts it. The service starts with graphical-session.target; when sway isn't started through systemd, the command to start it from the sway config is printed.".to_string(),
+        String::new(),
+        "Started without the variables of a session, for example by a service that didn't import them or by cron, wlstreamer looks for the Wayland displays in $XDG_RUNTIME_DIR and the IPC socket of the sway, niri or Hyprland instance behind each. If there is more than one, pick one with --session.".to_string(),
+        String::new(),
+        "Without systemd, --daemonize detaches wlstreamer from the terminal once the options are checked, so it keeps running when the terminal is closed. Stop it with kill and the process id it prints.".to_string(),
+        String::new(),
+        "PACKAGING".to_string(),
+        String::new(),
+        "wlstreamer completions <shell> prints completions for bash, zsh or fish, and wlstreamer manpage prints this help as a man page. Both are made from the same description of the options as this help, in English whatever the locale, so they always match it. For example, for packages:".to_string(),
+        String::new(),
+        "  wlstreamer completions bash > /usr/share/bash-completion/completions/wlstreamer"
+            .to_string(),
+        "  wlstreamer completions zsh > /usr/share/zsh/site-functions/_wlstreamer".to_string(),
+        "  wlstreamer completions fish > /usr/share/fish/vendor_completions.d/wlstreamer.fish"
+            .to_string(),
+        "  wlstreamer manpage | gzip > /usr/share/man/man1/wlstreamer.1.gz".to_string(),
+    ]
+}
 
+fn help() -> ! {
+    for line in help_text() {
+        println!("{}", line);
+    }
     std::process::exit(0);
 }
 
-fn stream_black(config: &mut Config) -> Result<Vec<Box<Child>>, Error> {
-    let cmd = Command::new("ffmpeg")
-        .args(&[
-            "-i",
-            format!(
-                "color=c=black:s={}x{}:r=25/1",
-                config.resolutions[0].width, config.resolutions[0].height
-            )
-            .as_str(),
-            "-vcodec",
-            "rawvideo",
-            "-pix_fmt",
-            "yuyv422",
-            "-f",
-            "v4l2",
-            format!("/dev/video{}", config.devices_from).as_str(),
-        ])
-        .stdin(Stdio::piped())
-        .stdout(if config.verbose {
-            Stdio::piped()
-        } else {
-            Stdio::inherit()
-        })
-        .stderr(if config.verbose {
-            Stdio::piped()
-        } else {
-            Stdio::inherit()
+fn stream_black(config: &mut Config, text: Option<&str>) -> Result<Pipeline, Error> {
+    stream_placeholder(config, Pipeline::black(text.map(str::to_string)))
+}
+
+/// Streams a black screen counting down the rest of the --countdown.
+fn stream_countdown(config: &mut Config) -> Result<Pipeline, Error> {
+    let left = config
+        .runtime
+        .countdown
+        .map_or(0, |until| {
+            until
+                .saturating_duration_since(time::Instant::now())
+                .as_secs_f64()
+                .ceil() as u64
         })
-        .spawn()?;
+        .max(1);
+    stream_placeholder(config, Pipeline::countdown(left))
+}
 
-    config.current_output = "".to_string();
+fn stream_placeholder(config: &mut Config, builder: pipeline::Builder) -> Result<Pipeline, Error> {
+    // Consumers that locked onto the frame rate of the screens keep getting it
+    let rate = config
+        .placeholder_fps()
+        .or(config.frame_rate())
+        .or(config.runtime.recorded_rate);
+    let mut pipeline = builder
+        .rate(rate)
+        .scale(config.runtime.resolutions[0])
+        .sink(config.options.devices_from)
+        .build(config);
+    pipeline.start()?;
 
-    return Ok(vec![Box::new(cmd)]);
+    Ok(pipeline)
 }
 
-fn record_screen(config: &mut Config, output: SwayOutput) -> Result<Vec<Box<Child>>, Error> {
-    let resolution = Resolution {
-        height: output.current_mode.height,
-        width: output.current_mode.width,
-    };
+/// The text to write onto the black screen, if the focused workspace is hidden with --not-ws
+/// or --not-screen and --hidden-text is on.
+fn placeholder_text(config: &Config) -> Option<String> {
+    if !config.hidden_text() || config.blanked() {
+        return None;
+    }
+
+    get_workspaces(config)
+        .into_iter()
+        .find(|w| w.focused)
+        .filter(|w| workspace_blacklisted(config, w) || config.screen_blacklisted(&w.output))
+        .map(|w| i18n::tr("Workspace {} (hidden)", &[&w.name]))
+}
+
+fn record_screen(config: &mut Config, output: SwayOutput) -> Result<Pipeline, Error> {
+    config.runtime.recorded_rate = output.refresh_rate();
+    if config.options.standby {
+        return relay_standby(config, &output);
+    }
+
+    let resolution = output.resolution();
+    let mut pipeline = Pipeline::capture(output.clone())
+        .scale(config.runtime.resolutions[0])
+        .sink(config.options.devices_from);
 
-    let device_number = match config.outputs.get(&resolution) {
+    if config.options.capture_protocol == CaptureProtocol::WlrScreencopy {
+        if let Some(render_node) = config.options.render_node.clone() {
+            let render_node = match config.options.drm_device {
+                Some(_) => render_node,
+                None => capture::output_render_node(output.name.as_str()).unwrap_or(render_node),
+            };
+            if config.options.verbose {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Using device number {} through {}",
+                        &[&config.options.devices_from, &render_node]
+                    )
+                );
+            }
+
+            let mut pipeline = pipeline.on_gpu(render_node.as_str()).build(config);
+            pipeline.start()?;
+            return Ok(pipeline);
+        }
+    }
+
+    let device_number = match config.runtime.outputs.get(&resolution) {
+        Some(device_number) if *device_number == config.options.devices_from => {
+            config.runtime.through_device.unwrap_or(*device_number)
+        }
         Some(device_number) => *device_number,
-        None => {
-            config.last_device_index += 1;
-            config.outputs.insert(resolution, config.last_device_index);
-            config.last_device_index
+        None => claim_device(config, resolution).map_err(Error::other)?,
+    };
+
+    if config.options.verbose {
+        println!("{}", i18n::tr("Using device number {}", &[&device_number]));
+    }
+
+    if device_number != config.options.devices_from {
+        if config.options.verbose {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Does not have the maximum combined resolution, filtering through ffmpeg",
+                    &[]
+                )
+            );
+        }
+
+        pipeline = pipeline.through(device_number);
+    }
+
+    let pan = config
+        .runtime
+        .zoom
+        .as_ref()
+        .map(|zoom| zoom.pan(output.name.as_str(), resolution));
+    if let Some(pan) = pan.clone() {
+        pipeline = pipeline.zoom(pan);
+    }
+    config.runtime.masked = masks_of(config, &output.name);
+    pipeline = pipeline.mask(config.runtime.masked.clone());
+
+    let mut pipeline = pipeline.build(config);
+    pipeline.start()?;
+    // The scaler built into wlstreamer moves the crop itself
+    if let (Some(pan), Some(stdin)) = (pan, pipeline.take_stdin()) {
+        pan.steer(stdin);
+    }
+    Ok(pipeline)
+}
+
+/// The parts of the output to cover for --mask-app.
+fn masks_of(config: &Config, output: &str) -> Vec<mask::Mask> {
+    config
+        .runtime
+        .masks
+        .get(output)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Passes on the device the output is kept ready on with --standby.
+fn relay_standby(config: &mut Config, output: &SwayOutput) -> Result<Pipeline, Error> {
+    let (device_number, resolution) = config
+        .runtime
+        .standby
+        .iter()
+        .find(|(name, ..)| *name == output.name)
+        .map(|(_, device_number, resolution)| (*device_number, *resolution))
+        .ok_or_else(|| Error::other(format!("{} is not kept ready", output.name)))?;
+
+    if config.options.verbose {
+        println!("{}", i18n::tr("Passing on /dev/video{}", &[&device_number]));
+    }
+
+    let mut pipeline = Pipeline::relay(device_number)
+        .scale(config.runtime.resolutions[0])
+        .sink(config.options.devices_from);
+    let pan = config
+        .runtime
+        .zoom
+        .as_ref()
+        .map(|zoom| zoom.pan(output.name.as_str(), resolution));
+    if let Some(pan) = pan.clone() {
+        pipeline = pipeline.zoom(pan);
+    }
+    config.runtime.masked = masks_of(config, &output.name);
+    pipeline = pipeline.mask(config.runtime.masked.clone());
+
+    let mut pipeline = pipeline.build(config);
+    pipeline.start()?;
+    // The scaler built into wlstreamer moves the crop itself
+    if let (Some(pan), Some(stdin)) = (pan, pipeline.take_stdin()) {
+        pan.steer(stdin);
+    }
+    Ok(pipeline)
+}
+
+/// Hands out a device to every resolution up front, in order of size after the combined one on
+/// the output device, so the same outputs always end up on the same devices. Resolutions that
+/// only show up later get the next free device.
+fn assign_devices(config: &mut Config) {
+    let mut resolutions = config.runtime.resolutions.clone();
+    resolutions.sort_by_key(|r| (r.width, r.height));
+
+    for resolution in resolutions {
+        if !config.runtime.outputs.contains_key(&resolution)
+            && assign_device(config, resolution).is_none()
+        {
+            println!(
+                "{}",
+                i18n::tr(
+                    "No device left for {}x{}, screens with that resolution can't be streamed. Add one to --intermediate-devices.",
+                    &[&resolution.width, &resolution.height]
+                )
+            );
         }
+    }
+}
+
+/// Hands out the first unused device to a resolution, from --intermediate-devices if it is given
+/// and after the output device otherwise. Returns None if they are all used.
+fn assign_device(config: &mut Config, resolution: Resolution) -> Option<usize> {
+    let device_number = unused_device(config)?;
+    config.runtime.outputs.insert(resolution, device_number);
+    Some(device_number)
+}
+
+/// Hands out a device to a resolution that shows up while streaming, checking and locking it like
+/// the devices handed out at startup.
+fn claim_device(config: &mut Config, resolution: Resolution) -> Result<usize, String> {
+    let device_number = unused_device(config).ok_or_else(|| {
+        i18n::tr(
+            "No device left for {}x{}, add one to --intermediate-devices",
+            &[&resolution.width, &resolution.height],
+        )
+    })?;
+    loopback::validate(device_number, Some(resolution))?;
+    let lock = loopback::lock(device_number)?;
+
+    config.runtime.device_locks.insert(device_number, lock);
+    config.runtime.outputs.insert(resolution, device_number);
+    Ok(device_number)
+}
+
+/// The first device after the output device, or from --intermediate-devices, that no
+/// resolution has and --zoom or --mask-app don't use.
+fn unused_device(config: &Config) -> Option<usize> {
+    let unused = |n: &usize| {
+        !config.runtime.outputs.values().any(|used| used == n)
+            && config.runtime.through_device != Some(*n)
     };
+    if config.options.intermediate_devices.is_empty() {
+        (config.options.devices_from + 1..).find(unused)
+    } else {
+        config
+            .options
+            .intermediate_devices
+            .iter()
+            .copied()
+            .find(unused)
+    }
+}
 
-    if config.verbose {
-        println!("Using device number {}", device_number);
-    }
-
-    let output_str = format!("--file=/dev/video{}", device_number);
-    let screen_str = format!("-o{}", output.name.as_str());
-    let recorder = Command::new("wf-recorder")
-        .args(&[
-            "--muxer=v4l2",
-            "--codec=rawvideo",
-            "--pixel-format=yuyv422",
-            screen_str.as_str(),
-            output_str.as_str(),
-        ])
-        .stdin(Stdio::piped())
-        .stdout(if config.verbose {
-            Stdio::inherit()
-        } else {
-            Stdio::piped()
-        })
-        .stderr(if config.verbose {
-            Stdio::inherit()
-        } else {
-            Stdio::piped()
+/// An estimate of how long frames take from the screen to the sinks, in milliseconds: v4l2loopback
+/// keeps up to two frames on every device, and scaling screens adds a device and an ffmpeg.
+fn video_delay(config: &Config) -> i64 {
+    let fps = config
+        .frame_rate()
+        .or_else(|| {
+            get_outputs(config)
+                .iter()
+                .filter_map(SwayOutput::refresh_rate)
+                .max()
         })
-        .spawn()?;
-
-    config.current_output = output.name.as_str().to_string();
-
-    let mut processes = vec![Box::new(recorder)];
-
-    if device_number != config.devices_from {
-        if config.verbose {
-            println!("Does not have the maximum combined resolution, filtering through ffmpeg");
-        }
-
-        // TODO: This is slow, ugly, and prone to failure. ffmpeg will fail if wf-recorder isn't
-        // writing yet, however I'm not sure how to get an exact timing of when it's okay to start
-        // reading from the device.
-        thread::sleep(time::Duration::from_millis(100));
-
-        let upscaler = Command::new("ffmpeg")
-            .args(&[
-                "-i",
-                format!("/dev/video{}", device_number).as_str(),
-                "-vcodec",
-                "rawvideo",
-                "-pix_fmt",
-                "yuyv422",
-                "-f",
-                "v4l2",
-                "-vf",
-                format!("scale={}:{}:force_original_aspect_ratio=decrease,pad={}:{}:(ow-iw)/2:(oh-ih)/2,setsar=1",
-                    config.resolutions[0].width, config.resolutions[0].height,
-                    config.resolutions[0].width, config.resolutions[0].height).as_str(),
-                format!("/dev/video{}", config.devices_from).as_str(),
-            ])
-            .stdin(Stdio::piped())
-            .stdout(if config.verbose {
-                Stdio::inherit()
-            } else {
-                Stdio::piped()
-            })
-            .stderr(if config.verbose {
-                Stdio::inherit()
-            } else {
-                Stdio::piped()
-            })
-            .spawn()?;
+        .unwrap_or(60);
+    let scaled = config.options.mode != Mode::Follow
+        || config.options.standby
+        || config.runtime.zoom.is_some()
+        || !config.options.mask_apps.is_empty()
+        || (config.options.render_node.is_none() && config.runtime.resolutions.len() > 1);
+    let frames = if scaled { 4 } else { 2 };
 
-        processes.push(Box::new(upscaler));
-    }
+    frames * 1000 / fps as i64
+}
+
+/// Frees the devices of resolutions no screen has anymore, for example after unplugging a monitor,
+/// so long sessions don't run out of devices. The output device is kept.
+fn release_devices(config: &mut Config) {
+    let resolutions: Vec<Resolution> = get_outputs(config)
+        .iter()
+        .map(SwayOutput::resolution)
+        .collect();
+    let devices_from = config.options.devices_from;
+    let device_locks = &mut config.runtime.device_locks;
+    config.runtime.outputs.retain(|resolution, device_number| {
+        let keep = *device_number == devices_from || resolutions.contains(resolution);
+        if !keep {
+            println!(
+                "{}",
+                i18n::tr(
+                    "No screen has {}x{} anymore, releasing /dev/video{}",
+                    &[&resolution.width, &resolution.height, &device_number]
+                )
+            );
+            device_locks.remove(device_number);
+        }
+        keep
+    });
+}
+
+fn ipc(options: &Options, request: IpcRequest) -> Command {
+    options
+        .backend
+        .ipc_command(request, options.ipc_socket.as_deref())
+}
+
+fn ipc_output(options: &Options, request: IpcRequest, name: &str) -> Result<String, String> {
+    let output = ipc(options, request).output().map_err(|err| {
+        i18n::tr(
+            "Could not run {}: {}",
+            &[&options.backend.ipc_program(), &err],
+        )
+    })?;
+
+    String::from_utf8(output.stdout).map_err(|_| {
+        i18n::tr(
+            "The {} reply of {} is not UTF-8",
+            &[&name, &options.backend.ipc_program()],
+        )
+    })
+}
 
-    return Ok(processes);
+/// Says what is wrong with a reply of the window manager that can't be parsed.
+fn invalid_reply(options: &Options, name: &str, err: serde_json::Error) -> String {
+    i18n::tr(
+        "Could not understand the {} reply of {}: {}",
+        &[&name, &options.backend.ipc_program(), &err],
+    )
 }
 
-fn get_outputs(config: &mut Config) -> Vec<SwayOutput> {
-    let command = "swaymsg -t get_outputs";
-    let output = Command::new("sh")
-        .args(&["-c", command])
-        .output()
-        .expect("Error running swaymsg");
+/// The outputs, or why the window manager couldn't be asked for them.
+fn query_outputs(config: &Config) -> Result<Vec<SwayOutput>, String> {
+    let mut outputs: Vec<SwayOutput> = match (config.options.backend, &config.runtime.toplevel) {
+        (Backend::ForeignToplevel, Some(tracker)) => tracker.outputs(),
+        (Backend::Niri, _) => config.runtime.layout.outputs(|| {
+            let stdout_string = ipc_output(&config.options, IpcRequest::Outputs, "get_outputs")?;
+            niri::parse_outputs(stdout_string.as_str())
+                .map_err(|err| invalid_reply(&config.options, "get_outputs", err))
+        })?,
+        _ => config.runtime.layout.outputs(|| {
+            let stdout_string = ipc_output(&config.options, IpcRequest::Outputs, "get_outputs")?;
+            serde_json::from_str(stdout_string.as_str())
+                .map_err(|err| invalid_reply(&config.options, "get_outputs", err))
+        })?,
+    };
+
+    if config.options.normalize_scale {
+        for output in outputs.iter_mut() {
+            if output.scale.is_some_and(|scale| scale != 1.0) {
+                output.scaled_to = Some(output.logical_resolution());
+            }
+        }
+    }
 
-    let stdout_string = String::from_utf8(output.stdout).expect("Invalid UTF-8 from get_outputs");
-    let outputs: Vec<SwayOutput> =
-        serde_json::from_str(stdout_string.as_str()).expect("Invalid json from get_outputs");
+    outputs.retain(SwayOutput::enabled);
 
-    if config.verbose {
-        println!("Found outputs");
+    if config.options.verbose {
+        println!("{}", i18n::tr("Found outputs", &[]));
         for elem in outputs.iter() {
             println!("{:?}", elem);
         }
     }
 
-    return outputs;
+    Ok(outputs)
 }
 
-fn get_output(config: &mut Config, screen: &str) -> SwayOutput {
-    let outputs = get_outputs(config);
-    let output = match outputs.iter().find(|o| o.name == screen) {
-        Some(o) => o.to_owned(),
-        None => panic!("Could not find output"),
-    };
+/// The outputs, or none if the window manager couldn't be asked for them, which callers handle
+/// like every output being unplugged until it can be asked again.
+fn get_outputs(config: &Config) -> Vec<SwayOutput> {
+    query_outputs(config).unwrap_or_else(|err| {
+        println!("{}", err);
+        Vec::new()
+    })
+}
+
+/// Looks up the output with this name. It may be unplugged since the workspaces were listed,
+/// or only show up a moment later, so it is looked up once more before giving up.
+fn get_output(config: &Config, screen: &str) -> Result<SwayOutput, String> {
+    let mut failure = None;
+    for attempt in 0..2 {
+        if attempt > 0 {
+            thread::sleep(time::Duration::from_millis(200));
+            config.runtime.layout.invalidate();
+        }
+        match query_outputs(config) {
+            Ok(outputs) => {
+                if let Some(output) = outputs.into_iter().find(|o| o.name == screen) {
+                    return Ok(output);
+                }
+                failure = None;
+            }
+            Err(err) => failure = Some(err),
+        }
+    }
 
-    return output.clone();
+    Err(failure.unwrap_or_else(|| i18n::tr("Could not find output {}", &[&screen])))
 }
 
 fn get_resolutions(config: &mut Config) -> Vec<Resolution> {
-    let outputs = get_outputs(config);
+    let started = time::Instant::now();
+    let mut outputs = get_outputs(config);
+    let mut attempts = 0;
+    while outputs.is_empty() {
+        if started.elapsed() > OUTPUTS_TIMEOUT {
+            println!(
+                "{}",
+                i18n::tr(
+                    "No enabled outputs found after {} seconds",
+                    &[&OUTPUTS_TIMEOUT.as_secs()]
+                )
+            );
+            std::process::exit(1);
+        }
+        // Right away and then every five seconds, so a slow compositor doesn't look like a hang
+        if attempts % 10 == 0 {
+            println!(
+                "{}",
+                i18n::tr(
+                    "No enabled outputs yet, waiting for the compositor to set them up",
+                    &[]
+                )
+            );
+        }
+        attempts += 1;
+        thread::sleep(time::Duration::from_millis(500));
+        config.runtime.layout.invalidate();
+        outputs = get_outputs(config);
+    }
+    let resolutions = plan_resolutions(&outputs);
+
+    if config.options.verbose {
+        println!(
+            "{}",
+            i18n::tr(
+                "Found resolutions, the combined maximum resolution first:",
+                &[]
+            )
+        );
+        println!("{:?}", resolutions);
+    }
+
+    resolutions
+}
+
+/// The resolutions of the outputs, after the combined maximum resolution every one of them
+/// fits in, which is what the output device gets.
+fn plan_resolutions(outputs: &[SwayOutput]) -> Vec<Resolution> {
     let mut resolutions: Vec<Resolution> = outputs
         .iter()
-        .map(|o| Resolution {
-            height: o.current_mode.height,
-            width: o.current_mode.width,
-        })
+        .map(|o| o.resolution())
         .unique()
         .collect_vec();
 
-    if config.verbose {
-        println!("Found resolutions:");
-        println!("{:?}", resolutions);
-    }
-
     let combined_resolution: Resolution = resolutions.iter().fold(
         Resolution {
             width: 0,
@@ -291,52 +1487,80 @@ fn get_resolutions(config: &mut Config) -> Vec<Resolution> {
         },
     );
 
-    if config.verbose {
-        println!(
-            "Found combined maximum resolution {:?}",
-            combined_resolution
-        );
-    }
-
     resolutions.insert(0, combined_resolution);
     resolutions = resolutions.into_iter().unique().collect_vec();
 
-    return resolutions;
+    resolutions
+}
+
+/// Puts `canvas` in place of the combined resolution, dropping an output resolution that is the
+/// same, since the output device already has it.
+fn set_canvas(resolutions: &mut Vec<Resolution>, canvas: Resolution) {
+    resolutions[0] = canvas;
+    *resolutions = resolutions.iter().copied().unique().collect_vec();
+}
+
+/// The workspaces, or none if the window manager couldn't be asked for them, which streams the
+/// black screen until it can be asked again.
+fn get_workspaces(config: &Config) -> Vec<SwayWorkspace> {
+    let workspaces = match (config.options.backend, &config.runtime.toplevel) {
+        (Backend::ForeignToplevel, Some(tracker)) => Ok(tracker.workspaces()),
+        (Backend::Niri, _) => config.runtime.layout.workspaces(|| {
+            let stdout_string =
+                ipc_output(&config.options, IpcRequest::Workspaces, "get_workspaces")?;
+            niri::parse_workspaces(stdout_string.as_str())
+                .map_err(|err| invalid_reply(&config.options, "get_workspaces", err))
+        }),
+        _ => config.runtime.layout.workspaces(|| {
+            let stdout_string =
+                ipc_output(&config.options, IpcRequest::Workspaces, "get_workspaces")?;
+            serde_json::from_str(stdout_string.as_str())
+                .map_err(|err| invalid_reply(&config.options, "get_workspaces", err))
+        }),
+    };
+
+    workspaces.unwrap_or_else(|err| {
+        println!("{}", err);
+        Vec::new()
+    })
+}
+
+fn workspace_blacklisted(config: &Config, workspace: &SwayWorkspace) -> bool {
+    config
+        .workspace_blacklist()
+        .iter()
+        .any(|id| id.matches(workspace.num, workspace.name.as_str()))
 }
 
 fn get_valid_screens_for_recording(config: &Config) -> Vec<SwayWorkspace> {
-    let command = "swaymsg -t get_workspaces";
-    let output = Command::new("sh")
-        .args(&["-c", command])
-        .output()
-        .expect("Error running swaymsg");
-
-    let stdout_string =
-        String::from_utf8(output.stdout).expect("Invalid UTF-8 from get_workspaces");
-    let mut workspaces: Vec<SwayWorkspace> =
-        serde_json::from_str(stdout_string.as_str()).expect("Invalid json from get_workspaces");
-
-    if config.verbose {
-        println!("Found workspaces:");
+    let mut workspaces = get_workspaces(config);
+    let focus_hidden = workspaces
+        .iter()
+        .find(|w| w.focused)
+        .is_some_and(|w| workspace_blacklisted(config, w) || config.screen_blacklisted(&w.output));
+
+    if config.options.verbose {
+        println!("{}", i18n::tr("Found workspaces:", &[]));
         for elem in workspaces.iter() {
             println!("{:?}", elem);
         }
     }
 
-    workspaces = workspaces
-        .into_iter()
-        .filter(|w| {
-            w.visible
-                && !config
-                    .screen_blacklist
-                    .iter()
-                    .any(|screen| screen.eq(&w.output))
-                && !config.workspace_blacklist.iter().any(|&num| num == w.num)
-        })
-        .collect();
+    workspaces.retain(|w| {
+        w.visible && !config.screen_blacklisted(&w.output) && !workspace_blacklisted(config, w)
+    });
+
+    if config.options.backend == Backend::Sway {
+        let powered_off: Vec<String> = get_outputs(config)
+            .into_iter()
+            .filter(|o| !o.powered())
+            .map(|o| o.name)
+            .collect();
+        workspaces.retain(|w| !powered_off.contains(&w.output));
+    }
 
-    if config.verbose {
-        println!("Blacklisted workspaces filtered out:");
+    if config.options.verbose {
+        println!("{}", i18n::tr("Blacklisted workspaces filtered out:", &[]));
         for elem in workspaces.iter() {
             println!("{:?}", elem);
         }
@@ -352,106 +1576,2538 @@ fn get_valid_screens_for_recording(config: &Config) -> Vec<SwayWorkspace> {
         }
     });
 
-    return workspaces;
+    if focus_hidden && config.options.blank_on_hidden {
+        return Vec::new();
+    }
+
+    // A window to redact on the screen that would be recorded blanks the stream, as do windows
+    // to mask that moved away from under their boxes
+    if workspaces.first().is_some_and(|w| {
+        config.runtime.redacted.contains(&w.output)
+            || config.runtime.unmasked.as_ref() == Some(&w.output)
+    }) {
+        return Vec::new();
+    }
+
+    workspaces
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut config = Config {
-        current_output: "".to_string(),
-        devices_from: 0,
-        last_device_index: 0,
-        screen_blacklist: Vec::new(),
-        workspace_blacklist: Vec::new(),
-        verbose: false,
-        resolutions: Vec::new(),
-        outputs: HashMap::new(),
+/// Moves the indicator to the screens that are being streamed, if it is turned on.
+fn update_indicator(config: &Config, outputs: &[String]) {
+    if let Some(indicator) = &config.runtime.indicator {
+        indicator.show(outputs);
+    }
+}
+
+/// Moves the latency stamp to the screen being recorded, and reads it back from the devices of
+/// the new pipeline.
+fn update_latency(config: &mut Config) {
+    let mut meter = match config.runtime.latency.take() {
+        Some(meter) => meter,
+        None => return,
     };
-    let args: Vec<String> = env::args().collect();
 
-    let mut i = 1;
-    loop {
-        if i >= args.len() {
-            break;
+    let output = get_outputs(config)
+        .into_iter()
+        .find(|o| o.name == config.runtime.streamer.output());
+    match output {
+        Some(output) => {
+            let resolution = output.resolution();
+            let mut stages = Vec::new();
+            match config.runtime.outputs.get(&resolution) {
+                Some(device_number)
+                    if *device_number != config.options.devices_from
+                        && config.options.render_node.is_none() =>
+                {
+                    stages.push(("capture", *device_number, resolution));
+                }
+                _ => {}
+            }
+            stages.push((
+                "output device",
+                config.options.devices_from,
+                config.runtime.resolutions[0],
+            ));
+            meter.measure(
+                &config.options.ffmpeg_path,
+                &output.name,
+                resolution,
+                &stages,
+            );
         }
+        None => meter.stop(),
+    }
 
-        let arg = &args[i];
-        if arg == "--not-ws" {
-            i += 1;
-            config
-                .workspace_blacklist
-                .push(args[i].clone().parse::<usize>().unwrap());
-        } else if arg == "--not-screen" {
-            i += 1;
-            config.screen_blacklist.push(args[i].clone());
-        } else if arg == "-d" || arg == "--devices-from" {
-            i += 1;
-            config.devices_from = args[i].clone().parse::<usize>().unwrap();
-        } else if arg == "--verbose" {
-            config.verbose = true;
-        } else if arg == "-v" || arg == "--version" {
-            println!("v{}", VERSION);
-            std::process::exit(0);
-        } else if arg == "-h" || arg == "--help" {
-            help();
-        } else {
-            println!("Unknown option: {}", arg);
-            help();
+    config.runtime.latency = Some(meter);
+}
+
+/// Whether frames keep reaching the output device while the screen doesn't change, so missing
+/// ones mean the pipeline is stuck. wf-recorder only captures changes unless told otherwise, and
+/// --damage stops ext-image-copy-capture frames from being repeated and drops unchanged frames of
+/// x11grab, while it makes the devices wf-recorder writes to repeat them.
+fn frames_guaranteed(options: &Options) -> bool {
+    match options.capture_protocol {
+        CaptureProtocol::WlrScreencopy => {
+            options.damage
+                || options
+                    .wf_recorder_args
+                    .iter()
+                    .any(|arg| arg == "--no-damage" || arg == "-D")
         }
-        i += 1;
+        CaptureProtocol::ExtImageCopyCapture | CaptureProtocol::X11Grab => !options.damage,
     }
+}
 
-    config.resolutions = get_resolutions(&mut config);
-    config
+/// Whether part of a pipeline exited, or it stopped producing frames with --stall-timeout.
+fn stalled(config: &Config, pipeline: &mut Pipeline) -> bool {
+    let timeout = config.options.stall_timeout;
+    pipeline.exited()
+        || (timeout > 0
+            && frames_guaranteed(&config.options)
+            && config
+                .runtime
+                .stats
+                .stalled(time::Duration::from_secs(timeout)))
+}
+
+fn emit_switch(config: &mut Config) {
+    let output = match &config.runtime.streamer {
+        StreamerState::Streaming { output } => Some(output.as_str()),
+        _ => None,
+    };
+    config
+        .runtime
+        .event_log
+        .emit("switch", json!({ "output": output }));
+    config
+        .runtime
+        .chapters
+        .mark(output.unwrap_or("Black screen"));
+
+    let workspace = output.and_then(|output| {
+        get_workspaces(config)
+            .into_iter()
+            .find(|w| w.visible && w.output == output)
+            .map(|w| w.name)
+    });
+    let reason = output.is_none().then(|| black_reason(config));
+    scene::set(output, workspace.as_deref());
+    let output = output.map(str::to_string);
+    config
+        .runtime
+        .segments
+        .mark(output.as_deref(), workspace.as_deref());
+    config
+        .runtime
+        .timeline
+        .mark(output.as_deref(), workspace.as_deref(), reason);
+}
+
+/// Why a black screen is streamed, for the timeline.
+fn black_reason(config: &Config) -> &'static str {
+    let runtime = &config.runtime;
+    if matches!(runtime.streamer, StreamerState::Failed { .. }) {
+        "failed"
+    } else if runtime.streamer == StreamerState::CountingDown {
+        "countdown"
+    } else if runtime.paused {
+        "blanked"
+    } else if runtime.idle {
+        "idle"
+    } else if runtime.unwatched {
+        "unwatched"
+    } else if runtime.off_schedule {
+        "off schedule"
+    } else if runtime.no_call {
+        "no call"
+    } else if !runtime.redacted.is_empty() || runtime.unmasked.is_some() {
+        "redacted"
+    } else {
+        "hidden"
+    }
+}
+
+fn recording_status(config: &Config) -> String {
+    match &config.runtime.streamer {
+        StreamerState::Streaming { output } => i18n::tr("Recording {}", &[&output]),
+        StreamerState::Failed { reason, .. } => i18n::tr("Failed: {}", &[&reason]),
+        _ => i18n::tr("Streaming a black screen", &[]),
+    }
+}
+
+/// Switches to the named profile, on top of the options wlstreamer was started with. Locked
+/// outputs stay locked.
+fn apply_profile(options: &Options, runtime: &mut RuntimeState, name: &str) -> Result<(), String> {
+    runtime.profile = match options.profiles.get(name) {
+        Some(profile) => profile.clone(),
+        None if name == "default" => config_file::Profile::default(),
+        None => return Err(i18n::tr("unknown profile: {}", &[&name])),
+    };
+    update_sinks(options, runtime);
+
+    println!("{}", i18n::tr("Switched to profile {}", &[&name]));
+    runtime.event_log.emit("profile", json!({ "name": name }));
+    Ok(())
+}
+
+/// Starts the sinks of the profile that aren't running yet, and stops the ones it leaves out.
+/// Sinks in both keep running, so their files and connections aren't cut.
+fn update_sinks(options: &Options, runtime: &mut RuntimeState) {
+    let wanted = runtime.profile.sinks.as_ref().unwrap_or(&options.sinks);
+    let running = match &mut runtime.sinks {
+        Some(running) => running,
+        None => return,
+    };
+
+    running.retain(|url, sinks| {
+        if !wanted.contains(url) {
+            sinks.stop();
+        }
+        wanted.contains(url)
+    });
+    if wanted.iter().all(|url| running.contains_key(url)) {
+        return;
+    }
+    let render_node = capture::render_node(options.drm_device.as_deref());
+    for url in wanted {
+        if !running.contains_key(url) {
+            let sinks = sink::Sinks::start(
+                options,
+                std::slice::from_ref(url),
+                render_node.as_deref(),
+                runtime.audio_delay,
+            );
+            running.insert(url.clone(), sinks);
+        }
+    }
+}
+
+fn parse_redact(pattern: &str) -> Regex {
+    match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            println!(
+                "{}",
+                i18n::tr("Invalid --redact pattern {}: {}", &[&pattern, &err])
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_schedule(text: &str) -> schedule::Window {
+    match schedule::Window::parse(text) {
+        Ok(window) => window,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_duration(text: &str) -> time::Duration {
+    match schedule::parse_duration(text) {
+        Ok(duration) => duration,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A frame rate given to `option`, which can't be 0 since frame times are divided by it.
+fn parse_frame_rate(option: &str, text: &str) -> u32 {
+    match text.parse::<u32>() {
+        Ok(fps) if fps > 0 => fps,
+        _ => {
+            println!(
+                "{}",
+                i18n::tr("Invalid frame rate for {}: {}", &[&option, &text])
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A frame rate from the config file, checked like `parse_frame_rate`.
+fn check_frame_rate(key: &str, fps: Option<u32>) -> Option<u32> {
+    if fps == Some(0) {
+        println!(
+            "{}",
+            i18n::tr("Invalid frame rate for {} in config file: 0", &[&key])
+        );
+        std::process::exit(1);
+    }
+    fps
+}
+
+/// The battery charge in percent below which `--battery-fps` applies.
+fn parse_battery_threshold(text: &str) -> u8 {
+    match text.parse::<u8>() {
+        Ok(percent) if percent <= 100 => percent,
+        _ => {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Invalid --battery-threshold {}, it should be a percentage from 0 to 100",
+                    &[&text]
+                )
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Checks the open windows for call apps, for titles to redact and for apps to mask.
+fn update_windows(config: &mut Config) {
+    if config.options.call_apps.is_empty()
+        && config.options.urgent_apps.is_empty()
+        && config.options.redact.is_empty()
+        && config.options.mask_apps.is_empty()
+        && config.runtime.zoom.is_none()
+    {
+        return;
+    }
+    let windows = windows::list(config);
+    zoom_to_focus(config, &windows);
+    if !config.options.mask_apps.is_empty() {
+        config.runtime.masks =
+            mask::find(&config.options.mask_apps, &windows, &get_outputs(config));
+    }
+
+    // The stream is blanked while none of the call apps has a window
+    let in_call = windows
+        .iter()
+        .any(|window| config.options.call_apps.contains(&window.app_id));
+    if !config.options.call_apps.is_empty() && in_call == config.runtime.no_call {
+        config.runtime.no_call = !in_call;
+        if in_call {
+            println!("{}", i18n::tr("A call started, streaming the screens", &[]));
+        } else {
+            println!(
+                "{}",
+                i18n::tr("No call app is open, streaming a black screen", &[])
+            );
+        }
+        config
+            .runtime
+            .event_log
+            .emit("call", json!({ "active": in_call }));
+    }
+
+    // Only a window that starts asking for attention cuts to its screen, not one that keeps asking
+    let urgent: Vec<(String, String)> = windows
+        .iter()
+        .filter(|window| window.urgent && window.visible)
+        .filter(|window| config.options.urgent_apps.contains(&window.app_id))
+        .filter_map(|window| Some((window.app_id.clone(), window.output.clone()?)))
+        .collect();
+    if let Some((app_id, output)) = urgent
+        .iter()
+        .find(|window| !config.runtime.urgent_windows.contains(window))
+    {
+        println!(
+            "{}",
+            i18n::tr(
+                "{} asks for attention on {}, streaming it for {}s",
+                &[&app_id, &output, &config.options.urgent_seconds]
+            )
+        );
+        config
+            .runtime
+            .event_log
+            .emit("urgent", json!({ "app_id": app_id, "output": output }));
+        let until = time::Instant::now() + time::Duration::from_secs(config.options.urgent_seconds);
+        config.runtime.urgent = Some((output.clone(), until));
+    }
+    config.runtime.urgent_windows = urgent;
+
+    let mut redacted: Vec<String> = windows
+        .iter()
+        .filter(|window| window.visible)
+        .filter(|window| {
+            config
+                .options
+                .redact
+                .iter()
+                .any(|redact| redact.is_match(&window.title))
+        })
+        .filter_map(|window| window.output.clone())
+        // Windows to mask that can't be found on their screen hide all of it
+        .chain(mask::unplaced(&config.options.mask_apps, &windows))
+        .unique()
+        .collect();
+    redacted.sort();
+    if redacted != config.runtime.redacted {
+        for output in redacted
+            .iter()
+            .filter(|o| !config.runtime.redacted.contains(o))
+        {
+            println!(
+                "{}",
+                i18n::tr("A window to redact is visible on {}, hiding it", &[&output])
+            );
+        }
+        for output in config
+            .runtime
+            .redacted
+            .iter()
+            .filter(|o| !redacted.contains(o))
+        {
+            println!(
+                "{}",
+                i18n::tr("Nothing to redact on {} anymore", &[&output])
+            );
+        }
+        config
+            .runtime
+            .event_log
+            .emit("redact", json!({ "outputs": redacted }));
+        config.runtime.redacted = redacted;
+    }
+}
+
+/// Tells --zoom where the focused window is, in the pixels captured from its output.
+fn zoom_to_focus(config: &Config, windows: &[windows::Window]) {
+    let zoom = match config.runtime.zoom.as_ref() {
+        Some(zoom) => zoom,
+        None => return,
+    };
+    let (name, rect) = match windows.iter().find(|window| window.focused) {
+        Some(windows::Window {
+            output: Some(name),
+            rect: Some(rect),
+            ..
+        }) => (name, rect),
+        _ => return,
+    };
+    let output = match get_outputs(config).into_iter().find(|o| o.name == *name) {
+        Some(output) => output,
+        None => return,
+    };
+
+    // The layout is in logical pixels, which scaled outputs capture more of
+    let resolution = output.resolution();
+    let scale_x = resolution.width as f64 / output.rect.width.max(1) as f64;
+    let scale_y = resolution.height as f64 / output.rect.height.max(1) as f64;
+    zoom.focus(
+        name,
+        (rect.x - output.rect.x) as f64 * scale_x + rect.width as f64 * scale_x / 2.0,
+        (rect.y - output.rect.y) as f64 * scale_y + rect.height as f64 * scale_y / 2.0,
+    );
+}
+
+fn blank(config: &mut Config, seconds: Option<u64>) {
+    match seconds {
+        Some(seconds) => println!("{}", i18n::tr("Blanking the stream for {}s", &[&seconds])),
+        None => println!("{}", i18n::tr("Blanking the stream", &[])),
+    }
+    config
+        .runtime
+        .event_log
+        .emit("blank", json!({ "seconds": seconds }));
+    config.runtime.paused = true;
+    config.runtime.blank_until =
+        seconds.map(|seconds| time::Instant::now() + time::Duration::from_secs(seconds));
+}
+
+fn resume(config: &mut Config) {
+    println!("{}", i18n::tr("Resuming the stream", &[]));
+    config.runtime.event_log.emit("resume", json!({}));
+    if config.runtime.paused {
+        start_countdown(config);
+    }
+    config.runtime.paused = false;
+    config.runtime.blank_until = None;
+}
+
+/// Resumes once the time given to wlstreamer ctl blank is up, returning whether it did. Checked
+/// on every tick, from the deadline set when the command was carried out.
+fn blank_over(config: &mut Config) -> bool {
+    match config.runtime.blank_until {
+        Some(until) if time::Instant::now() >= until => {
+            resume(config);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Counts down for --countdown seconds before the screens are streamed, in the modes that stream
+/// a single screen.
+fn start_countdown(config: &mut Config) {
+    if config.options.countdown == 0
+        || !matches!(config.options.mode, Mode::Follow | Mode::Headless)
+    {
+        return;
+    }
+    println!(
+        "{}",
+        i18n::tr("Going live in {}s", &[&config.options.countdown])
+    );
+    config.runtime.countdown =
+        Some(time::Instant::now() + time::Duration::from_secs(config.options.countdown));
+}
+
+/// Forgets the countdown once it is over, returning whether it was.
+fn countdown_over(config: &mut Config) -> bool {
+    match config.runtime.countdown {
+        Some(until) if time::Instant::now() >= until => {
+            config.runtime.countdown = None;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn lock(config: &mut Config, output: String) {
+    println!("{}", i18n::tr("Locked {}", &[&output]));
+    if !config.runtime.locked.contains(&output) {
+        config.runtime.locked.push(output.clone());
+    }
+    config
+        .runtime
+        .event_log
+        .emit("lock", json!({ "output": output }));
+}
+
+fn unlock(config: &mut Config, output: String) {
+    println!("{}", i18n::tr("Unlocked {}", &[&output]));
+    config.runtime.locked.retain(|screen| *screen != output);
+    config
+        .runtime
+        .event_log
+        .emit("unlock", json!({ "output": output }));
+}
+
+/// Carries out control commands, and returns whether wlstreamer should keep running. Every
+/// event is followed by a check of what should be streamed, so nothing else needs to be done
+/// here for focus changes.
+fn handle_event(config: &mut Config, event: Event) -> bool {
+    match event {
+        Event::Changed | Event::Windows => {
+            update_windows(config);
+            true
+        }
+        Event::Control(command, reply) => {
+            let result = match command {
+                control::Command::Profile(name) => {
+                    apply_profile(&config.options, &mut config.runtime, name.as_str())
+                        .map(|_| String::new())
+                }
+                control::Command::Blank(seconds) => {
+                    blank(config, seconds);
+                    Ok(String::new())
+                }
+                control::Command::Resume => {
+                    resume(config);
+                    Ok(String::new())
+                }
+                control::Command::Toggle => {
+                    if config.runtime.paused {
+                        resume(config);
+                    } else {
+                        blank(config, None);
+                    }
+                    Ok(String::new())
+                }
+                control::Command::Status { json } => Ok(status::report(config, json)),
+                control::Command::Lock(output) => {
+                    lock(config, output);
+                    Ok(String::new())
+                }
+                control::Command::Unlock(output) => {
+                    unlock(config, output);
+                    Ok(String::new())
+                }
+                control::Command::ToggleLock(output) => {
+                    if config.runtime.locked.contains(&output) {
+                        unlock(config, output);
+                    } else {
+                        lock(config, output);
+                    }
+                    Ok(String::new())
+                }
+                control::Command::SaveReplay(path) => match config.options.replay_buffer {
+                    Some(_) => {
+                        // ffmpeg copies the segments for a while, which would hold up switching
+                        let ffmpeg_path = config.options.ffmpeg_path.clone();
+                        let event_log = config.runtime.event_log.clone();
+                        thread::spawn(move || {
+                            let result = replay::save(&ffmpeg_path, path.as_deref()).map(|path| {
+                                println!("{}", i18n::tr("Saved the replay to {}", &[&path]));
+                                event_log.emit("replay", json!({ "path": path }));
+                                path
+                            });
+                            let _ = reply.send(result);
+                        });
+                        return true;
+                    }
+                    None => Err(i18n::tr(
+                        "The replay buffer is off, start wlstreamer with --replay-buffer",
+                        &[],
+                    )),
+                },
+                control::Command::Screenshot(path) => {
+                    if config.options.mode == Mode::MirrorAll {
+                        Err(i18n::tr(
+                            "There is no single stream to take a screenshot of in mirror-all mode",
+                            &[],
+                        ))
+                    } else {
+                        // Waiting for ffmpeg to read a frame would hold up switching
+                        let ffmpeg_path = config.options.ffmpeg_path.clone();
+                        let device_number = config.options.devices_from;
+                        let event_log = config.runtime.event_log.clone();
+                        thread::spawn(move || {
+                            let result = screenshot::take(
+                                &ffmpeg_path,
+                                device_number,
+                                std::path::Path::new(&path),
+                            )
+                            .map(|_| {
+                                println!("{}", i18n::tr("Saved a screenshot to {}", &[&path]));
+                                event_log.emit("screenshot", json!({ "path": path }));
+                                String::new()
+                            });
+                            let _ = reply.send(result);
+                        });
+                        return true;
+                    }
+                }
+            };
+            // The client may have hung up already
+            let _ = reply.send(result);
+            true
+        }
+        Event::Idle(idle) => {
+            if config.options.verbose {
+                let message = if idle {
+                    "User is idle"
+                } else {
+                    "User is active"
+                };
+                println!("{}", i18n::tr(message, &[]));
+            }
+            config.runtime.idle = idle;
+            config
+                .runtime
+                .event_log
+                .emit(if idle { "idle" } else { "active" }, json!({}));
+            true
+        }
+        Event::Watched(watched) => {
+            println!(
+                "{}",
+                if watched {
+                    i18n::tr(
+                        "The output device is being watched, capturing the screens",
+                        &[],
+                    )
+                } else {
+                    i18n::tr(
+                        "Nobody is watching the output device anymore, streaming a black screen",
+                        &[],
+                    )
+                }
+            );
+            config.runtime.unwatched = !watched;
+            config
+                .runtime
+                .event_log
+                .emit(if watched { "watched" } else { "unwatched" }, json!({}));
+            true
+        }
+        Event::Battery(on_battery) => {
+            println!(
+                "{}",
+                if on_battery {
+                    i18n::tr("Running on battery, capturing at a lower frame rate", &[])
+                } else {
+                    i18n::tr("Plugged in, capturing at the full frame rate", &[])
+                }
+            );
+            config.runtime.on_battery = on_battery;
+            config
+                .runtime
+                .event_log
+                .emit("power", json!({ "battery": on_battery }));
+            true
+        }
+        Event::Scheduled(open) => {
+            println!(
+                "{}",
+                if open {
+                    i18n::tr("The schedule starts, streaming the screens", &[])
+                } else {
+                    i18n::tr("The schedule is over, streaming a black screen", &[])
+                }
+            );
+            config.runtime.off_schedule = !open;
+            config
+                .runtime
+                .event_log
+                .emit("schedule", json!({ "open": open }));
+            true
+        }
+        Event::Tick => true,
+        Event::Disconnected | Event::Quit => false,
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = Options::default();
+    let mut runtime = RuntimeState::default();
+    let mut requested_backend = None;
+    let mut requested_protocol = None;
+    let mut daemonize = false;
+    let mut selftest = false;
+    let mut args: Vec<String> = env::args().collect();
+
+    // Looked at first, since ctl and tui need it to find the instance they talk to
+    if let Some(i) = args.iter().position(|arg| arg == "--instance") {
+        if let Some(name) = args.get(i + 1) {
+            control::set_instance(name);
+        }
+        if matches!(
+            args.get(1).map(|arg| arg.as_str()),
+            Some("ctl" | "tui" | "tray")
+        ) {
+            args.drain(i..(i + 2).min(args.len()));
+        }
+    }
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("ctl") => control::run_client(&args[2..]),
+        Some("tui") => tui::run(),
+        Some("tray") => tray::run(),
+        Some("install-service") => service::install(&args[2..]),
+        Some("completions") => cli::completions(&args[2..]),
+        Some("manpage") => cli::manpage(),
+        Some("capture-output") => image_copy::run(&args[2..]),
+        _ => {}
+    }
+
+    let config_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .and_then(|i| args.get(i + 1));
+    let file = match config_file::load(config_path.map(|path| path.as_str())) {
+        Ok(file) => file,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    options.workspace_blacklist = file.not_ws;
+    options.screen_blacklist = file.not_screen;
+    options.devices_from = file.devices_from.unwrap_or(options.devices_from);
+    let mut output_device = file.output_device;
+    let mut intermediate_devices = file.intermediate_devices;
+    if let Some(name) = file.capture_protocol {
+        requested_protocol = match CaptureProtocol::parse(name.as_str()) {
+            Some(protocol) => Some(protocol),
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr("Unknown capture protocol in config file: {}", &[&name])
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(name) = file.mode {
+        options.mode = match Mode::parse(name.as_str()) {
+            Some(mode) => mode,
+            None => {
+                println!("{}", i18n::tr("Unknown mode in config file: {}", &[&name]));
+                std::process::exit(1);
+            }
+        };
+    }
+    options.side_by_side = file.side_by_side;
+    options.enlarge_focused = file.enlarge_focused.unwrap_or(options.enlarge_focused);
+    options.highlight = file.highlight;
+    options.highlight_width = file.highlight_width.unwrap_or(options.highlight_width);
+    options.gpu_scale = file.gpu_scale.unwrap_or(options.gpu_scale);
+    options.drm_device = file.drm_device;
+    options.blank_when_idle = file.blank_when_idle;
+    options.schedule = schedule::Schedule {
+        windows: file
+            .schedule
+            .iter()
+            .map(|window| parse_schedule(window))
+            .collect(),
+        duration: file.duration.as_deref().map(parse_duration),
+    };
+    options.hidden_text = file.hidden_text.unwrap_or(options.hidden_text);
+    options.blank_on_hidden = file.blank_on_hidden.unwrap_or(options.blank_on_hidden);
+    options.placeholder_fps = check_frame_rate("placeholder-fps", file.placeholder_fps);
+    options.dnd = file.dnd.unwrap_or(options.dnd);
+    options.lazy = file.lazy.unwrap_or(options.lazy);
+    options.standby = file.standby.unwrap_or(options.standby);
+    options.compat = file.compat.unwrap_or(options.compat);
+    if let Some(name) = file.quality {
+        options.quality = quality::Quality::parse(&name).unwrap_or_else(|| {
+            println!("{}", i18n::tr("Unknown quality: {}", &[&name]));
+            std::process::exit(1);
+        });
+    }
+    options.zoom = file.zoom;
+    options.zoom_duration = file.zoom_duration.unwrap_or(options.zoom_duration);
+    options.damage = file.damage.unwrap_or(options.damage);
+    options.normalize_scale = file.normalize_scale.unwrap_or(options.normalize_scale);
+    options.fps = check_frame_rate("fps", file.fps);
+    if let Some(name) = file.color_range {
+        options.color.range = match color::Range::parse(name.as_str()) {
+            Some(range) => Some(range),
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr("Unknown color range in config file: {}", &[&name])
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(name) = file.colorspace {
+        options.color.space = match color::Space::parse(name.as_str()) {
+            Some(space) => Some(space),
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr("Unknown colorspace in config file: {}", &[&name])
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(name) = file.tonemap {
+        options.tonemap.algorithm = match color::Tonemap::parse_algorithm(name.as_str()) {
+            Some(algorithm) => algorithm,
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr("Unknown tonemap algorithm in config file: {}", &[&name])
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+    options.tonemap.peak = file.tonemap_peak.unwrap_or(options.tonemap.peak);
+    options.sinks = file.sinks;
+    if let Some(name) = &file.instance {
+        control::set_instance(name);
+    }
+    let mut session = file.session;
+    if let Some(name) = file.codec {
+        if options.encoding.parse_codec(name.as_str()).is_none() {
+            println!("{}", i18n::tr("Unknown codec in config file: {}", &[&name]));
+            std::process::exit(1);
+        }
+    }
+    options.encoding.bitrate = file.bitrate;
+    options.encoding.min_bitrate = file.min_bitrate;
+    options.encoding.preset = file.preset;
+    options.encoding.crf = file.crf;
+    options.transport.passphrase = file.sink_passphrase;
+    options.transport.latency = file.sink_latency;
+    options.transport.token = file.whip_token;
+    options.secrets_file = file.secrets_file;
+    options.serve_hls = file.serve_hls;
+    options.preview_listen = file.preview_listen;
+    options.replay_buffer = file.replay_buffer;
+    options.record_segments = file.record_segments;
+    options.call_apps = file.call_apps;
+    options.urgent_apps = file.urgent_apps;
+    options.urgent_seconds = file.urgent_seconds.unwrap_or(options.urgent_seconds);
+    options.countdown = file.countdown.unwrap_or(options.countdown);
+    options.redact = file
+        .redact
+        .iter()
+        .map(|pattern| parse_redact(pattern))
+        .collect();
+    options.mask_apps = file.mask_apps;
+    options.audio.desktop = file.audio_desktop.unwrap_or(options.audio.desktop);
+    options.audio.mic = file.audio_mic.unwrap_or(options.audio.mic);
+    options.audio.desktop_volume = file.desktop_volume.unwrap_or(options.audio.desktop_volume);
+    options.audio.desktop_device = file.desktop_device;
+    options.audio.mic_device = file.mic_device;
+    options.audio.mic_volume = file.mic_volume.unwrap_or(options.audio.mic_volume);
+    options.audio.mic_denoise = file.mic_denoise.as_deref().map(audio::Denoise::parse);
+    options.audio.mic_limiter = file.mic_limiter.unwrap_or(options.audio.mic_limiter);
+    match file.av_offset {
+        Some(config_file::AvOffset::Ms(ms)) => options.audio.offset = audio::Offset::Fixed(ms),
+        Some(config_file::AvOffset::Name(name)) => {
+            options.audio.offset = match audio::Offset::parse(name.as_str()) {
+                Some(offset) => offset,
+                None => {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "av-offset has to be auto or a number of milliseconds, not {}",
+                            &[&name]
+                        )
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {}
+    }
+    options.battery_fps = check_frame_rate("battery-fps", file.battery_fps);
+    if let Some(percent) = file.battery_threshold.filter(|percent| *percent > 100) {
+        println!(
+            "{}",
+            i18n::tr(
+                "Invalid battery-threshold {} in config file, it should be a percentage from 0 to 100",
+                &[&percent]
+            )
+        );
+        std::process::exit(1);
+    }
+    options.battery_threshold = file.battery_threshold.unwrap_or(options.battery_threshold);
+    options.stats_interval = file.stats;
+    options.stall_timeout = file.stall_timeout.unwrap_or(options.stall_timeout);
+    if let Some(path) = file.events_json {
+        runtime.event_log = event_log::EventLog::open(path.as_str());
+    }
+    if let Some(path) = file.timeline {
+        runtime.timeline = timeline::Timeline::start(path.as_str());
+    }
+    options.show_indicator = file.indicator.unwrap_or(options.show_indicator);
+    options.verbose = file.verbose.unwrap_or(options.verbose);
+    options.wf_recorder_args = file.wf_recorder_args;
+    options.ffmpeg_args = file.ffmpeg_args;
+    options.capture_args = file
+        .output
+        .into_iter()
+        .map(|(name, output)| (name, output.capture_args))
+        .collect();
+    options.wf_recorder_path = file.wf_recorder_path.unwrap_or(options.wf_recorder_path);
+    options.ffmpeg_path = file.ffmpeg_path.unwrap_or(options.ffmpeg_path);
+    options.ipc_socket = file.ipc_socket;
+    for (name, profile) in &file.profiles {
+        check_frame_rate(
+            &format!("placeholder-fps of profile {}", name),
+            profile.placeholder_fps,
+        );
+    }
+    options.profiles = file.profiles;
+    let mut profile = file.profile;
+    if let Some(name) = file.backend {
+        requested_backend = match Backend::parse(name.as_str()) {
+            Some(backend) => Some(backend),
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr("Unknown backend in config file: {}", &[&name])
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let bench = args.get(1).map(|arg| arg.as_str()) == Some("bench");
+    let bindings = args.get(1).map(|arg| arg.as_str()) == Some("bindings");
+    let mut i = if bench || bindings { 2 } else { 1 };
+    loop {
+        if i >= args.len() {
+            break;
+        }
+
+        let arg = &args[i];
+        if arg == "--not-ws" {
+            i += 1;
+            options
+                .workspace_blacklist
+                .push(config_file::WorkspaceId::parse(args[i].as_str()));
+        } else if arg == "--not-screen" {
+            i += 1;
+            options.screen_blacklist.push(args[i].clone());
+        } else if arg == "-d" || arg == "--devices-from" {
+            i += 1;
+            options.devices_from = args[i].clone().parse::<usize>().unwrap();
+            output_device = None;
+        } else if arg == "--output-device" {
+            i += 1;
+            output_device = Some(args[i].clone());
+        } else if arg == "--intermediate-devices" {
+            i += 1;
+            intermediate_devices = args[i].split(',').map(String::from).collect();
+        } else if arg == "--capture-protocol" {
+            i += 1;
+            requested_protocol = match CaptureProtocol::parse(args[i].as_str()) {
+                Some(protocol) => Some(protocol),
+                None => {
+                    println!("{}", i18n::tr("Unknown capture protocol: {}", &[&args[i]]));
+                    help();
+                }
+            };
+        } else if arg == "--backend" {
+            i += 1;
+            requested_backend = match Backend::parse(args[i].as_str()) {
+                Some(backend) => Some(backend),
+                None => {
+                    println!("{}", i18n::tr("Unknown backend: {}", &[&args[i]]));
+                    help();
+                }
+            };
+        } else if arg == "--mode" {
+            i += 1;
+            options.mode = match Mode::parse(args[i].as_str()) {
+                Some(mode) => mode,
+                None => {
+                    println!("{}", i18n::tr("Unknown mode: {}", &[&args[i]]));
+                    help();
+                }
+            };
+            if options.mode == Mode::SideBySide {
+                i += 1;
+                match args.get(i) {
+                    Some(outputs) => {
+                        options.side_by_side = outputs.split(',').map(String::from).collect()
+                    }
+                    None => {
+                        println!(
+                            "{}",
+                            i18n::tr(
+                                "side-by-side needs the screens to show, for example --mode side-by-side DP-1,DP-2",
+                                &[]
+                            )
+                        );
+                        help();
+                    }
+                }
+            }
+        } else if arg == "--enlarge-focused" {
+            options.enlarge_focused = true;
+        } else if arg == "--highlight" {
+            i += 1;
+            options.highlight = Some(args[i].clone());
+        } else if arg == "--highlight-width" {
+            i += 1;
+            options.highlight_width = args[i].clone().parse::<usize>().unwrap();
+        } else if arg == "--blank-when-idle" {
+            i += 1;
+            options.blank_when_idle = Some(args[i].clone().parse::<u64>().unwrap());
+        } else if arg == "--schedule" {
+            i += 1;
+            options
+                .schedule
+                .windows
+                .push(parse_schedule(args[i].as_str()));
+        } else if arg == "--duration" {
+            i += 1;
+            options.schedule.duration = Some(parse_duration(args[i].as_str()));
+        } else if arg == "--indicator" {
+            options.show_indicator = true;
+        } else if arg == "--stats" {
+            i += 1;
+            options.stats_interval = Some(args[i].clone().parse::<u64>().unwrap());
+        } else if arg == "--stall-timeout" {
+            i += 1;
+            options.stall_timeout = args[i].clone().parse::<u64>().unwrap();
+        } else if arg == "--events-json" {
+            i += 1;
+            runtime.event_log = event_log::EventLog::open(args[i].as_str());
+        } else if arg == "--timeline" {
+            i += 1;
+            runtime.timeline = timeline::Timeline::start(args[i].as_str());
+        } else if arg == "--dnd" {
+            options.dnd = true;
+        } else if arg == "--lazy" {
+            options.lazy = true;
+        } else if arg == "--standby" {
+            options.standby = true;
+        } else if arg == "--compat" {
+            options.compat = true;
+        } else if arg == "--quality" {
+            i += 1;
+            options.quality = quality::Quality::parse(args[i].as_str()).unwrap_or_else(|| {
+                println!("{}", i18n::tr("Unknown quality: {}", &[&args[i]]));
+                std::process::exit(1);
+            });
+        } else if arg == "--zoom" {
+            i += 1;
+            options.zoom = Some(args[i].clone().parse::<f64>().unwrap());
+        } else if arg == "--zoom-duration" {
+            i += 1;
+            options.zoom_duration = args[i].clone().parse::<u64>().unwrap();
+        } else if arg == "--damage" {
+            options.damage = true;
+        } else if arg == "--normalize-scale" {
+            options.normalize_scale = true;
+        } else if arg == "--measure-latency" {
+            options.measure_latency = true;
+        } else if arg == "--sink" {
+            i += 1;
+            options.sinks.push(args[i].clone());
+        } else if arg == "--codec" {
+            i += 1;
+            if options.encoding.parse_codec(args[i].as_str()).is_none() {
+                println!("{}", i18n::tr("Unknown codec: {}", &[&args[i]]));
+                std::process::exit(1);
+            }
+        } else if arg == "--bitrate" {
+            i += 1;
+            options.encoding.bitrate = Some(args[i].clone());
+        } else if arg == "--min-bitrate" {
+            i += 1;
+            options.encoding.min_bitrate = Some(args[i].clone());
+        } else if arg == "--preset" {
+            i += 1;
+            options.encoding.preset = Some(args[i].clone());
+        } else if arg == "--crf" {
+            i += 1;
+            options.encoding.crf = Some(args[i].clone().parse::<u32>().unwrap());
+        } else if arg == "--sink-passphrase" {
+            i += 1;
+            options.transport.passphrase = Some(args[i].clone());
+        } else if arg == "--sink-latency" {
+            i += 1;
+            options.transport.latency = Some(args[i].clone().parse::<u32>().unwrap());
+        } else if arg == "--whip-token" {
+            i += 1;
+            options.transport.token = Some(args[i].clone());
+        } else if arg == "--secrets-file" {
+            i += 1;
+            options.secrets_file = Some(args[i].clone());
+        } else if arg == "--audio-desktop" {
+            options.audio.desktop = true;
+        } else if arg == "--audio-mic" {
+            options.audio.mic = true;
+        } else if arg == "--desktop-device" {
+            i += 1;
+            options.audio.desktop_device = Some(args[i].clone());
+        } else if arg == "--mic-device" {
+            i += 1;
+            options.audio.mic_device = Some(args[i].clone());
+        } else if arg == "--desktop-volume" {
+            i += 1;
+            options.audio.desktop_volume = args[i].clone().parse::<f64>().unwrap();
+        } else if arg == "--mic-volume" {
+            i += 1;
+            options.audio.mic_volume = args[i].clone().parse::<f64>().unwrap();
+        } else if arg == "--mic-denoise" {
+            i += 1;
+            options.audio.mic_denoise = Some(audio::Denoise::parse(args[i].as_str()));
+        } else if arg == "--mic-limiter" {
+            options.audio.mic_limiter = true;
+        } else if arg == "--av-offset" {
+            i += 1;
+            options.audio.offset = match audio::Offset::parse(args[i].as_str()) {
+                Some(offset) => offset,
+                None => {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "--av-offset has to be auto or a number of milliseconds",
+                            &[]
+                        )
+                    );
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--hidden-text" {
+            options.hidden_text = true;
+        } else if arg == "--blank-on-hidden" {
+            options.blank_on_hidden = true;
+        } else if arg == "--placeholder-fps" {
+            i += 1;
+            options.placeholder_fps = Some(parse_frame_rate(arg, &args[i]));
+        } else if arg == "--redact" {
+            i += 1;
+            options.redact.push(parse_redact(args[i].as_str()));
+        } else if arg == "--mask-app" {
+            i += 1;
+            options.mask_apps.push(args[i].clone());
+        } else if arg == "--urgent-app" {
+            i += 1;
+            options.urgent_apps.push(args[i].clone());
+        } else if arg == "--urgent-seconds" {
+            i += 1;
+            options.urgent_seconds = args[i].clone().parse::<u64>().unwrap();
+        } else if arg == "--countdown" {
+            i += 1;
+            options.countdown = args[i].clone().parse::<u64>().unwrap();
+        } else if arg == "--call-app" {
+            i += 1;
+            options.call_apps.push(args[i].clone());
+        } else if arg == "--replay-buffer" {
+            i += 1;
+            options.replay_buffer = Some(args[i].clone().parse::<u32>().unwrap());
+        } else if arg == "--record-segments" {
+            i += 1;
+            options.record_segments = Some(args[i].clone());
+        } else if arg == "--serve-hls" {
+            i += 1;
+            options.serve_hls = Some(args[i].clone());
+        } else if arg == "--preview-listen" {
+            i += 1;
+            options.preview_listen = Some(args[i].clone());
+        } else if arg == "--fps" {
+            i += 1;
+            options.fps = Some(parse_frame_rate(arg, &args[i]));
+        } else if arg == "--color-range" {
+            i += 1;
+            options.color.range = match color::Range::parse(args[i].as_str()) {
+                Some(range) => Some(range),
+                None => {
+                    println!("{}", i18n::tr("Unknown color range: {}", &[&args[i]]));
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--colorspace" {
+            i += 1;
+            options.color.space = match color::Space::parse(args[i].as_str()) {
+                Some(space) => Some(space),
+                None => {
+                    println!("{}", i18n::tr("Unknown colorspace: {}", &[&args[i]]));
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--tonemap" {
+            i += 1;
+            options.tonemap.algorithm = match color::Tonemap::parse_algorithm(args[i].as_str()) {
+                Some(algorithm) => algorithm,
+                None => {
+                    println!("{}", i18n::tr("Unknown tonemap algorithm: {}", &[&args[i]]));
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--tonemap-peak" {
+            i += 1;
+            options.tonemap.peak = args[i].clone().parse::<u32>().unwrap();
+        } else if arg == "--battery-fps" {
+            i += 1;
+            options.battery_fps = Some(parse_frame_rate(arg, &args[i]));
+        } else if arg == "--battery-threshold" {
+            i += 1;
+            options.battery_threshold = parse_battery_threshold(&args[i]);
+        } else if arg == "--gpu-scale" {
+            options.gpu_scale = true;
+        } else if arg == "--drm-device" {
+            i += 1;
+            options.drm_device = Some(args[i].clone());
+        } else if arg == "--wf-recorder-arg" {
+            i += 1;
+            options.wf_recorder_args.push(args[i].clone());
+        } else if arg == "--ffmpeg-arg" {
+            i += 1;
+            options.ffmpeg_args.push(args[i].clone());
+        } else if arg == "--wf-recorder-path" {
+            i += 1;
+            options.wf_recorder_path = args[i].clone();
+        } else if arg == "--ffmpeg-path" {
+            i += 1;
+            options.ffmpeg_path = args[i].clone();
+        } else if arg == "--ipc-socket" {
+            i += 1;
+            options.ipc_socket = Some(args[i].clone());
+        } else if arg == "--profile" {
+            i += 1;
+            profile = Some(args[i].clone());
+        } else if arg == "--config" {
+            // Already read before parsing the other options
+            i += 1;
+        } else if arg == "--instance" {
+            // Already taken care of before anything else
+            i += 1;
+        } else if arg == "--session" {
+            i += 1;
+            session = Some(args[i].clone());
+        } else if arg == "--daemonize" {
+            daemonize = true;
+        } else if arg == "--selftest" {
+            selftest = true;
+        } else if arg == "--verbose" {
+            options.verbose = true;
+        } else if arg == "-v" || arg == "--version" {
+            println!("v{}", VERSION);
+            std::process::exit(0);
+        } else if arg == "-h" || arg == "--help" {
+            help();
+        } else {
+            println!("{}", i18n::tr("Unknown option: {}", &[&arg]));
+            help();
+        }
+        i += 1;
+    }
+
+    if let Some(path) = output_device {
+        options.devices_from = match loopback::device_number(path.as_str()) {
+            Ok(device_number) => device_number,
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        };
+    }
+    for path in intermediate_devices {
+        match loopback::device_number(path.as_str()) {
+            Ok(device_number) => options.intermediate_devices.push(device_number),
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(name) = profile {
+        if let Err(err) = apply_profile(&options, &mut runtime, name.as_str()) {
+            println!(
+                "{}",
+                i18n::tr("Could not start with profile {}: {}", &[&name, &err])
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if options.mode == Mode::SideBySide && options.side_by_side.len() < 2 {
+        println!(
+            "{}",
+            i18n::tr(
+                "side-by-side needs at least two screens, for example --mode side-by-side DP-1,DP-2",
+                &[]
+            )
+        );
+        std::process::exit(1);
+    }
+
+    if let Err(err) = session::apply(session.as_deref()) {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+
+    if selftest && options.mode != Mode::Follow {
+        println!(
+            "{}",
+            i18n::tr("The self-test only works in follow mode", &[])
+        );
+        std::process::exit(1);
+    }
+    if selftest {
+        // Screens kept ready would need follow mode's event loop, a single pipeline does the same
+        options.standby = false;
+    }
+
+    if daemonize && !bench && !bindings && !selftest {
+        if let Err(err) = service::daemonize() {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(target) = &options.serve_hls {
+        let (dir, address) = match hls::prepare(target) {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        let playlist = dir.join(hls::PLAYLIST).to_string_lossy().to_string();
+        // Whatever the profile, the page keeps getting the stream
+        for profile in options.profiles.values_mut() {
+            if let Some(sinks) = &mut profile.sinks {
+                sinks.push(playlist.clone());
+            }
+        }
+        options.sinks.push(playlist);
+        match address {
+            Some(address) => {
+                if let Err(err) = hls::serve(dir, address) {
+                    println!("{}", err);
+                    std::process::exit(1);
+                }
+                if address.ip().is_unspecified() {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "Watch the stream at http://localhost:{}/ or this computer's address on the network",
+                            &[&address.port()]
+                        )
+                    );
+                } else {
+                    println!(
+                        "{}",
+                        i18n::tr("Watch the stream at http://{}/", &[&address])
+                    );
+                }
+            }
+            None => println!("{}", i18n::tr("Writing HLS to {}", &[&dir.display()])),
+        }
+    }
+
+    if let Some(address) = &options.preview_listen {
+        if let Err(err) = preview::serve(address, &options.ffmpeg_path, options.devices_from) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+        println!(
+            "{}",
+            i18n::tr(
+                "Preview of /dev/video{} at http://{}/",
+                &[&options.devices_from, &address]
+            )
+        );
+    }
+
+    if !options.every_sink().is_empty() {
+        if options.mode == Mode::MirrorAll {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Sinks need a single stream, they don't work in mirror-all mode",
+                    &[]
+                )
+            );
+            std::process::exit(1);
+        }
+        if let Err(err) = secrets::resolve(&mut options) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+        let sinks = options.every_sink();
+        if let Err(err) = sink::validate(&options.ffmpeg_path, &options.encoding, &sinks) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+        if let Err(err) = options.transport.validate() {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if options.audio.enabled()
+        && options.sinks.is_empty()
+        && options.replay_buffer.is_none()
+        && options.record_segments.is_none()
+    {
+        println!(
+            "{}",
+            i18n::tr(
+                "Audio is only added to sinks and the replay buffer, the virtual camera has no sound",
+                &[]
+            )
+        );
+    }
+
+    if options.replay_buffer.is_some() {
+        if options.mode == Mode::MirrorAll {
+            println!(
+                "{}",
+                i18n::tr(
+                    "The replay buffer needs a single stream, it doesn't work in mirror-all mode",
+                    &[]
+                )
+            );
+            std::process::exit(1);
+        }
+        if let Err(err) = sink::validate_encoder(&options.ffmpeg_path, &options.encoding) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if options.record_segments.is_some() {
+        if options.mode == Mode::MirrorAll {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Segments need a single stream, they don't work in mirror-all mode",
+                    &[]
+                )
+            );
+            std::process::exit(1);
+        }
+        if let Err(err) = sink::validate_encoder(&options.ffmpeg_path, &options.encoding) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(socket) = &options.ipc_socket {
+        if let Err(err) = tools::validate_socket(socket) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(device) = &options.drm_device {
+        if let Err(err) = capture::validate_drm_device(device) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    inprocess::init()?;
+
+    options.backend = requested_backend.unwrap_or_else(backend::detect);
+    if options.verbose {
+        println!(
+            "{}",
+            i18n::tr("Following focus in {}", &[&options.backend.name()])
+        );
+        println!(
+            "{}",
+            i18n::tr("Streaming in {} mode", &[&options.mode.name()])
+        );
+    }
+
+    options.capture_protocol = if options.backend == Backend::I3 {
+        match requested_protocol {
+            None | Some(CaptureProtocol::X11Grab) => CaptureProtocol::X11Grab,
+            Some(protocol) => {
+                println!(
+                    "{}",
+                    i18n::tr("{} capture is not available on X11", &[&protocol.name()])
+                );
+                std::process::exit(1);
+            }
+        }
+    } else {
+        let available_protocols = capture::detect_protocols(options.verbose);
+        match capture::select_protocol(requested_protocol, &available_protocols) {
+            Some(protocol) => protocol,
+            None => {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "The compositor does not offer a supported screen capture protocol (wlr-screencopy or ext-image-copy-capture)",
+                        &[]
+                    )
+                );
+                std::process::exit(1);
+            }
+        }
+    };
+
+    if options.verbose {
+        println!(
+            "{}",
+            i18n::tr("Capturing with {}", &[&options.capture_protocol.name()])
+        );
+    }
+
+    let programs = match options.capture_protocol {
+        CaptureProtocol::WlrScreencopy if cfg!(feature = "ffmpeg") => {
+            vec![&options.wf_recorder_path]
+        }
+        CaptureProtocol::WlrScreencopy => vec![&options.wf_recorder_path, &options.ffmpeg_path],
+        CaptureProtocol::ExtImageCopyCapture => vec![&options.ffmpeg_path],
+        CaptureProtocol::X11Grab => vec![&options.ffmpeg_path],
+    };
+    for program in programs {
+        if let Err(err) = tools::validate_executable(program) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if options.mode == Mode::Headless {
+        if options.backend != Backend::Sway {
+            println!(
+                "{}",
+                i18n::tr(
+                    "headless mode needs sway, which is the only compositor that can create outputs on request",
+                    &[]
+                )
+            );
+            std::process::exit(1);
+        }
+        if let Err(err) = tools::validate_executable("wl-mirror") {
+            println!(
+                "{}",
+                i18n::tr("{}, headless mode needs it to mirror screens", &[&err])
+            );
+            std::process::exit(1);
+        }
+    }
+    if options.compat {
+        compat::apply(&mut options);
+    }
+    quality::apply(&mut options);
+    if let Some(factor) = options.zoom {
+        if options.mode != Mode::Follow {
+            println!("{}", i18n::tr("--zoom only works in follow mode", &[]));
+            options.zoom = None;
+        } else if factor <= 1.0 {
+            println!("{}", i18n::tr("--zoom needs a factor larger than 1", &[]));
+            std::process::exit(1);
+        } else if options.gpu_scale {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Scaling on the GPU is not supported with --zoom, scaling with ffmpeg",
+                    &[]
+                )
+            );
+            options.gpu_scale = false;
+        }
+        let duration = time::Duration::from_millis(options.zoom_duration);
+        runtime.zoom = options.zoom.map(|factor| zoom::Zoom::new(factor, duration));
+    }
+    if !options.mask_apps.is_empty() {
+        if options.mode != Mode::Follow {
+            println!("{}", i18n::tr("--mask-app only works in follow mode", &[]));
+            options.mask_apps.clear();
+        } else if options.gpu_scale {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Scaling on the GPU is not supported with --mask-app, scaling with ffmpeg",
+                    &[]
+                )
+            );
+            options.gpu_scale = false;
+        }
+    }
+    if options.standby {
+        if options.mode != Mode::Follow {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Screens can only be kept ready with --standby in follow mode",
+                    &[]
+                )
+            );
+            options.standby = false;
+        } else if options.gpu_scale {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Scaling on the GPU is not supported with --standby, scaling with ffmpeg",
+                    &[]
+                )
+            );
+            options.gpu_scale = false;
+        }
+    }
+    if options.gpu_scale {
+        if options.capture_protocol != CaptureProtocol::WlrScreencopy {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Scaling on the GPU is not supported with {}, scaling with ffmpeg",
+                    &[&options.capture_protocol.name()]
+                )
+            );
+        } else {
+            options.render_node = capture::render_node(options.drm_device.as_deref());
+            match &options.render_node {
+                Some(render_node) => {
+                    if options.verbose {
+                        println!(
+                            "{}",
+                            i18n::tr("Scaling on the GPU using {}", &[&render_node])
+                        );
+                    }
+                }
+                None => println!(
+                    "{}",
+                    i18n::tr("No DRM render node found, scaling with ffmpeg", &[])
+                ),
+            }
+        }
+    }
+
+    let mut config = Config { options, runtime };
+
+    let (events_sender, events) = mpsc::channel();
+    if config.options.backend == Backend::ForeignToplevel {
+        config.runtime.toplevel = match toplevel::start(events_sender.clone()) {
+            Ok(tracker) => Some(tracker),
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    if bindings {
+        bindings::print(&config);
+    }
+
+    if bench {
+        config.runtime.resolutions = get_resolutions(&mut config);
+        bench::run(&config);
+    }
+
+    let mut subscriber = None;
+    if config.options.backend != Backend::ForeignToplevel {
+        let mut child = ipc(&config.options, IpcRequest::Subscribe)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => panic!(
+                "Could not open {} stdout",
+                config.options.backend.ipc_program()
+            ),
+        };
+
+        let sender = events_sender.clone();
+        let backend = config.options.backend;
+        let watch_windows = !config.options.call_apps.is_empty()
+            || !config.options.urgent_apps.is_empty()
+            || !config.options.redact.is_empty()
+            || !config.options.mask_apps.is_empty()
+            || config.options.zoom.is_some();
+        let layout = config.runtime.layout.clone();
+        thread::spawn(move || {
+            let reader = BufReader::new(stdout);
+            for line in reader.lines().map_while(Result::ok) {
+                let event = subscribe::parse(line.as_str());
+                layout.apply(backend, &event);
+                let relevant = match subscribe::change(backend, &event) {
+                    subscribe::Change::Focus => true,
+                    subscribe::Change::Windows => watch_windows,
+                    subscribe::Change::Irrelevant => false,
+                };
+                if relevant && sender.send(Event::Changed).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(Event::Disconnected);
+        });
+        subscriber = Some(child);
+    }
+    if config.options.measure_latency {
+        if config.options.mode != Mode::Follow {
+            println!(
+                "{}",
+                i18n::tr("Latency can only be measured in follow mode", &[])
+            );
+        } else {
+            match latency::start() {
+                Ok(meter) => {
+                    meter.log_every(time::Duration::from_secs(5));
+                    config.runtime.latency = Some(meter);
+                }
+                Err(err) => println!("{}", i18n::tr("{}, not measuring latency", &[&err])),
+            }
+        }
+    }
+    if config.options.show_indicator {
+        match indicator::start() {
+            Ok(indicator) => config.runtime.indicator = Some(indicator),
+            Err(err) => println!("{}", i18n::tr("{}, not showing the indicator", &[&err])),
+        }
+    }
+    if let Some(minutes) = config.options.blank_when_idle {
+        let timeout = time::Duration::from_secs(minutes * 60);
+        if let Err(err) = idle::start(timeout, events_sender.clone()) {
+            println!(
+                "{}",
+                i18n::tr("{}, not blanking the stream when idle", &[&err])
+            );
+        }
+    }
+    if !config.options.mask_apps.is_empty() {
+        mask::watch(events_sender.clone());
+    }
+    // Ticks also end the countdown, and the time on the screen of an urgent window
+    let ticks = events_sender.clone();
+    thread::spawn(move || {
+        while ticks.send(Event::Tick).is_ok() {
+            thread::sleep(time::Duration::from_secs(1));
+        }
+    });
+    if let Err(err) = signals::listen(events_sender.clone()) {
+        println!("{}", err);
+    }
+    if let Err(err) = control::listen(events_sender.clone()) {
+        println!("{}", i18n::tr("{}, wlstreamer ctl won't work", &[&err]));
+    }
+    match dbus::serve(events_sender.clone()) {
+        Ok(Some(bus)) => config.runtime.event_log.signal_on(bus),
+        Ok(None) => {}
+        Err(err) => println!(
+            "{}",
+            i18n::tr("{}, the D-Bus interface won't be available", &[&err])
+        ),
+    }
+
+    config.runtime.resolutions = get_resolutions(&mut config);
+    if config.options.compat {
+        compat::canvas(&mut config.runtime.resolutions);
+    }
+    quality::canvas(config.options.quality, &mut config.runtime.resolutions);
+    config
+        .runtime
         .outputs
-        .insert(config.resolutions[0], config.devices_from);
-    config.last_device_index = config.devices_from;
-    let valid_screens = get_valid_screens_for_recording(&config);
-    let mut recorders: Vec<Box<Child>> = if valid_screens.len() == 0 {
-        stream_black(&mut config)?
+        .insert(config.runtime.resolutions[0], config.options.devices_from);
+    if config.options.mode == Mode::Follow {
+        // With --gpu-scale, everything is scaled on the GPU straight into the output device, and
+        // with --standby every screen has a device of its own
+        if config.options.render_node.is_none() && !config.options.standby {
+            assign_devices(&mut config);
+        }
+        let through = config.runtime.zoom.is_some() || !config.options.mask_apps.is_empty();
+        if through && !config.options.standby {
+            config.runtime.through_device = unused_device(&config);
+            if config.runtime.through_device.is_none() {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "No device left to zoom or mask from, add one to --intermediate-devices",
+                        &[]
+                    )
+                );
+                std::process::exit(1);
+            }
+        }
+        status::print_devices(&config);
+    }
+
+    if let Err(err) = loopback::validate_all(&config) {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+    if config.options.compat {
+        compat::check(&config);
+    }
+    config.runtime.device_locks = match loopback::lock_all(&config) {
+        Ok(locks) => locks,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if config.options.damage {
+        if config
+            .options
+            .wf_recorder_args
+            .iter()
+            .any(|arg| arg == "--no-damage" || arg == "-D")
+        {
+            println!(
+                "{}",
+                i18n::tr(
+                    "wf-recorder is told to ignore damage, --damage only keeps the frame rate up",
+                    &[]
+                )
+            );
+        }
+        for (device_number, _) in loopback::devices_in_use(&config) {
+            if let Err(err) = loopback::sustain_frame_rate(device_number) {
+                println!(
+                    "{}",
+                    i18n::tr("{}, readers may time out on a static screen", &[&err])
+                );
+            }
+        }
+    }
+
+    if config.options.stall_timeout > 0 && !frames_guaranteed(&config.options) {
+        println!(
+            "{}",
+            i18n::tr(
+                "No frames are sent while the screen doesn't change, so --stall-timeout only rebuilds pipelines that exit. Add --wf-recorder-arg --no-damage or --damage to count frames.",
+                &[]
+            )
+        );
+    }
+
+    if selftest {
+        selftest::run(&mut config);
+    }
+
+    if let Some(seconds) = config.options.stats_interval {
+        stats::log_every(
+            config.runtime.stats.clone(),
+            time::Duration::from_secs(seconds),
+        );
+    }
+
+    if config.options.lazy {
+        let devices = if config.options.mode == Mode::MirrorAll {
+            loopback::devices_in_use(&config)
+                .into_iter()
+                .map(|(device_number, _)| device_number)
+                .collect()
+        } else {
+            vec![config.options.devices_from]
+        };
+        config.runtime.unwatched = !consumers::watch(&devices, events_sender.clone());
+        if config.runtime.unwatched {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Nobody is watching the output device, streaming a black screen until someone does",
+                    &[]
+                )
+            );
+        }
+    }
+    if config.options.schedule.is_set() {
+        config.runtime.off_schedule =
+            !schedule::watch(config.options.schedule.clone(), events_sender.clone());
+        if config.runtime.off_schedule {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Outside of the schedule, streaming a black screen until it starts",
+                    &[]
+                )
+            );
+        }
+    }
+    if config.options.battery_fps.is_some() {
+        config.runtime.on_battery =
+            power::watch(config.options.battery_threshold, events_sender.clone());
+        if config.runtime.on_battery {
+            println!(
+                "{}",
+                i18n::tr("Running on battery, capturing at a lower frame rate", &[])
+            );
+        }
+    }
+    update_windows(&mut config);
+    drop(events_sender);
+
+    let dnd = if config.options.dnd {
+        dnd::enable()
     } else {
-        let output = get_output(&mut config, valid_screens[0].output.as_str());
-        record_screen(&mut config, output)?
+        None
+    };
+
+    let render_node = capture::render_node(config.options.drm_device.as_deref());
+    config.runtime.chapters = chapters::Chapters::start(config.sinks());
+    if config.options.audio.enabled() && config.options.audio.offset == audio::Offset::Auto {
+        let delay = video_delay(&config);
+        println!(
+            "{}",
+            i18n::tr("Delaying the audio by {}ms to match the screens", &[&delay])
+        );
+        config.runtime.audio_delay = delay;
+    }
+    config.runtime.sinks = Some(BTreeMap::new());
+    update_sinks(&config.options, &mut config.runtime);
+    let mut replay = sink::Sinks::default();
+    if let Some(seconds) = config.options.replay_buffer {
+        match replay::command(
+            &config.options,
+            render_node.as_deref(),
+            seconds,
+            config.runtime.audio_delay,
+        ) {
+            Ok(command) => replay.supervise(
+                "the replay buffer".to_string(),
+                command,
+                None,
+                None,
+                false,
+                #[cfg(feature = "ndi")]
+                None,
+            ),
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(dir) = &config.options.record_segments {
+        match segments::Segments::start(
+            dir,
+            &config.options,
+            render_node.as_deref(),
+            config.runtime.audio_delay,
+        ) {
+            Ok(segments) => config.runtime.segments = segments,
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    start_countdown(&mut config);
+    let result = match config.options.mode {
+        Mode::Follow => follow(&mut config, events),
+        Mode::MirrorAll => mirror::run(&mut config, events),
+        Mode::Grid | Mode::SideBySide => grid::run(&mut config, events),
+        Mode::Headless => headless::run(&mut config, events),
     };
 
-    let stdout = match Command::new("sh")
-        .args(&["-c", "swaymsg -t subscribe -m \"['window']\""])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .stdin(Stdio::piped())
-        .spawn()?
-        .stdout
+    for sinks in config
+        .runtime
+        .sinks
+        .iter_mut()
+        .flat_map(BTreeMap::values_mut)
     {
-        Some(stdout) => stdout,
-        None => panic!("Could not open swaymsg stdout"),
+        sinks.stop();
+    }
+    replay.stop();
+    config.runtime.segments.stop();
+    if let Some(mut subscriber) = subscriber {
+        if let Err(err) = children::stop(&mut subscriber, libc::SIGTERM, children::KILL_TIMEOUT) {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Could not stop {}: {}",
+                    &[&config.options.backend.ipc_program(), &format!("{:?}", err)]
+                )
+            );
+        }
+    }
+    config.runtime.chapters.write();
+    config.runtime.timeline.write();
+    replay::clean();
+    if let Some(dnd) = dnd {
+        dnd.restore();
+    }
+    update_indicator(&config, &[]);
+    if let Some(meter) = config.runtime.latency.as_mut() {
+        meter.stop();
+    }
+
+    Ok(result?)
+}
+
+/// What `--mode follow` should stream now, followed by what to fall back to if a screen is gone
+/// by the time it would be recorded. The black screen always comes last.
+fn wanted_targets(config: &Config) -> Vec<Target> {
+    let valid_screens = if config.blanked() {
+        Vec::new()
+    } else {
+        get_valid_screens_for_recording(config)
     };
+    let outputs = valid_screens
+        .into_iter()
+        .map(|workspace| workspace.output)
+        .unique()
+        .collect();
+    let mut outputs = merge_mirrored(config, outputs);
+    let counting_down = config.runtime.countdown.is_some() && !config.blanked();
+    if counting_down && config.runtime.health.healthy(&Target::Countdown) {
+        return vec![Target::Countdown, Target::Black(placeholder_text(config))];
+    }
+    if let Some((urgent, _)) = &config.runtime.urgent {
+        if let Some(i) = outputs.iter().position(|output| output == urgent) {
+            let urgent = outputs.remove(i);
+            outputs.insert(0, urgent);
+        }
+    }
+    outputs
+        .into_iter()
+        .map(Target::Output)
+        .filter(|target| config.runtime.health.healthy(target))
+        .chain(std::iter::once(Target::Black(placeholder_text(config))))
+        .collect()
+}
 
-    let reader = BufReader::new(stdout);
-    reader.lines().filter_map(|line| line.ok()).for_each(|_| {
-        println!("Focus switched event");
-        let valid_screens = get_valid_screens_for_recording(&config);
-        if valid_screens.len() > 0 && valid_screens[0].output == config.current_output {
-            println!("Screen is the same, no need to switch");
-            return;
-        }
-        for recorder in recorders.iter_mut() {
-            if config.verbose {
-                println!("Killing child");
-            }
-            match recorder.kill() {
-                Ok(_) => {}
-                Err(err) => panic!("{:?}", err),
-            };
+/// Forgets the window that asked for attention once its screen was streamed for long enough,
+/// returning whether it did.
+fn urgency_over(config: &mut Config) -> bool {
+    match &config.runtime.urgent {
+        Some((_, until)) if time::Instant::now() >= *until => {
+            config.runtime.urgent = None;
+            true
         }
+        _ => false,
+    }
+}
 
-        recorders = if valid_screens.len() == 0 {
-            stream_black(&mut config).unwrap()
-        } else {
-            let output = get_output(&mut config, valid_screens[0].output.as_str());
-            record_screen(&mut config, output).unwrap()
+/// Keeps one of the outputs that take up the same place in the layout, which mirror each other, so
+/// the stream doesn't switch between them when the focus does. The one being streamed stays.
+fn merge_mirrored(config: &Config, outputs: Vec<String>) -> Vec<String> {
+    let rects: HashMap<String, SwayScreenRect> = get_outputs(config)
+        .into_iter()
+        .map(|output| (output.name, output.rect))
+        .collect();
+    let current = config.runtime.streamer.output();
+
+    let mut merged: Vec<String> = Vec::new();
+    for name in outputs.iter() {
+        let rect = rects.get(name);
+        let mut twins = outputs
+            .iter()
+            .filter(|twin| *twin == name || (rect.is_some() && rects.get(*twin) == rect));
+        let first = twins.clone().next().unwrap_or(name);
+        let pick = twins.find(|twin| *twin == current).unwrap_or(first);
+        if !merged.contains(pick) {
+            merged.push(pick.clone());
+        }
+        if pick != name && config.options.verbose {
+            println!(
+                "{}",
+                i18n::tr(
+                    "{} mirrors {}, streaming {} for both",
+                    &[&name, &pick, &pick]
+                )
+            );
+        }
+    }
+
+    merged
+}
+
+/// Replaces the recorders by a pipeline streaming the first of the targets that can be streamed.
+/// Targets whose pipeline fails to start are left alone for a while, and the next one is tried.
+fn switch(
+    config: &mut Config,
+    recorders: &mut Pipeline,
+    targets: Vec<Target>,
+) -> Result<(), Error> {
+    let mut stopped = false;
+    let mut failure = None;
+
+    for target in targets {
+        let output = match &target {
+            Target::Output(name) => match get_output(config, name) {
+                Ok(output) => Some(output),
+                Err(err) => {
+                    println!("{}", i18n::tr("{}, falling back", &[&err]));
+                    continue;
+                }
+            },
+            Target::Black(_) | Target::Countdown => None,
         };
 
-        println!("Recording {}", config.current_output);
-    });
+        config.runtime.streamer.begin(target.clone());
+        if !stopped {
+            if config.options.verbose {
+                println!("{}", i18n::tr("Stopping the pipeline", &[]));
+            }
+            // The new pipeline can start even if the old one did not stop cleanly
+            if let Err(err) = recorders.stop() {
+                println!(
+                    "{}",
+                    i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+                );
+                config
+                    .runtime
+                    .event_log
+                    .emit("error", json!({ "message": format!("{:?}", err) }));
+            }
+            stopped = true;
+        }
+
+        config.runtime.stats.restart();
+        let started = match (&target, output) {
+            (Target::Output(_), Some(output)) => record_screen(config, output),
+            (Target::Black(text), _) => stream_black(config, text.as_deref()),
+            (Target::Countdown, _) => stream_countdown(config),
+            (Target::Output(_), None) => unreachable!("Outputs are looked up before switching"),
+        };
+        match started {
+            Ok(pipeline) => {
+                *recorders = pipeline;
+                config.runtime.streamer.finish();
+                return Ok(());
+            }
+            Err(err) => {
+                config.runtime.streamer.fail(err.to_string());
+                let backoff = config.runtime.health.failed(&target);
+                if let Target::Output(name) = &target {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "Could not record {}: {}, leaving it alone for {}s",
+                            &[&name, &err, &backoff.as_secs()]
+                        )
+                    );
+                    config.runtime.event_log.emit(
+                        "error",
+                        json!({ "message": format!("{:?}", err), "output": name }),
+                    );
+                }
+                failure = Some(err);
+            }
+        }
+    }
+
+    Err(failure.expect("The black screen is always tried"))
+}
+
+/// `--mode follow`: streams whichever screen has focus.
+fn follow(config: &mut Config, events: Receiver<Event>) -> Result<(), Error> {
+    let mut recorders = Pipeline::default();
+    let mut standby = config
+        .options
+        .standby
+        .then(|| Mirrors::standby(config.options.devices_from + 1));
+    sync_standby(config, &mut standby);
+    let targets = wanted_targets(config);
+    switch(config, &mut recorders, targets)?;
+    let mut frame_rate = config.frame_rate();
+    update_indicator(config, &[config.runtime.streamer.output().to_string()]);
+    update_latency(config);
+    emit_switch(config);
+    systemd::ready(recording_status(config).as_str());
+
+    while let Some(event) = systemd::next_event(&events) {
+        let tick = matches!(event, Event::Tick);
+        let look = matches!(event, Event::Windows);
+        if !handle_event(config, event) {
+            break;
+        }
+        let failed = matches!(config.runtime.streamer, StreamerState::Failed { .. });
+        // Once something else was streamed for a while, the screen can come back with the boxes
+        // where the windows are now
+        let streamed = config.runtime.streamer.output().to_string();
+        let remasked = look
+            && config
+                .runtime
+                .unmasked
+                .take_if(|output| *output != streamed)
+                .is_some();
+        // The boxes are part of the pipeline, so until there is one covering where the windows
+        // are now, the screen is hidden
+        let unmasked =
+            !tick && !streamed.is_empty() && masks_of(config, &streamed) != config.runtime.masked;
+        if unmasked {
+            config.runtime.unmasked = Some(streamed);
+        }
+        // What failed or stalled is only tried again once it isn't left alone anymore
+        let current = config.runtime.streamer.target();
+        let healthy = current
+            .as_ref()
+            .is_some_and(|target| config.runtime.health.healthy(target));
+        let retry = tick && failed && healthy;
+        let stalled = tick && !failed && healthy && stalled(config, &mut recorders);
+        let recovered = tick && config.runtime.health.recovered();
+        let returned = tick && urgency_over(config);
+        let live = tick && countdown_over(config);
+        let resumed = tick && blank_over(config);
+        if let Some(standby) = standby.as_mut().filter(|_| tick) {
+            standby.restart_exited(config);
+        }
+        let rebuild = retry || stalled;
+        if tick && !rebuild && !recovered && !returned && !live && !resumed {
+            continue;
+        }
+        if look
+            && !unmasked
+            && !remasked
+            && !config
+                .runtime
+                .streamer
+                .needs_switch(&wanted_targets(config)[0])
+        {
+            continue;
+        }
+
+        if retry {
+            println!("{}", i18n::tr("Retrying to start the pipeline", &[]));
+        } else if stalled {
+            let backoff = config
+                .runtime
+                .health
+                .failed(current.as_ref().expect("Only a pipeline can stall"));
+            println!(
+                "{}",
+                i18n::tr(
+                    "Pipeline stalled, rebuilding it and leaving what it streamed alone for {}s",
+                    &[&backoff.as_secs()]
+                )
+            );
+            config
+                .runtime
+                .event_log
+                .emit("restart", json!({ "reason": "stalled" }));
+        } else if recovered {
+            println!(
+                "{}",
+                i18n::tr("Trying the screens that were left alone again", &[])
+            );
+        } else if returned {
+            println!("{}", i18n::tr("Following the focus again", &[]));
+        } else if live {
+            println!("{}", i18n::tr("Going live", &[]));
+        } else if unmasked {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Windows to mask moved, hiding the screen until they are covered again",
+                    &[]
+                )
+            );
+        } else if remasked {
+            println!(
+                "{}",
+                i18n::tr("Covering the windows to mask at their new places", &[])
+            );
+        } else if !resumed {
+            println!("{}", i18n::tr("Focus switched event", &[]));
+        }
+        release_devices(config);
+        sync_standby(config, &mut standby);
+        let targets = wanted_targets(config);
+        if !rebuild
+            && frame_rate == config.frame_rate()
+            && !config.runtime.streamer.needs_switch(&targets[0])
+        {
+            println!("{}", i18n::tr("Screen is the same, no need to switch", &[]));
+            continue;
+        }
+
+        frame_rate = config.frame_rate();
+        if let Err(err) = switch(config, &mut recorders, targets) {
+            println!("{}", i18n::tr("Could not start the pipeline: {}", &[&err]));
+            config
+                .runtime
+                .event_log
+                .emit("error", json!({ "message": format!("{:?}", err) }));
+            systemd::status(recording_status(config).as_str());
+            continue;
+        }
+
+        println!(
+            "{}",
+            i18n::tr("Recording {}", &[&config.runtime.streamer.output()])
+        );
+        update_indicator(config, &[config.runtime.streamer.output().to_string()]);
+        update_latency(config);
+        emit_switch(config);
+        systemd::status(recording_status(config).as_str());
+    }
+
+    if let Err(err) = recorders.stop() {
+        println!(
+            "{}",
+            i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+        );
+    }
+    if let Some(standby) = standby.as_mut() {
+        standby.stop();
+    }
 
     Ok(())
 }
+
+/// Starts and stops the pipelines kept ready with --standby, to match the current outputs.
+fn sync_standby(config: &mut Config, standby: &mut Option<Mirrors>) {
+    if let Some(standby) = standby {
+        if let Err(err) = standby.sync(config) {
+            println!("{}", i18n::tr("Could not keep a screen ready: {}", &[&err]));
+            config
+                .runtime
+                .event_log
+                .emit("error", json!({ "message": format!("{:?}", err) }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outputs(json: &str) -> Vec<SwayOutput> {
+        let mut outputs: Vec<SwayOutput> = serde_json::from_str(json).unwrap();
+        outputs.retain(SwayOutput::enabled);
+        outputs
+    }
+
+    fn resolution(width: usize, height: usize) -> Resolution {
+        Resolution { width, height }
+    }
+
+    #[test]
+    fn lid_closed_output_is_left_out() {
+        // sway keeps the mode of an output disabled with `output eDP-1 disable`
+        let outputs = outputs(
+            r#"[
+                {"name":"eDP-1","active":false,"rect":{"x":0,"y":0,"width":0,"height":0},
+                 "current_mode":{"width":2880,"height":1800,"refresh":90000}},
+                {"name":"HDMI-A-1","active":true,"rect":{"x":0,"y":0,"width":1920,"height":1080},
+                 "current_mode":{"width":1920,"height":1080,"refresh":60000}}
+            ]"#,
+        );
+
+        assert_eq!(
+            outputs.iter().map(|o| o.name.as_str()).collect_vec(),
+            vec!["HDMI-A-1"]
+        );
+        assert_eq!(plan_resolutions(&outputs), vec![resolution(1920, 1080)]);
+    }
+
+    #[test]
+    fn lid_opened_output_is_planned() {
+        let outputs = outputs(
+            r#"[
+                {"name":"eDP-1","active":true,"rect":{"x":1920,"y":0,"width":1440,"height":900},
+                 "current_mode":{"width":2880,"height":1800,"refresh":90000},"scale":2.0},
+                {"name":"HDMI-A-1","active":true,"rect":{"x":0,"y":0,"width":1920,"height":1080},
+                 "current_mode":{"width":1920,"height":1080,"refresh":60000}}
+            ]"#,
+        );
+
+        assert_eq!(
+            plan_resolutions(&outputs),
+            vec![resolution(2880, 1800), resolution(1920, 1080)]
+        );
+    }
+
+    #[test]
+    fn output_without_mode_is_left_out() {
+        // Outputs disabled in the config have a null mode and an empty rect
+        let outputs = outputs(
+            r#"[
+                {"name":"DP-1","active":true,"rect":{"x":0,"y":0,"width":2560,"height":1440},
+                 "current_mode":{"width":2560,"height":1440,"refresh":144000}},
+                {"name":"DP-2","active":false,"rect":{"x":0,"y":0,"width":0,"height":0},
+                 "current_mode":null},
+                {"name":"DP-3","rect":{"x":0,"y":0,"width":0,"height":0}}
+            ]"#,
+        );
+
+        assert_eq!(
+            outputs.iter().map(|o| o.name.as_str()).collect_vec(),
+            vec!["DP-1"]
+        );
+        assert_eq!(plan_resolutions(&outputs), vec![resolution(2560, 1440)]);
+    }
+
+    #[test]
+    fn unnumbered_workspace_has_no_number() {
+        let workspaces: Vec<SwayWorkspace> = serde_json::from_str(
+            r#"[
+                {"name":"1","num":1,"output":"DP-1","visible":true},
+                {"name":"chat","num":-1,"output":"DP-1"},
+                {"name":"mail","output":"DP-1"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            workspaces.iter().map(|w| w.num).collect_vec(),
+            vec![Some(1), None, None]
+        );
+    }
+
+    #[test]
+    fn null_mode_is_no_mode() {
+        let outputs: Vec<SwayOutput> =
+            serde_json::from_str(r#"[{"name":"DP-2","active":false,"current_mode":null}]"#)
+                .unwrap();
+
+        assert!(outputs[0].current_mode.is_none());
+        assert!(!outputs[0].enabled());
+    }
+
+    #[test]
+    fn mistyped_field_is_left_out() {
+        // Only the field is dropped, not the output
+        let outputs = outputs(
+            r#"[
+                {"name":"DP-1","rect":{"x":0,"y":0,"width":1920,"height":1080},
+                 "current_mode":{"width":1920,"height":1080,"refresh":60000},
+                 "scale":"2","power":"on","transform":90}
+            ]"#,
+        );
+
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].scale, None);
+        assert_eq!(outputs[0].power, None);
+        assert_eq!(outputs[0].transform, None);
+        assert_eq!(plan_resolutions(&outputs), vec![resolution(1920, 1080)]);
+    }
+
+    #[test]
+    fn combined_resolution_fits_every_output() {
+        let outputs = outputs(
+            r#"[
+                {"name":"DP-1","rect":{"x":0,"y":0,"width":1920,"height":1080},
+                 "current_mode":{"width":1920,"height":1080,"refresh":60000}},
+                {"name":"DP-2","rect":{"x":-1440,"y":0,"width":1440,"height":2560},
+                 "current_mode":{"width":2560,"height":1440,"refresh":60000},"transform":"90"}
+            ]"#,
+        );
+
+        assert_eq!(
+            plan_resolutions(&outputs),
+            vec![
+                resolution(1920, 2560),
+                resolution(1920, 1080),
+                resolution(1440, 2560)
+            ]
+        );
+    }
+
+    #[test]
+    fn profiles_keep_locked_and_created_outputs_out() {
+        let mut config = Config::default();
+        config.options.screen_blacklist = vec!["DP-3".to_string()];
+        config.options.profiles.insert(
+            "talk".to_string(),
+            config_file::Profile {
+                not_screen: Some(vec!["eDP-1".to_string()]),
+                ..config_file::Profile::default()
+            },
+        );
+        lock(&mut config, "DP-1".to_string());
+        config.runtime.excluded.push("HEADLESS-1".to_string());
+
+        apply_profile(&config.options, &mut config.runtime, "talk").unwrap();
+        assert!(config.screen_blacklisted("eDP-1"));
+        assert!(!config.screen_blacklisted("DP-3"));
+        assert!(config.screen_blacklisted("DP-1"));
+        assert!(config.screen_blacklisted("HEADLESS-1"));
+
+        apply_profile(&config.options, &mut config.runtime, "default").unwrap();
+        assert!(!config.screen_blacklisted("eDP-1"));
+        assert!(config.screen_blacklisted("DP-3"));
+        assert!(config.screen_blacklisted("DP-1"));
+
+        unlock(&mut config, "DP-1".to_string());
+        assert!(!config.screen_blacklisted("DP-1"));
+        assert!(config.screen_blacklisted("HEADLESS-1"));
+    }
+
+    #[test]
+    fn profiles_switch_sinks_and_placeholder() {
+        let mut config = Config::default();
+        // Sinks start in the background and give up when there is no ffmpeg
+        config.options.ffmpeg_path = "/nonexistent/ffmpeg".to_string();
+        config.options.sinks = vec!["rtmp://example.com/live/key".to_string()];
+        config.options.placeholder_fps = Some(30);
+        config.options.profiles.insert(
+            "gaming".to_string(),
+            config_file::Profile {
+                hidden_text: Some(true),
+                placeholder_fps: Some(5),
+                sinks: Some(vec![
+                    "rtmp://example.com/live/key".to_string(),
+                    "srt://example.com:9000".to_string(),
+                ]),
+                ..config_file::Profile::default()
+            },
+        );
+        config.runtime.sinks = Some(BTreeMap::new());
+        update_sinks(&config.options, &mut config.runtime);
+        let running = |config: &Config| -> Vec<String> {
+            config
+                .runtime
+                .sinks
+                .as_ref()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect()
+        };
+        assert_eq!(running(&config), ["rtmp://example.com/live/key"]);
+
+        apply_profile(&config.options, &mut config.runtime, "gaming").unwrap();
+        assert_eq!(
+            running(&config),
+            ["rtmp://example.com/live/key", "srt://example.com:9000"]
+        );
+        assert_eq!(config.sinks(), running(&config));
+        assert!(config.hidden_text());
+        assert_eq!(config.placeholder_fps(), Some(5));
+
+        apply_profile(&config.options, &mut config.runtime, "default").unwrap();
+        assert_eq!(running(&config), ["rtmp://example.com/live/key"]);
+        assert!(!config.hidden_text());
+        assert_eq!(config.placeholder_fps(), Some(30));
+    }
+}
@@ -0,0 +1,93 @@
+//! `--mask-app`: covers the windows of some apps with black boxes, for apps that should never be
+//! seen on stream while the rest of their screen is. Wayland has no way for a recorder to leave
+//! windows of other clients out of what it captures, so the boxes are drawn by the scaler, from
+//! where the window manager says the windows are.
+
+use crate::windows::Window;
+use crate::{Event, SwayOutput};
+use std::collections::BTreeMap;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+/// How often to look where the windows are, since sway tells nobody about floating windows being
+/// dragged or resized
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// A part of the captured frames to cover.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Mask {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Mask {
+    /// The drawbox filter painting it black, followed by a comma.
+    pub fn filter(&self) -> String {
+        format!(
+            "drawbox=x={}:y={}:w={}:h={}:color=black:t=fill,",
+            self.x, self.y, self.width, self.height
+        )
+    }
+}
+
+/// The masks of the visible windows of `apps`, in the pixels captured from each output. Windows
+/// without a position can't be masked, `unplaced` returns their outputs.
+pub fn find(
+    apps: &[String],
+    windows: &[Window],
+    outputs: &[SwayOutput],
+) -> BTreeMap<String, Vec<Mask>> {
+    let mut masks: BTreeMap<String, Vec<Mask>> = BTreeMap::new();
+    for window in windows
+        .iter()
+        .filter(|window| window.visible && apps.contains(&window.app_id))
+    {
+        let (name, rect) = match (&window.output, &window.rect) {
+            (Some(name), Some(rect)) => (name, rect),
+            _ => continue,
+        };
+        let output = match outputs.iter().find(|output| output.name == *name) {
+            Some(output) => output,
+            None => continue,
+        };
+
+        // The layout is in logical pixels, which scaled outputs capture more of
+        let resolution = output.resolution();
+        let scale_x = resolution.width as f64 / output.rect.width.max(1) as f64;
+        let scale_y = resolution.height as f64 / output.rect.height.max(1) as f64;
+        let x = ((rect.x - output.rect.x).max(0) as f64 * scale_x) as usize;
+        let y = ((rect.y - output.rect.y).max(0) as f64 * scale_y) as usize;
+        masks.entry(name.clone()).or_default().push(Mask {
+            x,
+            y,
+            width: ((rect.width as f64 * scale_x).ceil() as usize)
+                .min(resolution.width.saturating_sub(x)),
+            height: ((rect.height as f64 * scale_y).ceil() as usize)
+                .min(resolution.height.saturating_sub(y)),
+        });
+    }
+    masks
+}
+
+/// The outputs showing a window of `apps` whose position the window manager doesn't tell, which
+/// is every window on niri and with foreign-toplevel.
+pub fn unplaced(apps: &[String], windows: &[Window]) -> Vec<String> {
+    windows
+        .iter()
+        .filter(|window| window.visible && window.rect.is_none())
+        .filter(|window| apps.contains(&window.app_id))
+        .filter_map(|window| window.output.clone())
+        .collect()
+}
+
+/// Signals `Event::Windows` every `INTERVAL` in a background thread.
+pub fn watch(events: Sender<Event>) {
+    thread::spawn(move || {
+        while events.send(Event::Windows).is_ok() {
+            thread::sleep(INTERVAL);
+        }
+    });
+}
@@ -0,0 +1,248 @@
+//! `--mode mirror-all`: every output that isn't blacklisted is recorded into a device of its
+//! own, without any switching. Devices are handed out in order of output name, and an output
+//! keeps its device for as long as wlstreamer runs.
+//!
+//! With `--standby`, follow mode keeps the same recorders running as standby pipelines, and
+//! only passes on the device of the focused output to the output device.
+
+use crate::pipeline::Pipeline;
+use crate::systemd;
+use crate::{
+    blank_over, get_outputs, handle_event, i18n, update_indicator, Config, Event, Resolution,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::Error;
+use std::sync::mpsc::Receiver;
+
+struct Mirror {
+    device_number: usize,
+    resolution: Resolution,
+    frame_rate: Option<u32>,
+    pipeline: Pipeline,
+}
+
+/// Keeps one recorder running for every output that isn't blacklisted, or only for the outputs in
+/// `only` if it isn't empty.
+pub struct Mirrors {
+    first_device: usize,
+    only: Vec<String>,
+    /// Whether the recorders are standby pipelines for follow mode
+    standby: bool,
+    devices: HashMap<String, usize>,
+    mirrors: HashMap<String, Mirror>,
+}
+
+impl Mirrors {
+    pub fn new(first_device: usize, only: Vec<String>) -> Mirrors {
+        Mirrors {
+            first_device,
+            only,
+            standby: false,
+            devices: HashMap::new(),
+            mirrors: HashMap::new(),
+        }
+    }
+
+    /// Standby pipelines for every output that can be streamed, on the devices from
+    /// `first_device`.
+    pub fn standby(first_device: usize) -> Mirrors {
+        Mirrors {
+            standby: true,
+            ..Mirrors::new(first_device, Vec::new())
+        }
+    }
+
+    /// The outputs currently being recorded with their devices and resolutions, in order of
+    /// output name.
+    pub fn devices(&self) -> Vec<(String, usize, Resolution)> {
+        let mut devices: Vec<(String, usize, Resolution)> = self
+            .mirrors
+            .iter()
+            .map(|(name, mirror)| (name.clone(), mirror.device_number, mirror.resolution))
+            .collect();
+        devices.sort_by(|a, b| a.0.cmp(&b.0));
+        devices
+    }
+
+    fn stop_recording(&mut self, name: &str) {
+        if let Some(mut mirror) = self.mirrors.remove(name) {
+            println!("{}", i18n::tr("Stopped recording {}", &[&name]));
+            if let Err(err) = mirror.pipeline.stop() {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Could not stop recorder for {}: {}",
+                        &[&name, &format!("{:?}", err)]
+                    )
+                );
+            }
+        }
+    }
+
+    /// Restarts the recorders of outputs where part of the pipeline exited. The ones that can't
+    /// be restarted are stopped, for the next sync to start them again. Returns whether there
+    /// were any.
+    pub fn restart_exited(&mut self, config: &Config) -> bool {
+        let mut exited = false;
+        let mut failed = Vec::new();
+        for (name, mirror) in self.mirrors.iter_mut() {
+            if !mirror.pipeline.exited() {
+                continue;
+            }
+
+            println!(
+                "{}",
+                i18n::tr("Recorder for {} stopped, restarting it", &[&name])
+            );
+            config
+                .runtime
+                .event_log
+                .emit("restart", json!({ "reason": "exited", "output": name }));
+            if let Err(err) = mirror.pipeline.restart() {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Could not restart recorder for {}: {}",
+                        &[&name, &format!("{:?}", err)]
+                    )
+                );
+                failed.push(name.clone());
+            }
+            exited = true;
+        }
+
+        for name in failed.iter() {
+            self.stop_recording(name);
+        }
+        exited
+    }
+
+    /// Stops all recorders, when shutting down.
+    pub fn stop(&mut self) {
+        let names: Vec<String> = self.mirrors.keys().cloned().collect();
+        for name in names {
+            self.stop_recording(&name);
+        }
+    }
+
+    /// The outputs currently being recorded, in order of output name.
+    pub fn names(&self) -> Vec<String> {
+        self.devices().into_iter().map(|(name, ..)| name).collect()
+    }
+
+    pub fn status(&self) -> String {
+        i18n::tr("Recording {} screens", &[&self.mirrors.len()])
+    }
+
+    /// Starts and stops recorders so every current output has exactly one.
+    pub fn sync(&mut self, config: &mut Config) -> Result<(), Error> {
+        let mut outputs = get_outputs(config);
+        outputs.retain(|o| {
+            !config.blanked()
+                && o.powered()
+                && !config.screen_blacklisted(&o.name)
+                && !config.runtime.redacted.contains(&o.name)
+                && (self.only.is_empty() || self.only.contains(&o.name))
+        });
+        outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let stale: Vec<String> = self
+            .mirrors
+            .iter()
+            .filter(|(name, mirror)| {
+                mirror.frame_rate != config.frame_rate()
+                    || !outputs
+                        .iter()
+                        .any(|o| &o.name == *name && o.resolution() == mirror.resolution)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in stale {
+            self.stop_recording(&name);
+        }
+
+        for output in outputs {
+            if self.mirrors.contains_key(&output.name) {
+                continue;
+            }
+
+            let device_number = match self.devices.get(&output.name) {
+                Some(device_number) => *device_number,
+                None => {
+                    let device_number = (self.first_device..)
+                        .find(|n| !self.devices.values().any(|used| used == n))
+                        .unwrap();
+                    self.devices.insert(output.name.clone(), device_number);
+                    device_number
+                }
+            };
+
+            if self.standby {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Keeping {} ready on /dev/video{}",
+                        &[&output.name, &device_number]
+                    )
+                );
+            } else {
+                println!(
+                    "{}",
+                    i18n::tr(
+                        "Recording {} to /dev/video{}",
+                        &[&output.name, &device_number]
+                    )
+                );
+                config.runtime.event_log.emit(
+                    "switch",
+                    json!({ "output": output.name, "device": format!("/dev/video{}", device_number) }),
+                );
+            }
+            let mut pipeline = Pipeline::capture(output.clone())
+                .sink(device_number)
+                .build(config);
+            pipeline.start()?;
+            self.mirrors.insert(
+                output.name.clone(),
+                Mirror {
+                    device_number,
+                    resolution: output.resolution(),
+                    frame_rate: config.frame_rate(),
+                    pipeline,
+                },
+            );
+        }
+
+        if self.standby {
+            config.runtime.standby = self.devices();
+        } else {
+            config.runtime.mirrored = self.devices();
+        }
+        Ok(())
+    }
+}
+
+pub fn run(config: &mut Config, events: Receiver<Event>) -> Result<(), Error> {
+    let mut mirrors = Mirrors::new(config.options.devices_from, Vec::new());
+
+    mirrors.sync(config)?;
+    update_indicator(config, &mirrors.names());
+    systemd::ready(mirrors.status().as_str());
+    while let Some(event) = systemd::next_event(&events) {
+        let tick = matches!(event, Event::Tick);
+        if !handle_event(config, event) {
+            break;
+        }
+        if tick && !mirrors.restart_exited(config) && !blank_over(config) {
+            continue;
+        }
+        mirrors.sync(config)?;
+        update_indicator(config, &mirrors.names());
+        systemd::status(mirrors.status().as_str());
+    }
+
+    mirrors.stop();
+    Ok(())
+}
@@ -0,0 +1,37 @@
+/// What ends up on the output devices.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// A single stream that switches to whichever output has focus
+    Follow,
+    /// Every output is recorded into its own device at the same time
+    MirrorAll,
+    /// All outputs tiled into a single stream
+    Grid,
+    /// A chosen set of outputs next to each other in a single stream
+    SideBySide,
+    /// The focused output mirrored by the compositor onto a headless output, which is captured
+    Headless,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Option<Mode> {
+        match name {
+            "follow" => Some(Mode::Follow),
+            "mirror-all" => Some(Mode::MirrorAll),
+            "grid" => Some(Mode::Grid),
+            "side-by-side" => Some(Mode::SideBySide),
+            "headless" => Some(Mode::Headless),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Follow => "follow",
+            Mode::MirrorAll => "mirror-all",
+            Mode::Grid => "grid",
+            Mode::SideBySide => "side-by-side",
+            Mode::Headless => "headless",
+        }
+    }
+}
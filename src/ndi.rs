@@ -0,0 +1,219 @@
+//! NDI sinks, `ndi://<name>`: publishes the stream as an NDI source on the network. The NDI
+//! runtime is loaded when the sink starts, so it only needs to be installed where NDI is used.
+//! Enabled with the `ndi` cargo feature.
+//!
+//! ffmpeg reads the output device and writes YUV4MPEG2, whose header has the size and frame rate,
+//! and the frames are handed to the NDI runtime as they are.
+
+use crate::i18n;
+use std::ffi::{c_void, CString};
+use std::io::{BufRead, BufReader, Read};
+use std::os::raw::{c_char, c_float, c_int};
+use std::process::ChildStdout;
+use std::ptr;
+
+const LIBRARIES: [&str; 2] = ["libndi.so.6", "libndi.so.5"];
+/// Where the NDI installers put the runtime, if it's not in the library path
+const RUNTIME_DIRS: [&str; 2] = ["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR_V5"];
+/// Lets the runtime make up timecodes
+const TIMECODE_SYNTHESIZE: i64 = i64::MAX;
+const FRAME_FORMAT_PROGRESSIVE: c_int = 1;
+
+type Initialize = unsafe extern "C" fn() -> bool;
+type SendCreateFn = unsafe extern "C" fn(*const SendCreate) -> *mut c_void;
+type SendVideo = unsafe extern "C" fn(*mut c_void, *const VideoFrame);
+type SendDestroy = unsafe extern "C" fn(*mut c_void);
+
+#[repr(C)]
+struct SendCreate {
+    ndi_name: *const c_char,
+    groups: *const c_char,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+#[repr(C)]
+struct VideoFrame {
+    xres: c_int,
+    yres: c_int,
+    four_cc: u32,
+    frame_rate_n: c_int,
+    frame_rate_d: c_int,
+    picture_aspect_ratio: c_float,
+    frame_format_type: c_int,
+    timecode: i64,
+    data: *const u8,
+    line_stride_in_bytes: c_int,
+    metadata: *const c_char,
+    timestamp: i64,
+}
+
+/// The functions of the NDI runtime that are used.
+struct Library {
+    initialize: Initialize,
+    send_create: SendCreateFn,
+    send_video: SendVideo,
+    send_destroy: SendDestroy,
+}
+
+fn open() -> Result<*mut c_void, String> {
+    let mut paths: Vec<String> = RUNTIME_DIRS
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .flat_map(|dir| LIBRARIES.map(|library| format!("{}/{}", dir, library)))
+        .collect();
+    paths.extend(LIBRARIES.map(String::from));
+
+    for path in paths {
+        let path = CString::new(path).map_err(|err| err.to_string())?;
+        let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW) };
+        if !handle.is_null() {
+            return Ok(handle);
+        }
+    }
+    Err(i18n::tr(
+        "Could not load the NDI runtime, is it installed?",
+        &[],
+    ))
+}
+
+fn symbol(handle: *mut c_void, name: &str) -> Result<*mut c_void, String> {
+    let c_name = CString::new(name).map_err(|err| err.to_string())?;
+    let symbol = unsafe { libc::dlsym(handle, c_name.as_ptr()) };
+    if symbol.is_null() {
+        Err(i18n::tr("The NDI runtime has no {}", &[&name]))
+    } else {
+        Ok(symbol)
+    }
+}
+
+impl Library {
+    fn load() -> Result<Library, String> {
+        let handle = open()?;
+        unsafe {
+            Ok(Library {
+                initialize: std::mem::transmute::<*mut c_void, Initialize>(symbol(
+                    handle,
+                    "NDIlib_initialize",
+                )?),
+                send_create: std::mem::transmute::<*mut c_void, SendCreateFn>(symbol(
+                    handle,
+                    "NDIlib_send_create",
+                )?),
+                send_video: std::mem::transmute::<*mut c_void, SendVideo>(symbol(
+                    handle,
+                    "NDIlib_send_send_video_v2",
+                )?),
+                send_destroy: std::mem::transmute::<*mut c_void, SendDestroy>(symbol(
+                    handle,
+                    "NDIlib_send_destroy",
+                )?),
+            })
+        }
+    }
+}
+
+/// Checks that the NDI runtime can be loaded.
+pub fn validate() -> Result<(), String> {
+    Library::load().map(|_| ())
+}
+
+/// An NDI source.
+pub struct Sender {
+    library: Library,
+    instance: *mut c_void,
+}
+
+// The runtime allows sending from any thread, one at a time
+unsafe impl Send for Sender {}
+
+impl Sender {
+    pub fn create(name: &str) -> Result<Sender, String> {
+        let library = Library::load()?;
+        if !unsafe { (library.initialize)() } {
+            return Err(i18n::tr("The NDI runtime does not support this CPU", &[]));
+        }
+
+        let name = CString::new(name).map_err(|err| err.to_string())?;
+        let settings = SendCreate {
+            ndi_name: name.as_ptr(),
+            groups: ptr::null(),
+            // Frames come in as fast as the device has them
+            clock_video: false,
+            clock_audio: false,
+        };
+        let instance = unsafe { (library.send_create)(&settings) };
+        if instance.is_null() {
+            return Err(i18n::tr(
+                "Could not create the NDI source {}",
+                &[&name.to_string_lossy()],
+            ));
+        }
+
+        Ok(Sender { library, instance })
+    }
+
+    /// Sends the YUV4MPEG2 frames ffmpeg writes, until it stops.
+    pub fn forward(&self, stdout: ChildStdout) -> Result<(), String> {
+        let mut reader = BufReader::new(stdout);
+        let mut header = String::new();
+        reader
+            .read_line(&mut header)
+            .map_err(|err| err.to_string())?;
+
+        let (mut width, mut height, mut rate) = (0, 0, (30, 1));
+        for field in header.split_whitespace().skip(1) {
+            let (key, value) = field.split_at(1);
+            match key {
+                "W" => width = value.parse().unwrap_or(0),
+                "H" => height = value.parse().unwrap_or(0),
+                "F" => {
+                    if let Some((n, d)) = value.split_once(':') {
+                        rate = (n.parse().unwrap_or(30), d.parse().unwrap_or(1));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if width == 0 || height == 0 {
+            return Err(i18n::tr("Unexpected stream header: {}", &[&header.trim()]));
+        }
+
+        let mut frame = vec![0; width * height * 3 / 2];
+        let mut line = String::new();
+        loop {
+            line.clear();
+            // Every frame starts with a FRAME line
+            match reader.read_line(&mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(err) => return Err(err.to_string()),
+            }
+            if reader.read_exact(&mut frame).is_err() {
+                return Ok(());
+            }
+
+            let video = VideoFrame {
+                xres: width as c_int,
+                yres: height as c_int,
+                four_cc: u32::from_le_bytes(*b"I420"),
+                frame_rate_n: rate.0,
+                frame_rate_d: rate.1,
+                picture_aspect_ratio: 0.0,
+                frame_format_type: FRAME_FORMAT_PROGRESSIVE,
+                timecode: TIMECODE_SYNTHESIZE,
+                data: frame.as_ptr(),
+                line_stride_in_bytes: width as c_int,
+                metadata: ptr::null(),
+                timestamp: 0,
+            };
+            unsafe { (self.library.send_video)(self.instance, &video) };
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        unsafe { (self.library.send_destroy)(self.instance) };
+    }
+}
@@ -0,0 +1,161 @@
+//! Translates niri's JSON IPC into the sway structures the rest of wlstreamer works with.
+
+use crate::windows::Window;
+use crate::{SwayOutput, SwayOutputMode, SwayScreenRect, SwayWorkspace};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug)]
+struct NiriMode {
+    width: usize,
+    height: usize,
+    refresh_rate: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct NiriLogicalOutput {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    #[serde(default)]
+    transform: Option<String>,
+    #[serde(default)]
+    scale: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NiriOutput {
+    name: String,
+    modes: Vec<NiriMode>,
+    current_mode: Option<usize>,
+    logical: Option<NiriLogicalOutput>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NiriWorkspace {
+    #[serde(default)]
+    id: u64,
+    idx: usize,
+    name: Option<String>,
+    output: Option<String>,
+    is_active: bool,
+    is_focused: bool,
+}
+
+/// Turns niri's transform names like `_90` or `Flipped270` into sway's, like `90` or
+/// `flipped-270`.
+fn transform_name(transform: &str) -> String {
+    let transform = transform.to_lowercase();
+    match transform.strip_prefix("flipped") {
+        Some("") => "flipped".to_string(),
+        Some(degrees) => format!("flipped-{}", degrees),
+        None => transform.trim_start_matches('_').to_string(),
+    }
+}
+
+/// Parses the output of `niri msg --json outputs`, which is a map from output name to output.
+pub fn parse_outputs(json: &str) -> serde_json::Result<Vec<SwayOutput>> {
+    let outputs: HashMap<String, NiriOutput> = serde_json::from_str(json)?;
+
+    Ok(outputs
+        .into_values()
+        .map(|o| {
+            let current_mode =
+                o.current_mode
+                    .and_then(|i| o.modes.get(i))
+                    .map(|mode| SwayOutputMode {
+                        width: mode.width,
+                        height: mode.height,
+                        refresh: mode.refresh_rate,
+                    });
+            let transform = o
+                .logical
+                .as_ref()
+                .and_then(|logical| logical.transform.as_deref())
+                .map(transform_name);
+            let scale = o.logical.as_ref().and_then(|logical| logical.scale);
+            let rect = match o.logical {
+                Some(logical) => SwayScreenRect {
+                    x: logical.x as i64,
+                    y: logical.y as i64,
+                    width: logical.width,
+                    height: logical.height,
+                },
+                None => SwayScreenRect::default(),
+            };
+
+            SwayOutput {
+                name: o.name,
+                // Disabled outputs have neither a mode nor a place in the layout
+                active: current_mode.is_some(),
+                rect,
+                current_mode,
+                power: None,
+                dpms: None,
+                transform,
+                scale,
+                hdr: None,
+                scaled_to: None,
+            }
+        })
+        .collect())
+}
+
+#[derive(Deserialize, Debug)]
+struct NiriWindow {
+    title: Option<String>,
+    app_id: Option<String>,
+    workspace_id: Option<u64>,
+    is_focused: bool,
+    /// Only told by niri 25.08 and newer
+    #[serde(default)]
+    is_urgent: bool,
+}
+
+/// Parses the output of `niri msg --json windows`, with the output of `niri msg --json
+/// workspaces` to find out where they are. Windows on the active workspace of an output count as
+/// visible, even when they are scrolled out of view.
+pub fn parse_windows(windows_json: &str, workspaces_json: &str) -> serde_json::Result<Vec<Window>> {
+    let windows: Vec<NiriWindow> = serde_json::from_str(windows_json)?;
+    let workspaces: Vec<NiriWorkspace> = serde_json::from_str(workspaces_json)?;
+
+    Ok(windows
+        .into_iter()
+        .map(|w| {
+            let workspace = workspaces.iter().find(|ws| Some(ws.id) == w.workspace_id);
+            Window {
+                app_id: w.app_id.unwrap_or_default(),
+                title: w.title.unwrap_or_default(),
+                output: workspace.and_then(|ws| ws.output.clone()),
+                focused: w.is_focused,
+                visible: workspace.is_some_and(|ws| ws.is_active),
+                urgent: w.is_urgent,
+                rect: None,
+            }
+        })
+        .collect())
+}
+
+/// Parses the output of `niri msg --json workspaces`.
+///
+/// niri has a column of workspaces per output, of which the active one is what is visible.
+pub fn parse_workspaces(json: &str) -> serde_json::Result<Vec<SwayWorkspace>> {
+    let workspaces: Vec<NiriWorkspace> = serde_json::from_str(json)?;
+
+    Ok(workspaces
+        .into_iter()
+        .filter_map(|w| {
+            let idx = w.idx;
+            Some(SwayWorkspace {
+                name: w.name.unwrap_or_else(|| idx.to_string()),
+                focus: Vec::new(),
+                output: w.output?,
+                focused: w.is_focused,
+                rect: SwayScreenRect::default(),
+                visible: w.is_active,
+                num: Some(idx),
+            })
+        })
+        .collect())
+}
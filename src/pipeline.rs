@@ -0,0 +1,1316 @@
+//! The processes streaming a screen, or the black screen, into a device. A [`Builder`] works out
+//! which processes are needed and how they are started for the current options, and the
+//! [`Pipeline`] it builds starts and stops them together, through the [`CommandRunner`] of the
+//! config.
+
+use crate::capture::CaptureProtocol;
+use crate::children;
+#[cfg(feature = "ffmpeg")]
+use crate::inprocess;
+use crate::mask::Mask;
+use crate::stats::{self, Stats};
+use crate::zoom::Pan;
+use crate::{i18n, Config, Options, Resolution, SwayOutput};
+use std::env;
+use std::fmt::Debug;
+use std::io::Error;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Frame rate of the black screen if nothing else decides it
+const BLACK_FRAME_RATE: u32 = 25;
+
+/// A running part of the streaming pipeline.
+pub enum Stage {
+    Process(Child),
+    #[cfg(feature = "ffmpeg")]
+    InProcess(inprocess::Worker),
+    /// A process a fake runner pretended to start
+    #[cfg(test)]
+    Fake,
+}
+
+impl Stage {
+    pub fn kill(&mut self) -> Result<(), Error> {
+        match self {
+            Stage::Process(child) => children::stop(child, libc::SIGTERM, children::KILL_TIMEOUT),
+            #[cfg(feature = "ffmpeg")]
+            Stage::InProcess(worker) => worker.kill(),
+            #[cfg(test)]
+            Stage::Fake => Ok(()),
+        }
+    }
+
+    /// Whether the stage stopped on its own.
+    pub fn exited(&mut self) -> bool {
+        match self {
+            Stage::Process(child) => !matches!(child.try_wait(), Ok(None)),
+            #[cfg(feature = "ffmpeg")]
+            Stage::InProcess(worker) => worker.exited(),
+            #[cfg(test)]
+            Stage::Fake => false,
+        }
+    }
+}
+
+/// Starts the processes of pipelines. Tests put a fake one into the config, to see what would be
+/// started without starting anything.
+pub trait CommandRunner: Debug {
+    /// Starts the process, with the stage before it in the pipeline to read from.
+    fn run(&self, invocation: &Invocation, previous: Option<&mut Stage>) -> Result<Stage, Error>;
+}
+
+/// Starts real processes.
+#[derive(Debug)]
+pub struct System;
+
+impl CommandRunner for System {
+    fn run(&self, invocation: &Invocation, previous: Option<&mut Stage>) -> Result<Stage, Error> {
+        if !invocation.delay.is_zero() {
+            thread::sleep(invocation.delay);
+        }
+        Ok(Stage::Process(invocation.command(previous)?.spawn()?))
+    }
+}
+
+/// Where a process reads from.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Input {
+    /// A pipe nothing is written to, so ffmpeg doesn't read keys from the terminal
+    Piped,
+    /// The output of the process before it
+    Previous,
+}
+
+/// Where a process writes its output or errors to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Stream {
+    Inherit,
+    Piped,
+    /// Thrown away, for processes that write more than a pipe nobody reads from can hold
+    Null,
+}
+
+impl Stream {
+    /// The terminal with --verbose, a pipe nothing reads from otherwise.
+    pub fn verbose(verbose: bool) -> Stream {
+        if verbose {
+            Stream::Inherit
+        } else {
+            Stream::Piped
+        }
+    }
+
+    fn stdio(self) -> Stdio {
+        match self {
+            Stream::Inherit => Stdio::inherit(),
+            Stream::Piped => Stdio::piped(),
+            Stream::Null => Stdio::null(),
+        }
+    }
+}
+
+/// A process of a pipeline, before it is started.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    pub stdin: Input,
+    pub stdout: Stream,
+    pub stderr: Stream,
+    /// Whether ffmpeg reports its progress on stdout, which is then read for the frame statistics
+    pub progress: bool,
+    /// How long to wait before starting it
+    pub delay: Duration,
+}
+
+impl Invocation {
+    pub fn new(program: &str) -> Invocation {
+        Invocation {
+            program: program.to_string(),
+            args: Vec::new(),
+            stdin: Input::Piped,
+            stdout: Stream::Piped,
+            stderr: Stream::Piped,
+            progress: false,
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn arg<S: AsRef<str>>(mut self, arg: S) -> Invocation {
+        self.args.push(arg.as_ref().to_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Invocation
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    /// Makes ffmpeg report its progress if `counted`, which has to come before its other options.
+    pub fn progress(mut self, counted: bool) -> Invocation {
+        if !counted {
+            return self;
+        }
+        self.progress = true;
+        self.args(stats::PROGRESS_ARGS)
+    }
+
+    pub fn stdin(mut self, stdin: Input) -> Invocation {
+        self.stdin = stdin;
+        self
+    }
+
+    pub fn stdout(mut self, stdout: Stream) -> Invocation {
+        self.stdout = stdout;
+        self
+    }
+
+    pub fn stderr(mut self, stderr: Stream) -> Invocation {
+        self.stderr = stderr;
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Invocation {
+        self.delay = delay;
+        self
+    }
+
+    /// The command starting the process, reading from `previous` if it reads from the process
+    /// before it.
+    fn command(&self, previous: Option<&mut Stage>) -> Result<Command, Error> {
+        let stdin = match self.stdin {
+            Input::Piped => Stdio::piped(),
+            Input::Previous => match previous {
+                Some(Stage::Process(child)) => child.stdout.take().map(Stdio::from),
+                _ => None,
+            }
+            .ok_or_else(|| Error::other(format!("{} has nothing to read from", self.program)))?,
+        };
+        let stdout = if self.progress {
+            Stdio::piped()
+        } else {
+            self.stdout.stdio()
+        };
+
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(self.stderr.stdio());
+        Ok(command)
+    }
+}
+
+/// A part of a pipeline, before it is started.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    Process(Invocation),
+    /// Scales the frames from one device onto another, inside wlstreamer
+    #[cfg(feature = "ffmpeg")]
+    Upscale {
+        from: String,
+        to: String,
+        width: u32,
+        height: u32,
+        color: String,
+        /// The crop of --zoom, moved on every frame
+        pan: Option<Pan>,
+        /// Filters covering the windows of --mask-app, each followed by a comma
+        masks: String,
+    },
+    /// Writes black frames passed through `filters` to the device, inside wlstreamer
+    #[cfg(feature = "ffmpeg")]
+    Black {
+        width: u32,
+        height: u32,
+        rate: u32,
+        device: String,
+        filters: String,
+    },
+}
+
+impl Step {
+    fn start(
+        &self,
+        runner: &dyn CommandRunner,
+        previous: Option<&mut Stage>,
+        stats: &Stats,
+    ) -> Result<Stage, Error> {
+        match self {
+            Step::Process(invocation) => {
+                let mut stage = runner.run(invocation, previous)?;
+                if let (true, Stage::Process(child)) = (invocation.progress, &mut stage) {
+                    stats.follow(child);
+                }
+                Ok(stage)
+            }
+            #[cfg(feature = "ffmpeg")]
+            Step::Upscale {
+                from,
+                to,
+                width,
+                height,
+                color,
+                pan,
+                masks,
+            } => Ok(Stage::InProcess(inprocess::upscale(
+                from.clone(),
+                to.clone(),
+                *width,
+                *height,
+                color.clone(),
+                masks.clone(),
+                pan.clone(),
+            ))),
+            #[cfg(feature = "ffmpeg")]
+            Step::Black {
+                width,
+                height,
+                rate,
+                device,
+                filters,
+            } => Ok(Stage::InProcess(inprocess::stream_black(
+                *width,
+                *height,
+                *rate,
+                device.clone(),
+                filters.clone(),
+            )?)),
+        }
+    }
+}
+
+/// The processes streaming into a device, which are stopped when it is dropped.
+pub struct Pipeline {
+    steps: Vec<Step>,
+    stages: Vec<Stage>,
+    stats: Stats,
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline {
+            steps: Vec::new(),
+            stages: Vec::new(),
+            stats: Stats::default(),
+            runner: Arc::new(System),
+        }
+    }
+}
+
+impl Pipeline {
+    /// Records an output.
+    pub fn capture(output: SwayOutput) -> Builder {
+        Builder::new(Source::Capture(output))
+    }
+
+    /// Streams a black screen, with the text in the middle.
+    pub fn black(text: Option<String>) -> Builder {
+        Builder::new(Source::Black(text))
+    }
+
+    /// Streams a black screen counting down from `seconds` in the middle.
+    pub fn countdown(seconds: u64) -> Builder {
+        Builder::new(Source::Countdown(seconds))
+    }
+
+    /// Passes on what another pipeline writes to a device, scaled with ffmpeg.
+    pub fn relay(from: usize) -> Builder {
+        Builder::new(Source::Device(from))
+    }
+
+    /// A pipeline of the given steps, started by the runner of `config` and reporting its
+    /// progress to its statistics.
+    pub fn new(config: &Config, steps: Vec<Step>) -> Pipeline {
+        Pipeline {
+            steps,
+            stages: Vec::new(),
+            stats: config.runtime.stats.clone(),
+            runner: config.runtime.runner.clone(),
+        }
+    }
+
+    /// Starts every step in order. If one can't be started, the ones before it are stopped again.
+    pub fn start(&mut self) -> Result<(), Error> {
+        for step in self.steps.iter() {
+            match step.start(self.runner.as_ref(), self.stages.last_mut(), &self.stats) {
+                Ok(stage) => self.stages.push(stage),
+                Err(err) => {
+                    if let Err(err) = self.stop() {
+                        println!(
+                            "{}",
+                            i18n::tr("Could not stop the pipeline: {}", &[&format!("{:?}", err)])
+                        );
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops every step, even when one of them can't be stopped. Returns the first error.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for mut stage in self.stages.drain(..) {
+            if let Err(err) = stage.kill() {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    /// Stops the steps and starts them again.
+    pub fn restart(&mut self) -> Result<(), Error> {
+        // Starting again can work even if stopping didn't
+        if let Err(err) = self.stop() {
+            println!(
+                "{}",
+                i18n::tr("Could not stop the pipeline: {}", &[&format!("{:?}", err)])
+            );
+        }
+        self.start()
+    }
+
+    /// Whether part of the pipeline stopped on its own.
+    pub fn exited(&mut self) -> bool {
+        self.stages.iter_mut().any(Stage::exited)
+    }
+
+    /// The input of the last process, for typing commands into ffmpeg while it runs.
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        match self.stages.last_mut() {
+            Some(Stage::Process(child)) => child.stdin.take(),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+enum Source {
+    Capture(SwayOutput),
+    Black(Option<String>),
+    Countdown(u64),
+    Device(usize),
+}
+
+/// What a pipeline streams, and how it gets to the device.
+pub struct Builder {
+    source: Source,
+    size: Option<Resolution>,
+    through: Option<usize>,
+    render_node: Option<String>,
+    device: Option<usize>,
+    rate: Option<u32>,
+    pan: Option<Pan>,
+    masks: Vec<Mask>,
+}
+
+impl Builder {
+    fn new(source: Source) -> Builder {
+        Builder {
+            source,
+            size: None,
+            through: None,
+            render_node: None,
+            device: None,
+            rate: None,
+            pan: None,
+            masks: Vec::new(),
+        }
+    }
+
+    /// The size of the frames on the device. Captured frames are scaled to it, keeping their
+    /// aspect ratio, on their way through an intermediate device or the GPU.
+    pub fn scale(mut self, to: Resolution) -> Builder {
+        self.size = Some(to);
+        self
+    }
+
+    /// Captures into this device at the output's own size first, and scales from there with
+    /// ffmpeg.
+    pub fn through(mut self, device: usize) -> Builder {
+        self.through = Some(device);
+        self
+    }
+
+    /// Keeps captured frames on the GPU of this render node while they are scaled.
+    pub fn on_gpu(mut self, render_node: &str) -> Builder {
+        self.render_node = Some(render_node.to_string());
+        self
+    }
+
+    /// The frame rate of a black screen, the frame rate limit or 25 if not set.
+    pub fn rate(mut self, fps: Option<u32>) -> Builder {
+        self.rate = fps;
+        self
+    }
+
+    /// Only streams this part of the frames, which ffmpeg scales from the device they are captured
+    /// into or passed on from.
+    pub fn zoom(mut self, pan: Pan) -> Builder {
+        self.pan = Some(pan);
+        self
+    }
+
+    /// Covers these parts of the captured frames in black, in the scaler that puts them onto the
+    /// device.
+    pub fn mask(mut self, masks: Vec<Mask>) -> Builder {
+        self.masks = masks;
+        self
+    }
+
+    /// The device to stream to, the output device if not set.
+    pub fn sink(mut self, device: usize) -> Builder {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn build(self, config: &Config) -> Pipeline {
+        let device = self.device.unwrap_or(config.options.devices_from);
+        let size = self.size.unwrap_or(config.runtime.resolutions[0]);
+
+        let steps = match self.source {
+            Source::Black(text) => {
+                let rate = self
+                    .rate
+                    .or(config.frame_rate())
+                    .unwrap_or(BLACK_FRAME_RATE);
+                let overlay = text.as_deref().map_or(String::new(), drawtext_filter);
+                vec![black(config, &overlay, size, rate, device)]
+            }
+            Source::Countdown(seconds) => {
+                let rate = self
+                    .rate
+                    .or(config.frame_rate())
+                    .unwrap_or(BLACK_FRAME_RATE);
+                vec![black(
+                    config,
+                    &countdown_filter(seconds),
+                    size,
+                    rate,
+                    device,
+                )]
+            }
+            Source::Device(from) => {
+                vec![upscale(
+                    &config.options,
+                    from,
+                    size,
+                    device,
+                    self.pan,
+                    &self.masks,
+                )]
+            }
+            Source::Capture(output) => match (self.render_node, self.through) {
+                (Some(render_node), _) => {
+                    let size = self.size.unwrap_or_else(|| output.resolution());
+                    vec![capture_on_gpu(config, &output, &render_node, size, device)]
+                }
+                (None, Some(through)) if through != device => {
+                    let mut steps = capture(config, &output, through);
+                    steps.push(upscale(
+                        &config.options,
+                        through,
+                        size,
+                        device,
+                        self.pan,
+                        &self.masks,
+                    ));
+                    steps
+                }
+                (None, _) => capture(config, &output, device),
+            },
+        };
+
+        Pipeline::new(config, steps)
+    }
+}
+
+/// A drawtext filter writing the text centered onto the frame, followed by a comma so it can be
+/// put in front of other filters.
+fn drawtext_filter(text: &str) -> String {
+    // Quotes and backslashes would need several levels of escaping, so they are replaced
+    let text: String = text
+        .chars()
+        .map(|c| match c {
+            '\'' => '\u{2019}',
+            '\\' => '/',
+            c => c,
+        })
+        .collect();
+    format!(
+        "drawtext=text='{}':expansion=none:fontcolor=white:fontsize=h/16:x=(w-text_w)/2:y=(h-text_h)/2,",
+        text
+    )
+}
+
+/// A drawtext filter counting down the seconds from `seconds` to 1 in large digits, from the
+/// time of the frames, so a single ffmpeg shows the whole countdown.
+fn countdown_filter(seconds: u64) -> String {
+    format!(
+        "drawtext=text='%{{eif\\:max(1,ceil({}-t))\\:d}}':fontcolor=white:fontsize=h/4:x=(w-text_w)/2:y=(h-text_h)/2,",
+        seconds
+    )
+}
+
+/// The filters writing the overlay onto the black screen, and converting it to yuyv422.
+fn black_filters(options: &Options, overlay: &str) -> String {
+    let convert = options
+        .color
+        .convert_filter()
+        .unwrap_or_else(|| "format=yuyv422".to_string());
+    // The same square pixels the upscaler writes, so the device keeps its format when switching
+    format!("{}{},setsar=1", overlay, convert)
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn black(config: &Config, overlay: &str, size: Resolution, rate: u32, device: usize) -> Step {
+    Step::Process(
+        Invocation::new(&config.options.ffmpeg_path)
+            .progress(true)
+            .args([
+                "-f",
+                "lavfi",
+                "-i",
+                format!("color=c=black:s={}x{}:r={}", size.width, size.height, rate).as_str(),
+                "-vf",
+                black_filters(&config.options, overlay).as_str(),
+                "-vcodec",
+                "rawvideo",
+                "-pix_fmt",
+                "yuyv422",
+                "-f",
+                "v4l2",
+            ])
+            .args(config.options.color.tag_args())
+            .args(&config.options.ffmpeg_args)
+            .arg(format!("/dev/video{}", device))
+            .stderr(Stream::verbose(config.options.verbose)),
+    )
+}
+
+#[cfg(feature = "ffmpeg")]
+fn black(config: &Config, overlay: &str, size: Resolution, rate: u32, device: usize) -> Step {
+    Step::Black {
+        width: size.width as u32,
+        height: size.height as u32,
+        rate,
+        device: format!("/dev/video{}", device),
+        filters: black_filters(&config.options, overlay),
+    }
+}
+
+/// wf-recorder options for the current frame rate limit, if there is one.
+fn wf_recorder_frame_rate(config: &Config) -> Vec<String> {
+    match config.frame_rate() {
+        Some(fps) => vec![format!("--framerate={}", fps)],
+        None => Vec::new(),
+    }
+}
+
+/// ffmpeg output options for the current frame rate limit, if there is one.
+fn ffmpeg_frame_rate(config: &Config) -> Vec<String> {
+    match config.frame_rate() {
+        Some(fps) => vec!["-r".to_string(), fps.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// ffmpeg output options for capturing through ext-image-copy-capture or x11grab: turning frames
+/// upright and scaling them with --normalize-scale, dropping frames identical to the previous one
+/// with --damage, since unlike wf-recorder x11grab doesn't know what changed on the screen, and
+/// converting them with --color-range and --colorspace.
+fn ffmpeg_filters(config: &Config, output: &SwayOutput) -> Vec<String> {
+    let mut filters = Vec::new();
+    match config.options.capture_protocol {
+        CaptureProtocol::ExtImageCopyCapture => filters.extend(output.capture_filter()),
+        _ => filters.extend(
+            output
+                .scaled_to
+                .map(|size| format!("scale={}:{}", size.width, size.height)),
+        ),
+    }
+    if config.options.damage && config.options.capture_protocol == CaptureProtocol::X11Grab {
+        filters.push("mpdecimate".to_string());
+    }
+    if output.hdr() {
+        filters.extend(config.options.tonemap.filter());
+    }
+    filters.extend(config.options.color.convert_filter());
+
+    let mut args = config.options.color.tag_args();
+    if !filters.is_empty() {
+        args.extend(["-vf".to_string(), filters.join(",")]);
+    }
+    args
+}
+
+/// The arguments the config file adds for capturing the output.
+fn capture_args<'a>(config: &'a Config, output: &SwayOutput) -> &'a [String] {
+    config
+        .options
+        .capture_args
+        .get(&output.name)
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Captures an output at its own resolution into the device.
+fn capture(config: &Config, output: &SwayOutput, device: usize) -> Vec<Step> {
+    // Only what ends up on the output device is counted
+    let counted = device == config.options.devices_from;
+    let verbose = Stream::verbose(config.options.verbose);
+
+    match config.options.capture_protocol {
+        CaptureProtocol::WlrScreencopy => {
+            let filters: Vec<String> = output
+                .capture_filter()
+                .into_iter()
+                .chain(
+                    output
+                        .hdr()
+                        .then(|| config.options.tonemap.filter())
+                        .flatten(),
+                )
+                .chain(config.options.color.convert_filter())
+                .collect();
+            let filter_args = if filters.is_empty() {
+                None
+            } else {
+                Some(format!("--filter={}", filters.join(",")))
+            };
+
+            vec![Step::Process(
+                Invocation::new(&config.options.wf_recorder_path)
+                    .args([
+                        "--muxer=v4l2",
+                        "--codec=rawvideo",
+                        "--pixel-format=yuyv422",
+                        format!("-o{}", output.name).as_str(),
+                        format!("--file=/dev/video{}", device).as_str(),
+                    ])
+                    .args(filter_args)
+                    .args(wf_recorder_frame_rate(config))
+                    .args(&config.options.wf_recorder_args)
+                    .args(capture_args(config, output))
+                    .stdout(verbose)
+                    .stderr(verbose),
+            )]
+        }
+        CaptureProtocol::ExtImageCopyCapture => {
+            // wf-recorder only speaks wlr-screencopy, so wlstreamer captures the frames itself
+            // and lets ffmpeg turn them into a video stream
+            let exe = env::current_exe().unwrap_or_else(|_| "wlstreamer".into());
+            let mut grabber = Invocation::new(&exe.to_string_lossy())
+                .args(["capture-output", output.name.as_str()])
+                .stderr(verbose);
+            if !config.options.damage {
+                let fps = config
+                    .frame_rate()
+                    .or_else(|| output.refresh_rate())
+                    .unwrap_or(60);
+                grabber = grabber.args(["--repeat".to_string(), fps.to_string()]);
+            }
+
+            let encoder = Invocation::new(&config.options.ffmpeg_path)
+                .progress(counted)
+                .args([
+                    "-f",
+                    "image2pipe",
+                    "-c:v",
+                    "ppm",
+                    "-i",
+                    "-",
+                    "-vcodec",
+                    "rawvideo",
+                    "-pix_fmt",
+                    "yuyv422",
+                    "-f",
+                    "v4l2",
+                ])
+                .args(ffmpeg_filters(config, output))
+                .args(ffmpeg_frame_rate(config))
+                .args(&config.options.ffmpeg_args)
+                .args(capture_args(config, output))
+                .arg(format!("/dev/video{}", device))
+                .stdin(Input::Previous)
+                .stdout(verbose)
+                .stderr(verbose);
+
+            vec![Step::Process(grabber), Step::Process(encoder)]
+        }
+        CaptureProtocol::X11Grab => {
+            let display = env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
+
+            vec![Step::Process(
+                Invocation::new(&config.options.ffmpeg_path)
+                    .progress(counted)
+                    .args([
+                        "-f",
+                        "x11grab",
+                        "-video_size",
+                        format!("{}x{}", output.rect.width, output.rect.height).as_str(),
+                        "-i",
+                        format!("{}+{},{}", display, output.rect.x, output.rect.y).as_str(),
+                        "-vcodec",
+                        "rawvideo",
+                        "-pix_fmt",
+                        "yuyv422",
+                        "-f",
+                        "v4l2",
+                    ])
+                    .args(ffmpeg_filters(config, output))
+                    .args(ffmpeg_frame_rate(config))
+                    .args(&config.options.ffmpeg_args)
+                    .args(capture_args(config, output))
+                    .arg(format!("/dev/video{}", device))
+                    .stdout(verbose)
+                    .stderr(verbose),
+            )]
+        }
+    }
+}
+
+/// Scales the frames captured into `from` onto `device`, padding them to keep their aspect
+/// ratio. With a crop, ffmpeg confirms every command that moves it, so it writes to the terminal
+/// or nowhere.
+#[cfg(not(feature = "ffmpeg"))]
+fn upscale(
+    options: &Options,
+    from: usize,
+    size: Resolution,
+    device: usize,
+    pan: Option<Pan>,
+    masks: &[Mask],
+) -> Step {
+    let stderr = match (options.verbose, &pan) {
+        (true, _) => Stream::Inherit,
+        (false, Some(_)) => Stream::Null,
+        (false, None) => Stream::Piped,
+    };
+    Step::Process(
+        Invocation::new(&options.ffmpeg_path)
+            .progress(true)
+            .args([
+                "-i",
+                format!("/dev/video{}", from).as_str(),
+                "-vcodec",
+                "rawvideo",
+                "-pix_fmt",
+                "yuyv422",
+                "-f",
+                "v4l2",
+                "-vf",
+                format!("{}{}scale={}:{}:force_original_aspect_ratio=decrease{},pad={}:{}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+                    masks.iter().map(Mask::filter).collect::<String>(),
+                    pan.map(|pan| pan.start.filter()).unwrap_or_default(),
+                    size.width, size.height,
+                    options.color.rescale_options(),
+                    size.width, size.height).as_str(),
+            ])
+            .args(options.color.tag_args())
+            .args(&options.ffmpeg_args)
+            .arg(format!("/dev/video{}", device))
+            .stderr(stderr)
+            // TODO: This is slow, ugly, and prone to failure. ffmpeg will fail if wf-recorder
+            // isn't writing yet, however I'm not sure how to get an exact timing of when it's
+            // okay to start reading from the device.
+            .delay(Duration::from_millis(100)),
+    )
+}
+
+#[cfg(feature = "ffmpeg")]
+fn upscale(
+    options: &Options,
+    from: usize,
+    size: Resolution,
+    device: usize,
+    pan: Option<Pan>,
+    masks: &[Mask],
+) -> Step {
+    Step::Upscale {
+        from: format!("/dev/video{}", from),
+        to: format!("/dev/video{}", device),
+        width: size.width as u32,
+        height: size.height as u32,
+        color: options.color.rescale_options(),
+        pan,
+        masks: masks.iter().map(Mask::filter).collect(),
+    }
+}
+
+/// Records a screen, scaling it on the GPU.
+///
+/// wf-recorder uploads the frames to the GPU, scales them to the combined resolution with VAAPI
+/// and downloads them again for the output device, so neither an intermediate device nor a
+/// separate ffmpeg upscaler is needed. This isn't zero-copy: wf-recorder only captures into
+/// dmabufs for its hardware encoders, so rawvideo frames arrive in main memory.
+fn capture_on_gpu(
+    config: &Config,
+    output: &SwayOutput,
+    render_node: &str,
+    size: Resolution,
+    device: usize,
+) -> Step {
+    let transform = output
+        .transform_filter()
+        .map(|filter| format!("{},", filter))
+        .unwrap_or_default();
+    let (tag_hdr, tonemap) = match config.options.tonemap.vaapi_filters() {
+        Some((tag, tonemap)) if output.hdr() => (format!("{},", tag), format!("{},", tonemap)),
+        _ => (String::new(), String::new()),
+    };
+    let filter = format!(
+        "--filter={}{}hwupload,{}scale_vaapi=w={}:h={}:force_original_aspect_ratio=decrease{},hwdownload,format=nv12,pad={}:{}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+        transform, tag_hdr, tonemap, size.width, size.height, config.options.color.vaapi_options(), size.width, size.height
+    );
+    let verbose = Stream::verbose(config.options.verbose);
+
+    Step::Process(
+        Invocation::new(&config.options.wf_recorder_path)
+            .args([
+                "--muxer=v4l2",
+                "--codec=rawvideo",
+                "--pixel-format=yuyv422",
+                format!("--device={}", render_node).as_str(),
+                filter.as_str(),
+                format!("-o{}", output.name).as_str(),
+                format!("--file=/dev/video{}", device).as_str(),
+            ])
+            .args(wf_recorder_frame_rate(config))
+            .args(&config.options.wf_recorder_args)
+            .args(capture_args(config, output))
+            .stdout(verbose)
+            .stderr(verbose),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zoom::Zoom;
+    use std::sync::Mutex;
+
+    /// Remembers the command lines it was asked to run, failing the ones of `fail`.
+    #[derive(Debug, Default)]
+    struct FakeRunner {
+        fail: Option<&'static str>,
+        started: Mutex<Vec<String>>,
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run(&self, invocation: &Invocation, _: Option<&mut Stage>) -> Result<Stage, Error> {
+            if self.fail == Some(invocation.program.as_str()) {
+                return Err(Error::other("No such file or directory"));
+            }
+            let mut argv = vec![invocation.program.clone()];
+            argv.extend(invocation.args.iter().cloned());
+            self.started.lock().unwrap().push(argv.join(" "));
+            Ok(Stage::Fake)
+        }
+    }
+
+    /// A config for two outputs, with the runner put in.
+    fn follow_config(runner: &Arc<FakeRunner>) -> Config {
+        let mut config = Config::default();
+        config.runtime.resolutions = vec![
+            Resolution {
+                width: 1920,
+                height: 1080,
+            },
+            Resolution {
+                width: 1280,
+                height: 1024,
+            },
+        ];
+        config
+            .runtime
+            .outputs
+            .insert(config.runtime.resolutions[0], config.options.devices_from);
+        config.runtime.runner = runner.clone();
+        config
+    }
+
+    fn started(runner: &FakeRunner) -> Vec<String> {
+        runner.started.lock().unwrap().clone()
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.options.devices_from = 10;
+        config.runtime.resolutions = vec![Resolution {
+            width: 2560,
+            height: 1440,
+        }];
+        config
+    }
+
+    fn output() -> SwayOutput {
+        serde_json::from_str(
+            r#"{"name":"HDMI-A-1","rect":{"x":2560,"y":0,"width":1920,"height":1080},
+                "current_mode":{"width":1920,"height":1080,"refresh":60000}}"#,
+        )
+        .unwrap()
+    }
+
+    fn invocations(pipeline: &Pipeline) -> Vec<&Invocation> {
+        pipeline
+            .steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Process(invocation) => Some(invocation),
+                #[allow(unreachable_patterns)]
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn capture_writes_to_the_sink() {
+        let config = config();
+        let pipeline = Pipeline::capture(output()).sink(10).build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].program, "wf-recorder");
+        assert_eq!(
+            invocations[0].args,
+            vec![
+                "--muxer=v4l2",
+                "--codec=rawvideo",
+                "--pixel-format=yuyv422",
+                "-oHDMI-A-1",
+                "--file=/dev/video10",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn capture_through_a_device_is_scaled_onto_the_sink() {
+        let config = config();
+        let pipeline = Pipeline::capture(output())
+            .through(11)
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations.len(), 2);
+        assert!(invocations[0]
+            .args
+            .contains(&"--file=/dev/video11".to_string()));
+        assert_eq!(invocations[1].program, "ffmpeg");
+        assert!(invocations[1].progress);
+        assert_eq!(
+            &invocations[1].args[3..5],
+            &["-i".to_string(), "/dev/video11".to_string()]
+        );
+        assert!(invocations[1].args.contains(
+            &"scale=2560:1440:force_original_aspect_ratio=decrease,pad=2560:1440:(ow-iw)/2:(oh-ih)/2,setsar=1".to_string()
+        ));
+        assert_eq!(invocations[1].args.last().unwrap(), "/dev/video10");
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn relay_only_scales() {
+        let config = config();
+        let pipeline = Pipeline::relay(11)
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].program, "ffmpeg");
+        assert_eq!(
+            &invocations[0].args[3..5],
+            &["-i".to_string(), "/dev/video11".to_string()]
+        );
+        assert_eq!(invocations[0].args.last().unwrap(), "/dev/video10");
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn zoom_crops_before_scaling() {
+        let config = config();
+        let pipeline = Pipeline::capture(output())
+            .through(11)
+            .scale(config.runtime.resolutions[0])
+            .zoom(Zoom::new(2.0, Duration::ZERO).pan("HDMI-A-1", output().resolution()))
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert!(invocations[1].args.contains(
+            &"crop=960:540:480:270,scale=2560:1440:force_original_aspect_ratio=decrease,pad=2560:1440:(ow-iw)/2:(oh-ih)/2,setsar=1".to_string()
+        ));
+        assert_eq!(invocations[1].stdin, Input::Piped);
+        assert_eq!(invocations[1].stderr, Stream::Null);
+    }
+
+    #[test]
+    fn capture_into_its_own_device_is_not_scaled() {
+        let config = config();
+        let pipeline = Pipeline::capture(output())
+            .through(10)
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        assert_eq!(pipeline.steps.len(), 1);
+    }
+
+    #[test]
+    fn only_the_output_device_is_counted() {
+        let mut config = config();
+        config.options.capture_protocol = CaptureProtocol::X11Grab;
+
+        let counted = Pipeline::capture(output()).sink(10).build(&config);
+        assert!(invocations(&counted)[0].progress);
+        assert_eq!(
+            &invocations(&counted)[0].args[..3],
+            &stats::PROGRESS_ARGS.map(str::to_string)
+        );
+
+        let mirrored = Pipeline::capture(output()).sink(12).build(&config);
+        assert!(!invocations(&mirrored)[0].progress);
+        assert_eq!(invocations(&mirrored)[0].args[1], "x11grab");
+    }
+
+    #[test]
+    fn capture_args_only_apply_to_their_output() {
+        let mut config = config();
+        config
+            .options
+            .capture_args
+            .insert("HDMI-A-1".to_string(), vec!["--framerate=30".to_string()]);
+        let pipeline = Pipeline::capture(output()).sink(10).build(&config);
+        assert_eq!(
+            invocations(&pipeline)[0].args.last().unwrap(),
+            "--framerate=30"
+        );
+
+        config.options.capture_args.clear();
+        config
+            .options
+            .capture_args
+            .insert("DP-3".to_string(), vec!["--framerate=30".to_string()]);
+        let pipeline = Pipeline::capture(output()).sink(10).build(&config);
+        assert_eq!(
+            invocations(&pipeline)[0].args.last().unwrap(),
+            "--file=/dev/video10"
+        );
+    }
+
+    #[test]
+    fn captured_frames_are_piped_into_ffmpeg() {
+        let mut config = config();
+        config.options.capture_protocol = CaptureProtocol::ExtImageCopyCapture;
+        let pipeline = Pipeline::capture(output()).sink(10).build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(
+            invocations[0].args,
+            ["capture-output", "HDMI-A-1", "--repeat", "60"]
+        );
+        assert_eq!(invocations[1].stdin, Input::Previous);
+        assert_eq!(invocations[1].args.last().unwrap(), "/dev/video10");
+    }
+
+    #[test]
+    fn gpu_scaling_needs_no_upscaler() {
+        let config = config();
+        let pipeline = Pipeline::capture(output())
+            .through(11)
+            .on_gpu("/dev/dri/renderD128")
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].args[3], "--device=/dev/dri/renderD128");
+        assert!(invocations[0].args[4].contains("scale_vaapi=w=2560:h=1440"));
+        assert_eq!(invocations[0].args[6], "--file=/dev/video10");
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn black_screen_has_the_text() {
+        let config = config();
+        let pipeline = Pipeline::black(Some("Workspace 'mail' (hidden)".to_string()))
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations[0].args[6], "color=c=black:s=2560x1440:r=25");
+        assert_eq!(
+            invocations[0].args[8],
+            "drawtext=text='Workspace \u{2019}mail\u{2019} (hidden)':expansion=none:fontcolor=white:fontsize=h/16:x=(w-text_w)/2:y=(h-text_h)/2,format=yuyv422,setsar=1"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn countdown_counts_down_on_black() {
+        let config = config();
+        let pipeline = Pipeline::countdown(5)
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations[0].args[6], "color=c=black:s=2560x1440:r=25");
+        assert_eq!(
+            invocations[0].args[8],
+            "drawtext=text='%{eif\\:max(1,ceil(5-t))\\:d}':fontcolor=white:fontsize=h/4:x=(w-text_w)/2:y=(h-text_h)/2,format=yuyv422,setsar=1"
+        );
+    }
+
+    #[test]
+    fn black_screen_keeps_the_frame_rate() {
+        let config = config();
+        let pipeline = Pipeline::black(None)
+            .rate(Some(60))
+            .scale(config.runtime.resolutions[0])
+            .sink(10)
+            .build(&config);
+
+        let invocations = invocations(&pipeline);
+        assert_eq!(invocations[0].args[6], "color=c=black:s=2560x1440:r=60");
+    }
+
+    #[test]
+    fn output_with_the_combined_resolution_is_recorded_directly() {
+        let runner = Arc::new(FakeRunner::default());
+        let mut config = follow_config(&runner);
+        config.options.fps = Some(30);
+        config.options.wf_recorder_args = vec!["--no-damage".to_string()];
+
+        crate::record_screen(&mut config, output()).unwrap();
+
+        assert_eq!(
+            started(&runner),
+            vec!["wf-recorder --muxer=v4l2 --codec=rawvideo --pixel-format=yuyv422 -oHDMI-A-1 --file=/dev/video0 --framerate=30 --no-damage"]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn smaller_output_is_scaled_from_its_own_device() {
+        let runner = Arc::new(FakeRunner::default());
+        let mut config = follow_config(&runner);
+        crate::assign_devices(&mut config);
+        let output: SwayOutput = serde_json::from_str(
+            r#"{"name":"DP-2","rect":{"x":1920,"y":0,"width":1280,"height":1024},
+                "current_mode":{"width":1280,"height":1024,"refresh":60000}}"#,
+        )
+        .unwrap();
+
+        crate::record_screen(&mut config, output).unwrap();
+
+        assert_eq!(
+            started(&runner),
+            vec![
+                "wf-recorder --muxer=v4l2 --codec=rawvideo --pixel-format=yuyv422 -oDP-2 --file=/dev/video1",
+                "ffmpeg -nostats -progress pipe:1 -i /dev/video1 -vcodec rawvideo -pix_fmt yuyv422 -f v4l2 -vf scale=1920:1080:force_original_aspect_ratio=decrease,pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1 /dev/video0",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn masked_windows_are_covered_while_scaling() {
+        let runner = Arc::new(FakeRunner::default());
+        let mut config = follow_config(&runner);
+        crate::assign_devices(&mut config);
+        config.runtime.masks.insert(
+            "DP-2".to_string(),
+            vec![Mask {
+                x: 0,
+                y: 24,
+                width: 640,
+                height: 1000,
+            }],
+        );
+        let output: SwayOutput = serde_json::from_str(
+            r#"{"name":"DP-2","rect":{"x":1920,"y":0,"width":1280,"height":1024},
+                "current_mode":{"width":1280,"height":1024,"refresh":60000}}"#,
+        )
+        .unwrap();
+
+        crate::record_screen(&mut config, output).unwrap();
+
+        assert_eq!(
+            started(&runner)[1],
+            "ffmpeg -nostats -progress pipe:1 -i /dev/video1 -vcodec rawvideo -pix_fmt yuyv422 -f v4l2 -vf drawbox=x=0:y=24:w=640:h=1000:color=black:t=fill,scale=1920:1080:force_original_aspect_ratio=decrease,pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1 /dev/video0"
+        );
+        assert_eq!(config.runtime.masked.len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn black_screen_is_streamed_by_ffmpeg() {
+        let runner = Arc::new(FakeRunner::default());
+        let mut config = follow_config(&runner);
+        config.options.color.range = Some(crate::color::Range::Full);
+
+        crate::stream_black(&mut config, None).unwrap();
+
+        assert_eq!(
+            started(&runner),
+            vec!["ffmpeg -nostats -progress pipe:1 -f lavfi -i color=c=black:s=1920x1080:r=25 -vf scale=out_range=pc,format=yuyv422,setsar=1 -vcodec rawvideo -pix_fmt yuyv422 -f v4l2 -color_range pc /dev/video0"]
+        );
+    }
+
+    #[test]
+    fn failed_start_stops_what_was_started() {
+        let runner = Arc::new(FakeRunner {
+            fail: Some("ffmpeg"),
+            ..FakeRunner::default()
+        });
+        let mut config = follow_config(&runner);
+        config.options.capture_protocol = CaptureProtocol::ExtImageCopyCapture;
+        let mut pipeline = Pipeline::capture(output()).build(&config);
+
+        assert!(pipeline.start().is_err());
+        assert_eq!(started(&runner).len(), 1);
+        assert!(pipeline.stages.is_empty());
+    }
+
+    #[test]
+    fn restart_starts_every_step_again() {
+        let runner = Arc::new(FakeRunner::default());
+        let mut config = follow_config(&runner);
+        config.options.capture_protocol = CaptureProtocol::ExtImageCopyCapture;
+        let mut pipeline = Pipeline::capture(output()).build(&config);
+
+        pipeline.start().unwrap();
+        pipeline.restart().unwrap();
+
+        let started = started(&runner);
+        assert_eq!(started.len(), 4);
+        assert_eq!(started[..2], started[2..]);
+        assert_eq!(pipeline.stages.len(), 2);
+    }
+}
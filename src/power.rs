@@ -0,0 +1,66 @@
+//! `--battery-fps`: reads the power supplies from sysfs, so capture can be throttled on laptops
+//! running on battery and go back to full speed once they are plugged in.
+
+use crate::Event;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+const INTERVAL: Duration = Duration::from_secs(10);
+
+fn read(supply: &Path, attribute: &str) -> String {
+    fs::read_to_string(supply.join(attribute))
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// Whether the computer runs on a battery that is charged to `threshold` percent or less.
+/// Computers without a battery are always plugged in.
+pub fn on_battery(threshold: u8) -> bool {
+    let supplies = match fs::read_dir("/sys/class/power_supply") {
+        Ok(supplies) => supplies,
+        Err(_) => return false,
+    };
+
+    let mut discharging = false;
+    let mut capacity = None;
+    for supply in supplies.map_while(Result::ok) {
+        let supply = supply.path();
+        match read(&supply, "type").as_str() {
+            "Mains" | "USB" if read(&supply, "online") == "1" => return false,
+            // Only the batteries of the computer itself, not those of mice and headsets
+            "Battery" if read(&supply, "scope") != "Device" => {
+                discharging |= read(&supply, "status") == "Discharging";
+                if let Ok(percent) = read(&supply, "capacity").parse::<u8>() {
+                    capacity = Some(capacity.map_or(percent, |c: u8| c.min(percent)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    discharging && capacity.is_none_or(|capacity| capacity <= threshold)
+}
+
+/// Checks the power supplies every ten seconds in a background thread, and signals
+/// `Event::Battery` whenever the computer switches between battery and AC. Returns whether it is
+/// on battery right now.
+pub fn watch(threshold: u8, events: Sender<Event>) -> bool {
+    let mut was_on_battery = on_battery(threshold);
+    let initial = was_on_battery;
+    thread::spawn(move || loop {
+        thread::sleep(INTERVAL);
+        let is_on_battery = on_battery(threshold);
+        if is_on_battery != was_on_battery {
+            was_on_battery = is_on_battery;
+            if events.send(Event::Battery(is_on_battery)).is_err() {
+                return;
+            }
+        }
+    });
+
+    initial
+}
@@ -0,0 +1,203 @@
+//! `--preview-listen`: serves what is written to the output device as a low frame rate MJPEG
+//! stream over HTTP, which browsers show in a tab on their own. ffmpeg only reads from the device
+//! while someone is looking at the preview.
+
+use crate::i18n;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const FPS: u32 = 2;
+const WIDTH: usize = 640;
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// How long a viewer waits for a frame before sending the last one again, which is how viewers
+/// that went away are noticed while ffmpeg sends nothing.
+const RESEND_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct State {
+    /// Counts up with every frame, so viewers can wait for the next one
+    number: u64,
+    frame: Vec<u8>,
+    viewers: usize,
+    /// Whether ffmpeg still sends frames
+    reading: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// Reads the next JPEG from ffmpeg's mpjpeg output, where every frame comes after a boundary
+/// and headers that include its length.
+fn next_frame<R: BufRead>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim();
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        } else if line.is_empty() && length.is_some() {
+            break;
+        }
+    }
+
+    let mut frame = vec![0; length?];
+    reader.read_exact(&mut frame).ok()?;
+    Some(frame)
+}
+
+fn spawn_ffmpeg(ffmpeg_path: &str, device_number: usize) -> std::io::Result<Child> {
+    Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-loglevel", "error", "-f", "v4l2", "-i"])
+        .arg(format!("/dev/video{}", device_number))
+        .arg("-vf")
+        .arg(format!("fps={},scale={}:-2", FPS, WIDTH))
+        .args(["-q:v", "7", "-f", "mpjpeg", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+}
+
+/// Hands the frames of ffmpeg to the viewers until it stops sending them.
+fn read(shared: &Shared, stdout: ChildStdout) {
+    let mut stdout = BufReader::new(stdout);
+    while let Some(frame) = next_frame(&mut stdout) {
+        let mut state = shared.state.lock().unwrap();
+        state.number += 1;
+        state.frame = frame;
+        shared.changed.notify_all();
+    }
+
+    shared.state.lock().unwrap().reading = false;
+    shared.changed.notify_all();
+}
+
+/// Runs ffmpeg while there are viewers, and stops it when the last one leaves, even if it
+/// doesn't send anything.
+fn capture(shared: Arc<Shared>, ffmpeg_path: String, device_number: usize) {
+    loop {
+        {
+            let mut state = shared.state.lock().unwrap();
+            while state.viewers == 0 {
+                state = shared.changed.wait(state).unwrap();
+            }
+        }
+
+        let mut child = match spawn_ffmpeg(&ffmpeg_path, device_number) {
+            Ok(child) => child,
+            Err(err) => {
+                println!("{}", i18n::tr("Could not start the preview: {}", &[&err]));
+                thread::sleep(RESTART_DELAY);
+                continue;
+            }
+        };
+        let stdout = child.stdout.take().unwrap();
+        shared.state.lock().unwrap().reading = true;
+        let reading = shared.clone();
+        let reader = thread::spawn(move || read(&reading, stdout));
+        {
+            let mut state = shared.state.lock().unwrap();
+            while state.viewers > 0 && state.reading {
+                state = shared.changed.wait(state).unwrap();
+            }
+        }
+
+        let _ = child.kill();
+        let _ = child.wait();
+        let _ = reader.join();
+        thread::sleep(RESTART_DELAY);
+    }
+}
+
+/// Sends frames to a viewer until it goes away, and the last frame again when no new one came
+/// for a while, so a viewer that left stops counting even when ffmpeg fails.
+fn respond(shared: &Shared, mut stream: TcpStream) {
+    let mut line = String::new();
+    if BufReader::new(&stream).read_line(&mut line).is_err() {
+        return;
+    }
+    if !line.starts_with("GET ") {
+        let _ = stream.write_all(b"HTTP/1.0 405 Method Not Allowed\r\n\r\n");
+        return;
+    }
+    // A viewer that stops reading is dropped like one that went away
+    let _ = stream.set_write_timeout(Some(RESEND_AFTER));
+    if stream
+        .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\nCache-Control: no-cache\r\n\r\n")
+        .is_err()
+    {
+        return;
+    }
+
+    let mut last = {
+        let mut state = shared.state.lock().unwrap();
+        state.viewers += 1;
+        shared.changed.notify_all();
+        state.number
+    };
+    loop {
+        let frame = {
+            let state = shared.state.lock().unwrap();
+            let (state, _) = shared
+                .changed
+                .wait_timeout_while(state, RESEND_AFTER, |state| state.number == last)
+                .unwrap();
+            last = state.number;
+            state.frame.clone()
+        };
+
+        let sent = if frame.is_empty() {
+            // Nothing to resend yet, but line breaks before a boundary are ignored
+            stream.write_all(b"\r\n")
+        } else {
+            let header = format!(
+                "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                frame.len()
+            );
+            stream
+                .write_all(header.as_bytes())
+                .and_then(|()| stream.write_all(&frame))
+                .and_then(|()| stream.write_all(b"\r\n"))
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+
+    shared.state.lock().unwrap().viewers -= 1;
+    shared.changed.notify_all();
+}
+
+/// Serves the preview of the device on the address in background threads.
+pub fn serve(address: &str, ffmpeg_path: &str, device_number: usize) -> Result<(), String> {
+    let address: SocketAddr = address
+        .parse()
+        .map_err(|_| i18n::tr("{} is not an address like 127.0.0.1:8642", &[&address]))?;
+    let listener = TcpListener::bind(address)
+        .map_err(|err| i18n::tr("Could not listen on {}: {}", &[&address, &err]))?;
+
+    let shared = Arc::new(Shared::default());
+    let ffmpeg_path = ffmpeg_path.to_string();
+    let capturing = shared.clone();
+    thread::spawn(move || capture(capturing, ffmpeg_path, device_number));
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let shared = shared.clone();
+            thread::spawn(move || respond(&shared, stream));
+        }
+    });
+
+    Ok(())
+}
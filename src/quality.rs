@@ -0,0 +1,126 @@
+//! `--quality`: frame rate, size and encoder settings picked together, for streaming without
+//! knowing what a CRF or an x264 preset is. Sinks sent over the network get a bitrate, files a
+//! constant quality instead, which keeps them small on a still desktop. Options given on their own
+//! win over the preset.
+
+use crate::sink::{Codec, Hardware};
+use crate::{set_canvas, Options, Resolution};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+    /// Only the options given on their own
+    #[default]
+    Custom,
+}
+
+/// What a quality sets.
+struct Preset {
+    /// None for the refresh rate of the screens
+    fps: Option<u32>,
+    /// The largest stream, None for the combined resolution of the screens
+    size: Option<Resolution>,
+    bitrate: &'static str,
+    crf: u32,
+    /// x264 and x265 preset, faster ones for lower qualities to leave the CPU to the screens
+    preset: &'static str,
+    /// SVT-AV1 preset, numbered from 0 for the slowest
+    av1_preset: &'static str,
+}
+
+impl Quality {
+    pub fn parse(name: &str) -> Option<Quality> {
+        match name {
+            "low" => Some(Quality::Low),
+            "medium" => Some(Quality::Medium),
+            "high" => Some(Quality::High),
+            "custom" => Some(Quality::Custom),
+            _ => None,
+        }
+    }
+
+    fn preset(&self) -> Option<Preset> {
+        match self {
+            Quality::Low => Some(Preset {
+                fps: Some(15),
+                size: Some(Resolution {
+                    width: 1280,
+                    height: 720,
+                }),
+                bitrate: "2500k",
+                crf: 28,
+                preset: "veryfast",
+                av1_preset: "12",
+            }),
+            Quality::Medium => Some(Preset {
+                fps: Some(30),
+                size: Some(Resolution {
+                    width: 1920,
+                    height: 1080,
+                }),
+                bitrate: "6M",
+                crf: 23,
+                preset: "faster",
+                av1_preset: "10",
+            }),
+            Quality::High => Some(Preset {
+                fps: None,
+                size: None,
+                bitrate: "12M",
+                crf: 18,
+                preset: "medium",
+                av1_preset: "8",
+            }),
+            Quality::Custom => None,
+        }
+    }
+}
+
+/// Fills in the options the preset sets and that weren't given on their own.
+pub fn apply(options: &mut Options) {
+    let preset = match options.quality.preset() {
+        Some(preset) => preset,
+        None => return,
+    };
+
+    if options.fps.is_none() {
+        options.fps = preset.fps;
+    }
+    let encoding = &mut options.encoding;
+    if encoding.bitrate.is_none() && encoding.crf.is_none() {
+        encoding.bitrate = Some(preset.bitrate.to_string());
+        encoding.file_crf = Some(preset.crf);
+    }
+    // Hardware encoders have presets of their own, which they pick well enough
+    if encoding.preset.is_none() && encoding.hardware == Hardware::Software {
+        encoding.preset = Some(match encoding.codec {
+            Codec::H264 | Codec::Hevc => preset.preset.to_string(),
+            Codec::Av1 => preset.av1_preset.to_string(),
+        });
+    }
+}
+
+/// Shrinks the combined resolution the screens are scaled to down to the size of the preset,
+/// keeping its aspect ratio.
+pub fn canvas(quality: Quality, resolutions: &mut Vec<Resolution>) {
+    let size = match quality.preset().and_then(|preset| preset.size) {
+        Some(size) => size,
+        None => return,
+    };
+    let canvas = resolutions[0];
+    if canvas.width <= size.width && canvas.height <= size.height {
+        return;
+    }
+
+    let factor = f64::min(
+        size.width as f64 / canvas.width as f64,
+        size.height as f64 / canvas.height as f64,
+    );
+    let shrunk = Resolution {
+        width: (canvas.width as f64 * factor) as usize & !1,
+        height: (canvas.height as f64 * factor) as usize & !1,
+    };
+    set_canvas(resolutions, shrunk);
+}
@@ -0,0 +1,131 @@
+//! `--replay-buffer`: keeps the last seconds of the stream as HLS segments in the runtime dir,
+//! and saves them to a file with `wlstreamer ctl save-replay`. The buffer reads from the output
+//! device, so it has the screens in the order they were streamed.
+
+use crate::{i18n, runtime_dir, Options};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+
+/// The length of a segment, the same as the keyframe interval of the encoding
+const SEGMENT_SECONDS: u32 = 2;
+const PLAYLIST: &str = "replay.m3u8";
+
+/// Where the segments are kept.
+fn dir() -> Result<PathBuf, String> {
+    runtime_dir::join(&format!("wlstreamer-replay-{}", process::id()))
+}
+
+/// The ffmpeg command that keeps the last `seconds` of the output device, with the audio delayed
+/// by `audio_delay` for `--av-offset auto`.
+pub fn command(
+    options: &Options,
+    render_node: Option<&str>,
+    seconds: u32,
+    audio_delay: i64,
+) -> Result<Command, String> {
+    let dir = dir()?;
+    fs::create_dir_all(&dir)
+        .map_err(|err| i18n::tr("Could not create {}: {}", &[&dir.display(), &err]))?;
+
+    let segments = seconds.div_ceil(SEGMENT_SECONDS).max(1);
+    let mut command = Command::new(&options.ffmpeg_path);
+    command
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(options.encoding.input_args(render_node))
+        .args(options.color.tag_args())
+        .args(["-f", "v4l2", "-i"])
+        .arg(format!("/dev/video{}", options.devices_from))
+        .args(options.audio.input_args(audio_delay))
+        .args(options.encoding.output_args(None))
+        // Saved to a file, so with a track for each source
+        .args(options.audio.output_args(true, false))
+        .args(["-f", "hls", "-hls_time"])
+        .arg(SEGMENT_SECONDS.to_string())
+        .arg("-hls_list_size")
+        .arg(segments.to_string())
+        .args(["-hls_flags", "delete_segments+omit_endlist"])
+        .arg("-hls_segment_filename")
+        .arg(dir.join("%d.ts"))
+        .arg("-y")
+        .arg(dir.join(PLAYLIST))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(if options.verbose {
+            Stdio::inherit()
+        } else {
+            Stdio::null()
+        });
+
+    Ok(command)
+}
+
+/// `~/Videos/replay-<date>.mkv`, or in the home directory if there is no Videos directory.
+fn default_path() -> PathBuf {
+    let home = PathBuf::from(env::var_os("HOME").unwrap_or_else(|| "/tmp".into()));
+    let videos = home.join("Videos");
+    let dir = if videos.is_dir() { videos } else { home };
+    dir.join(format!("replay-{}.mkv", date()))
+}
+
+/// The local time as `2024-05-17-21-03-09`, for names of files.
+pub fn date() -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02}-{:02}-{:02}-{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Writes the buffered segments to a file, and returns its path.
+pub fn save(ffmpeg_path: &str, path: Option<&str>) -> Result<String, String> {
+    let dir = dir()?;
+    let playlist = fs::read_to_string(dir.join(PLAYLIST))
+        .map_err(|_| i18n::tr("The replay buffer is still empty", &[]))?;
+
+    // ffmpeg keeps deleting old segments, so the current ones are linked somewhere else first
+    let saving = dir.join("saving");
+    let _ = fs::remove_dir_all(&saving);
+    fs::create_dir(&saving)
+        .map_err(|err| i18n::tr("Could not create {}: {}", &[&saving.display(), &err]))?;
+    for segment in playlist.lines().filter(|line| !line.starts_with('#')) {
+        fs::hard_link(dir.join(segment), saving.join(segment))
+            .map_err(|err| i18n::tr("Could not keep {}: {}", &[&segment, &err]))?;
+    }
+    fs::write(
+        saving.join(PLAYLIST),
+        format!("{}#EXT-X-ENDLIST\n", playlist),
+    )
+    .map_err(|err| i18n::tr("Could not write the playlist: {}", &[&err]))?;
+
+    let path = path.map_or_else(default_path, PathBuf::from);
+    let status = Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-loglevel", "error", "-i"])
+        .arg(saving.join(PLAYLIST))
+        .args(["-c", "copy", "-y"])
+        .arg(&path)
+        .stdin(Stdio::null())
+        .status();
+    let _ = fs::remove_dir_all(&saving);
+
+    match status {
+        Ok(status) if status.success() => Ok(path.display().to_string()),
+        Ok(_) => Err(i18n::tr("ffmpeg could not write {}", &[&path.display()])),
+        Err(err) => Err(i18n::tr("Could not run {}: {}", &[&ffmpeg_path, &err])),
+    }
+}
+
+/// Removes the segments when wlstreamer stops.
+pub fn clean() {
+    if let Ok(dir) = dir() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
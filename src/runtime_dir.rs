@@ -0,0 +1,64 @@
+//! The directory the control socket, the device locks, the log and the segments are kept in:
+//! `$XDG_RUNTIME_DIR`, or `/tmp/wlstreamer-<uid>` where there is none. Whatever is found in it is
+//! trusted, so it has to belong to the user and be closed to everyone else, or it isn't used.
+
+use crate::i18n;
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Checks that `dir` is a directory of the user's that nobody else can get into, and not a link
+/// to one.
+fn validate(dir: &Path) -> Result<(), String> {
+    let metadata = fs::symlink_metadata(dir)
+        .map_err(|err| i18n::tr("Could not access {}: {}", &[&dir.display(), &err]))?;
+    let uid = unsafe { libc::getuid() };
+
+    if !metadata.is_dir() {
+        Err(i18n::tr("{} is not a directory", &[&dir.display()]))
+    } else if metadata.uid() != uid {
+        Err(i18n::tr(
+            "{} belongs to somebody else, it should be the runtime dir of user {}",
+            &[&dir.display(), &uid],
+        ))
+    } else if metadata.mode() & 0o077 != 0 {
+        Err(i18n::tr(
+            "{} can be accessed by others, it should have mode 0700",
+            &[&dir.display()],
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// `$XDG_RUNTIME_DIR`, or a private directory in /tmp that is created if needed.
+pub fn path() -> Result<PathBuf, String> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let dir = PathBuf::from(format!("/tmp/wlstreamer-{}", unsafe { libc::getuid() }));
+            // Fails when it already exists, which is checked below like any other runtime dir
+            let _ = DirBuilder::new().mode(0o700).create(&dir);
+            dir
+        }
+    };
+
+    validate(&dir)?;
+    Ok(dir)
+}
+
+/// `name` in the runtime dir.
+pub fn join(name: &str) -> Result<PathBuf, String> {
+    Ok(path()?.join(name))
+}
+
+/// Opens `path` with `options`, creating it readable for the user only, and refusing to follow a
+/// link that was put in its place.
+pub fn open(path: &Path, options: &mut OpenOptions) -> Result<File, String> {
+    options
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .map_err(|err| i18n::tr("Could not open {}: {}", &[&path.display(), &err]))
+}
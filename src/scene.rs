@@ -0,0 +1,156 @@
+//! The scene, the output and workspace being streamed, sent to RTMP sinks on every switch.
+//!
+//! ffmpeg can't change the metadata of a stream it is sending, but it passes on a text track. An
+//! RTMP sink reads a second input from its stdin, an FLV stream that wlstreamer writes an
+//! `onTextData` tag into whenever it switches, and ffmpeg sends those on to the ingest like any
+//! other data tag. Every ffmpeg process of a sink gets a pipe of its own, so a restarted sink
+//! starts with a new stream that names the current scene first.
+
+use std::io::{self, Write};
+use std::process::ChildStdin;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// The current scene, and how many switches there were, so writers notice a switch back.
+static SCENE: Mutex<(u64, String)> = Mutex::new((0, String::new()));
+static SWITCHED: Condvar = Condvar::new();
+
+/// The input that reads the scene from stdin, added after the video and audio inputs. It is read
+/// at once, since nothing more is written into it until the next switch.
+pub const INPUT_ARGS: [&str; 8] = [
+    "-probesize",
+    "32",
+    "-analyzeduration",
+    "0",
+    "-f",
+    "flv",
+    "-i",
+    "pipe:0",
+];
+
+/// The start of an FLV stream without audio or video, and the size of the tag before the first.
+const HEADER: [u8; 13] = [b'F', b'L', b'V', 1, 0, 0, 0, 0, 9, 0, 0, 0, 0];
+
+/// ffmpeg options that send the scene of `input` along with the video, and don't hold the video
+/// back waiting for the sparse text track. `mapped` is whether the video is mapped already.
+pub fn output_args(input: usize, mapped: bool) -> Vec<String> {
+    let mut args = Vec::new();
+    if !mapped {
+        args.extend(["-map", "0:v"].map(String::from));
+    }
+    args.extend(["-map".to_string(), format!("{}:s", input)]);
+    args.extend(["-c:s", "copy", "-max_interleave_delta", "100000"].map(String::from));
+    args
+}
+
+/// Names the scene after the output and the workspace on it, or the black screen.
+pub fn set(output: Option<&str>, workspace: Option<&str>) {
+    let scene = match (output, workspace) {
+        (Some(output), Some(workspace)) => format!("{}/{}", output, workspace),
+        (Some(output), None) => output.to_string(),
+        (None, _) => "black".to_string(),
+    };
+
+    let mut current = SCENE.lock().unwrap();
+    if current.1 != scene {
+        *current = (current.0 + 1, scene);
+        SWITCHED.notify_all();
+    }
+}
+
+/// Writes the scene into the stdin of a sink in a background thread, until the sink stops
+/// reading it.
+pub fn follow(stdin: ChildStdin) {
+    thread::spawn(move || {
+        // Ends with a broken pipe once ffmpeg is gone
+        let _ = write(stdin);
+    });
+}
+
+fn write(mut stdin: ChildStdin) -> io::Result<()> {
+    let started = Instant::now();
+    stdin.write_all(&HEADER)?;
+
+    let mut current = SCENE.lock().unwrap();
+    let mut written = None;
+    loop {
+        if written != Some(current.0) {
+            // Black until the first switch
+            let text = Some(current.1.as_str()).filter(|scene| !scene.is_empty());
+            let tag = text_tag(
+                text.unwrap_or("black"),
+                started.elapsed().as_millis() as u32,
+            );
+            written = Some(current.0);
+            // Not written while holding the lock, in case ffmpeg doesn't read
+            drop(current);
+            stdin.write_all(&tag)?;
+            current = SCENE.lock().unwrap();
+        } else {
+            current = SWITCHED.wait(current).unwrap();
+        }
+    }
+}
+
+/// An FLV script tag at `timestamp` milliseconds that calls `onTextData` with `text`, followed by
+/// its size.
+fn text_tag(text: &str, timestamp: u32) -> Vec<u8> {
+    let mut body = Vec::new();
+    amf_string(&mut body, "onTextData");
+    // An ECMA array of two properties, ended by an empty name and the object end marker
+    body.push(8);
+    body.extend(2u32.to_be_bytes());
+    amf_name(&mut body, "type");
+    amf_string(&mut body, "Text");
+    amf_name(&mut body, "text");
+    amf_string(&mut body, text);
+    body.extend([0, 0, 9]);
+
+    let mut tag = vec![18];
+    tag.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    tag.extend(&timestamp.to_be_bytes()[1..]);
+    tag.push((timestamp >> 24) as u8);
+    tag.extend([0, 0, 0]);
+    tag.extend(body);
+    let size = tag.len() as u32;
+    tag.extend(size.to_be_bytes());
+    tag
+}
+
+/// A property name, or the bytes of a string after its type marker.
+fn amf_name(buffer: &mut Vec<u8>, name: &str) {
+    // Property names and strings are limited to 64 KiB
+    let bytes = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+    buffer.extend((bytes.len() as u16).to_be_bytes());
+    buffer.extend(bytes);
+}
+
+fn amf_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.push(2);
+    amf_name(buffer, string);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenes_are_flv_text_tags() {
+        let tag = text_tag("DP-1/2", 0x0102_0304);
+        let body_size = 13 + 5 + 6 + 7 + 6 + 9 + 3;
+        assert_eq!(tag[0], 18);
+        assert_eq!(tag[1..4], [0, 0, body_size as u8]);
+        // The lower 24 bits of the timestamp, then the upper 8, then the stream ID
+        assert_eq!(tag[4..11], [2, 3, 4, 1, 0, 0, 0]);
+        assert_eq!(tag[11..24], *b"\x02\x00\x0aonTextData");
+        assert_eq!(tag[24..29], [8, 0, 0, 0, 2]);
+        assert_eq!(tag[29..35], *b"\x00\x04type");
+        assert_eq!(tag[35..42], *b"\x02\x00\x04Text");
+        assert_eq!(tag[42..48], *b"\x00\x04text");
+        assert_eq!(tag[48..57], *b"\x02\x00\x06DP-1/2");
+        assert_eq!(tag[57..60], [0, 0, 9]);
+        assert_eq!(tag.len(), 11 + body_size + 4);
+        assert_eq!(tag[60..], (11 + body_size as u32).to_be_bytes());
+    }
+}
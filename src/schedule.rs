@@ -0,0 +1,262 @@
+//! `--schedule` and `--duration`: when the screens are streamed, for displays that should only
+//! show something during office hours or for a while after starting. Outside of that the black
+//! screen is streamed, so whatever reads the output device keeps running.
+
+use crate::i18n;
+use crate::Event;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INTERVAL: Duration = Duration::from_secs(1);
+const DAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// Parses a duration like `2h`, `90m`, `45s` or `1h30m`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || {
+        i18n::tr(
+            "Invalid duration: {}, use for example 2h, 90m or 1h30m",
+            &[&text],
+        )
+    };
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().map_err(|_| invalid())?;
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        seconds = value
+            .checked_mul(unit)
+            .and_then(|value| seconds.checked_add(value))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() || seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Days of the week and a time of day, as in `Mon-Fri 09:00-17:00`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// Indexed by the day of the week, starting on Sunday
+    days: [bool; 7],
+    /// Minutes since midnight
+    start: u32,
+    /// Minutes since midnight, at or before `start` if the window goes past midnight
+    end: u32,
+}
+
+/// The day of the week, from its name or the first three or more letters of it.
+fn parse_day(name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    if name.len() < 3 {
+        return None;
+    }
+    DAYS.iter().position(|day| day.starts_with(&name))
+}
+
+fn parse_time(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    let time = hours.checked_mul(60)?.checked_add(minutes)?;
+    if minutes >= 60 || time > 24 * 60 {
+        return None;
+    }
+    Some(time)
+}
+
+impl Window {
+    /// Parses `[<days>] <from>-<to>`, where the days are names or ranges of names separated by
+    /// commas, like `Mon-Fri` or `Sat,Sun`. Without days, the window is open every day.
+    pub fn parse(text: &str) -> Result<Window, String> {
+        let invalid = || {
+            i18n::tr(
+                "Invalid schedule: {}, use for example \"Mon-Fri 09:00-17:00\"",
+                &[&text],
+            )
+        };
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        let (days, times) = match parts.as_slice() {
+            [times] => (None, *times),
+            [days, times] => (Some(*days), *times),
+            _ => return Err(invalid()),
+        };
+
+        let mut open = [days.is_none(); 7];
+        for range in days.into_iter().flat_map(|days| days.split(',')) {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let first = parse_day(first).ok_or_else(invalid)?;
+            let last = parse_day(last).ok_or_else(invalid)?;
+            // Ranges like Fri-Mon go on over the weekend
+            let mut day = first;
+            loop {
+                open[day] = true;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        Ok(Window {
+            days: open,
+            start: parse_time(start).ok_or_else(invalid)?,
+            end: parse_time(end).ok_or_else(invalid)?,
+        })
+    }
+
+    /// Whether the window is open on `day` at `minute` past midnight.
+    fn contains(&self, day: usize, minute: u32) -> bool {
+        if self.start < self.end {
+            self.days[day] && self.start <= minute && minute < self.end
+        } else {
+            // Goes past midnight, so the early hours belong to the day before
+            (self.days[day] && minute >= self.start)
+                || (self.days[(day + 6) % 7] && minute < self.end)
+        }
+    }
+}
+
+/// The day of the week and the minutes since midnight, in local time.
+fn local_now() -> (usize, u32) {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    (tm.tm_wday as usize, (tm.tm_hour * 60 + tm.tm_min) as u32)
+}
+
+/// When the screens may be streamed.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    /// Any of them, or always if there are none
+    pub windows: Vec<Window>,
+    /// How long after starting
+    pub duration: Option<Duration>,
+}
+
+impl Schedule {
+    pub fn is_set(&self) -> bool {
+        !self.windows.is_empty() || self.duration.is_some()
+    }
+
+    fn open(&self, started: Instant) -> bool {
+        if self
+            .duration
+            .is_some_and(|duration| started.elapsed() >= duration)
+        {
+            return false;
+        }
+        let (day, minute) = local_now();
+        self.windows.is_empty()
+            || self
+                .windows
+                .iter()
+                .any(|window| window.contains(day, minute))
+    }
+}
+
+/// Checks the schedule every second in a background thread, and signals `Event::Scheduled`
+/// whenever it opens or closes. Returns whether it is open right now.
+pub fn watch(schedule: Schedule, events: Sender<Event>) -> bool {
+    let started = Instant::now();
+    let mut was_open = schedule.open(started);
+    let initial = was_open;
+    thread::spawn(move || loop {
+        thread::sleep(INTERVAL);
+        let is_open = schedule.open(started);
+        if is_open != was_open {
+            was_open = is_open;
+            if events.send(Event::Scheduled(is_open)).is_err() {
+                return;
+            }
+        }
+    });
+
+    initial
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 45s "), Ok(Duration::from_secs(45)));
+        for invalid in ["", "0m", "2", "h", "2d", "1h30", "-1h"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+        // Too long to count in seconds
+        assert!(parse_duration("18446744073709551615h").is_err());
+        assert!(parse_duration("5124095576030431h5124095576030431h").is_err());
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window = Window::parse("Mon-Fri 09:00-17:30").unwrap();
+        assert_eq!(window.days, [false, true, true, true, true, true, false]);
+        assert_eq!((window.start, window.end), (9 * 60, 17 * 60 + 30));
+
+        // Over the weekend, and every day without days
+        assert_eq!(
+            Window::parse("fri-monday 22:00-02:00").unwrap().days,
+            [true, true, false, false, false, true, true]
+        );
+        assert_eq!(
+            Window::parse("Sat,Sun 10:00-12:00").unwrap().days,
+            [true, false, false, false, false, false, true]
+        );
+        assert_eq!(Window::parse("00:00-24:00").unwrap().days, [true; 7]);
+
+        for invalid in [
+            "",
+            "Mo 09:00-17:00",
+            "Mon-Fri",
+            "Mon-Fri 09:00",
+            "09:60-17:00",
+            "09:00-24:01",
+            "9-17",
+            "Mon 09:00-17:00 extra",
+            "4294967295:00-17:00",
+        ] {
+            assert!(Window::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn windows_past_midnight_belong_to_the_day_they_start() {
+        let window = Window::parse("Fri 22:00-02:00").unwrap();
+        // Friday night and the early hours of Saturday
+        assert!(window.contains(5, 23 * 60));
+        assert!(window.contains(6, 60));
+        assert!(!window.contains(6, 2 * 60));
+        assert!(!window.contains(5, 60));
+        assert!(!window.contains(5, 21 * 60 + 59));
+
+        let window = Window::parse("09:00-17:00").unwrap();
+        assert!(window.contains(0, 9 * 60));
+        assert!(!window.contains(0, 17 * 60));
+    }
+}
@@ -0,0 +1,50 @@
+//! `wlstreamer ctl screenshot <file>`: saves the frame that is on the output device right now, so
+//! after scaling and everything else, exactly what viewers see.
+
+use crate::i18n;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a frame, the device may not have a producer at the moment.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Writes the current frame of the device to a PNG file.
+pub fn take(ffmpeg_path: &str, device_number: usize, path: &Path) -> Result<(), String> {
+    let mut child = Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-loglevel", "error", "-f", "v4l2", "-i"])
+        .arg(format!("/dev/video{}", device_number))
+        .args([
+            "-frames:v",
+            "1",
+            "-update",
+            "1",
+            "-f",
+            "image2",
+            "-c:v",
+            "png",
+            "-y",
+        ])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| i18n::tr("Could not run {}: {}", &[&ffmpeg_path, &err]))?;
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(_)) => return Err(i18n::tr("ffmpeg could not write {}", &[&path.display()])),
+            Ok(None) if started.elapsed() < TIMEOUT => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(i18n::tr("There was no frame on the output device", &[]));
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+}
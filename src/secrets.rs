@@ -0,0 +1,242 @@
+//! Stream keys and passphrases that shouldn't be written into shell history, service files and
+//! config files kept in git, or show up in logs and `ctl status`, which end up in bug reports
+//! and on stream. Sink URLs, `--sink-passphrase` and `--whip-token` can name a secret as
+//! `${NAME}`, which is looked up in the `--secrets-file`, then the environment, then the keyring
+//! with `secret-tool`. Everything that looks like a key is replaced by `***` wherever wlstreamer
+//! prints a URL, and in what the ffmpeg of sinks prints with `--verbose`.
+//!
+//! ffmpeg only takes the URL to send to on its command line, so the keys are still in the
+//! arguments of the sinks' ffmpeg processes, which other users of the computer can read.
+
+use crate::{i18n, sink, Options};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+
+/// What is replaced in everything printed
+static HIDDEN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// Query parameters of SRT and RIST URLs that carry secrets
+const SECRET_PARAMS: [&str; 3] = ["passphrase", "secret", "streamid"];
+
+/// Reads `NAME=value` lines, skipping empty ones and comments.
+fn read_file(path: &str) -> Result<HashMap<String, String>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| i18n::tr("Could not read the secrets from {}: {}", &[&path, &err]))?;
+    if fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o077 != 0) {
+        println!(
+            "{}",
+            i18n::tr(
+                "{} can be read by other users, make it only yours with chmod 600",
+                &[&path]
+            )
+        );
+    }
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect())
+}
+
+/// Looks the secret up in the keyring, where `secret-tool store --label=<name> service
+/// wlstreamer key <name>` puts it.
+fn keyring(name: &str) -> Option<String> {
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", "wlstreamer", "key", name])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let value = String::from_utf8(output.stdout).ok()?;
+    Some(value.trim_end_matches('\n').to_string()).filter(|value| !value.is_empty())
+}
+
+fn lookup(name: &str, file: &HashMap<String, String>) -> Result<String, String> {
+    file.get(name)
+        .cloned()
+        .or_else(|| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .or_else(|| keyring(name))
+        .ok_or_else(|| {
+            i18n::tr("The secret {} is not in the --secrets-file, the environment or the keyring. Store it with: secret-tool store --label={} service wlstreamer key {}", &[&name, &name, &name])
+        })
+}
+
+/// Replaces every `${NAME}` with the secret.
+fn expand(value: &str, file: &HashMap<String, String>) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| i18n::tr("{} has a ${ without a }", &[&redact(value)]))?;
+        let secret = lookup(&rest[start + 2..start + end], file)?;
+        hide(&secret);
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&secret);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The parts of a sink URL that give access to the stream: the key at the end of RTMP URLs and
+/// the passphrase and stream ID of SRT and RIST ones.
+fn url_secrets(url: &str) -> Vec<&str> {
+    let mut secrets = Vec::new();
+    let (url, query) = url.split_once('?').unwrap_or((url, ""));
+    if let Some(path) = url
+        .strip_prefix("rtmp://")
+        .or_else(|| url.strip_prefix("rtmps://"))
+    {
+        // rtmp://<host>/<app>/<key>
+        let parts: Vec<&str> = path.split('/').collect();
+        if parts.len() > 2 {
+            secrets.extend(parts.last().filter(|key| !key.is_empty()));
+        }
+    }
+    secrets.extend(query.split('&').filter_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        Some(value).filter(|value| SECRET_PARAMS.contains(&name) && !value.is_empty())
+    }));
+    secrets
+}
+
+/// Keeps the value out of everything printed from now on.
+pub fn hide(value: &str) {
+    let mut hidden = HIDDEN.lock().unwrap();
+    if !value.is_empty() && !hidden.iter().any(|known| known == value) {
+        hidden.push(value.to_string());
+        // Longer ones first, so one that contains another is hidden as a whole
+        hidden.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+/// The text with every secret replaced by `***`.
+pub fn redact(text: &str) -> String {
+    HIDDEN
+        .lock()
+        .unwrap()
+        .iter()
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), "***")
+        })
+}
+
+/// Where a process that may print secrets writes its errors: a pipe for `print_stderr` with
+/// `--verbose`, nowhere otherwise.
+pub fn stderr(verbose: bool) -> Stdio {
+    if verbose {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    }
+}
+
+/// Prints what the child writes to its piped stderr in a background thread, with the secrets
+/// replaced.
+pub fn print_stderr(child: &mut Child) {
+    if let Some(stderr) = child.stderr.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{}", redact(&line));
+            }
+        });
+    }
+}
+
+/// Puts the secrets into the sink URLs and transport options, and hides them and the keys written
+/// out in them.
+pub fn resolve(options: &mut Options) -> Result<(), String> {
+    let file = match &options.secrets_file {
+        Some(path) => read_file(path)?,
+        None => HashMap::new(),
+    };
+
+    let profiles = options
+        .profiles
+        .values_mut()
+        .flat_map(|profile| profile.sinks.iter_mut().flatten());
+    for sink in options.sinks.iter_mut().chain(profiles) {
+        *sink = expand(sink, &file)?;
+        url_secrets(sink).into_iter().for_each(hide);
+    }
+    let transport = &mut options.transport;
+    for value in transport
+        .passphrase
+        .iter_mut()
+        .chain(transport.token.iter_mut())
+    {
+        *value = expand(value, &file)?;
+        hide(value);
+    }
+    // SRT and RIST URLs have the passphrase escaped
+    if let Some(passphrase) = &transport.passphrase {
+        hide(&sink::percent_encode(passphrase));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_keys_in_sink_urls() {
+        assert_eq!(
+            url_secrets("rtmp://live.twitch.tv/app/live_123_abc"),
+            ["live_123_abc"]
+        );
+        assert_eq!(
+            url_secrets("rtmps://a.rtmp.youtube.com/live2/abcd-efgh"),
+            ["abcd-efgh"]
+        );
+        // Without a key, the app is no secret
+        assert!(url_secrets("rtmp://localhost/live").is_empty());
+        assert!(url_secrets("rtmp://localhost/live/").is_empty());
+        assert_eq!(
+            url_secrets("srt://encoder:9000?latency=200&passphrase=a%20long%20one&streamid=cam"),
+            ["a%20long%20one", "cam"]
+        );
+        assert_eq!(url_secrets("rist://encoder:9000?secret=x1"), ["x1"]);
+        assert!(url_secrets("recording.mkv").is_empty());
+    }
+
+    #[test]
+    fn expands_secrets_from_the_file() {
+        let file: HashMap<String, String> = vec![
+            ("TEST_EXPAND_KEY".to_string(), "expanded-key-1".to_string()),
+            ("TEST_EXPAND_HOST".to_string(), "ingest".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            expand("rtmp://${TEST_EXPAND_HOST}/app/${TEST_EXPAND_KEY}", &file),
+            Ok("rtmp://ingest/app/expanded-key-1".to_string())
+        );
+        assert_eq!(expand("no secrets", &file), Ok("no secrets".to_string()));
+        assert!(expand("rtmp://host/app/${TEST_EXPAND_KEY", &file).is_err());
+        // What was looked up is hidden from then on
+        assert_eq!(redact("key expanded-key-1 used"), "key *** used");
+    }
+
+    #[test]
+    fn redacts_the_longest_secret_first() {
+        hide("test-redact-secret");
+        hide("test-redact-secret-longer");
+        hide("");
+        assert_eq!(
+            redact("rtmp://host/app/test-redact-secret-longer and test-redact-secret"),
+            "rtmp://host/app/*** and ***"
+        );
+        assert_eq!(redact("nothing to hide"), "nothing to hide");
+    }
+}
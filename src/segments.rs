@@ -0,0 +1,190 @@
+//! `--record-segments`: records a new file every time the stream switches to another screen,
+//! named after when it started and what it shows, for editors that would rather get a pile of
+//! clips than one long file with chapters. A manifest of all segments is kept next to them.
+
+use crate::{children, i18n, replay, sink, timeline, Options};
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+const MANIFEST: &str = "segments.json";
+
+/// A file that was or is being recorded.
+#[derive(Debug)]
+struct Segment {
+    file: PathBuf,
+    /// Milliseconds since the epoch
+    start: u128,
+    end: Option<u128>,
+    /// None for the black screen
+    output: Option<String>,
+    workspace: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Segments {
+    dir: Option<PathBuf>,
+    ffmpeg_path: String,
+    /// Everything ffmpeg is started with before the name of the file
+    args: Vec<String>,
+    verbose: bool,
+    segments: Vec<Segment>,
+    recording: Option<Child>,
+}
+
+impl Segments {
+    /// Prepares recording to `dir`, from the first `mark` on, with the audio delayed by
+    /// `audio_delay` for `--av-offset auto`.
+    pub fn start(
+        dir: &str,
+        options: &Options,
+        render_node: Option<&str>,
+        audio_delay: i64,
+    ) -> Result<Self, String> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)
+            .map_err(|err| i18n::tr("Could not create {}: {}", &[&dir.display(), &err]))?;
+
+        let mut args: Vec<String> = vec!["-hide_banner".into(), "-loglevel".into(), "error".into()];
+        args.extend(options.encoding.input_args(render_node));
+        args.extend(options.color.tag_args().iter().map(|arg| arg.to_string()));
+        args.extend(["-f".into(), "v4l2".into(), "-i".into()]);
+        args.push(format!("/dev/video{}", options.devices_from));
+        args.extend(options.audio.input_args(audio_delay));
+        args.extend(options.encoding.output_args(None));
+        // Files, so with a track for each source
+        args.extend(options.audio.output_args(true, false));
+
+        Ok(Segments {
+            dir: Some(dir),
+            ffmpeg_path: options.ffmpeg_path.clone(),
+            args,
+            verbose: options.verbose,
+            segments: Vec::new(),
+            recording: None,
+        })
+    }
+
+    /// Starts a new file, unless the stream still shows the same screen.
+    pub fn mark(&mut self, output: Option<&str>, workspace: Option<&str>) {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return,
+        };
+        if self.recording.is_some()
+            && self.segments.last().map(|last| last.output.as_deref()) == Some(output)
+        {
+            return;
+        }
+
+        let file = dir.join(format!(
+            "{:03}-{}-{}.mkv",
+            self.segments.len() + 1,
+            replay::date(),
+            output.unwrap_or("black").replace('/', "-")
+        ));
+        let mut command = Command::new(&self.ffmpeg_path);
+        command
+            .args(&self.args)
+            .arg("-y")
+            .arg(&file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(if self.verbose {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            });
+
+        // The next file starts before the last one stops, so nothing falls between them
+        let started = match command.spawn() {
+            Ok(child) => Some(child),
+            Err(err) => {
+                println!(
+                    "{}",
+                    i18n::tr("Could not record {}: {}", &[&file.display(), &err])
+                );
+                None
+            }
+        };
+        let now = timeline::now();
+        self.finish(now, false);
+        if started.is_some() {
+            println!(
+                "{}",
+                i18n::tr("Recording a segment to {}", &[&file.display()])
+            );
+            self.recording = started;
+            self.segments.push(Segment {
+                file,
+                start: now,
+                end: None,
+                output: output.map(String::from),
+                workspace: workspace.map(String::from),
+            });
+        }
+        self.write();
+    }
+
+    /// Stops the file being recorded, waiting for ffmpeg to finish it when `wait` is set.
+    fn finish(&mut self, end: u128, wait: bool) {
+        if let Some(last) = self.segments.last_mut() {
+            last.end.get_or_insert(end);
+        }
+        if let Some(mut child) = self.recording.take() {
+            // ffmpeg writes the trailer of the file on SIGINT
+            if wait {
+                let _ = children::stop(&mut child, libc::SIGINT, sink::STOP_TIMEOUT);
+            } else {
+                thread::spawn(move || {
+                    let _ = children::stop(&mut child, libc::SIGINT, sink::STOP_TIMEOUT);
+                });
+            }
+        }
+    }
+
+    /// Stops recording and writes the final manifest.
+    pub fn stop(&mut self) {
+        if self.dir.is_none() {
+            return;
+        }
+        self.finish(timeline::now(), true);
+        self.write();
+    }
+
+    /// Rewrites the manifest, with the segment being recorded ending now.
+    fn write(&self) {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return,
+        };
+        let now = timeline::now();
+        let segments: Vec<_> = self
+            .segments
+            .iter()
+            .map(|segment| {
+                let end = segment.end.unwrap_or(now);
+                json!({
+                    "file": segment.file.file_name().map(|name| name.to_string_lossy()),
+                    "start": segment.start as f64 / 1000.0,
+                    "end": end as f64 / 1000.0,
+                    "seconds": end.saturating_sub(segment.start) as f64 / 1000.0,
+                    "recording": segment.end.is_none(),
+                    "output": segment.output,
+                    "workspace": segment.workspace,
+                })
+            })
+            .collect();
+
+        let path = dir.join(MANIFEST);
+        let manifest = json!({ "segments": segments });
+        if let Err(err) = fs::write(&path, format!("{:#}\n", manifest)) {
+            println!(
+                "{}",
+                i18n::tr("Could not write {}: {}", &[&path.display(), &err])
+            );
+        }
+    }
+}
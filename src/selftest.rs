@@ -0,0 +1,130 @@
+//! `--selftest`: starts the pipeline for the focused screen the same way follow mode does, reads a
+//! few frames back from the output device like a video call app would, and checks that they have
+//! the combined resolution and show something other than a black screen. Exits with 0 if they
+//! do, for scripts that run before a meeting.
+
+use crate::pipeline::Pipeline;
+use crate::{i18n, switch, update_windows, wanted_targets, Config};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const FRAMES: usize = 5;
+/// How long the frames may take, including the pipeline starting up
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Luma above which a pixel counts as not black, with some room for limited range and noise
+const BLACK_LEVEL: u8 = 32;
+
+/// Reads `FRAMES` frames from the device as 8 bit luma, failing if they don't arrive in time.
+fn read_frames(config: &Config, device_number: usize) -> Result<Vec<u8>, String> {
+    let device = format!("/dev/video{}", device_number);
+    let mut child = Command::new(&config.options.ffmpeg_path)
+        .args([
+            "-loglevel",
+            "error",
+            "-f",
+            "v4l2",
+            "-input_format",
+            "yuyv422",
+        ])
+        .args(["-i", device.as_str()])
+        .args(["-frames:v", FRAMES.to_string().as_str()])
+        .args(["-pix_fmt", "gray", "-f", "rawvideo", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| i18n::tr("Could not run ffmpeg: {}", &[&err]))?;
+
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut frames = Vec::new();
+        let _ = stdout.read_to_end(&mut frames);
+        let _ = sender.send(frames);
+    });
+
+    let frames = receiver.recv_timeout(TIMEOUT);
+    let _ = child.kill();
+    let output = child
+        .wait_with_output()
+        .map_err(|err| i18n::tr("Could not run ffmpeg: {}", &[&err]))?;
+    match frames {
+        Ok(frames) if !frames.is_empty() => Ok(frames),
+        Ok(_) => Err(i18n::tr(
+            "Could not read from {}: {}",
+            &[&device, &String::from_utf8_lossy(&output.stderr).trim()],
+        )),
+        Err(_) => Err(i18n::tr(
+            "No frames arrived on {} within {} seconds",
+            &[&device, &TIMEOUT.as_secs()],
+        )),
+    }
+}
+
+/// Checks the frames read back, returning what is wrong with them.
+fn check(config: &Config, frames: &[u8]) -> Result<(), String> {
+    let resolution = config.runtime.resolutions[0];
+    let frame_size = resolution.width * resolution.height;
+    if frames.len() != frame_size * FRAMES {
+        return Err(i18n::tr(
+            "Got {} bytes instead of {} frames of {}x{}, the output device has another format than the pipeline writes",
+            &[&frames.len(), &FRAMES, &resolution.width, &resolution.height]
+        ));
+    }
+
+    // A dark desktop still has some text on it
+    let last = &frames[frame_size * (FRAMES - 1)..];
+    let lit = last.iter().filter(|luma| **luma > BLACK_LEVEL).count();
+    if lit * 1000 < frame_size {
+        return Err(i18n::tr("The output device only shows a black screen", &[]));
+    }
+
+    Ok(())
+}
+
+/// Runs the self-test and prints the result, then exits.
+pub fn run(config: &mut Config) -> ! {
+    update_windows(config);
+    let mut pipeline = Pipeline::default();
+    let targets = wanted_targets(config);
+    let result = switch(config, &mut pipeline, targets)
+        .map_err(|err| i18n::tr("Could not start the pipeline: {}", &[&err]))
+        .and_then(|()| match config.runtime.streamer.output() {
+            "" => Err(i18n::tr(
+                "There is no screen to stream, a black screen is shown instead",
+                &[],
+            )),
+            _ => Ok(()),
+        })
+        .and_then(|()| read_frames(config, config.options.devices_from))
+        .and_then(|frames| check(config, &frames));
+    if let Err(err) = pipeline.stop() {
+        println!(
+            "{}",
+            i18n::tr("Could not stop recorder: {}", &[&format!("{:?}", err)])
+        );
+    }
+
+    match result {
+        Ok(()) => {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Self-test passed, /dev/video{} shows {}",
+                    &[
+                        &config.options.devices_from,
+                        &config.runtime.streamer.output()
+                    ]
+                )
+            );
+            std::process::exit(0);
+        }
+        Err(err) => {
+            println!("{}", i18n::tr("Self-test failed: {}", &[&err]));
+            std::process::exit(1);
+        }
+    }
+}
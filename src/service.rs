@@ -0,0 +1,203 @@
+//! Running wlstreamer in the background: `wlstreamer install-service` sets it up as a systemd user
+//! service, and `--daemonize` detaches it from the terminal it was started in.
+
+use crate::{control, i18n, runtime_dir};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+/// The variables wlstreamer needs to find the compositor, which systemd user services don't get
+/// unless they are imported into the service manager.
+const SESSION_VARIABLES: [&str; 7] = [
+    "WAYLAND_DISPLAY",
+    "SWAYSOCK",
+    "I3SOCK",
+    "NIRI_SOCKET",
+    "DISPLAY",
+    "XDG_CURRENT_DESKTOP",
+    "XDG_SESSION_TYPE",
+];
+
+/// `$XDG_CONFIG_HOME/systemd/user/wlstreamer.service`, named after the instance with --instance.
+fn unit_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(
+        config_home
+            .join("systemd/user")
+            .join(format!("{}.service", control::instance_name())),
+    )
+}
+
+/// Quotes an argument for ExecStart=, which also expands % specifiers and $ variables.
+fn quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// The unit running wlstreamer with `args`, with the graphical session.
+fn unit(exe: &str, args: &[String]) -> String {
+    let exec_start = std::iter::once(exe)
+        .chain(args.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        "[Unit]
+Description=Virtual camera following the focused screen
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=notify
+ExecStart={}
+Restart=on-failure
+RestartSec=5
+WatchdogSec=30
+
+[Install]
+WantedBy=graphical-session.target
+",
+        exec_start
+    )
+}
+
+fn systemctl(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()
+        .map_err(|err| i18n::tr("Could not run systemctl: {}", &[&err]))?;
+
+    if !output.status.success() {
+        return Err(i18n::tr(
+            "systemctl --user {} failed: {}",
+            &[
+                &args.join(" "),
+                &String::from_utf8_lossy(&output.stderr).trim(),
+            ],
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writes the unit, passes the session's environment on to the service manager and starts the
+/// service, then exits.
+pub fn install(args: &[String]) -> ! {
+    let result = (|| {
+        let path = unit_path().ok_or("Could not find the config dir, $HOME is not set")?;
+        let exe = env::current_exe()
+            .map_err(|err| i18n::tr("Could not find the wlstreamer binary: {}", &[&err]))?;
+        fs::create_dir_all(path.parent().unwrap())
+            .map_err(|err| i18n::tr("Could not create {}: {}", &[&path.display(), &err]))?;
+        fs::write(&path, unit(exe.to_string_lossy().as_ref(), args))
+            .map_err(|err| i18n::tr("Could not write {}: {}", &[&path.display(), &err]))?;
+        println!("{}", i18n::tr("Wrote {}", &[&path.display()]));
+
+        let name = control::instance_name();
+        let variables: Vec<&str> = SESSION_VARIABLES
+            .iter()
+            .copied()
+            .filter(|name| env::var_os(name).is_some())
+            .collect();
+        if variables.is_empty() {
+            return Err(
+                i18n::tr("No graphical session found, run wlstreamer install-service from a terminal in sway, niri or i3", &[]),
+            );
+        }
+        let mut import = vec!["import-environment"];
+        import.extend(&variables);
+        systemctl(&import)?;
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", format!("{}.service", name).as_str()])?;
+        println!(
+            "{}",
+            i18n::tr(
+                "Started {}.service, follow it with journalctl --user -u {} -f",
+                &[&name, &name]
+            )
+        );
+
+        println!();
+        println!(
+            "{}",
+            i18n::tr(
+                "It starts with graphical-session.target, which sway doesn't reach on its own. Unless your session is started through systemd, add this to the sway config instead:",
+                &[]
+            )
+        );
+        println!();
+        println!(
+            "  exec \"systemctl --user import-environment {}; systemctl --user start {}\"",
+            variables.join(" "),
+            name
+        );
+
+        Ok::<(), String>(())
+    })();
+
+    match result {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// `wlstreamer.log` in the runtime dir, where a daemonized wlstreamer writes its output.
+fn log_path() -> Result<PathBuf, String> {
+    runtime_dir::join(&format!("{}.log", control::instance_name()))
+}
+
+/// Continues in a child process that is detached from the terminal, with its output going to the
+/// log, and exits. Has to happen before any thread is started, only the calling one survives.
+pub fn daemonize() -> Result<(), String> {
+    let path = log_path()?;
+    let log = runtime_dir::open(&path, OpenOptions::new().create(true).append(true))?;
+    let null = OpenOptions::new()
+        .read(true)
+        .open("/dev/null")
+        .map_err(|err| i18n::tr("Could not open /dev/null: {}", &[&err]))?;
+
+    match unsafe { libc::fork() } {
+        pid if pid < 0 => Err(i18n::tr(
+            "Could not fork: {}",
+            &[&std::io::Error::last_os_error()],
+        )),
+        0 => {
+            unsafe {
+                libc::setsid();
+                libc::dup2(null.as_raw_fd(), 0);
+                libc::dup2(log.as_raw_fd(), 1);
+                libc::dup2(log.as_raw_fd(), 2);
+            }
+            Ok(())
+        }
+        pid => {
+            println!(
+                "{}",
+                i18n::tr(
+                    "Running in the background as process {}, logging to {}",
+                    &[&pid, &path.display()]
+                )
+            );
+            process::exit(0);
+        }
+    }
+}
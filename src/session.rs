@@ -0,0 +1,202 @@
+//! Finding the Wayland session when wlstreamer is started without its environment, for example
+//! from a systemd unit that didn't import it or from cron. The displays in the runtime dir are
+//! matched to their compositor through the process listening on them, which also tells which
+//! IPC socket belongs to which display.
+
+use crate::i18n;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug)]
+struct Session {
+    /// The Wayland display, like wayland-1
+    display: String,
+    /// The compositor, if it is one with an IPC socket
+    compositor: Option<&'static str>,
+    /// The variables pointing to the session
+    variables: Vec<(&'static str, String)>,
+}
+
+impl Session {
+    fn describe(&self) -> String {
+        match self.compositor {
+            Some(compositor) => format!("{} ({})", self.display, compositor),
+            None => self.display.clone(),
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR`, or where it usually is when it isn't set.
+fn runtime_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// The inodes of the listening unix sockets bound to paths in `dir`, by path.
+fn socket_inodes(dir: &Path) -> HashMap<PathBuf, String> {
+    let table = fs::read_to_string("/proc/net/unix").unwrap_or_default();
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // Num RefCount Protocol Flags Type St Inode Path
+            let path = PathBuf::from(fields.get(7)?);
+            if path.parent() == Some(dir) {
+                Some((path, fields[6].to_string()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The process that has the socket with this inode open, among the ones we may look into.
+fn socket_owner(inode: &str) -> Option<u32> {
+    let link = format!("socket:[{}]", inode);
+    fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+        fs::read_dir(entry.path().join("fd"))
+            .ok()?
+            .flatten()
+            .any(|fd| {
+                fs::read_link(fd.path()).is_ok_and(|target| target.as_os_str() == link.as_str())
+            })
+            .then_some(pid)
+    })
+}
+
+/// The IPC socket of the compositor with this pid, named sway-ipc.<uid>.<pid>.sock by sway and
+/// niri.<display>.<pid>.sock by niri. Hyprland keeps its sockets in hypr/<signature>, next to a
+/// lock file starting with its pid; it is followed through wlr-foreign-toplevel-management, but
+/// hyprctl needs the signature.
+fn compositor_socket(dir: &Path, pid: u32) -> Option<(&'static str, &'static str, String)> {
+    let hyprland = fs::read_dir(dir.join("hypr")).ok().and_then(|entries| {
+        entries.flatten().find_map(|entry| {
+            let lock = fs::read_to_string(entry.path().join("hyprland.lock")).ok()?;
+            if lock.lines().next()?.trim().parse::<u32>().ok()? != pid {
+                return None;
+            }
+            let signature = entry.file_name().to_str()?.to_string();
+            Some(("hyprland", "HYPRLAND_INSTANCE_SIGNATURE", signature))
+        })
+    });
+    if hyprland.is_some() {
+        return hyprland;
+    }
+
+    let suffix = format!(".{}.sock", pid);
+    fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let name = entry.file_name().to_str()?.to_string();
+        if !name.ends_with(suffix.as_str()) {
+            return None;
+        }
+        let path = entry.path().to_string_lossy().to_string();
+        if name.starts_with("sway-ipc.") {
+            Some(("sway", "SWAYSOCK", path))
+        } else if name.starts_with("niri.") {
+            Some(("niri", "NIRI_SOCKET", path))
+        } else {
+            None
+        }
+    })
+}
+
+fn discover(dir: &Path) -> Vec<Session> {
+    let inodes = socket_inodes(dir);
+    let mut sessions: Vec<Session> = inodes
+        .iter()
+        .filter_map(|(path, inode)| {
+            let display = path.file_name()?.to_str()?;
+            if !display.starts_with("wayland-") || display.ends_with(".lock") {
+                return None;
+            }
+
+            let mut session = Session {
+                display: display.to_string(),
+                compositor: None,
+                variables: vec![("WAYLAND_DISPLAY", display.to_string())],
+            };
+            if let Some((compositor, variable, socket)) =
+                socket_owner(inode).and_then(|pid| compositor_socket(dir, pid))
+            {
+                session.compositor = Some(compositor);
+                session.variables.push((variable, socket));
+            }
+            Some(session)
+        })
+        .collect();
+    sessions.sort_by(|a, b| a.display.cmp(&b.display));
+    sessions
+}
+
+/// Points the environment to the session named `requested`, by its display or compositor, or to
+/// the only one there is. Does nothing if wlstreamer already runs in a session and none was
+/// requested.
+pub fn apply(requested: Option<&str>) -> Result<(), String> {
+    let in_session = [
+        "WAYLAND_DISPLAY",
+        "SWAYSOCK",
+        "NIRI_SOCKET",
+        "I3SOCK",
+        "DISPLAY",
+    ]
+    .iter()
+    .any(|name| env::var_os(name).is_some());
+    if in_session && requested.is_none() {
+        return Ok(());
+    }
+
+    let dir = runtime_dir();
+    let mut sessions = discover(&dir);
+    if let Some(requested) = requested {
+        sessions.retain(|session| {
+            session.display == requested || session.compositor == Some(requested)
+        });
+    }
+
+    let session = match sessions.as_slice() {
+        [session] => session,
+        [] => {
+            return Err(match requested {
+                Some(requested) => {
+                    i18n::tr("No session {} found in {}", &[&requested, &dir.display()])
+                }
+                None => i18n::tr(
+                    "Not running in a graphical session, and no Wayland session found in {}",
+                    &[&dir.display()],
+                ),
+            })
+        }
+        _ => {
+            return Err(i18n::tr(
+                "Found several sessions, pick one with --session: {}",
+                &[&sessions
+                    .iter()
+                    .map(Session::describe)
+                    .collect::<Vec<_>>()
+                    .join(", ")],
+            ))
+        }
+    };
+
+    println!(
+        "{}",
+        i18n::tr("Using the session on {}", &[&session.describe()])
+    );
+    if env::var_os("XDG_RUNTIME_DIR").is_none() {
+        env::set_var("XDG_RUNTIME_DIR", &dir);
+    }
+    for name in ["SWAYSOCK", "NIRI_SOCKET", "I3SOCK", "DISPLAY"] {
+        env::remove_var(name);
+    }
+    for (name, value) in session.variables.iter() {
+        env::set_var(name, value);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use crate::control::Command;
+use crate::i18n;
+use crate::Event;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// Turns signals into events. Termination signals let wlstreamer stop its recorders and undo its
+/// changes to the session before exiting, SIGUSR1 and SIGUSR2 blank and resume the stream the
+/// same as `wlstreamer ctl blank` and `wlstreamer ctl resume`.
+pub fn listen(events: Sender<Event>) -> Result<(), String> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1, SIGUSR2])
+        .map_err(|err| i18n::tr("Could not handle signals: {}", &[&err]))?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            // Nobody is waiting for an answer to a signal
+            let (reply, _) = mpsc::channel();
+            let event = match signal {
+                SIGUSR1 => Event::Control(Command::Blank(None), reply),
+                SIGUSR2 => Event::Control(Command::Resume, reply),
+                _ => Event::Quit,
+            };
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
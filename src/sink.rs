@@ -0,0 +1,804 @@
+//! `--sink`: encodes the stream on the output device and sends it somewhere else as well, like an
+//! RTMP server or a file. Every sink is an ffmpeg process reading from the output device, so it
+//! keeps running while wlstreamer switches between screens.
+//!
+//! SRT and RIST sinks are sent as MPEG-TS, for low latency feeds to encoders elsewhere. WHIP
+//! sinks, with URLs like `whip+https://…`, use ffmpeg's WHIP muxer to push WebRTC. NDI sinks,
+//! `ndi://<name>`, aren't encoded at all, see `ndi`.
+//!
+//! Sinks read the canvas at the combined resolution, unless their URL ends with a size like
+//! `@1920x1080`, which they are scaled to on their own. v4l2loopback devices, `/dev/video<n>`,
+//! are sinks too, which gives a second virtual camera at another size than the output device.
+//!
+//! RTMP sinks carry the scene as a text track, see `scene`.
+
+use crate::bitrate::{self, Adaptive, Monitor};
+use crate::{children, i18n, loopback, scene, secrets, stats, Options, Resolution};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before restarting a sink that stopped, for example because the output device
+/// had no frames yet. Files go on in a new part instead of being overwritten, see `part_path`.
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+/// How long a sink gets to finish its file or stream when wlstreamer stops, before it is killed.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Codec {
+    H264,
+    Hevc,
+    Av1,
+}
+
+/// Where the encoding happens.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Hardware {
+    Software,
+    Vaapi,
+    Nvenc,
+    Qsv,
+}
+
+/// How sinks encode the stream.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Encoding {
+    pub codec: Codec,
+    pub hardware: Hardware,
+    /// Target bitrate in ffmpeg's notation, like 6M
+    pub bitrate: Option<String>,
+    /// How far network sinks may lower the bitrate when the network can't keep up
+    pub min_bitrate: Option<String>,
+    pub preset: Option<String>,
+    /// Constant quality instead of a bitrate, lower is better
+    pub crf: Option<u32>,
+    /// Constant quality that files get instead of the bitrate, from `--quality`
+    pub file_crf: Option<u32>,
+}
+
+impl Default for Encoding {
+    fn default() -> Encoding {
+        Encoding {
+            codec: Codec::H264,
+            hardware: Hardware::Software,
+            bitrate: None,
+            min_bitrate: None,
+            preset: None,
+            crf: None,
+            file_crf: None,
+        }
+    }
+}
+
+impl Encoding {
+    /// Parses `--codec` values like `h264`, `hevc-vaapi` or `av1-nvenc`, keeping the other
+    /// options as they are.
+    pub fn parse_codec(&mut self, name: &str) -> Option<()> {
+        let (codec, hardware) = match name.split_once('-') {
+            Some((codec, hardware)) => (codec, hardware),
+            None => (name, "software"),
+        };
+
+        self.codec = match codec {
+            "h264" => Codec::H264,
+            "hevc" => Codec::Hevc,
+            "av1" => Codec::Av1,
+            _ => return None,
+        };
+        self.hardware = match hardware {
+            "software" => Hardware::Software,
+            "vaapi" => Hardware::Vaapi,
+            "nvenc" => Hardware::Nvenc,
+            "qsv" => Hardware::Qsv,
+            _ => return None,
+        };
+        Some(())
+    }
+
+    /// The ffmpeg encoder for the codec and hardware.
+    pub fn encoder(&self) -> &'static str {
+        match (self.codec, self.hardware) {
+            (Codec::H264, Hardware::Software) => "libx264",
+            (Codec::H264, Hardware::Vaapi) => "h264_vaapi",
+            (Codec::H264, Hardware::Nvenc) => "h264_nvenc",
+            (Codec::H264, Hardware::Qsv) => "h264_qsv",
+            (Codec::Hevc, Hardware::Software) => "libx265",
+            (Codec::Hevc, Hardware::Vaapi) => "hevc_vaapi",
+            (Codec::Hevc, Hardware::Nvenc) => "hevc_nvenc",
+            (Codec::Hevc, Hardware::Qsv) => "hevc_qsv",
+            (Codec::Av1, Hardware::Software) => "libsvtav1",
+            (Codec::Av1, Hardware::Vaapi) => "av1_vaapi",
+            (Codec::Av1, Hardware::Nvenc) => "av1_nvenc",
+            (Codec::Av1, Hardware::Qsv) => "av1_qsv",
+        }
+    }
+
+    /// The encoding for a sink: files get a constant quality if `--quality` picked one.
+    fn for_sink(&self, url: &str) -> Encoding {
+        match self.file_crf {
+            Some(crf) if is_file(url) => Encoding {
+                bitrate: None,
+                crf: Some(crf),
+                ..self.clone()
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// ffmpeg options placed before the input, setting up the hardware encoder.
+    pub fn input_args(&self, render_node: Option<&str>) -> Vec<String> {
+        match (self.hardware, render_node) {
+            (Hardware::Vaapi, Some(render_node)) => {
+                vec!["-vaapi_device".to_string(), render_node.to_string()]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// ffmpeg output options for encoding the video, after applying `filter` to it.
+    pub fn output_args(&self, filter: Option<String>) -> Vec<String> {
+        let mut args = Vec::new();
+        // Hardware encoders want frames in nv12, VAAPI ones in GPU memory
+        let mut filters: Vec<String> = filter.into_iter().collect();
+        match self.hardware {
+            Hardware::Software => args.extend(["-pix_fmt", "yuv420p"].map(String::from)),
+            Hardware::Vaapi => filters.push("format=nv12,hwupload".to_string()),
+            Hardware::Nvenc | Hardware::Qsv => args.extend(["-pix_fmt", "nv12"].map(String::from)),
+        }
+        if !filters.is_empty() {
+            args.extend(["-vf".to_string(), filters.join(",")]);
+        }
+        args.extend(["-c:v".to_string(), self.encoder().to_string()]);
+
+        if let Some(bitrate) = &self.bitrate {
+            args.extend(["-b:v".to_string(), bitrate.clone()]);
+            args.extend(["-maxrate".to_string(), bitrate.clone()]);
+            args.extend(["-bufsize".to_string(), bitrate.clone()]);
+        }
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+        if let Some(crf) = self.crf {
+            let quality = match self.hardware {
+                Hardware::Software => "-crf",
+                Hardware::Vaapi | Hardware::Qsv => "-global_quality",
+                Hardware::Nvenc => "-cq",
+            };
+            args.extend([quality.to_string(), crf.to_string()]);
+        }
+        // Keyframes every two seconds, so viewers can join quickly
+        args.extend(["-force_key_frames", "expr:gte(t,n_forced*2)"].map(String::from));
+
+        args
+    }
+}
+
+/// Options for SRT and RIST sinks.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Transport {
+    /// Encrypts the stream, the receiver needs the same one
+    pub passphrase: Option<String>,
+    /// How long the receiver waits for lost packets to be sent again, in milliseconds
+    pub latency: Option<u32>,
+    /// Bearer token for WHIP endpoints
+    pub token: Option<String>,
+}
+
+/// Escapes everything but letters, digits and `-._~` for the query of a URL, so a passphrase with
+/// `&`, `#` or spaces in it stays one parameter.
+pub fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The name of the NDI source for `ndi://<name>` sinks.
+fn ndi_name(url: &str) -> Option<&str> {
+    url.strip_prefix("ndi://").map(|name| match name {
+        "" => "wlstreamer",
+        name => name,
+    })
+}
+
+/// Splits the size a sink is scaled to off the end of its URL, as in `recording.mkv@1920x1080`.
+pub fn split_size(url: &str) -> (&str, Option<Resolution>) {
+    let size = url.rsplit_once('@').and_then(|(rest, size)| {
+        let (width, height) = size.split_once('x')?;
+        let resolution = Resolution {
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        };
+        Some((rest, resolution))
+    });
+    match size {
+        Some((rest, resolution)) => (rest, Some(resolution)),
+        None => (url, None),
+    }
+}
+
+/// Whether the sink is sent over the network as a live stream, where the bitrate can adapt.
+fn is_network(url: &str) -> bool {
+    ["rtmp://", "rtmps://", "srt://", "rist://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+/// The number of the v4l2loopback device for `/dev/video<n>` sinks.
+fn loopback_number(url: &str) -> Option<usize> {
+    url.strip_prefix("/dev/video")?.parse().ok()
+}
+
+/// The v4l2loopback devices among the sinks.
+pub fn loopback_devices(sinks: &[String]) -> Vec<usize> {
+    sinks
+        .iter()
+        .filter_map(|sink| loopback_number(split_size(sink).0))
+        .collect()
+}
+
+/// Whether the sink is a file to keep, as opposed to a stream, a live playlist or a device.
+pub fn is_file(url: &str) -> bool {
+    !url.contains("://")
+        && !url.ends_with(".m3u8")
+        && !url.ends_with(".mpd")
+        && loopback_number(url).is_none()
+}
+
+/// Whether the sink is an RTMP server, which gets the scene.
+fn is_rtmp(url: &str) -> bool {
+    url.starts_with("rtmp://") || url.starts_with("rtmps://")
+}
+
+/// Whether the sink is a WHIP endpoint.
+fn is_whip(url: &str) -> bool {
+    url.starts_with("whip+http://") || url.starts_with("whip+https://")
+}
+
+impl Transport {
+    /// Checks the options against what SRT accepts.
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.passphrase {
+            Some(passphrase) if passphrase.len() < 10 || passphrase.len() > 79 => Err(i18n::tr(
+                "--sink-passphrase must be between 10 and 79 characters long",
+                &[],
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// ffmpeg output options for the sink.
+    fn output_args(&self, url: &str) -> Vec<String> {
+        let mut args: Vec<String> = format_args(url).into_iter().map(String::from).collect();
+        if is_whip(url) {
+            // WebRTC has no B-frames
+            args.extend(["-bf", "0"].map(String::from));
+            if let Some(token) = &self.token {
+                args.extend(["-authorization".to_string(), token.clone()]);
+            }
+        }
+        args
+    }
+
+    /// Adds the options to SRT and RIST URLs, unless the URL sets them itself, and turns WHIP
+    /// URLs into the HTTP ones ffmpeg expects.
+    pub fn url(&self, url: &str) -> String {
+        if is_whip(url) {
+            return url.trim_start_matches("whip+").to_string();
+        }
+
+        let mut params = Vec::new();
+        let has = |name: &str| {
+            url.split_once('?').is_some_and(|(_, query)| {
+                query
+                    .split('&')
+                    .any(|param| param.split('=').next() == Some(name))
+            })
+        };
+
+        if url.starts_with("srt://") {
+            if let Some(passphrase) = self.passphrase.as_ref().filter(|_| !has("passphrase")) {
+                params.push(format!("passphrase={}", percent_encode(passphrase)));
+            }
+            // In microseconds for SRT
+            if let Some(latency) = self.latency.filter(|_| !has("latency")) {
+                params.push(format!("latency={}", latency as u64 * 1000));
+            }
+        } else if url.starts_with("rist://") {
+            if let Some(secret) = self.passphrase.as_ref().filter(|_| !has("secret")) {
+                params.push(format!("secret={}", percent_encode(secret)));
+                if !has("encryption") {
+                    params.push("encryption=128".to_string());
+                }
+            }
+            if let Some(latency) = self.latency.filter(|_| !has("buffer_size")) {
+                params.push(format!("buffer_size={}", latency));
+            }
+        }
+
+        if params.is_empty() {
+            url.to_string()
+        } else if url.contains('?') {
+            format!("{}&{}", url, params.join("&"))
+        } else {
+            format!("{}?{}", url, params.join("&"))
+        }
+    }
+}
+
+/// The container for a sink, if ffmpeg can't guess it from the URL or file name, and the options
+/// for segmented ones. HLS and DASH playlists only keep the last few segments, like a live stream.
+pub fn format_args(url: &str) -> Vec<&'static str> {
+    if url.starts_with("rtmp://") || url.starts_with("rtmps://") {
+        vec!["-f", "flv"]
+    } else if url.starts_with("srt://") || url.starts_with("rist://") {
+        vec!["-f", "mpegts"]
+    } else if is_whip(url) {
+        vec!["-f", "whip"]
+    } else if url.ends_with(".m3u8") {
+        vec![
+            "-f",
+            "hls",
+            "-hls_time",
+            "2",
+            "-hls_list_size",
+            "6",
+            "-hls_flags",
+            "delete_segments+independent_segments+omit_endlist",
+        ]
+    } else if url.ends_with(".mpd") {
+        vec![
+            "-f",
+            "dash",
+            "-seg_duration",
+            "2",
+            "-window_size",
+            "6",
+            "-remove_at_exit",
+            "1",
+        ]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Checks that ffmpeg was built with the encoder.
+pub fn validate_encoder(ffmpeg_path: &str, encoding: &Encoding) -> Result<(), String> {
+    let output = Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .map_err(|err| i18n::tr("Could not run {}: {}", &[&ffmpeg_path, &err]))?;
+    let encoders = String::from_utf8_lossy(&output.stdout);
+
+    let encoder = encoding.encoder();
+    if !encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(encoder))
+    {
+        return Err(i18n::tr(
+            "{} does not support the {} encoder, pick another --codec",
+            &[&ffmpeg_path, &encoder],
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that everything the sinks need is there: the encoder, the WHIP muxer for WHIP sinks,
+/// the NDI runtime for NDI sinks and the devices of device sinks.
+pub fn validate(ffmpeg_path: &str, encoding: &Encoding, sinks: &[String]) -> Result<(), String> {
+    let mut urls = Vec::new();
+    for sink in sinks {
+        let (url, size) = split_size(sink);
+        if size.is_some_and(|size| size.width == 0 || size.height == 0 || size.width % 2 == 1) {
+            return Err(i18n::tr(
+                "{} can't be scaled to an empty size or an odd width",
+                &[&url],
+            ));
+        }
+        if let Some(device_number) = loopback_number(url) {
+            loopback::validate(device_number, size)?;
+        }
+        urls.push(url);
+    }
+    if let Some(min_bitrate) = &encoding.min_bitrate {
+        Adaptive::new(min_bitrate, encoding.bitrate.as_deref(), false)?;
+    }
+    let needs_encoder = urls
+        .iter()
+        .any(|url| ndi_name(url).is_none() && loopback_number(url).is_none());
+
+    if urls.iter().any(|url| ndi_name(url).is_some()) {
+        #[cfg(feature = "ndi")]
+        crate::ndi::validate()?;
+        #[cfg(not(feature = "ndi"))]
+        return Err(i18n::tr(
+            "wlstreamer was built without NDI support, build it with --features ndi",
+            &[],
+        ));
+    }
+    if needs_encoder {
+        validate_encoder(ffmpeg_path, encoding)?;
+    }
+
+    if urls.iter().any(|url| is_whip(url)) {
+        if encoding.codec != Codec::H264 {
+            return Err(i18n::tr("WHIP sinks only support --codec h264", &[]));
+        }
+        let output = Command::new(ffmpeg_path)
+            .args(["-hide_banner", "-muxers"])
+            .output()
+            .map_err(|err| i18n::tr("Could not run {}: {}", &[&ffmpeg_path, &err]))?;
+        if !String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some("whip"))
+        {
+            return Err(i18n::tr(
+                "{} can't send WHIP, it needs ffmpeg 8 or newer",
+                &[&ffmpeg_path],
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// `recording.mkv` with the number of a part, as in `recording.001.mkv`.
+fn part_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{:03}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}.{:03}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+/// The command for the next part of a file sink that stopped, writing to the first part that
+/// doesn't exist yet and refusing to overwrite anything, so what was recorded before is kept.
+fn next_part(command: &Command, path: &Path, verbose: bool) -> (Command, PathBuf) {
+    let part = (1..)
+        .map(|number| part_path(path, number))
+        .find(|part| !part.exists())
+        .unwrap_or_else(|| path.to_path_buf());
+
+    let mut args: Vec<_> = command.get_args().map(|arg| arg.to_os_string()).collect();
+    args.pop();
+    let mut rebuilt = Command::new(command.get_program());
+    rebuilt
+        .args(
+            args.into_iter()
+                .map(|arg| if arg == "-y" { "-n".into() } else { arg }),
+        )
+        .arg(&part)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(secrets::stderr(verbose));
+    (rebuilt, part)
+}
+
+/// The running sinks.
+#[derive(Default, Debug)]
+pub struct Sinks {
+    children: Vec<Arc<Mutex<Option<Child>>>>,
+    /// Set by `stop`, and held while a sink is started so none starts after it
+    stopped: Arc<Mutex<bool>>,
+    verbose: bool,
+}
+
+impl Sinks {
+    /// Starts an ffmpeg process for every sink in `urls`, each restarted whenever it stops until
+    /// `stop`. `audio_delay` is what `--av-offset auto` came to.
+    pub fn start(
+        options: &Options,
+        urls: &[String],
+        render_node: Option<&str>,
+        audio_delay: i64,
+    ) -> Sinks {
+        let mut sinks = Sinks {
+            verbose: options.verbose,
+            ..Sinks::default()
+        };
+        let ffmpeg_path = options.ffmpeg_path.as_str();
+        let device_number = options.devices_from;
+        let encoding = &options.encoding;
+        let transport = &options.transport;
+        let adaptive = encoding.min_bitrate.as_ref().and_then(|min_bitrate| {
+            Adaptive::new(min_bitrate, encoding.bitrate.as_deref(), options.verbose).ok()
+        });
+
+        for sink in urls {
+            let (url, size) = split_size(sink);
+            // Scaled from the canvas here, so the pipeline before it keeps the full resolution
+            let scale = size.map(|size| {
+                format!(
+                    "scale={}:{}:force_original_aspect_ratio=decrease{},pad={}:{}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+                    size.width,
+                    size.height,
+                    options.color.rescale_options(),
+                    size.width,
+                    size.height
+                )
+            });
+            let mut command = Command::new(ffmpeg_path);
+            command.args(["-hide_banner", "-loglevel", "error"]);
+            if ndi_name(url).is_some() {
+                command
+                    .args(options.color.tag_args())
+                    .args(["-f", "v4l2", "-i"])
+                    .arg(format!("/dev/video{}", device_number))
+                    .args(
+                        scale
+                            .map(|scale| ["-vf".to_string(), scale])
+                            .into_iter()
+                            .flatten(),
+                    )
+                    .args(["-pix_fmt", "yuv420p", "-f", "yuv4mpegpipe", "-"])
+                    .stdout(Stdio::piped());
+            } else if loopback_number(url).is_some() {
+                command
+                    .args(options.color.tag_args())
+                    .args(["-f", "v4l2", "-i"])
+                    .arg(format!("/dev/video{}", device_number))
+                    .args(
+                        scale
+                            .map(|scale| ["-vf".to_string(), scale])
+                            .into_iter()
+                            .flatten(),
+                    )
+                    .args(["-pix_fmt", "yuyv422"])
+                    .args(options.color.tag_args())
+                    .args(["-f", "v4l2", url])
+                    .stdout(Stdio::null());
+            } else {
+                if adaptive.is_some() && is_network(url) {
+                    command.args(stats::PROGRESS_ARGS).stdout(Stdio::piped());
+                } else {
+                    command.stdout(Stdio::null());
+                }
+                command
+                    .args(encoding.input_args(render_node))
+                    .args(options.color.tag_args())
+                    .args(["-f", "v4l2", "-i"])
+                    .arg(format!("/dev/video{}", device_number))
+                    .args(options.audio.input_args(audio_delay));
+                if is_rtmp(url) {
+                    command.args(scene::INPUT_ARGS);
+                }
+                command
+                    .args(encoding.for_sink(url).output_args(scale))
+                    .args(options.audio.output_args(is_file(url), is_whip(url)));
+                if is_rtmp(url) {
+                    let audio = options.audio.input_count();
+                    command.args(scene::output_args(audio + 1, audio > 0));
+                }
+                command
+                    .args(transport.output_args(url))
+                    .arg("-y")
+                    .arg(transport.url(url));
+            }
+            command
+                .stdin(Stdio::null())
+                .stderr(secrets::stderr(options.verbose));
+            if is_rtmp(url) {
+                command.stdin(Stdio::piped());
+            }
+
+            // Created once, so receivers keep the source while ffmpeg restarts
+            #[cfg(feature = "ndi")]
+            let sender = match ndi_name(url).map(crate::ndi::Sender::create) {
+                Some(Ok(sender)) => Some(sender),
+                Some(Err(err)) => {
+                    println!(
+                        "{}",
+                        i18n::tr("Could not send the stream to {}: {}", &[&url, &err])
+                    );
+                    continue;
+                }
+                None => None,
+            };
+
+            sinks.supervise(
+                secrets::redact(url),
+                command,
+                adaptive.clone().filter(|_| is_network(url)),
+                Some(PathBuf::from(url)).filter(|_| is_file(url)),
+                is_rtmp(url),
+                #[cfg(feature = "ndi")]
+                sender,
+            );
+        }
+
+        sinks
+    }
+
+    /// Runs a command in a background thread, restarting it whenever it stops until `stop`, and
+    /// with another bitrate whenever `adaptive` asks for one. Commands writing to a `file` are
+    /// restarted into a new part of it. Commands reading the `scene` from stdin get it written
+    /// there.
+    pub fn supervise(
+        &mut self,
+        name: String,
+        mut command: Command,
+        mut adaptive: Option<Adaptive>,
+        file: Option<PathBuf>,
+        scene: bool,
+        #[cfg(feature = "ndi")] sender: Option<crate::ndi::Sender>,
+    ) {
+        let child = Arc::new(Mutex::new(None));
+        self.children.push(child.clone());
+        let stopped = self.stopped.clone();
+        let verbose = self.verbose;
+
+        thread::spawn(move || loop {
+            let mut monitor = None;
+            // Started while holding the lock stop takes, so it either kills this one or none starts
+            let stop = stopped.lock().unwrap();
+            if *stop {
+                return;
+            }
+            match command.spawn() {
+                #[allow(unused_mut)]
+                Ok(mut spawned) => {
+                    println!("{}", i18n::tr("Sending the stream to {}", &[&name]));
+                    secrets::print_stderr(&mut spawned);
+                    if let Some(stdin) = spawned.stdin.take() {
+                        scene::follow(stdin);
+                    }
+                    if adaptive.is_some() {
+                        monitor = Some(Monitor::follow(&mut spawned));
+                    }
+                    #[cfg(feature = "ndi")]
+                    let stdout = spawned.stdout.take();
+                    *child.lock().unwrap() = Some(spawned);
+                    drop(stop);
+
+                    #[cfg(feature = "ndi")]
+                    if let (Some(sender), Some(stdout)) = (&sender, stdout) {
+                        if let Err(err) = sender.forward(stdout) {
+                            println!(
+                                "{}",
+                                i18n::tr("Could not send the stream to {}: {}", &[&name, &err])
+                            );
+                        }
+                    }
+                }
+                Err(err) => {
+                    println!(
+                        "{}",
+                        i18n::tr("Could not send the stream to {}: {}", &[&name, &err])
+                    );
+                    return;
+                }
+            }
+
+            // Polled, so the child stays available for stop to kill it
+            let mut bitrate = None;
+            loop {
+                thread::sleep(Duration::from_millis(500));
+                let mut child = child.lock().unwrap();
+                match child.as_mut().map(|child| child.try_wait()) {
+                    Some(Ok(None)) => {}
+                    _ => break,
+                }
+
+                if let (Some(adaptive), Some(monitor)) = (&mut adaptive, &monitor) {
+                    bitrate = adaptive.next(monitor);
+                    if let (Some(_), Some(child)) = (bitrate, child.as_mut()) {
+                        let _ = children::stop(child, libc::SIGINT, children::KILL_TIMEOUT);
+                        break;
+                    }
+                }
+            }
+
+            if *stopped.lock().unwrap() {
+                return;
+            }
+            match (bitrate, &adaptive) {
+                (Some(bitrate), Some(adaptive)) => {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "Sending to {} at {} now",
+                            &[&name, &bitrate::format(bitrate)]
+                        )
+                    );
+                    command = adaptive.command(&command);
+                    if scene {
+                        command.stdin(Stdio::piped());
+                    }
+                }
+                _ => {
+                    println!(
+                        "{}",
+                        i18n::tr("Sending to {} stopped, restarting it", &[&name])
+                    );
+                    if let Some(file) = &file {
+                        let (next, part) = next_part(&command, file, verbose);
+                        println!("{}", i18n::tr("Going on in {}", &[&part.display()]));
+                        command = next;
+                    }
+                    thread::sleep(RESTART_DELAY);
+                }
+            }
+        });
+    }
+
+    /// Stops all sinks, letting ffmpeg finish the files it writes.
+    pub fn stop(&mut self) {
+        *self.stopped.lock().unwrap() = true;
+        for child in self.children.iter() {
+            if let Some(child) = child.lock().unwrap().as_mut() {
+                // ffmpeg writes the trailer of the file on SIGINT
+                let _ = children::stop(child, libc::SIGINT, STOP_TIMEOUT);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_options_go_into_the_url() {
+        let transport = Transport {
+            passphrase: Some("pass word&latency=1#x%".to_string()),
+            latency: Some(200),
+            token: None,
+        };
+        assert_eq!(
+            transport.url("srt://encoder:9000"),
+            "srt://encoder:9000?passphrase=pass%20word%26latency%3D1%23x%25&latency=200000"
+        );
+        assert_eq!(
+            transport.url("rist://encoder:9000?cname=cam"),
+            "rist://encoder:9000?cname=cam&secret=pass%20word%26latency%3D1%23x%25&encryption=128&buffer_size=200"
+        );
+        // What the URL sets itself wins
+        assert_eq!(
+            transport.url("srt://encoder:9000?passphrase=fromurl1234&latency=5"),
+            "srt://encoder:9000?passphrase=fromurl1234&latency=5"
+        );
+        assert_eq!(
+            transport.url("whip+https://example.com/whip"),
+            "https://example.com/whip"
+        );
+        assert_eq!(
+            transport.url("rtmp://example.com/live/key"),
+            "rtmp://example.com/live/key"
+        );
+        assert_eq!(
+            Transport::default().url("srt://encoder:9000"),
+            "srt://encoder:9000"
+        );
+    }
+
+    #[test]
+    fn restarted_files_go_on_in_numbered_parts() {
+        assert_eq!(
+            part_path(Path::new("/videos/talk.mkv"), 1),
+            PathBuf::from("/videos/talk.001.mkv")
+        );
+        assert_eq!(part_path(Path::new("talk"), 12), PathBuf::from("talk.012"));
+
+        let command = {
+            let mut command = Command::new("ffmpeg");
+            command.args(["-i", "/dev/video0", "-y", "/nonexistent/talk.mkv"]);
+            command
+        };
+        let (next, part) = next_part(&command, Path::new("/nonexistent/talk.mkv"), false);
+        assert_eq!(part, PathBuf::from("/nonexistent/talk.001.mkv"));
+        let args: Vec<_> = next.get_args().collect();
+        assert_eq!(
+            args,
+            ["-i", "/dev/video0", "-n", "/nonexistent/talk.001.mkv"]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! What `--mode follow` is streaming, and the transitions between that. Every change of the
+//! pipeline goes through `Transitioning`: it is entered before the old pipeline is stopped, and
+//! left once the new one runs, or failed to start.
+
+use std::mem;
+
+/// What should be streamed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// Recording the output with this name
+    Output(String),
+    /// A black screen, with this text on it
+    Black(Option<String>),
+    /// A black screen counting down the seconds until going live
+    Countdown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum StreamerState {
+    /// Nothing was started yet
+    #[default]
+    Idle,
+    /// Recording the output
+    Streaming { output: String },
+    /// Streaming a black screen, with this text on it
+    Blanked { text: Option<String> },
+    /// Counting down before going live
+    CountingDown,
+    /// The pipeline is being replaced by one streaming `to`
+    Transitioning { to: Target },
+    /// The pipeline streaming `to` could not be started
+    Failed { to: Target, reason: String },
+}
+
+impl StreamerState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamerState::Idle => "idle",
+            StreamerState::Streaming { .. } => "streaming",
+            StreamerState::Blanked { .. } => "blanked",
+            StreamerState::CountingDown => "countdown",
+            StreamerState::Transitioning { .. } => "transitioning",
+            StreamerState::Failed { .. } => "failed",
+        }
+    }
+
+    /// The output being recorded, empty if there is none.
+    pub fn output(&self) -> &str {
+        match self {
+            StreamerState::Streaming { output } => output.as_str(),
+            _ => "",
+        }
+    }
+
+    /// What is streamed, or was last meant to be.
+    pub fn target(&self) -> Option<Target> {
+        match self {
+            StreamerState::Idle => None,
+            StreamerState::Streaming { output } => Some(Target::Output(output.clone())),
+            StreamerState::Blanked { text } => Some(Target::Black(text.clone())),
+            StreamerState::CountingDown => Some(Target::Countdown),
+            StreamerState::Transitioning { to } | StreamerState::Failed { to, .. } => {
+                Some(to.clone())
+            }
+        }
+    }
+
+    /// Whether the pipeline has to be replaced to stream `target`. Anything but a running
+    /// pipeline always has to be.
+    pub fn needs_switch(&self, target: &Target) -> bool {
+        match (self, target) {
+            (StreamerState::Streaming { output }, Target::Output(wanted)) => output != wanted,
+            (StreamerState::Blanked { text }, Target::Black(wanted)) => text != wanted,
+            (StreamerState::CountingDown, Target::Countdown) => false,
+            _ => true,
+        }
+    }
+
+    /// Starts replacing the pipeline by one streaming `target`.
+    pub fn begin(&mut self, target: Target) {
+        if let StreamerState::Transitioning { to } = self {
+            panic!(
+                "Switching to {:?} while still switching to {:?}",
+                target, to
+            );
+        }
+        *self = StreamerState::Transitioning { to: target };
+    }
+
+    /// The new pipeline is running.
+    pub fn finish(&mut self) {
+        *self = match mem::take(self) {
+            StreamerState::Transitioning {
+                to: Target::Output(output),
+            } => StreamerState::Streaming { output },
+            StreamerState::Transitioning {
+                to: Target::Black(text),
+            } => StreamerState::Blanked { text },
+            StreamerState::Transitioning {
+                to: Target::Countdown,
+            } => StreamerState::CountingDown,
+            state => panic!("Finished switching while {:?}", state),
+        };
+    }
+
+    /// The new pipeline could not be started.
+    pub fn fail(&mut self, reason: String) {
+        *self = match mem::take(self) {
+            StreamerState::Transitioning { to } => StreamerState::Failed { to, reason },
+            state => panic!("Failed switching while {:?}", state),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str) -> Target {
+        Target::Output(name.to_string())
+    }
+
+    #[test]
+    fn starts_idle_and_always_switches() {
+        let state = StreamerState::default();
+        assert_eq!(state, StreamerState::Idle);
+        assert_eq!(state.output(), "");
+        assert!(state.needs_switch(&output("DP-1")));
+        assert!(state.needs_switch(&Target::Black(None)));
+    }
+
+    #[test]
+    fn streams_after_switching_to_an_output() {
+        let mut state = StreamerState::Idle;
+        state.begin(output("DP-1"));
+        assert_eq!(state.name(), "transitioning");
+        assert_eq!(state.output(), "");
+
+        state.finish();
+        assert_eq!(
+            state,
+            StreamerState::Streaming {
+                output: "DP-1".to_string()
+            }
+        );
+        assert_eq!(state.output(), "DP-1");
+        assert!(!state.needs_switch(&output("DP-1")));
+        assert!(state.needs_switch(&output("DP-2")));
+        assert!(state.needs_switch(&Target::Black(None)));
+    }
+
+    #[test]
+    fn blanked_switches_when_the_text_changes() {
+        let mut state = StreamerState::Streaming {
+            output: "DP-1".to_string(),
+        };
+        state.begin(Target::Black(Some("Workspace 3 (hidden)".to_string())));
+        state.finish();
+        assert_eq!(state.name(), "blanked");
+        assert_eq!(state.output(), "");
+        assert!(!state.needs_switch(&Target::Black(Some("Workspace 3 (hidden)".to_string()))));
+        assert!(state.needs_switch(&Target::Black(Some("Workspace 4 (hidden)".to_string()))));
+        assert!(state.needs_switch(&Target::Black(None)));
+        assert!(state.needs_switch(&output("DP-1")));
+    }
+
+    #[test]
+    fn failed_keeps_the_target_and_retries() {
+        let mut state = StreamerState::Idle;
+        state.begin(output("DP-1"));
+        state.fail("No such device".to_string());
+        assert_eq!(
+            state,
+            StreamerState::Failed {
+                to: output("DP-1"),
+                reason: "No such device".to_string()
+            }
+        );
+        assert_eq!(state.output(), "");
+        assert!(state.needs_switch(&output("DP-1")));
+
+        state.begin(output("DP-1"));
+        state.finish();
+        assert_eq!(state.output(), "DP-1");
+    }
+
+    #[test]
+    #[should_panic]
+    fn cannot_begin_while_transitioning() {
+        let mut state = StreamerState::Idle;
+        state.begin(output("DP-1"));
+        state.begin(output("DP-2"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn cannot_finish_without_transitioning() {
+        let mut state = StreamerState::Idle;
+        state.finish();
+    }
+
+    #[test]
+    #[should_panic]
+    fn cannot_fail_without_transitioning() {
+        let mut state = StreamerState::Blanked { text: None };
+        state.fail("No such device".to_string());
+    }
+}
@@ -0,0 +1,138 @@
+//! Frame statistics for the output device, read from the progress reports of the ffmpeg process
+//! writing to it. Pipelines where wf-recorder writes to the output device directly don't report
+//! any, only their uptime is known.
+
+use crate::i18n;
+use std::io::{BufRead, BufReader};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Makes ffmpeg print its progress to stdout, instead of the human readable stats on stderr.
+pub const PROGRESS_ARGS: [&str; 3] = ["-nostats", "-progress", "pipe:1"];
+
+#[derive(Debug)]
+struct Counters {
+    tracked: bool,
+    frames: u64,
+    dropped: u64,
+    fps: f64,
+    started: Instant,
+    last_frame: Instant,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stats {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            counters: Arc::new(Mutex::new(Counters {
+                tracked: false,
+                frames: 0,
+                dropped: 0,
+                fps: 0.0,
+                started: Instant::now(),
+                last_frame: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl Stats {
+    /// Starts counting from zero, for a new pipeline.
+    pub fn restart(&self) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.tracked = false;
+        counters.frames = 0;
+        counters.dropped = 0;
+        counters.fps = 0.0;
+        counters.started = Instant::now();
+        counters.last_frame = Instant::now();
+    }
+
+    /// Reads the progress of an ffmpeg process started with `PROGRESS_ARGS` and a piped stdout.
+    pub fn follow(&self, child: &mut Child) {
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return,
+        };
+        self.counters.lock().unwrap().tracked = true;
+
+        let counters = self.counters.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                let (key, value) = match line.split_once('=') {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+
+                let mut counters = counters.lock().unwrap();
+                match key {
+                    "frame" => {
+                        let frames = value.trim().parse().unwrap_or(counters.frames);
+                        if frames > counters.frames {
+                            counters.last_frame = Instant::now();
+                        }
+                        counters.frames = frames;
+                    }
+                    "drop_frames" => {
+                        counters.dropped = value.trim().parse().unwrap_or(counters.dropped)
+                    }
+                    "fps" => counters.fps = value.trim().parse().unwrap_or(counters.fps),
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    /// Whether no frame was written for `timeout`. Always false if the pipeline doesn't report
+    /// its frames.
+    pub fn stalled(&self, timeout: Duration) -> bool {
+        let counters = self.counters.lock().unwrap();
+        counters.tracked && counters.last_frame.elapsed() > timeout
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.counters.lock().unwrap().started.elapsed()
+    }
+
+    /// Frames per second, frames written and frames dropped, if the pipeline reports them.
+    pub fn frames(&self) -> Option<(f64, u64, u64)> {
+        let counters = self.counters.lock().unwrap();
+        if counters.tracked {
+            Some((counters.fps, counters.frames, counters.dropped))
+        } else {
+            None
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let uptime = self.uptime().as_secs();
+        let uptime = format!(
+            "up {}:{:02}:{:02}",
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60
+        );
+
+        match self.frames() {
+            Some((fps, frames, dropped)) => format!(
+                "{:.1} fps, {} frames, {} dropped, {}",
+                fps, frames, dropped, uptime
+            ),
+            None => format!("no frame statistics for this pipeline, {}", uptime),
+        }
+    }
+}
+
+/// Logs the statistics every `interval` on a background thread.
+pub fn log_every(stats: Stats, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        println!("{}", i18n::tr("Output device: {}", &[&stats.summary()]));
+    });
+}
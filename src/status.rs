@@ -0,0 +1,176 @@
+//! What `wlstreamer ctl status` reports about the running instance.
+
+use crate::{get_outputs, i18n, secrets, Config, Resolution};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// The devices resolutions are recorded into in follow mode, ordered by device.
+fn resolution_devices(config: &Config) -> Vec<(Resolution, usize)> {
+    let mut devices: Vec<(Resolution, usize)> = config
+        .runtime
+        .outputs
+        .iter()
+        .map(|(resolution, device_number)| (*resolution, *device_number))
+        .collect();
+    devices.sort_by_key(|(_, device_number)| *device_number);
+    devices
+}
+
+/// Prints which resolution ends up on which device, for setting up the applications reading
+/// from them.
+pub fn print_devices(config: &Config) {
+    for (resolution, device_number) in resolution_devices(config) {
+        let message = if device_number == config.options.devices_from {
+            "{}x{} on /dev/video{} (output)"
+        } else {
+            "{}x{} on /dev/video{}"
+        };
+        println!(
+            "{}",
+            i18n::tr(
+                message,
+                &[&resolution.width, &resolution.height, &device_number]
+            )
+        );
+    }
+}
+
+pub fn report(config: &Config, as_json: bool) -> String {
+    let devices = resolution_devices(config);
+    let sinks: Vec<String> = config
+        .sinks()
+        .iter()
+        .map(|sink| secrets::redact(sink))
+        .collect();
+
+    if as_json {
+        let devices: Vec<_> = devices
+            .iter()
+            .map(|(resolution, device_number)| {
+                json!({
+                    "width": resolution.width,
+                    "height": resolution.height,
+                    "device": format!("/dev/video{}", device_number),
+                    "output": *device_number == config.options.devices_from,
+                })
+            })
+            .collect();
+        let screens: Vec<_> = config
+            .runtime
+            .mirrored
+            .iter()
+            .map(|screen| (screen, false))
+            .chain(config.runtime.standby.iter().map(|screen| (screen, true)))
+            .map(|((name, device_number, resolution), standby)| {
+                json!({
+                    "name": name,
+                    "width": resolution.width,
+                    "height": resolution.height,
+                    "device": format!("/dev/video{}", device_number),
+                    "standby": standby,
+                })
+            })
+            .collect();
+
+        let outputs: Vec<_> = get_outputs(config)
+            .iter()
+            .map(|output| {
+                let resolution = output.resolution();
+                json!({
+                    "name": output.name,
+                    "width": resolution.width,
+                    "height": resolution.height,
+                    "live": output.name == config.runtime.streamer.output()
+                        || config.runtime.mirrored.iter().any(|(name, ..)| *name == output.name),
+                    "locked": config.runtime.locked.contains(&output.name),
+                })
+            })
+            .collect();
+
+        return json!({
+            "mode": config.options.mode.name(),
+            "state": config.runtime.streamer.name(),
+            "recording": config.runtime.streamer.output(),
+            "blanked": config.blanked(),
+            "paused": config.runtime.paused,
+            "resume_in": config
+                .runtime
+                .blank_until
+                .map(|until| until.saturating_duration_since(Instant::now()).as_secs()),
+            "unwatched": config.runtime.unwatched,
+            "off_schedule": config.runtime.off_schedule,
+            "outputs": outputs,
+            "devices": devices,
+            "screens": screens,
+            "uptime": config.runtime.stats.uptime().as_secs(),
+            "fps": config.runtime.stats.frames().map(|(fps, _, _)| fps),
+            "frames": config.runtime.stats.frames().map(|(_, frames, _)| frames),
+            "dropped": config.runtime.stats.frames().map(|(_, _, dropped)| dropped),
+            "unhealthy": config
+                .runtime
+                .health
+                .unhealthy_outputs()
+                .iter()
+                .map(|(name, backoff)| json!({ "name": name, "retry_in": backoff.as_secs() }))
+                .collect::<Vec<_>>(),
+            "stalled": config.options.stall_timeout > 0
+                && config.runtime.stats.stalled(Duration::from_secs(config.options.stall_timeout)),
+            "sinks": sinks,
+            "events": config.runtime.event_log.recent(),
+        })
+        .to_string();
+    }
+
+    let mut lines = vec![format!("mode: {}", config.options.mode.name())];
+    if config.runtime.unwatched && !config.runtime.paused && !config.runtime.idle {
+        lines.push("waiting for someone to watch the output device".to_string());
+    } else if let Some(until) = config.runtime.blank_until {
+        lines.push(format!(
+            "blanked, resuming in {}s",
+            until.saturating_duration_since(Instant::now()).as_secs()
+        ));
+    } else if config.blanked() {
+        lines.push("blanked".to_string());
+    } else if let Some(until) = config.runtime.countdown {
+        lines.push(format!(
+            "going live in {}s",
+            until.saturating_duration_since(Instant::now()).as_secs()
+        ));
+    } else if !config.runtime.streamer.output().is_empty() {
+        lines.push(format!("recording: {}", config.runtime.streamer.output()));
+    }
+    for (name, backoff) in config.runtime.health.unhealthy_outputs() {
+        lines.push(format!(
+            "{} keeps failing, trying it again in {}s",
+            name,
+            backoff.as_secs()
+        ));
+    }
+    lines.push(format!("output device: {}", config.runtime.stats.summary()));
+    lines.push("devices:".to_string());
+    for (resolution, device_number) in devices {
+        lines.push(format!(
+            "  {}x{} /dev/video{}{}",
+            resolution.width,
+            resolution.height,
+            device_number,
+            if device_number == config.options.devices_from {
+                " (output)"
+            } else {
+                ""
+            }
+        ));
+    }
+    for (name, device_number, _) in config.runtime.mirrored.iter() {
+        lines.push(format!("  {} /dev/video{}", name, device_number));
+    }
+    for (name, device_number, _) in config.runtime.standby.iter() {
+        lines.push(format!("  {} /dev/video{} (standby)", name, device_number));
+    }
+    if !sinks.is_empty() {
+        lines.push("sinks:".to_string());
+        lines.extend(sinks.iter().map(|sink| format!("  {}", sink)));
+    }
+
+    lines.join("\n")
+}
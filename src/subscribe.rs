@@ -0,0 +1,78 @@
+//! The events printed by `swaymsg -t subscribe`, `i3-msg -t subscribe` and `niri msg --json
+//! event-stream`, one JSON object per line. Most of them, like title changes, marks or keyboard
+//! layout switches, can't change what is streamed, and looking at the outputs and workspaces
+//! again for each of them costs several IPC round trips.
+
+use crate::backend::Backend;
+use serde_json::Value;
+
+/// What an event may have changed, as far as wlstreamer is concerned.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Change {
+    /// Focus, outputs or workspaces, which decide what is streamed
+    Focus,
+    /// Windows were opened, closed, renamed or asked for attention, which only matters for the
+    /// options looking at windows, like --call-app and --redact
+    Windows,
+    /// Nothing wlstreamer looks at
+    Irrelevant,
+}
+
+/// Window events on sway and i3 have the container, workspace events the current workspace,
+/// and output events only `change`.
+fn sway_change(event: &Value) -> Change {
+    let change = event["change"].as_str().unwrap_or_default();
+
+    if event.get("container").is_some() {
+        match change {
+            "focus" | "move" | "fullscreen_mode" => Change::Focus,
+            "new" | "close" | "title" | "urgent" => Change::Windows,
+            _ => Change::Irrelevant,
+        }
+    } else if event.get("current").is_some() {
+        match change {
+            "urgent" => Change::Irrelevant,
+            _ => Change::Focus,
+        }
+    } else {
+        Change::Focus
+    }
+}
+
+/// niri events are objects with the name of the event as their only key.
+fn niri_change(event: &Value) -> Change {
+    let name = match event.as_object().and_then(|event| event.keys().next()) {
+        Some(name) => name.as_str(),
+        None => return Change::Focus,
+    };
+
+    match name {
+        "WindowOpenedOrChanged" | "WindowClosed" | "WindowsChanged" | "WindowUrgencyChanged" => {
+            Change::Windows
+        }
+        "KeyboardLayoutsChanged"
+        | "KeyboardLayoutSwitched"
+        | "OverviewOpenedOrClosed"
+        | "ConfigLoaded"
+        | "WorkspaceUrgencyChanged"
+        | "WindowLayoutsChanged"
+        | "ScreenshotCaptured" => Change::Irrelevant,
+        // Focus and workspace changes, and whatever newer versions of niri add
+        _ => Change::Focus,
+    }
+}
+
+/// Reads the event on a line. Lines that can't be read come out as null, which is taken as a
+/// change of everything, so nothing is missed.
+pub fn parse(line: &str) -> Value {
+    serde_json::from_str(line).unwrap_or(Value::Null)
+}
+
+/// What the event may have changed.
+pub fn change(backend: Backend, event: &Value) -> Change {
+    match backend {
+        Backend::Sway | Backend::I3 => sway_change(event),
+        Backend::Niri => niri_change(event),
+        Backend::ForeignToplevel => unreachable!("foreign-toplevel is followed without IPC"),
+    }
+}
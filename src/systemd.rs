@@ -0,0 +1,71 @@
+//! Service readiness and watchdog notifications for running under systemd with Type=notify.
+//! Everything in here does nothing when wlstreamer wasn't started by systemd.
+
+use crate::i18n;
+use crate::Event;
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Sends a state string like `READY=1` to the service manager through $NOTIFY_SOCKET.
+pub fn notify(state: &str) {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return,
+    };
+
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let result = address.and_then(|address| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &address)
+    });
+
+    if let Err(err) = result {
+        println!("{}", i18n::tr("Could not notify systemd: {}", &[&err]));
+    }
+}
+
+/// Marks the service as started, once the first pipeline is running.
+pub fn ready(status: &str) {
+    notify(format!("READY=1\nSTATUS={}", status).as_str());
+}
+
+pub fn status(status: &str) {
+    notify(format!("STATUS={}", status).as_str());
+}
+
+/// Half the watchdog timeout from WatchdogSec=, if the watchdog is meant for this process.
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Waits for the next event, keeping the watchdog fed in the meantime. Returns None once all
+/// event sources are gone.
+pub fn next_event(events: &Receiver<Event>) -> Option<Event> {
+    let interval = match watchdog_interval() {
+        Some(interval) => interval,
+        None => return events.recv().ok(),
+    };
+
+    loop {
+        notify("WATCHDOG=1");
+        match events.recv_timeout(interval) {
+            Ok(event) => return Some(event),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! `--timeline`: what was live when, from start to exit, for going through a stream afterwards or
+//! keeping a record of what was shown. Every switch starts a new entry, and the whole timeline is
+//! written once wlstreamer exits, as CSV if the file ends in `.csv` and as JSON otherwise.
+
+use crate::i18n;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A stretch of time in which the same thing was streamed.
+#[derive(Clone, Debug)]
+struct Entry {
+    /// Milliseconds since the epoch
+    start: u128,
+    /// None for the black screen
+    output: Option<String>,
+    workspace: Option<String>,
+    /// Why the black screen was shown
+    reason: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Timeline {
+    path: Option<PathBuf>,
+    started: u128,
+    entries: Vec<Entry>,
+}
+
+/// Milliseconds since the epoch.
+pub fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0)
+}
+
+/// Quotes a CSV field if it has to be.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl Timeline {
+    /// Starts a timeline to be written to `path`, from now.
+    pub fn start(path: &str) -> Timeline {
+        Timeline {
+            path: Some(PathBuf::from(path)),
+            started: now(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Starts an entry, unless the same thing is still being streamed.
+    pub fn mark(&mut self, output: Option<&str>, workspace: Option<&str>, reason: Option<&str>) {
+        if self.path.is_none() {
+            return;
+        }
+        if let Some(last) = self.entries.last() {
+            if last.output.as_deref() == output
+                && last.workspace.as_deref() == workspace
+                && last.reason.as_deref() == reason
+            {
+                return;
+            }
+        }
+
+        self.entries.push(Entry {
+            start: now(),
+            output: output.map(String::from),
+            workspace: workspace.map(String::from),
+            reason: reason.map(String::from),
+        });
+    }
+
+    /// The entries with their end, the last one ending at `end`, as JSON objects.
+    fn rows(&self, end: u128) -> Vec<Value> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let until = self.entries.get(i + 1).map_or(end, |next| next.start);
+                json!({
+                    "start": entry.start as f64 / 1000.0,
+                    "end": until as f64 / 1000.0,
+                    "seconds": until.saturating_sub(entry.start) as f64 / 1000.0,
+                    "live": if entry.output.is_some() { "screen" } else { "black" },
+                    "output": entry.output,
+                    "workspace": entry.workspace,
+                    "reason": entry.reason,
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the timeline, ending now.
+    pub fn write(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let end = now();
+        let rows = self.rows(end);
+
+        let contents = if path.extension().is_some_and(|extension| extension == "csv") {
+            let mut csv = "start,end,seconds,live,output,workspace,reason\n".to_string();
+            for row in rows.iter() {
+                let fields: Vec<String> = [
+                    "start",
+                    "end",
+                    "seconds",
+                    "live",
+                    "output",
+                    "workspace",
+                    "reason",
+                ]
+                .iter()
+                .map(|key| match &row[*key] {
+                    Value::String(value) => csv_field(value),
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                })
+                .collect();
+                csv.push_str(&fields.join(","));
+                csv.push('\n');
+            }
+            csv
+        } else {
+            let timeline = json!({
+                "started": self.started as f64 / 1000.0,
+                "stopped": end as f64 / 1000.0,
+                "entries": rows,
+            });
+            format!("{:#}\n", timeline)
+        };
+
+        match fs::write(path, contents) {
+            Ok(()) => println!(
+                "{}",
+                i18n::tr("Wrote the timeline to {}", &[&path.display()])
+            ),
+            Err(err) => println!(
+                "{}",
+                i18n::tr(
+                    "Could not write the timeline to {}: {}",
+                    &[&path.display(), &err]
+                )
+            ),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::i18n;
+use std::env;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Finds the file that would be run for `program`, looking it up in `$PATH` unless it already
+/// is a path.
+fn find_executable(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(PathBuf::from(program));
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Checks that `program` exists and is executable.
+pub fn validate_executable(program: &str) -> Result<(), String> {
+    let path = match find_executable(program) {
+        Some(path) => path,
+        None => return Err(i18n::tr("Could not find {} in $PATH", &[&program])),
+    };
+
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            return Err(i18n::tr(
+                "Could not access {}: {}",
+                &[&path.display(), &err],
+            ))
+        }
+    };
+
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(i18n::tr("{} is not an executable file", &[&path.display()]));
+    }
+
+    Ok(())
+}
+
+/// Checks that `path` is a unix socket, as used for compositor IPC.
+pub fn validate_socket(path: &str) -> Result<(), String> {
+    match fs::metadata(Path::new(path)) {
+        Ok(metadata) if metadata.file_type().is_socket() => Ok(()),
+        Ok(_) => Err(i18n::tr("{} is not a socket", &[&path])),
+        Err(err) => Err(i18n::tr("Could not access {}: {}", &[&path, &err])),
+    }
+}
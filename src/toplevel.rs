@@ -0,0 +1,365 @@
+//! Follows focus through the wlr-foreign-toplevel-management protocol, for wlroots compositors
+//! without an IPC we know how to talk to. The focused output is the one the activated toplevel
+//! is shown on.
+
+use crate::windows::Window;
+use crate::{i18n, Event, SwayOutput, SwayOutputMode, SwayScreenRect, SwayWorkspace};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_output, wl_registry};
+use wayland_client::{event_created_child, Connection, Dispatch, Proxy, QueueHandle, WEnum};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1 as toplevel_handle,
+    zwlr_foreign_toplevel_manager_v1 as toplevel_manager,
+};
+
+const STATE_MINIMIZED: u32 = 1;
+const STATE_ACTIVATED: u32 = 2;
+
+#[derive(Clone, Default, Debug)]
+struct Output {
+    global_name: u32,
+    name: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    refresh: i32,
+    transform: Option<String>,
+    scale: i32,
+}
+
+#[derive(Default, Debug)]
+struct Snapshot {
+    outputs: Vec<Output>,
+    focused: Option<String>,
+    windows: Vec<Window>,
+}
+
+/// The latest known outputs and focus, kept up to date from a background thread.
+#[derive(Clone, Default, Debug)]
+pub struct Tracker {
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl Tracker {
+    pub fn outputs(&self) -> Vec<SwayOutput> {
+        let snapshot = self.snapshot.lock().unwrap();
+        snapshot
+            .outputs
+            .iter()
+            .map(|o| SwayOutput {
+                name: o.name.clone(),
+                active: o.width > 0 && o.height > 0,
+                rect: SwayScreenRect {
+                    x: o.x as i64,
+                    y: o.y as i64,
+                    width: o.width as usize,
+                    height: o.height as usize,
+                },
+                current_mode: Some(SwayOutputMode {
+                    width: o.width as usize,
+                    height: o.height as usize,
+                    refresh: o.refresh as usize,
+                }),
+                power: None,
+                dpms: None,
+                transform: o.transform.clone(),
+                scale: Some(o.scale.max(1) as f64),
+                hdr: None,
+                scaled_to: None,
+            })
+            .collect()
+    }
+
+    pub fn windows(&self) -> Vec<Window> {
+        self.snapshot.lock().unwrap().windows.clone()
+    }
+
+    /// There are no workspaces in this protocol, so every output is presented as a single
+    /// visible workspace numbered after its position in the output list.
+    pub fn workspaces(&self) -> Vec<SwayWorkspace> {
+        let snapshot = self.snapshot.lock().unwrap();
+        snapshot
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, o)| SwayWorkspace {
+                name: o.name.clone(),
+                focus: Vec::new(),
+                output: o.name.clone(),
+                focused: snapshot.focused.as_ref() == Some(&o.name),
+                rect: SwayScreenRect::default(),
+                visible: true,
+                num: Some(i + 1),
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Toplevel {
+    activated: bool,
+    minimized: bool,
+    outputs: Vec<ObjectId>,
+    app_id: String,
+    title: String,
+}
+
+struct State {
+    outputs: HashMap<ObjectId, Output>,
+    toplevels: HashMap<ObjectId, Toplevel>,
+    tracker: Tracker,
+    changed: Sender<Event>,
+}
+
+impl State {
+    /// Copies the current state over to the tracker, and signals a change of focus, windows or
+    /// outputs.
+    fn publish(&mut self, outputs_changed: bool) {
+        let focused = self
+            .toplevels
+            .values()
+            .find(|t| t.activated)
+            .and_then(|t| t.outputs.first())
+            .and_then(|id| self.outputs.get(id))
+            .map(|o| o.name.clone());
+        let mut windows: Vec<Window> = self
+            .toplevels
+            .values()
+            .map(|t| Window {
+                app_id: t.app_id.clone(),
+                title: t.title.clone(),
+                output: t
+                    .outputs
+                    .first()
+                    .and_then(|id| self.outputs.get(id))
+                    .map(|o| o.name.clone()),
+                focused: t.activated,
+                visible: !t.minimized && !t.outputs.is_empty(),
+                urgent: false,
+                rect: None,
+            })
+            .collect();
+        windows.sort_by(|a, b| (&a.app_id, &a.title).cmp(&(&b.app_id, &b.title)));
+
+        let mut snapshot = self.tracker.snapshot.lock().unwrap();
+        let focus_changed = snapshot.focused != focused || snapshot.windows != windows;
+        snapshot.focused = focused;
+        snapshot.windows = windows;
+        snapshot.outputs = self.outputs.values().cloned().collect();
+        snapshot.outputs.sort_by(|a, b| a.name.cmp(&b.name));
+        drop(snapshot);
+
+        if focus_changed || outputs_changed {
+            // The receiving end only goes away when wlstreamer shuts down
+            let _ = self.changed.send(Event::Changed);
+        }
+    }
+
+    fn bind_output(
+        &mut self,
+        registry: &wl_registry::WlRegistry,
+        name: u32,
+        version: u32,
+        qh: &QueueHandle<State>,
+    ) {
+        let output: wl_output::WlOutput = registry.bind(name, version.min(4), qh, ());
+        self.outputs.insert(
+            output.id(),
+            Output {
+                global_name: name,
+                // Replaced by the name event on wl_output version 4 and up
+                name: format!("output-{}", name),
+                ..Output::default()
+            },
+        );
+    }
+}
+
+/// Connects to the compositor and starts following focus on a background thread. Every change
+/// of the focused output or the output layout is signalled through `changed`.
+pub fn start(changed: Sender<Event>) -> Result<Tracker, String> {
+    let conn = Connection::connect_to_env()
+        .map_err(|err| i18n::tr("Could not connect to the Wayland display: {}", &[&err]))?;
+    let (globals, mut queue) = registry_queue_init::<State>(&conn)
+        .map_err(|err| i18n::tr("Could not list Wayland globals: {}", &[&err]))?;
+    let qh = queue.handle();
+
+    globals
+        .bind::<toplevel_manager::ZwlrForeignToplevelManagerV1, _, _>(&qh, 1..=3, ())
+        .map_err(|_| {
+            "The compositor does not support wlr-foreign-toplevel-management".to_string()
+        })?;
+
+    let tracker = Tracker::default();
+    let mut state = State {
+        outputs: HashMap::new(),
+        toplevels: HashMap::new(),
+        tracker: tracker.clone(),
+        changed,
+    };
+
+    for global in globals.contents().clone_list() {
+        if global.interface == wl_output::WlOutput::interface().name {
+            state.bind_output(globals.registry(), global.name, global.version, &qh);
+        }
+    }
+
+    // Wait for the output details and the initial list of toplevels
+    queue
+        .roundtrip(&mut state)
+        .and_then(|_| queue.roundtrip(&mut state))
+        .map_err(|err| i18n::tr("Could not read the Wayland state: {}", &[&err]))?;
+    state.publish(true);
+
+    thread::spawn(move || loop {
+        if let Err(err) = queue.blocking_dispatch(&mut state) {
+            println!(
+                "{}",
+                i18n::tr("Lost connection to the Wayland display: {}", &[&err])
+            );
+            let _ = state.changed.send(Event::Disconnected);
+            break;
+        }
+    });
+
+    Ok(tracker)
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == wl_output::WlOutput::interface().name => {
+                state.bind_output(registry, name, version, qh);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                let before = state.outputs.len();
+                state.outputs.retain(|_, o| o.global_name != name);
+                if state.outputs.len() != before {
+                    state.publish(true);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let entry = match state.outputs.get_mut(&output.id()) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        match event {
+            wl_output::Event::Geometry {
+                x, y, transform, ..
+            } => {
+                entry.x = x;
+                entry.y = y;
+                entry.transform = match transform {
+                    WEnum::Value(wl_output::Transform::_90) => Some("90"),
+                    WEnum::Value(wl_output::Transform::_180) => Some("180"),
+                    WEnum::Value(wl_output::Transform::_270) => Some("270"),
+                    WEnum::Value(wl_output::Transform::Flipped) => Some("flipped"),
+                    WEnum::Value(wl_output::Transform::Flipped90) => Some("flipped-90"),
+                    WEnum::Value(wl_output::Transform::Flipped180) => Some("flipped-180"),
+                    WEnum::Value(wl_output::Transform::Flipped270) => Some("flipped-270"),
+                    _ => None,
+                }
+                .map(|transform| transform.to_string());
+            }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                refresh,
+            } if flags.contains(wl_output::Mode::Current) => {
+                entry.width = width;
+                entry.height = height;
+                entry.refresh = refresh;
+            }
+            wl_output::Event::Name { name } => entry.name = name,
+            wl_output::Event::Scale { factor } => entry.scale = factor,
+            wl_output::Event::Done => state.publish(true),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<toplevel_manager::ZwlrForeignToplevelManagerV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &toplevel_manager::ZwlrForeignToplevelManagerV1,
+        _: toplevel_manager::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // New toplevels are set up by event_created_child, and we never stop the manager
+    }
+
+    event_created_child!(State, toplevel_manager::ZwlrForeignToplevelManagerV1, [
+        toplevel_manager::EVT_TOPLEVEL_OPCODE => (toplevel_handle::ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<toplevel_handle::ZwlrForeignToplevelHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        handle: &toplevel_handle::ZwlrForeignToplevelHandleV1,
+        event: toplevel_handle::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let toplevel = state.toplevels.entry(handle.id()).or_default();
+
+        match event {
+            toplevel_handle::Event::State { state: states } => {
+                let states: Vec<u32> = states
+                    .chunks_exact(4)
+                    .map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect();
+                toplevel.activated = states.contains(&STATE_ACTIVATED);
+                toplevel.minimized = states.contains(&STATE_MINIMIZED);
+            }
+            toplevel_handle::Event::AppId { app_id } => toplevel.app_id = app_id,
+            toplevel_handle::Event::Title { title } => toplevel.title = title,
+            toplevel_handle::Event::OutputEnter { output } => toplevel.outputs.push(output.id()),
+            toplevel_handle::Event::OutputLeave { output } => {
+                toplevel.outputs.retain(|id| *id != output.id())
+            }
+            toplevel_handle::Event::Done => state.publish(false),
+            toplevel_handle::Event::Closed => {
+                state.toplevels.remove(&handle.id());
+                handle.destroy();
+                state.publish(false);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,511 @@
+//! `wlstreamer tray`: an icon in the tray of KDE, of GNOME with the AppIndicator extension and of
+//! the bars that host StatusNotifierItems, which shows whether the stream is live or blanked and
+//! has a menu to pause it and lock screens. Like `wlstreamer tui`, it follows the running instance
+//! through `ctl status --json` once a second and changes it with the commands `wlstreamer ctl`
+//! sends, so it can be started and stopped independently of the instance.
+
+use crate::control;
+use crate::dbus::{self, Arg, Bus, Message, METHOD_CALL, SIGNAL, UNKNOWN_METHOD};
+use crate::i18n;
+use serde_json::{json, Value};
+use std::process;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+const REFRESH: Duration = Duration::from_secs(1);
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM: &str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const MENU: &str = "com.canonical.dbusmenu";
+const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const FAILED: &str = "org.freedesktop.DBus.Error.Failed";
+
+/// The entries of the menu, followed by one for every screen to lock
+const ROOT: i32 = 0;
+const PAUSE: i32 = 1;
+const LOCK: i32 = 2;
+const SEPARATOR: i32 = 3;
+const QUIT: i32 = 4;
+const FIRST_OUTPUT: i32 = 100;
+
+/// An entry of the menu, with its properties and the entries below it.
+struct Entry {
+    id: i32,
+    properties: Vec<(&'static str, Arg)>,
+    children: Vec<i32>,
+}
+
+fn text(value: &str) -> Arg {
+    Arg::Str(value.to_string())
+}
+
+/// A dictionary from names to values of any type.
+fn dict(entries: Vec<(&str, Arg)>) -> Arg {
+    Arg::Array(
+        "{sv}".to_string(),
+        entries
+            .into_iter()
+            .map(|(name, value)| {
+                Arg::Entry(
+                    Box::new(text(name)),
+                    Box::new(Arg::Variant(Box::new(value))),
+                )
+            })
+            .collect(),
+    )
+}
+
+struct Tray {
+    bus: Bus,
+    /// The name the item is known by on the bus
+    name: String,
+    status: Result<Value, String>,
+    fetched: Instant,
+    /// Counts the changes of the menu, for the hosts to fetch it again
+    revision: u32,
+    /// The serial of the call that puts the item into the tray, until it is answered
+    registering: Option<u32>,
+    /// Quit was picked from the menu, which is answered before quitting
+    quit: bool,
+}
+
+impl Tray {
+    fn running(&self) -> Option<&Value> {
+        self.status.as_ref().ok()
+    }
+
+    fn outputs(&self) -> Vec<Value> {
+        self.running()
+            .and_then(|status| status["outputs"].as_array().cloned())
+            .unwrap_or_default()
+    }
+
+    /// What the icon and the menu show, to tell when they have to be sent again.
+    fn shown(&self) -> Value {
+        match self.running() {
+            Some(status) => json!({
+                "blanked": status["blanked"],
+                "paused": status["paused"],
+                "recording": status["recording"],
+                "outputs": status["outputs"]
+                    .as_array()
+                    .map(|outputs| outputs
+                        .iter()
+                        .map(|output| json!([output["name"], output["locked"]]))
+                        .collect::<Vec<_>>()),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn icon(&self) -> &'static str {
+        match self.running() {
+            Some(status) if status["blanked"].as_bool() == Some(true) => "media-playback-pause",
+            Some(_) => "media-record",
+            None => "media-playback-stop",
+        }
+    }
+
+    fn tooltip(&self) -> String {
+        match self.running() {
+            Some(status) if status["blanked"].as_bool() == Some(true) => {
+                "The stream is blanked".to_string()
+            }
+            Some(status) => i18n::tr(
+                "Streaming {}",
+                &[&status["recording"].as_str().unwrap_or_default()],
+            ),
+            None => "wlstreamer isn't running".to_string(),
+        }
+    }
+
+    fn item_properties(&self) -> Vec<(&'static str, Arg)> {
+        vec![
+            ("Category", text("ApplicationStatus")),
+            ("Id", text(&control::instance_name())),
+            ("Title", text("wlstreamer")),
+            (
+                "Status",
+                text(if self.running().is_some() {
+                    "Active"
+                } else {
+                    "Passive"
+                }),
+            ),
+            ("IconName", text(self.icon())),
+            ("IconThemePath", text("")),
+            (
+                "ToolTip",
+                Arg::Struct(vec![
+                    text(""),
+                    Arg::Array("(iiay)".to_string(), Vec::new()),
+                    text("wlstreamer"),
+                    text(&self.tooltip()),
+                ]),
+            ),
+            ("ItemIsMenu", Arg::Bool(false)),
+            ("Menu", Arg::Path(MENU_PATH.to_string())),
+        ]
+    }
+
+    fn menu_properties(&self) -> Vec<(&'static str, Arg)> {
+        vec![
+            ("Version", Arg::U32(3)),
+            ("TextDirection", text("ltr")),
+            ("Status", text("normal")),
+            ("IconThemePath", Arg::Array("s".to_string(), Vec::new())),
+        ]
+    }
+
+    fn entries(&self) -> Vec<Entry> {
+        let running = self.running().is_some();
+        let paused = self
+            .running()
+            .is_some_and(|status| status["paused"].as_bool() == Some(true));
+        let outputs = self.outputs();
+        let screens: Vec<i32> = (0..outputs.len() as i32)
+            .map(|i| FIRST_OUTPUT + i)
+            .collect();
+
+        let mut entries = vec![
+            Entry {
+                id: ROOT,
+                properties: vec![("children-display", text("submenu"))],
+                children: vec![PAUSE, LOCK, SEPARATOR, QUIT],
+            },
+            Entry {
+                id: PAUSE,
+                properties: vec![
+                    ("label", text("Pause the stream")),
+                    ("enabled", Arg::Bool(running)),
+                    ("toggle-type", text("checkmark")),
+                    ("toggle-state", Arg::I32(paused as i32)),
+                ],
+                children: Vec::new(),
+            },
+            Entry {
+                id: LOCK,
+                properties: vec![
+                    ("label", text("Lock screens")),
+                    ("enabled", Arg::Bool(!screens.is_empty())),
+                    ("children-display", text("submenu")),
+                ],
+                children: screens.clone(),
+            },
+            Entry {
+                id: SEPARATOR,
+                properties: vec![("type", text("separator"))],
+                children: Vec::new(),
+            },
+            Entry {
+                id: QUIT,
+                properties: vec![("label", text("Quit"))],
+                children: Vec::new(),
+            },
+        ];
+        for (id, output) in screens.into_iter().zip(outputs.iter()) {
+            entries.push(Entry {
+                id,
+                properties: vec![
+                    ("label", text(output["name"].as_str().unwrap_or_default())),
+                    ("toggle-type", text("checkmark")),
+                    (
+                        "toggle-state",
+                        Arg::I32((output["locked"].as_bool() == Some(true)) as i32),
+                    ),
+                ],
+                children: Vec::new(),
+            });
+        }
+        entries
+    }
+
+    /// The entry with the entries below it, as GetLayout returns it.
+    fn layout(entries: &[Entry], entry: &Entry) -> Arg {
+        let children = entry
+            .children
+            .iter()
+            .filter_map(|id| entries.iter().find(|entry| entry.id == *id))
+            .map(|child| Arg::Variant(Box::new(Tray::layout(entries, child))))
+            .collect();
+        Arg::Struct(vec![
+            Arg::I32(entry.id),
+            dict(entry.properties.clone()),
+            Arg::Array("v".to_string(), children),
+        ])
+    }
+
+    /// Puts the item into the tray, which answers later.
+    fn register(&mut self) {
+        let call = Message::call(
+            WATCHER,
+            "/StatusNotifierWatcher",
+            WATCHER,
+            "RegisterStatusNotifierItem",
+        )
+        .with_args(&[text(&self.name)]);
+        self.registering = self.bus.send(call).ok();
+    }
+
+    /// Runs the command of a menu entry or a click on the icon.
+    fn activate(&mut self, id: i32) {
+        let paused = self
+            .running()
+            .is_some_and(|status| status["paused"].as_bool() == Some(true));
+        let command = match id {
+            PAUSE if paused => "resume".to_string(),
+            PAUSE => "blank".to_string(),
+            QUIT => {
+                self.quit = true;
+                return;
+            }
+            _ => match self.outputs().get((id - FIRST_OUTPUT) as usize) {
+                Some(output) if id >= FIRST_OUTPUT => {
+                    let name = output["name"].as_str().unwrap_or_default();
+                    if output["locked"].as_bool() == Some(true) {
+                        format!("unlock {}", name)
+                    } else {
+                        format!("lock {}", name)
+                    }
+                }
+                _ => return,
+            },
+        };
+
+        if let Err(err) = control::request(command.as_str()) {
+            println!("{}: {}", command, err);
+        }
+        self.refresh();
+    }
+
+    /// Fetches the status again, and tells the tray if anything it shows changed.
+    fn refresh(&mut self) {
+        let shown = self.shown();
+        self.status = control::status();
+        self.fetched = Instant::now();
+        if self.shown() == shown {
+            return;
+        }
+
+        self.revision += 1;
+        let signals = [
+            (ITEM_PATH, ITEM, "NewIcon", Vec::new()),
+            (ITEM_PATH, ITEM, "NewToolTip", Vec::new()),
+            (
+                ITEM_PATH,
+                ITEM,
+                "NewStatus",
+                vec![text(if self.running().is_some() {
+                    "Active"
+                } else {
+                    "Passive"
+                })],
+            ),
+            (
+                MENU_PATH,
+                MENU,
+                "LayoutUpdated",
+                vec![Arg::U32(self.revision), Arg::I32(ROOT)],
+            ),
+        ];
+        for (path, interface, member, args) in signals.iter() {
+            let _ = self
+                .bus
+                .send(Message::signal(path, interface, member).with_args(args));
+        }
+    }
+
+    /// The answer to a method call, or the name of the error and why.
+    fn call(&mut self, message: &Message) -> Result<Vec<Arg>, (&'static str, String)> {
+        let path = message.path.as_deref().unwrap_or_default();
+        let interface = message.interface.as_deref().unwrap_or_default();
+        let member = message.member.as_deref().unwrap_or_default();
+        let args = message.args();
+        let unknown = || {
+            (
+                UNKNOWN_METHOD,
+                format!(
+                    "There is no method {}.{}({}) at {}",
+                    interface, member, message.signature, path
+                ),
+            )
+        };
+
+        let properties = match path {
+            ITEM_PATH => self.item_properties(),
+            MENU_PATH => self.menu_properties(),
+            _ => Vec::new(),
+        };
+        match (path, interface, member, args.as_slice()) {
+            (_, PROPERTIES, "GetAll", [_]) => Ok(vec![dict(properties)]),
+            (_, PROPERTIES, "Get", [_, name]) => properties
+                .into_iter()
+                .find(|(property, _)| Some(*property) == name.str())
+                .map(|(_, value)| vec![Arg::Variant(Box::new(value))])
+                .ok_or_else(|| {
+                    (
+                        "org.freedesktop.DBus.Error.UnknownProperty",
+                        format!("There is no property {}", name.str().unwrap_or_default()),
+                    )
+                }),
+
+            // A click on the icon pauses or resumes the stream, the menu is shown by the tray
+            (ITEM_PATH, ITEM, "Activate", [_, _]) => {
+                self.activate(PAUSE);
+                Ok(Vec::new())
+            }
+            (ITEM_PATH, ITEM, "SecondaryActivate" | "ContextMenu", [_, _])
+            | (ITEM_PATH, ITEM, "Scroll", [_, _]) => Ok(Vec::new()),
+
+            (MENU_PATH, MENU, "GetLayout", [parent, _, _]) => {
+                let entries = self.entries();
+                let parent = entries
+                    .iter()
+                    .find(|entry| Some(entry.id) == parent.i32())
+                    .ok_or_else(|| (FAILED, "There is no such menu entry".to_string()))?;
+                Ok(vec![
+                    Arg::U32(self.revision),
+                    Tray::layout(&entries, parent),
+                ])
+            }
+            (MENU_PATH, MENU, "GetGroupProperties", [Arg::Array(_, ids), _]) => {
+                let entries = self.entries();
+                let found = entries
+                    .into_iter()
+                    .filter(|entry| ids.is_empty() || ids.contains(&Arg::I32(entry.id)))
+                    .map(|entry| Arg::Struct(vec![Arg::I32(entry.id), dict(entry.properties)]))
+                    .collect();
+                Ok(vec![Arg::Array("(ia{sv})".to_string(), found)])
+            }
+            (MENU_PATH, MENU, "GetProperty", [id, name]) => self
+                .entries()
+                .into_iter()
+                .find(|entry| Some(entry.id) == id.i32())
+                .and_then(|entry| {
+                    entry
+                        .properties
+                        .into_iter()
+                        .find(|(property, _)| Some(*property) == name.str())
+                })
+                .map(|(_, value)| vec![Arg::Variant(Box::new(value))])
+                .ok_or_else(|| (FAILED, "There is no such menu entry".to_string())),
+            (MENU_PATH, MENU, "Event", [id, event, _, _]) => {
+                if event.str() == Some("clicked") {
+                    self.activate(id.i32().unwrap_or_default());
+                }
+                Ok(Vec::new())
+            }
+            (MENU_PATH, MENU, "EventGroup", [Arg::Array(_, events)]) => {
+                for event in events {
+                    if let Arg::Struct(fields) = event {
+                        if fields.get(1).and_then(Arg::str) == Some("clicked") {
+                            self.activate(fields[0].i32().unwrap_or_default());
+                        }
+                    }
+                }
+                Ok(vec![Arg::Array("i".to_string(), Vec::new())])
+            }
+            (MENU_PATH, MENU, "AboutToShow", [_]) => Ok(vec![Arg::Bool(false)]),
+            (MENU_PATH, MENU, "AboutToShowGroup", [_]) => Ok(vec![
+                Arg::Array("i".to_string(), Vec::new()),
+                Arg::Array("i".to_string(), Vec::new()),
+            ]),
+            _ => Err(unknown()),
+        }
+    }
+
+    fn handle(&mut self, message: Message) {
+        match message.kind {
+            METHOD_CALL => {
+                let result = self.call(&message);
+                if message.wants_reply() {
+                    let reply = match result {
+                        Ok(args) => message.reply().with_args(&args),
+                        Err((name, text)) => message.error(name, &text),
+                    };
+                    let _ = self.bus.send(reply);
+                }
+            }
+            SIGNAL => {
+                // A tray that starts after the item, or starts again, doesn't know about it
+                let args = message.args();
+                if message.member.as_deref() == Some("NameOwnerChanged")
+                    && args.first().and_then(Arg::str) == Some(WATCHER)
+                    && args
+                        .get(2)
+                        .and_then(Arg::str)
+                        .is_some_and(|owner| !owner.is_empty())
+                {
+                    self.register();
+                }
+            }
+            _ if message.reply_serial.is_some() && message.reply_serial == self.registering => {
+                self.registering = None;
+                if let Some(error) = message.error_name {
+                    println!(
+                        "{}",
+                        i18n::tr(
+                            "Could not put the icon into the tray, waiting for one to start: {}",
+                            &[&error]
+                        )
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn serve() -> Result<(), String> {
+    let (bus, mut reader) =
+        Bus::open()?.ok_or_else(|| i18n::tr("There is no session bus to show the icon on", &[]))?;
+    let name = format!("{}-{}-1", ITEM, process::id());
+    bus.request_name(&mut reader, &name)?;
+    bus.call_bus(
+        &mut reader,
+        "AddMatch",
+        &[text(&format!(
+            "type='signal',sender='org.freedesktop.DBus',member='NameOwnerChanged',arg0='{}'",
+            WATCHER
+        ))],
+    )?;
+
+    let messages = dbus::incoming(reader);
+    let mut tray = Tray {
+        bus,
+        name,
+        status: control::status(),
+        fetched: Instant::now(),
+        revision: 1,
+        registering: None,
+        quit: false,
+    };
+    tray.register();
+
+    loop {
+        match messages.recv_timeout(REFRESH.saturating_sub(tray.fetched.elapsed())) {
+            Ok(message) => tray.handle(message),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(i18n::tr("Lost the connection to the session bus", &[]))
+            }
+        }
+        if tray.quit {
+            return Ok(());
+        }
+        if tray.fetched.elapsed() >= REFRESH {
+            tray.refresh();
+        }
+    }
+}
+
+/// Shows the icon until Quit is picked from its menu.
+pub fn run() -> ! {
+    if let Err(err) = serve() {
+        println!("{}", err);
+        process::exit(1);
+    }
+    process::exit(0);
+}
@@ -0,0 +1,278 @@
+//! `wlstreamer tui`: a live view of a running instance, drawn from `ctl status --json` once a
+//! second. Keys are turned into the same commands `wlstreamer ctl` sends.
+
+use crate::control;
+use crate::i18n;
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+const REFRESH: Duration = Duration::from_secs(1);
+const KEYS: &str = "up/down or j/k select, l lock/unlock, p pause/resume, q quit";
+
+/// Puts the terminal into non-canonical mode without echo for as long as it lives, so single key
+/// presses can be read. Reads return after at most a tenth of a second.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> Result<RawMode, String> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(i18n::tr(
+                "stdin is not a terminal: {}",
+                &[&io::Error::last_os_error()],
+            ));
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 1;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(i18n::tr(
+                "Could not set up the terminal: {}",
+                &[&io::Error::last_os_error()],
+            ));
+        }
+
+        // Hide the cursor
+        print!("\x1b[?25l");
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        println!("\x1b[?25h");
+        let _ = io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Key {
+    Up,
+    Down,
+    Lock,
+    Pause,
+    Quit,
+}
+
+/// Turns whatever was read from the terminal into keys, arrow keys arriving as escape sequences.
+fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        match &input[i..] {
+            [0x1b, b'[', b'A', ..] => {
+                keys.push(Key::Up);
+                i += 2;
+            }
+            [0x1b, b'[', b'B', ..] => {
+                keys.push(Key::Down);
+                i += 2;
+            }
+            [b'k', ..] => keys.push(Key::Up),
+            [b'j', ..] => keys.push(Key::Down),
+            [b'l', ..] => keys.push(Key::Lock),
+            [b'p', ..] | [b' ', ..] => keys.push(Key::Pause),
+            [b'q', ..] | [0x03, ..] | [0x04, ..] => keys.push(Key::Quit),
+            _ => {}
+        }
+        i += 1;
+    }
+    keys
+}
+
+fn render(status: &Result<Value, String>, selected: usize, message: &str) -> String {
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    let mut line = |text: String| {
+        screen.push_str(text.as_str());
+        screen.push('\n');
+    };
+
+    line("wlstreamer".to_string());
+    line(String::new());
+
+    let status = match status {
+        Ok(status) => status,
+        Err(err) => {
+            line(err.clone());
+            line(String::new());
+            line(KEYS.to_string());
+            return screen;
+        }
+    };
+
+    let state = if status["paused"].as_bool() == Some(true) {
+        "paused"
+    } else if status["unwatched"].as_bool() == Some(true) {
+        "nobody is watching"
+    } else if status["blanked"].as_bool() == Some(true) {
+        "blanked while idle"
+    } else {
+        "streaming"
+    };
+    line(format!(
+        "mode: {}, {}",
+        status["mode"].as_str().unwrap_or("?"),
+        state
+    ));
+
+    let health = if status["stalled"].as_bool() == Some(true) {
+        "stalled".to_string()
+    } else {
+        match (status["fps"].as_f64(), status["uptime"].as_u64()) {
+            (Some(fps), Some(uptime)) => format!(
+                "{:.1} fps, {} frames, {} dropped, up {}s",
+                fps,
+                status["frames"].as_u64().unwrap_or(0),
+                status["dropped"].as_u64().unwrap_or(0),
+                uptime
+            ),
+            _ => "no frame statistics yet".to_string(),
+        }
+    };
+    line(format!("pipeline: {}", health));
+    line(String::new());
+
+    line("Screens:".to_string());
+    let outputs = status["outputs"].as_array().cloned().unwrap_or_default();
+    for (i, output) in outputs.iter().enumerate() {
+        line(format!(
+            "{} {} {:<12} {}x{}{}",
+            if i == selected { ">" } else { " " },
+            if output["live"].as_bool() == Some(true) {
+                "●"
+            } else {
+                " "
+            },
+            output["name"].as_str().unwrap_or("?"),
+            output["width"],
+            output["height"],
+            if output["locked"].as_bool() == Some(true) {
+                "  locked"
+            } else {
+                ""
+            }
+        ));
+    }
+    line(String::new());
+
+    let devices = status["devices"].as_array().cloned().unwrap_or_default();
+    if !devices.is_empty() {
+        line("Devices:".to_string());
+        for device in devices {
+            line(format!(
+                "  {:<12} {}x{}{}",
+                device["device"].as_str().unwrap_or("?"),
+                device["width"],
+                device["height"],
+                if device["output"].as_bool() == Some(true) {
+                    "  output"
+                } else {
+                    ""
+                }
+            ));
+        }
+        line(String::new());
+    }
+
+    line("Recent events:".to_string());
+    let events = status["events"].as_array().cloned().unwrap_or_default();
+    for event in events.iter().rev().take(8) {
+        let mut fields = event.as_object().cloned().unwrap_or_default();
+        let name = fields.remove("event").unwrap_or(Value::Null);
+        fields.remove("time");
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        line(format!(
+            "  {} {}",
+            name.as_str().unwrap_or("?"),
+            fields.join(" ")
+        ));
+    }
+    line(String::new());
+
+    line(message.to_string());
+    line(KEYS.to_string());
+    screen
+}
+
+/// Runs the monitor until q is pressed.
+pub fn run() -> ! {
+    let raw = match RawMode::enable() {
+        Ok(raw) => raw,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut selected = 0;
+    let mut message = String::new();
+    let mut status = control::status();
+    let mut fetched = Instant::now();
+    let mut input = [0; 32];
+
+    'outer: loop {
+        let outputs = match &status {
+            Ok(status) => status["outputs"].as_array().cloned().unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+        selected = selected.min(outputs.len().saturating_sub(1));
+
+        print!("{}", render(&status, selected, message.as_str()));
+        let _ = io::stdout().flush();
+
+        let read = io::stdin().read(&mut input).unwrap_or(0);
+        let keys = parse_keys(&input[..read]);
+        let mut changed = false;
+        for key in keys {
+            let command = match key {
+                Key::Quit => break 'outer,
+                Key::Up => {
+                    selected = selected.saturating_sub(1);
+                    None
+                }
+                Key::Down => {
+                    selected += 1;
+                    None
+                }
+                Key::Lock => outputs.get(selected).map(|output| {
+                    let name = output["name"].as_str().unwrap_or_default();
+                    if output["locked"].as_bool() == Some(true) {
+                        format!("unlock {}", name)
+                    } else {
+                        format!("lock {}", name)
+                    }
+                }),
+                Key::Pause => Some(match &status {
+                    Ok(status) if status["paused"].as_bool() == Some(true) => "resume".to_string(),
+                    _ => "blank".to_string(),
+                }),
+            };
+
+            if let Some(command) = command {
+                message = match control::request(command.as_str()) {
+                    Ok(_) => format!("{}: ok", command),
+                    Err(err) => format!("{}: {}", command, err),
+                };
+                changed = true;
+            }
+        }
+
+        if changed || fetched.elapsed() >= REFRESH {
+            status = control::status();
+            fetched = Instant::now();
+        }
+    }
+
+    drop(raw);
+    std::process::exit(0);
+}
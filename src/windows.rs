@@ -0,0 +1,113 @@
+//! The windows that are open, for the options that depend on what is on the screens rather than
+//! which screen is focused.
+
+use crate::backend::{Backend, IpcRequest};
+use crate::{invalid_reply, ipc_output, niri, Config, SwayScreenRect, SwayWorkspace};
+use serde_json::Value;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Window {
+    /// The Wayland app_id, or the X11 class
+    pub app_id: String,
+    pub title: String,
+    pub output: Option<String>,
+    pub focused: bool,
+    /// Whether it is on a visible workspace and not minimized
+    pub visible: bool,
+    /// Whether it asks for attention
+    pub urgent: bool,
+    /// Where it is in the layout, which only sway and i3 tell
+    pub rect: Option<SwayScreenRect>,
+}
+
+/// Collects the windows from a sway or i3 tree. sway tells whether each window is visible, i3
+/// only which workspaces are.
+fn walk(
+    node: &Value,
+    output: Option<&str>,
+    workspace_visible: bool,
+    visible_workspaces: &[String],
+    windows: &mut Vec<Window>,
+) {
+    let name = node["name"].as_str();
+    let (output, workspace_visible) = match node["type"].as_str() {
+        Some("output") => (name, workspace_visible),
+        Some("workspace") => (
+            output,
+            name.is_some_and(|name| visible_workspaces.iter().any(|ws| ws == name)),
+        ),
+        _ => (output, workspace_visible),
+    };
+
+    let app_id = node["app_id"]
+        .as_str()
+        .or_else(|| node["window_properties"]["class"].as_str());
+    let is_window = matches!(node["type"].as_str(), Some("con") | Some("floating_con"))
+        && (app_id.is_some() || node["window"].is_u64());
+    if is_window {
+        windows.push(Window {
+            app_id: app_id.unwrap_or_default().to_string(),
+            title: name.unwrap_or_default().to_string(),
+            output: output.map(String::from),
+            focused: node["focused"].as_bool().unwrap_or(false),
+            visible: node["visible"].as_bool().unwrap_or(workspace_visible),
+            urgent: node["urgent"].as_bool().unwrap_or(false),
+            rect: serde_json::from_value(node["rect"].clone()).ok(),
+        });
+    }
+
+    for child in node["nodes"]
+        .as_array()
+        .into_iter()
+        .chain(node["floating_nodes"].as_array())
+        .flatten()
+    {
+        walk(
+            child,
+            output,
+            workspace_visible,
+            visible_workspaces,
+            windows,
+        );
+    }
+}
+
+/// All windows on all outputs, or none if the window manager couldn't be asked for them.
+pub fn list(config: &Config) -> Vec<Window> {
+    query(config).unwrap_or_else(|err| {
+        println!("{}", err);
+        Vec::new()
+    })
+}
+
+fn query(config: &Config) -> Result<Vec<Window>, String> {
+    match (config.options.backend, &config.runtime.toplevel) {
+        (Backend::ForeignToplevel, Some(tracker)) => Ok(tracker.windows()),
+        (Backend::ForeignToplevel, None) => Ok(Vec::new()),
+        (Backend::Niri, _) => {
+            let windows = ipc_output(&config.options, IpcRequest::Windows, "windows")?;
+            let workspaces = ipc_output(&config.options, IpcRequest::Workspaces, "workspaces")?;
+            niri::parse_windows(windows.as_str(), workspaces.as_str())
+                .map_err(|err| invalid_reply(&config.options, "windows", err))
+        }
+        _ => {
+            let tree: Value = serde_json::from_str(
+                ipc_output(&config.options, IpcRequest::Windows, "get_tree")?.as_str(),
+            )
+            .map_err(|err| invalid_reply(&config.options, "get_tree", err))?;
+            let workspaces: Vec<SwayWorkspace> = serde_json::from_str(
+                ipc_output(&config.options, IpcRequest::Workspaces, "get_workspaces")?.as_str(),
+            )
+            .map_err(|err| invalid_reply(&config.options, "get_workspaces", err))?;
+            let visible: Vec<String> = workspaces
+                .into_iter()
+                .filter(|w| w.visible)
+                .map(|w| w.name)
+                .collect();
+
+            let mut windows = Vec::new();
+            walk(&tree, None, false, &visible, &mut windows);
+            Ok(windows)
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! `--zoom`: streams a part of the recorded screen around the focused window, scaled up, for
+//! streams where the text on a large screen would be unreadable otherwise. The part is cut out by
+//! the scaler that puts the frames onto the output device, and glides over to the next window
+//! over `--zoom-duration` when the focus moves. The scaler built into wlstreamer moves it on
+//! every frame, an ffmpeg process by typing commands into it a few times a second. sway, i3 and
+//! niri don't tell anyone where the cursor is, so only the focus is followed.
+
+use crate::Resolution;
+use std::io::Write;
+use std::process::ChildStdin;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// ffmpeg reads a key from its input at most every 100ms, and every command starts with one
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// The part of the captured frames that is streamed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Crop {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// The crop filter cutting it out, which the commands move, followed by a comma.
+    pub fn filter(&self) -> String {
+        format!("crop={}:{}:{}:{},", self.width, self.height, self.x, self.y)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The output the focus is on, and the middle of the focused window in its captured pixels
+    focus: Option<(String, f64, f64)>,
+    /// Counts up with every pipeline that is steered, so the thread steering the one before stops
+    generation: u64,
+}
+
+/// Shared between the event loop, which tells it where the focus is, and the scalers moving the
+/// crop of the running pipeline.
+#[derive(Clone, Debug)]
+pub struct Zoom {
+    factor: f64,
+    /// How long the crop takes to get to the next window
+    duration: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl Zoom {
+    pub fn new(factor: f64, duration: Duration) -> Zoom {
+        Zoom {
+            factor,
+            duration,
+            state: Arc::default(),
+        }
+    }
+
+    /// Remembers where the focused window is, in the pixels of the frames captured from its output.
+    pub fn focus(&self, output: &str, x: f64, y: f64) {
+        self.state.lock().unwrap().focus = Some((output.to_string(), x, y));
+    }
+
+    /// The part of the frames of the output, sized `size`, to stream right now: the zoomed in
+    /// size around the focused window, kept within the frame. The middle if the focus is
+    /// somewhere else.
+    pub fn crop(&self, output: &str, size: Resolution) -> Crop {
+        let focus = self.state.lock().unwrap().focus.clone();
+        let (x, y) = match focus {
+            Some((name, x, y)) if name == output => (x, y),
+            _ => (size.width as f64 / 2.0, size.height as f64 / 2.0),
+        };
+        crop_around(size, self.factor, x, y)
+    }
+
+    /// The crop of a pipeline about to stream the output, starting where the focus is now.
+    pub fn pan(&self, output: &str, size: Resolution) -> Pan {
+        Pan {
+            zoom: self.clone(),
+            output: output.to_string(),
+            size,
+            start: self.crop(output, size),
+        }
+    }
+}
+
+/// The crop of the frames of one output, which follows the focus while its pipeline runs.
+#[derive(Clone, Debug)]
+pub struct Pan {
+    zoom: Zoom,
+    output: String,
+    size: Resolution,
+    /// Where the crop is when the pipeline starts
+    pub start: Crop,
+}
+
+impl PartialEq for Pan {
+    fn eq(&self, other: &Pan) -> bool {
+        Arc::ptr_eq(&self.zoom.state, &other.zoom.state)
+            && self.output == other.output
+            && self.size == other.size
+            && self.start == other.start
+    }
+}
+
+impl Eq for Pan {}
+
+impl Pan {
+    /// Starts moving the crop from where it starts.
+    pub fn glide(&self) -> Glide {
+        Glide {
+            pan: self.clone(),
+            from: self.start,
+            to: self.start,
+            started: Instant::now(),
+            current: self.start,
+        }
+    }
+
+    /// Moves the crop of the ffmpeg reading commands from `stdin` along with the focus, until
+    /// ffmpeg goes away or another pipeline is steered.
+    pub fn steer(&self, stdin: ChildStdin) {
+        let generation = {
+            let mut state = self.zoom.state.lock().unwrap();
+            state.generation += 1;
+            state.generation
+        };
+        let mut glide = self.glide();
+
+        thread::spawn(move || {
+            let mut stdin = stdin;
+            let mut sent = glide.current;
+            loop {
+                thread::sleep(INTERVAL);
+                if glide.pan.zoom.state.lock().unwrap().generation != generation {
+                    return;
+                }
+
+                // Only one command fits into every interval, so the axis that is further off
+                // goes first
+                let next = glide.position();
+                let dx = next.x.abs_diff(sent.x);
+                let dy = next.y.abs_diff(sent.y);
+                let command = if dx >= dy && dx > 0 {
+                    sent.x = next.x;
+                    format!("ccrop -1 x {}\n", next.x)
+                } else if dy > 0 {
+                    sent.y = next.y;
+                    format!("ccrop -1 y {}\n", next.y)
+                } else {
+                    continue;
+                };
+                if stdin.write_all(command.as_bytes()).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// The crop on its way to the focused window.
+pub struct Glide {
+    pan: Pan,
+    from: Crop,
+    to: Crop,
+    started: Instant,
+    current: Crop,
+}
+
+impl Glide {
+    /// Where the crop is right now. When the focus moved since it was last asked, it sets off
+    /// from there towards the new window.
+    pub fn position(&mut self) -> Crop {
+        let target = self.pan.zoom.crop(self.pan.output.as_str(), self.pan.size);
+        if target != self.to {
+            self.from = self.current;
+            self.to = target;
+            self.started = Instant::now();
+        }
+
+        let duration = self.pan.zoom.duration.as_secs_f64();
+        let progress = if duration > 0.0 {
+            (self.started.elapsed().as_secs_f64() / duration).min(1.0)
+        } else {
+            1.0
+        };
+        let eased = ease(progress);
+        self.current = Crop {
+            x: between(self.from.x, self.to.x, eased),
+            y: between(self.from.y, self.to.y, eased),
+            ..self.to
+        };
+        self.current
+    }
+}
+
+/// Speeds up and slows down again, so the crop doesn't start or stop with a jerk.
+fn ease(progress: f64) -> f64 {
+    if progress < 0.5 {
+        4.0 * progress.powi(3)
+    } else {
+        1.0 - (-2.0 * progress + 2.0).powi(3) / 2.0
+    }
+}
+
+/// The coordinate the part of the way from `from` to `to`.
+fn between(from: usize, to: usize, part: f64) -> usize {
+    (from as f64 + (to as f64 - from as f64) * part).round() as usize
+}
+
+/// The crop of `size` zoomed in by `factor` around the point, with even sizes and offsets for
+/// yuyv422.
+fn crop_around(size: Resolution, factor: f64, x: f64, y: f64) -> Crop {
+    let width = ((size.width as f64 / factor) as usize & !1).max(2);
+    let height = ((size.height as f64 / factor) as usize & !1).max(2);
+    let left = (x - width as f64 / 2.0).clamp(0.0, (size.width - width) as f64) as usize;
+    let top = (y - height as f64 / 2.0).clamp(0.0, (size.height - height) as f64) as usize;
+
+    Crop {
+        x: left & !1,
+        y: top,
+        width,
+        height,
+    }
+}